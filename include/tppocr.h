@@ -0,0 +1,45 @@
+/* C interface of tppocr, built with the cdylib feature:
+ *
+ *     cargo rustc --release --lib --features cdylib --crate-type cdylib
+ *
+ * Results are JSON strings {"items": [...], "events": [...]} freed with
+ * tppocr_string_free. Functions return NULL on error, described by
+ * tppocr_last_error on the calling thread. A pipeline whose call panicked
+ * returns only errors until it is freed.
+ */
+
+#ifndef TPPOCR_H
+#define TPPOCR_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TppocrPipeline TppocrPipeline;
+
+/* Creates a pipeline from TOML config text for frames of the size. */
+TppocrPipeline *tppocr_pipeline_new(const char *config, const char *tessdata_path,
+                                    const char *language, uint32_t width, uint32_t height);
+
+void tppocr_pipeline_free(TppocrPipeline *pipeline);
+
+/* Recognizes a frame of BGRA pixels whose rows are stride bytes apart. */
+char *tppocr_pipeline_process_bgra(TppocrPipeline *pipeline, const uint8_t *data,
+                                   size_t stride, uint32_t width, uint32_t height);
+
+/* Returns the items still held by the strategies as if time had passed. */
+char *tppocr_pipeline_flush(TppocrPipeline *pipeline);
+
+/* Valid until the next call on the thread. */
+const char *tppocr_last_error(void);
+
+void tppocr_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
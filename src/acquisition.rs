@@ -0,0 +1,261 @@
+//! Frame acquisition stage of the processor.
+//!
+//! Frames of the main stream and the `[[stream]]` sections are read on a
+//! blocking task of the runtime while the processor recognizes the previous
+//! ones. With the jump to live catch-up policy, newer frames replace the ones
+//! the processor hasn't taken yet, so it always gets the live frame. With a
+//! backlog, the stage waits for each frame to be taken so only the frames the
+//! backlog limit drops are skipped.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use slog_scope::{info, warn};
+use tokio::{
+    runtime::Handle,
+    sync::{oneshot, watch, Notify},
+    task::JoinHandle,
+};
+
+use crate::{
+    config::{CatchUpPolicy, ProcessorConfig},
+    error::FrameError,
+    frame::{Frame, FrameReader},
+};
+
+/// Frames of the streams read at the same time.
+pub struct FrameSet {
+    pub main: Frame,
+    /// Names and frames of the `[[stream]]` sections. A stream that failed
+    /// keeps its last frame so the other streams are still processed.
+    pub streams: Vec<(String, Frame)>,
+}
+
+impl FrameSet {
+    /// Returns the frame of the stream name, or of the main stream for None.
+    pub fn stream_frame(&self, stream_name: Option<&str>) -> &Frame {
+        stream_name
+            .and_then(|stream_name| self.streams.iter().find(|(name, _)| name == stream_name))
+            .map_or(&self.main, |(_, frame)| frame)
+    }
+}
+
+/// Frames of a `[[stream]]` section.
+struct StreamInput {
+    name: String,
+    frame_reader: FrameReader,
+    /// Whether the latest read failed, so the error is logged once.
+    failed: bool,
+}
+
+pub struct FrameAcquisition {
+    runtime: Handle,
+    width: u32,
+    height: u32,
+    frame_receiver: watch::Receiver<Option<Arc<FrameSet>>>,
+    /// Receives the error the stage stopped with.
+    error_receiver: oneshot::Receiver<FrameError>,
+    catch_up_policy_sender: watch::Sender<CatchUpPolicy>,
+    /// Notified when the processor takes a frame.
+    taken: Arc<Notify>,
+    stop_flag: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl FrameAcquisition {
+    /// Opens the streams of the config next to the main stream and starts
+    /// reading frames.
+    pub fn start(
+        runtime: Handle,
+        mut frame_reader: FrameReader,
+        config: &ProcessorConfig,
+    ) -> Result<Self, FrameError> {
+        let catch_up_policy = config.catch_up.policy.clone();
+        frame_reader.set_catch_up_policy(catch_up_policy.clone());
+
+        let stream_inputs = config
+            .stream
+            .iter()
+            .map(|stream| {
+                info!("opening stream"; "stream" => &stream.name, "id" => stream.id);
+
+                let mut stream_frame_reader =
+                    FrameReader::new(stream.id, frame_reader.width(), frame_reader.height())?;
+                stream_frame_reader.set_catch_up_policy(catch_up_policy.clone());
+
+                Ok(StreamInput {
+                    name: stream.name.clone(),
+                    frame_reader: stream_frame_reader,
+                    failed: false,
+                })
+            })
+            .collect::<Result<Vec<_>, FrameError>>()?;
+
+        let (width, height) = (frame_reader.width(), frame_reader.height());
+        let (frame_sender, frame_receiver) = watch::channel(None);
+        let (error_sender, error_receiver) = oneshot::channel();
+        let (catch_up_policy_sender, catch_up_policy_receiver) = watch::channel(catch_up_policy);
+        let taken = Arc::new(Notify::new());
+        let stop_flag = Arc::new(AtomicBool::new(false));
+
+        let mut stage = Stage {
+            runtime: runtime.clone(),
+            frame_reader,
+            stream_inputs,
+            frame_sender,
+            catch_up_policy_receiver,
+            taken: Arc::clone(&taken),
+            stop_flag: Arc::clone(&stop_flag),
+        };
+        let task = runtime.spawn_blocking(move || {
+            if let Err(error) = stage.run() {
+                let _ = error_sender.send(error);
+            }
+        });
+
+        Ok(Self {
+            runtime,
+            width,
+            height,
+            frame_receiver,
+            error_receiver,
+            catch_up_policy_sender,
+            taken,
+            stop_flag,
+            task: Some(task),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Sets which frame is read after the processor was busy.
+    pub fn set_catch_up_policy(&self, value: CatchUpPolicy) {
+        self.catch_up_policy_sender.send_replace(value);
+    }
+
+    /// Waits for frames newer than the ones taken last.
+    ///
+    /// Returns the error that stopped the stage, such as no frame arriving
+    /// within the reader's timeout.
+    pub fn next_frames(&mut self) -> Result<Arc<FrameSet>, FrameError> {
+        let frame_receiver = &mut self.frame_receiver;
+
+        if self.runtime.block_on(frame_receiver.changed()).is_ok() {
+            if let Some(frame_set) = frame_receiver.borrow_and_update().clone() {
+                self.taken.notify_one();
+
+                return Ok(frame_set);
+            }
+        }
+
+        // A stage that panicked sends no error
+        Err(self
+            .error_receiver
+            .try_recv()
+            .unwrap_or(FrameError::AcquisitionStopped))
+    }
+}
+
+impl Drop for FrameAcquisition {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        self.taken.notify_one();
+
+        // Returns once the read in progress is done or timed out
+        if let Some(task) = self.task.take() {
+            let _ = self.runtime.block_on(task);
+        }
+    }
+}
+
+/// The part of the acquisition running on the blocking task.
+struct Stage {
+    runtime: Handle,
+    frame_reader: FrameReader,
+    stream_inputs: Vec<StreamInput>,
+    frame_sender: watch::Sender<Option<Arc<FrameSet>>>,
+    catch_up_policy_receiver: watch::Receiver<CatchUpPolicy>,
+    taken: Arc<Notify>,
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl Stage {
+    fn run(&mut self) -> Result<(), FrameError> {
+        let mut catch_up_policy = self.catch_up_policy_receiver.borrow().clone();
+        let mut published = false;
+
+        while !self.stop_flag.load(Ordering::Relaxed) {
+            if self.catch_up_policy_receiver.has_changed().unwrap_or(false) {
+                catch_up_policy = self.catch_up_policy_receiver.borrow_and_update().clone();
+                self.frame_reader
+                    .set_catch_up_policy(catch_up_policy.clone());
+
+                for stream_input in &mut self.stream_inputs {
+                    stream_input
+                        .frame_reader
+                        .set_catch_up_policy(catch_up_policy.clone());
+                }
+            }
+
+            let frame_set = self.read()?;
+
+            if published && catch_up_policy != CatchUpPolicy::JumpToLive {
+                self.runtime.block_on(self.taken.notified());
+
+                if self.stop_flag.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+
+            if self.frame_sender.send(Some(Arc::new(frame_set))).is_err() {
+                break;
+            }
+
+            published = true;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next frame of every stream.
+    ///
+    /// The slowest stream paces the stage.
+    fn read(&mut self) -> Result<FrameSet, FrameError> {
+        self.frame_reader.read()?;
+
+        for stream_input in &mut self.stream_inputs {
+            match stream_input.frame_reader.read() {
+                Ok(()) => {
+                    if stream_input.failed {
+                        info!("stream recovered"; "stream" => &stream_input.name);
+                        stream_input.failed = false;
+                    }
+                }
+                Err(error) => {
+                    if !stream_input.failed {
+                        warn!("failed to read stream frame";
+                            "stream" => &stream_input.name, "error" => %error);
+                        stream_input.failed = true;
+                    }
+                }
+            }
+        }
+
+        Ok(FrameSet {
+            main: self.frame_reader.frame(),
+            streams: self
+                .stream_inputs
+                .iter()
+                .map(|stream_input| (stream_input.name.clone(), stream_input.frame_reader.frame()))
+                .collect(),
+        })
+    }
+}
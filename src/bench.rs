@@ -0,0 +1,250 @@
+//! Measurement of the recognition pipeline's speed on synthetic frames.
+//!
+//! The dialog lines of the demo are drawn on frames ahead of time, and each
+//! frame is run in-process through the region processors of the demo config,
+//! timing every stage. As in the processor, a region identical to the
+//! previous frame isn't recognized again, so the preprocess and OCR stages
+//! count only the frames where the dialog changed.
+
+use std::time::Instant;
+
+use chrono::{Duration, Utc};
+
+use crate::{
+    canvas::TextDrawer,
+    config::ProcessorConfig,
+    demo::{self, DIALOG_LINES, FRAME_INTERVAL, LINE_FRAMES},
+    error::Error,
+    latency::{LatencySummary, LatencyTracker},
+    processor::{self, RegionProcessor},
+    text_processor::TextItem,
+    text_recognizer::TextRecognizerSet,
+};
+
+/// Timings of a stage of the pipeline.
+pub struct StageReport {
+    pub name: &'static str,
+    /// Number of times the stage ran.
+    pub count: usize,
+    /// Time spent in the stage.
+    pub total: std::time::Duration,
+    /// Percentiles of the time of a single run.
+    pub latency: Option<LatencySummary>,
+}
+
+impl StageReport {
+    /// Returns the runs per second.
+    pub fn throughput(&self) -> f64 {
+        self.count as f64 / self.total.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+pub struct BenchReport {
+    /// Stages in pipeline order, followed by the whole frame.
+    pub stages: Vec<StageReport>,
+    pub items: Vec<TextItem>,
+    /// Number of dialog lines shown that were emitted exactly.
+    pub matched_line_count: usize,
+    /// Number of dialog lines shown.
+    pub line_count: usize,
+}
+
+struct Stage {
+    name: &'static str,
+    count: usize,
+    total: std::time::Duration,
+    tracker: LatencyTracker,
+}
+
+impl Stage {
+    fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            count: 0,
+            total: std::time::Duration::from_secs(0),
+            tracker: LatencyTracker::new(capacity),
+        }
+    }
+
+    /// Runs the function, adding its time to the stage.
+    fn time<T>(&mut self, function: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = function();
+
+        self.add(start.elapsed());
+
+        result
+    }
+
+    /// Adds a run of the stage that took the duration.
+    fn add(&mut self, duration: std::time::Duration) {
+        let end_date = Utc::now();
+        let start_date =
+            end_date - Duration::from_std(duration).unwrap_or_else(|_| Duration::zero());
+
+        self.total += duration;
+        self.count += 1;
+        self.tracker.record(&start_date, &end_date);
+    }
+
+    fn report(&self) -> StageReport {
+        StageReport {
+            name: self.name,
+            count: self.count,
+            total: self.total,
+            latency: self.tracker.summary(),
+        }
+    }
+}
+
+pub struct Benchmark {
+    text_recognizers: TextRecognizerSet,
+    region_processors: Vec<RegionProcessor>,
+    frame_width: u32,
+    /// A frame of each dialog line, each followed by a frame of an empty
+    /// box.
+    frames: Vec<Vec<u32>>,
+}
+
+impl Benchmark {
+    /// Draws the frames of the demo with the fonts of the display config.
+    pub fn new(
+        mut text_recognizers: TextRecognizerSet,
+        width: u32,
+        height: u32,
+        fonts: &[String],
+    ) -> Result<Self, Error> {
+        let config = ProcessorConfig::parse(demo::DEMO_CONFIG, width, height)?;
+        processor::load_region_engines(
+            std::slice::from_mut(&mut text_recognizers),
+            &config.region,
+        )?;
+        let mut text_drawer = TextDrawer::with_fonts(fonts)?;
+        let mut frames = Vec::new();
+
+        for line in DIALOG_LINES {
+            for text in &[*line, ""] {
+                let frame = demo::render_frame(&mut text_drawer, width, height, text);
+                frames.push(
+                    frame
+                        .chunks_exact(4)
+                        .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+                        .collect(),
+                );
+            }
+        }
+
+        Ok(Self {
+            text_recognizers,
+            region_processors: config
+                .region
+                .into_iter()
+                .map(RegionProcessor::new)
+                .collect(),
+            frame_width: width,
+            frames,
+        })
+    }
+
+    /// Recognizes the number of frames, showing each dialog line for as many
+    /// frames as the demo.
+    ///
+    /// Frames are dated as if they arrived at the demo's frame rate, so the
+    /// text processors behave the same however fast the machine is.
+    pub fn run(&mut self, frame_count: usize) -> Result<BenchReport, Error> {
+        let mut preprocess_stage = Stage::new("preprocess", frame_count);
+        let mut recognize_stage = Stage::new("ocr", frame_count);
+        let mut text_processing_stage = Stage::new("text_processing", frame_count);
+        let mut frame_stage = Stage::new("frame", frame_count);
+        let mut items = Vec::new();
+        let start_date = Utc::now();
+        let frame_interval = Duration::from_std(FRAME_INTERVAL).unwrap();
+        let mut date = start_date;
+        let frame_width = self.frame_width;
+        let text_recognizers = &self.text_recognizers;
+        let region_processors = &mut self.region_processors;
+
+        for index in 0..frame_count {
+            let frame_data = &self.frames[(index / LINE_FRAMES) % self.frames.len()];
+            date = start_date + frame_interval * index as i32;
+
+            frame_stage.time(|| -> Result<(), Error> {
+                for (region_index, region_processor) in region_processors.iter_mut().enumerate() {
+                    region_processor.recognize(text_recognizers, frame_data, frame_width, &date)?;
+
+                    let start = Instant::now();
+                    let text_items = region_processor.get_text(&date);
+                    let mut text_processing_duration = start.elapsed();
+
+                    if let Some(stage_durations) = region_processor.stage_durations() {
+                        preprocess_stage.add(stage_durations.preprocess);
+                        recognize_stage.add(stage_durations.ocr);
+                        text_processing_duration += stage_durations.text_processing;
+                    }
+
+                    text_processing_stage.add(text_processing_duration);
+                    region_processor.check_quota(region_index, text_items.len(), &date);
+
+                    if !region_processor.is_muted() {
+                        items.extend(text_items);
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+
+        // Flush items still buffered as if time had passed
+        for region_processor in region_processors.iter_mut() {
+            let text_items = region_processor.get_text(&(date + Duration::minutes(1)));
+
+            if !region_processor.is_muted() {
+                items.extend(text_items);
+            }
+        }
+
+        let shown_lines = shown_lines(frame_count);
+        let matched_line_count = shown_lines
+            .iter()
+            .filter(|line| items.iter().any(|item| item.text.trim() == **line))
+            .count();
+
+        Ok(BenchReport {
+            stages: vec![
+                preprocess_stage.report(),
+                recognize_stage.report(),
+                text_processing_stage.report(),
+                frame_stage.report(),
+            ],
+            items,
+            matched_line_count,
+            line_count: shown_lines.len(),
+        })
+    }
+}
+
+/// Returns the distinct dialog lines shown in the number of frames.
+fn shown_lines(frame_count: usize) -> Vec<&'static str> {
+    // Each line is followed by as many frames of an empty box
+    let frames_per_line = LINE_FRAMES * 2;
+
+    DIALOG_LINES
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| index * frames_per_line < frame_count)
+        .map(|(_, line)| *line)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shown_lines() {
+        assert!(shown_lines(0).is_empty());
+        assert_eq!(shown_lines(1), &DIALOG_LINES[..1]);
+        assert_eq!(shown_lines(LINE_FRAMES * 2 + 1), &DIALOG_LINES[..2]);
+        assert_eq!(shown_lines(1000), DIALOG_LINES);
+    }
+}
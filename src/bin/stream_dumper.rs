@@ -1,8 +1,26 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
 use clap::{App, Arg};
 use slog_scope::info;
+use tppocr::{
+    frame::{CropRect, FrameTimestamps, ReconnectPolicy},
+    frame_transport::FrameSender,
+    image_directory::ImageDirectoryDumper,
+    recording::RecordingConfig,
+    screen_capture::ScreenCaptureDumper,
+    stream_url::{HttpOptions, UrlResolver},
+};
+
+/// Input prefix of screen capture, followed by an optional window title.
+const X11_PREFIX: &str = "x11:";
 
 fn main() -> anyhow::Result<()> {
     tppocr::logging::set_up_logging();
+    tppocr::capability::CapabilityReport::probe(None).log();
 
     let arg_matches = App::new("stream frame dumper")
         .arg(
@@ -10,18 +28,54 @@ fn main() -> anyhow::Result<()> {
                 .value_name("INPUT")
                 .takes_value(true)
                 .required(true)
-                .help("URL of stream to be passed to ffmpeg's libav suite"),
+                .help(
+                    "URL of stream to be passed to ffmpeg's libav suite, \
+                    a capture device such as /dev/video0, \
+                    x11: for the X11 screen or x11:TITLE for a window, \
+                    or a directory of PNG and JPEG images",
+                ),
         )
         .arg(Arg::with_name("get_url").long("get-url").help(
-            "Interpret INPUT as a webpage link and get the actual stream URL using youtube-dl",
+            "Interpret INPUT as a webpage link and get the actual stream URL using yt-dlp, streamlink, or youtube-dl",
         ))
+        .arg(
+            Arg::with_name("url_resolver")
+                .long("url-resolver")
+                .value_name("PROGRAM")
+                .takes_value(true)
+                .possible_values(&["yt-dlp", "streamlink", "youtube-dl"])
+                .help("When --get-url is specified, program getting the stream URL instead of the first one installed"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Proxy for getting the stream URL and reading the stream, such as http://127.0.0.1:3128"),
+        )
+        .arg(
+            Arg::with_name("cookies")
+                .long("cookies")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Netscape format cookies file for getting the stream URL and reading the stream"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .value_name("HEADER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Header such as 'Referer: https://example.com/' for getting the stream URL and reading the stream"),
+        )
         .arg(
             Arg::with_name("format")
                 .long("format")
                 .value_name("FORMAT")
                 .takes_value(true)
                 .default_value("720p60")
-                .help("When --get-url is specified, resolution format of the stream"),
+                .help("When --get-url is specified, resolution format of the stream, or formats such as 720p60,720p,480p tried in order"),
         )
         .arg(
             Arg::with_name("width")
@@ -41,6 +95,116 @@ fn main() -> anyhow::Result<()> {
                 .default_value("8840")
                 .help("Instance ID number for shared memory and port number"),
         )
+        .arg(
+            Arg::with_name("slots")
+                .long("slots")
+                .default_value("4")
+                .help("Number of frames kept in shared memory, limiting the processor's backlog"),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .help("Also send frames over TCP to processors connecting to this address"),
+        )
+        .arg(Arg::with_name("reconnect").long("reconnect").help(
+            "Reconnect with increasing delays when the input fails or ends \
+            instead of exiting",
+        ))
+        .arg(
+            Arg::with_name("max_retries")
+                .long("max-retries")
+                .default_value("10")
+                .help("With --reconnect, consecutive attempts without a frame before exiting"),
+        )
+        .arg(
+            Arg::with_name("retry_delay")
+                .long("retry-delay")
+                .default_value("1")
+                .help("With --reconnect, seconds before the first attempt, doubling each attempt"),
+        )
+        .arg(
+            Arg::with_name("max_retry_delay")
+                .long("max-retry-delay")
+                .default_value("60")
+                .help("With --reconnect, maximum seconds between attempts"),
+        )
+        .arg(
+            Arg::with_name("image_rate")
+                .long("image-rate")
+                .default_value("1")
+                .help("When INPUT is a directory, images shown per second"),
+        )
+        .arg(
+            Arg::with_name("video_size")
+                .long("video-size")
+                .value_name("WIDTHxHEIGHT")
+                .takes_value(true)
+                .help("When INPUT is a capture device, resolution to capture at"),
+        )
+        .arg(
+            Arg::with_name("framerate")
+                .long("framerate")
+                .value_name("RATE")
+                .takes_value(true)
+                .help("When INPUT is a capture device, frames per second to capture at"),
+        )
+        .arg(
+            Arg::with_name("input_format")
+                .long("input-format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .help("When INPUT is a capture device, pixel format or codec such as mjpeg or yuyv422"),
+        )
+        .arg(
+            Arg::with_name("crop")
+                .long("crop")
+                .value_name("X,Y,WIDTH,HEIGHT")
+                .takes_value(true)
+                .help("Rectangle of the input frames, in input pixels, to keep before scaling"),
+        )
+        .arg(
+            Arg::with_name("capture_rate")
+                .long("capture-rate")
+                .default_value("10")
+                .help("When INPUT is a screen capture, frames captured per second"),
+        )
+        .arg(
+            Arg::with_name("record_dir")
+                .long("record-dir")
+                .value_name("DIRECTORY")
+                .takes_value(true)
+                .help("Also record the input to segment files in the directory"),
+        )
+        .arg(
+            Arg::with_name("segment_format")
+                .long("segment-format")
+                .possible_values(&["mp4", "ts"])
+                .default_value("mp4")
+                .help("Container of recorded segments"),
+        )
+        .arg(
+            Arg::with_name("segment_duration")
+                .long("segment-duration")
+                .value_name("SECONDS")
+                .default_value("600")
+                .help("Length of recorded segments"),
+        )
+        .arg(
+            Arg::with_name("pts_timestamps")
+                .long("pts-timestamps")
+                .help("Timestamp frames by presentation time instead of the wall clock"),
+        )
+        .arg(
+            Arg::with_name("stream_start")
+                .long("stream-start")
+                .value_name("DATE")
+                .help(
+                    "With --pts-timestamps, RFC 3339 date of presentation time 0, \
+                    such as the broadcast start of a VOD",
+                ),
+        )
         .arg(Arg::with_name("skip_sleep").long("skip-sleep").help(
             "Don't sleep to account for presentation time; \
             read the input as fast as possible.",
@@ -50,12 +214,85 @@ fn main() -> anyhow::Result<()> {
                 .long("loop")
                 .help("Loop the input source (for debugging)"),
         )
+        .arg(tppocr::logging::log_level_arg())
+        .arg(tppocr::ipc::namespace_arg())
         .get_matches();
 
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    if let Some(namespace) = arg_matches.value_of("ipc_namespace") {
+        tppocr::ipc::set_namespace(namespace)?;
+    }
+
     let mut url = arg_matches.value_of("input").unwrap().to_owned();
+    let slot_count = arg_matches.value_of("slots").unwrap().parse()?;
+    let frame_sender = match arg_matches.value_of("listen") {
+        Some(address) => Some(FrameSender::bind(address, slot_count as usize)?),
+        None => None,
+    };
+
+    if Path::new(&url).is_dir() {
+        let mut dumper = ImageDirectoryDumper::new(
+            Path::new(&url),
+            arg_matches.value_of("id").unwrap().parse()?,
+            arg_matches.value_of("width").unwrap().parse()?,
+            arg_matches.value_of("height").unwrap().parse()?,
+            slot_count,
+        )?;
+        dumper.set_frame_sender(frame_sender);
+        dumper.set_images_per_second(arg_matches.value_of("image_rate").unwrap().parse()?);
+        dumper.set_infinite_loop(arg_matches.is_present("loop"));
+        dumper.run()?;
+
+        return Ok(());
+    }
+
+    if let Some(window_name) = url.strip_prefix(X11_PREFIX) {
+        let mut dumper = ScreenCaptureDumper::new(
+            Some(window_name.to_string()).filter(|name| !name.is_empty()),
+            arg_matches.value_of("id").unwrap().parse()?,
+            arg_matches.value_of("width").unwrap().parse()?,
+            arg_matches.value_of("height").unwrap().parse()?,
+            slot_count,
+        )?;
+        dumper.set_frame_sender(frame_sender);
+        dumper.set_frames_per_second(arg_matches.value_of("capture_rate").unwrap().parse()?);
+
+        if let Some(crop) = arg_matches.value_of("crop") {
+            dumper.set_crop(Some(crop.parse::<CropRect>().map_err(anyhow::Error::msg)?));
+        }
 
-    if arg_matches.is_present("get_url") {
-        url = tppocr::stream_url::get_stream_url(&url, arg_matches.value_of("format").unwrap())?;
+        dumper.run()?;
+
+        return Ok(());
+    }
+
+    let http_options = HttpOptions {
+        proxy: arg_matches.value_of("proxy").map(String::from),
+        cookie_file: arg_matches.value_of("cookies").map(PathBuf::from),
+        headers: arg_matches
+            .values_of("header")
+            .map(|headers| headers.map(String::from).collect())
+            .unwrap_or_default(),
+    };
+    let url_resolver = if arg_matches.is_present("get_url") {
+        Some(match arg_matches.value_of("url_resolver") {
+            Some(program) => program.parse().map_err(anyhow::Error::msg)?,
+            None => UrlResolver::detect()?,
+        })
+    } else {
+        None
+    };
+
+    if let Some(url_resolver) = url_resolver {
+        url = tppocr::stream_url::get_stream_url(
+            url_resolver,
+            &url,
+            arg_matches.value_of("format").unwrap(),
+            &http_options,
+        )?;
         info!("got stream url"; "url" => &url);
     }
     ffmpeg_next::init()?;
@@ -65,8 +302,82 @@ fn main() -> anyhow::Result<()> {
         arg_matches.value_of("id").unwrap().parse()?,
         arg_matches.value_of("width").unwrap().parse()?,
         arg_matches.value_of("height").unwrap().parse()?,
+        slot_count,
     )?;
 
+    server.set_frame_sender(frame_sender);
+
+    if let Some(crop) = arg_matches.value_of("crop") {
+        server.set_crop(Some(crop.parse::<CropRect>().map_err(anyhow::Error::msg)?));
+    }
+
+    if let Some(directory) = arg_matches.value_of("record_dir") {
+        server.set_recording(Some(RecordingConfig {
+            directory: PathBuf::from(directory),
+            format: arg_matches
+                .value_of("segment_format")
+                .unwrap()
+                .parse()
+                .map_err(anyhow::Error::msg)?,
+            segment_duration: Duration::from_secs_f64(
+                arg_matches.value_of("segment_duration").unwrap().parse()?,
+            ),
+        }));
+    }
+
+    if tppocr::frame::is_capture_device(arg_matches.value_of("input").unwrap()) {
+        let device_options = ["video_size", "framerate", "input_format"]
+            .iter()
+            .filter_map(|name| {
+                arg_matches
+                    .value_of(name)
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        server.set_device_options(device_options);
+    } else {
+        server.set_input_options(http_options.ffmpeg_options()?);
+    }
+
+    if arg_matches.is_present("pts_timestamps") {
+        let stream_start = match arg_matches.value_of("stream_start") {
+            Some(date) => Some(DateTime::parse_from_rfc3339(date)?.with_timezone(&Utc)),
+            None => None,
+        };
+
+        server.set_timestamps(FrameTimestamps::Presentation { stream_start });
+    }
+
+    if arg_matches.is_present("reconnect") {
+        server.set_reconnect_policy(Some(ReconnectPolicy {
+            initial_delay: Duration::from_secs_f64(
+                arg_matches.value_of("retry_delay").unwrap().parse()?,
+            ),
+            max_delay: Duration::from_secs_f64(
+                arg_matches.value_of("max_retry_delay").unwrap().parse()?,
+            ),
+            max_attempts: arg_matches.value_of("max_retries").unwrap().parse()?,
+        }));
+
+        // Resolved stream URLs expire, so each attempt resolves the link again
+        if let Some(url_resolver) = url_resolver {
+            let link = arg_matches.value_of("input").unwrap().to_owned();
+            let format = arg_matches.value_of("format").unwrap().to_owned();
+
+            server.set_url_resolver(Some(Box::new(move || {
+                let url = tppocr::stream_url::get_stream_url(
+                    url_resolver,
+                    &link,
+                    &format,
+                    &http_options,
+                )?;
+                info!("got stream url"; "url" => &url);
+                Ok(url)
+            })));
+        }
+    }
+
     if arg_matches.is_present("loop") {
         server.set_infinite_loop(true);
     }
@@ -75,5 +386,7 @@ fn main() -> anyhow::Result<()> {
         server.set_skip_sleep(true);
     }
 
-    server.run()
+    server.run()?;
+
+    Ok(())
 }
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use clap::{App, Arg};
+use tppocr::{bench::Benchmark, text_recognizer::TextRecognizerSet};
+
+fn main() -> anyhow::Result<()> {
+    tppocr::logging::set_up_logging();
+
+    let arg_matches = App::new("recognition benchmark")
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .default_value("300")
+                .help("Number of synthetic frames to recognize"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .default_value("1280")
+                .help("Width of the frames"),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .default_value("720")
+                .help("Height of the frames"),
+        )
+        .arg(
+            Arg::with_name("tesseract_data_path")
+                .long("tesseract-data-path")
+                .default_value("/usr/share/tesseract-ocr/4.00/tessdata/")
+                .help("Path of the Tesseract 'tessdata' directory."),
+        )
+        .arg(
+            Arg::with_name("tesseract_language")
+                .long("tesseract-language")
+                .default_value("eng")
+                .help("Tesseract language codes used for recognition."),
+        )
+        .arg(
+            Arg::with_name("font")
+                .long("font")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FONT")
+                .help("Font file or installed font name replacing Unifont, repeated for fallbacks"),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .get_matches();
+
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    let frame_count: usize = arg_matches.value_of("frames").unwrap().parse()?;
+    let fonts: Vec<String> = arg_matches
+        .values_of("font")
+        .map(|fonts| fonts.map(String::from).collect())
+        .unwrap_or_default();
+    let text_recognizers = TextRecognizerSet::new(
+        arg_matches.value_of("tesseract_data_path").unwrap(),
+        arg_matches.value_of("tesseract_language").unwrap(),
+        BTreeMap::new(),
+    )?;
+    let mut benchmark = Benchmark::new(
+        text_recognizers,
+        arg_matches.value_of("width").unwrap().parse()?,
+        arg_matches.value_of("height").unwrap().parse()?,
+        &fonts,
+    )?;
+
+    let report = benchmark.run(frame_count)?;
+
+    println!(
+        "{:<16} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "count", "per_sec", "p50_ms", "p95_ms", "p99_ms"
+    );
+
+    for stage in &report.stages {
+        let (p50, p95, p99) = match &stage.latency {
+            Some(latency) => (latency.p50, latency.p95, latency.p99),
+            None => (0.0, 0.0, 0.0),
+        };
+
+        println!(
+            "{:<16} {:>8} {:>10.1} {:>10.2} {:>10.2} {:>10.2}",
+            stage.name,
+            stage.count,
+            stage.throughput(),
+            p50,
+            p95,
+            p99
+        );
+    }
+
+    println!();
+    println!(
+        "emitted {} items, {} of {} dialog lines exactly",
+        report.items.len(),
+        report.matched_line_count,
+        report.line_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use clap::{App, Arg};
+use slog_scope::warn;
+use tppocr::ipc_cleanup::find_stale_files;
+
+fn main() -> anyhow::Result<()> {
+    tppocr::logging::set_up_logging();
+
+    let arg_matches = App::new("shared memory and socket cleanup")
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("List the stale files without removing them"),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .arg(tppocr::ipc::namespace_arg())
+        .get_matches();
+
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    if let Some(namespace) = arg_matches.value_of("ipc_namespace") {
+        tppocr::ipc::set_namespace(namespace)?;
+    }
+
+    let dry_run = arg_matches.is_present("dry_run");
+
+    for stale_file in find_stale_files()? {
+        if dry_run {
+            println!("{}\t{}", stale_file.kind, stale_file.path.display());
+        } else if let Err(error) = stale_file.remove() {
+            warn!("failed to remove stale file";
+                "path" => %stale_file.path.display(), "error" => %error);
+        } else {
+            println!("removed {}\t{}", stale_file.kind, stale_file.path.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use clap::{App, Arg};
+use tppocr::telemetry::TelemetryCollector;
+
+fn main() -> anyhow::Result<()> {
+    tppocr::logging::set_up_logging();
+
+    let arg_matches = App::new("telemetry collector")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .default_value("0.0.0.0:9771")
+                .help("Address accepting reports from instances"),
+        )
+        .arg(
+            Arg::with_name("metrics_listen")
+                .long("metrics-listen")
+                .default_value("127.0.0.1:9772")
+                .help("Address serving the latest reports in the Prometheus text format"),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .get_matches();
+
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    TelemetryCollector::new().run(
+        arg_matches.value_of("listen").unwrap(),
+        arg_matches.value_of("metrics_listen").unwrap(),
+    )?;
+
+    Ok(())
+}
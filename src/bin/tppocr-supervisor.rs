@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg};
+use tppocr::{config::PipelineConfig, supervisor::Supervisor};
+
+fn main() -> anyhow::Result<()> {
+    tppocr::logging::set_up_logging();
+
+    let arg_matches = App::new("pipeline supervisor")
+        .arg(
+            Arg::with_name("config")
+                .takes_value(true)
+                .value_name("CONFIG")
+                .help("Filename of configuration file or directory of configuration fragments with a pipeline section")
+                .required(true),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .arg(tppocr::ipc::namespace_arg())
+        .get_matches();
+
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+        // The programs started by the supervisor read the filter at start
+        std::env::set_var("TPPOCR_LOG_LEVEL", spec);
+    }
+
+    let config_path = Path::new(arg_matches.value_of("config").unwrap());
+    let config = PipelineConfig::load(config_path)?;
+
+    if let Some(namespace) = arg_matches
+        .value_of("ipc_namespace")
+        .or(config.ipc_namespace.as_deref())
+    {
+        tppocr::ipc::set_namespace(namespace)?;
+        std::env::set_var(tppocr::ipc::NAMESPACE_VAR, namespace);
+    }
+
+    let bin_dir = match &config.bin_dir {
+        Some(bin_dir) => bin_dir.clone(),
+        None => std::env::current_exe()?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(PathBuf::new),
+    };
+
+    let mut supervisor = Supervisor::new(config_path, config, bin_dir);
+    supervisor.run()?;
+
+    Ok(())
+}
@@ -1,7 +1,19 @@
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
 use clap::{App, Arg};
+use tppocr::{
+    config::{MjpegConfig, RegionEditConfig, VncScreenConfig, VncServerConfig, VncWebConfig},
+    mjpeg::MjpegServer,
+    secret::Secret,
+    vnc::VncServer,
+};
 
 fn main() -> anyhow::Result<()> {
     tppocr::logging::set_up_logging();
+    tppocr::capability::CapabilityReport::probe(None).log();
 
     let arg_matches = App::new("VNC debug image server")
         .arg(
@@ -22,12 +34,131 @@ fn main() -> anyhow::Result<()> {
                 .default_value("8855")
                 .help("Instance ID number for shared memory and port number"),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("CONFIG")
+                .help("Serve the screens of the vnc_server section of a configuration file instead of one screen"),
+        )
+        .arg(
+            Arg::with_name("edit_config")
+                .long("edit-config")
+                .takes_value(true)
+                .value_name("CONFIG")
+                .help("Show the stream and edit the regions of a configuration file instead of showing the debug image"),
+        )
+        .arg(
+            Arg::with_name("stream_id")
+                .long("stream-id")
+                .default_value("8840")
+                .help("Instance ID number of the stream dumper shown when editing regions"),
+        )
+        .arg(
+            Arg::with_name("listen_address")
+                .long("listen-address")
+                .default_value("127.0.0.1")
+                .help("Address of the interface to listen on. Addresses other than loopback require a password"),
+        )
+        .arg(
+            Arg::with_name("password_env")
+                .long("password-env")
+                .takes_value(true)
+                .value_name("VARIABLE")
+                .help("Require the password in the environment variable from clients"),
+        )
+        .arg(
+            Arg::with_name("web_directory")
+                .long("web-directory")
+                .takes_value(true)
+                .value_name("DIRECTORY")
+                .help("Serve a web VNC client such as noVNC from the directory over HTTP"),
+        )
+        .arg(
+            Arg::with_name("http_port_offset")
+                .long("http-port-offset")
+                .default_value("100")
+                .help("Number added to the ID for the HTTP port of the web client"),
+        )
+        .arg(
+            Arg::with_name("mjpeg_address")
+                .long("mjpeg-address")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .help("Also serve the screen as an MJPEG stream over HTTP on the address such as 127.0.0.1:8890"),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .arg(tppocr::ipc::namespace_arg())
         .get_matches();
 
-    let mut server = tppocr::vnc::VncServer::new(
-        arg_matches.value_of("id").unwrap().parse()?,
-        arg_matches.value_of("width").unwrap().parse()?,
-        arg_matches.value_of("height").unwrap().parse()?,
-    )?;
-    server.run()
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    if let Some(namespace) = arg_matches.value_of("ipc_namespace") {
+        tppocr::ipc::set_namespace(namespace)?;
+    }
+
+    let config = match arg_matches.value_of("config") {
+        Some(config_path) => VncServerConfig::load(Path::new(config_path))?,
+        None => {
+            let edit = match arg_matches.value_of("edit_config") {
+                Some(config_path) => Some(RegionEditConfig {
+                    stream_id: arg_matches.value_of("stream_id").unwrap().parse()?,
+                    config: PathBuf::from(config_path),
+                }),
+                None => None,
+            };
+
+            let password = match arg_matches.value_of("password_env") {
+                Some(name) => {
+                    Some(Secret::new(std::env::var(name).map_err(|_| {
+                        anyhow::anyhow!("environment variable {} not set", name)
+                    })?))
+                }
+                None => None,
+            };
+
+            let web = match arg_matches.value_of("web_directory") {
+                Some(directory) => Some(VncWebConfig {
+                    directory: PathBuf::from(directory),
+                    port_offset: arg_matches.value_of("http_port_offset").unwrap().parse()?,
+                }),
+                None => None,
+            };
+
+            let mjpeg = arg_matches
+                .value_of("mjpeg_address")
+                .map(|address| MjpegConfig {
+                    address: address.to_string(),
+                    fps: 5.0,
+                    quality: 75,
+                });
+
+            VncServerConfig {
+                screen: vec![VncScreenConfig {
+                    id: arg_matches.value_of("id").unwrap().parse()?,
+                    width: arg_matches.value_of("width").unwrap().parse()?,
+                    height: arg_matches.value_of("height").unwrap().parse()?,
+                    edit,
+                }],
+                password,
+                listen_address: arg_matches
+                    .value_of("listen_address")
+                    .unwrap()
+                    .parse::<Ipv4Addr>()?,
+                web,
+                mjpeg,
+            }
+        }
+    };
+
+    let mut server = VncServer::from_config(&config)?;
+    let _mjpeg_server = match &config.mjpeg {
+        Some(mjpeg_config) => Some(MjpegServer::new(mjpeg_config, &config.screen)?),
+        None => None,
+    };
+    server.run()?;
+
+    Ok(())
 }
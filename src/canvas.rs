@@ -1,33 +1,64 @@
-use font_kit::font::Font;
-use raqote::{Color, DrawOptions, DrawTarget, Point, Source};
+use std::{ffi::OsStr, path::Path};
+
+use font_kit::{family_name::FamilyName, font::Font, properties::Properties, source::SystemSource};
+use raqote::{Color, DrawOptions, DrawTarget, PathBuilder, Point, Source, StrokeStyle};
+
+use crate::error::DisplayError;
+
+/// Unifont covers the Basic Multilingual Plane and Unifont Upper the planes
+/// above it.
+pub const DEFAULT_FONTS: [&str; 2] = ["UnifontMedium", "UnifontUpperMedium"];
+
+/// Height of the box drawn for characters missing from every font, relative
+/// to the font size.
+const PLACEHOLDER_HEIGHT: f32 = 0.75;
 
 pub struct TextDrawer {
-    fonts: [Font; 2],
-    glyph_ids: [Vec<u32>; 2],
-    glyph_positions: [Vec<Point>; 2],
+    fonts: Vec<Font>,
+    glyph_ids: Vec<Vec<u32>>,
+    glyph_positions: Vec<Vec<Point>>,
     color: Color,
     font_size: f32, // in points,
     position: Point,
 }
 
 impl TextDrawer {
-    pub fn new() -> anyhow::Result<Self> {
-        let source = font_kit::source::SystemSource::new();
-        let unifont = source.select_by_postscript_name("UnifontMedium")?.load()?;
-        let unifont_2 = source
-            .select_by_postscript_name("UnifontUpperMedium")?
-            .load()?;
+    pub fn new() -> Result<Self, DisplayError> {
+        let fonts: Vec<String> = DEFAULT_FONTS.iter().map(|name| name.to_string()).collect();
+
+        Self::with_fonts(&fonts)
+    }
+
+    /// Loads the fonts, which are tried in order for each character.
+    ///
+    /// Each font is a path to a font file if it contains a slash or ends with
+    /// a font file extension, otherwise a PostScript name or a family name of
+    /// an installed font.
+    pub fn with_fonts(fonts: &[String]) -> Result<Self, DisplayError> {
+        if fonts.is_empty() {
+            return Err(DisplayError::NoFonts);
+        }
+
+        let source = SystemSource::new();
+        let fonts = fonts
+            .iter()
+            .map(|font| load_font(&source, font))
+            .collect::<Result<Vec<Font>, DisplayError>>()?;
 
         Ok(Self {
-            fonts: [unifont, unifont_2],
-            glyph_ids: [Vec::new(), Vec::new()],
-            glyph_positions: [Vec::new(), Vec::new()],
+            glyph_ids: vec![Vec::new(); fonts.len()],
+            glyph_positions: vec![Vec::new(); fonts.len()],
+            fonts,
             color: Color::new(255, 255, 255, 255),
             font_size: 16.0,
             position: Point::new(0.0, 0.0),
         })
     }
 
+    pub fn font_names(&self) -> Vec<String> {
+        self.fonts.iter().map(|font| font.full_name()).collect()
+    }
+
     pub fn color(&self) -> &Color {
         &self.color
     }
@@ -55,19 +86,34 @@ impl TextDrawer {
     pub fn draw(&mut self, canvas: &mut DrawTarget, text: &str) {
         let mut x = self.position.x;
         let mut y = self.position.y;
-        let units_per_em = self.fonts[0].metrics().units_per_em as f32;
+        let mut placeholders = PathBuilder::new();
 
         for character in text.chars() {
-            for (index, font) in self.fonts.iter().enumerate() {
-                if let Some(glyph_id) = font.glyph_for_char(character) {
+            let found = self.fonts.iter().enumerate().find_map(|(index, font)| {
+                font.glyph_for_char(character)
+                    .map(|glyph_id| (index, font, glyph_id))
+            });
+
+            match found {
+                Some((index, font, glyph_id)) => {
                     self.glyph_ids[index].push(glyph_id);
                     self.glyph_positions[index].push(Point::new(x, y));
 
+                    // Fonts of the chain can have different units
+                    let scale = self.font_size / font.metrics().units_per_em as f32;
                     let advance = font.advance(glyph_id).unwrap();
-                    x += advance.x() * self.font_size / units_per_em;
-                    y += advance.y() * self.font_size / units_per_em;
-
-                    break;
+                    x += advance.x() * scale;
+                    y += advance.y() * scale;
+                }
+                None if character.is_control() => {}
+                None => {
+                    // Characters no font covers are drawn as a box so that
+                    // missing coverage is visible instead of text silently
+                    // disappearing
+                    let width = self.font_size / 2.0;
+                    let height = self.font_size * PLACEHOLDER_HEIGHT;
+                    placeholders.rect(x + 1.0, y - height, width - 2.0, height);
+                    x += width;
                 }
             }
         }
@@ -87,5 +133,62 @@ impl TextDrawer {
             self.glyph_ids[index].clear();
             self.glyph_positions[index].clear();
         }
+
+        canvas.stroke(
+            &placeholders.finish(),
+            &source,
+            &StrokeStyle::default(),
+            &options,
+        );
+    }
+}
+
+/// Returns an opaque color from the RGB components of the config.
+pub fn rgb_color(rgb: [u8; 3]) -> Color {
+    Color::new(255, rgb[0], rgb[1], rgb[2])
+}
+
+fn load_font(source: &SystemSource, font: &str) -> Result<Font, DisplayError> {
+    if is_font_file(font) {
+        return Ok(Font::from_path(Path::new(font), 0)?);
+    }
+
+    let handle = match source.select_by_postscript_name(font) {
+        Ok(handle) => handle,
+        Err(font_kit::error::SelectionError::NotFound) => source
+            .select_best_match(&[FamilyName::Title(font.to_string())], &Properties::new())
+            .map_err(|_| DisplayError::FontNotFound {
+                name: font.to_string(),
+            })?,
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(handle.load()?)
+}
+
+fn is_font_file(font: &str) -> bool {
+    let extension = Path::new(font)
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase);
+
+    font.contains('/')
+        || matches!(
+            extension.as_deref(),
+            Some("ttf") | Some("otf") | Some("ttc") | Some("otc")
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_font_file() {
+        assert!(is_font_file("fonts/unifont.otf"));
+        assert!(is_font_file("unifont.OTF"));
+        assert!(is_font_file("DejaVuSans.ttf"));
+        assert!(!is_font_file("UnifontMedium"));
+        assert!(!is_font_file("DejaVu Sans"));
     }
 }
@@ -0,0 +1,141 @@
+//! Display stage of the processor, copying the debug display canvas to the
+//! VNC server's shared memory and the local debug window.
+//!
+//! The copies are made on a blocking task of the runtime so a slow X server
+//! or a contended VNC lock doesn't hold up recognition. Only the latest
+//! canvas is shown, so canvases drawn while the stage is busy are skipped
+//! rather than queued.
+
+use std::time::Duration;
+
+use slog_scope::{debug, info, warn};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::{debug_window::DebugWindow, vnc::VncClient};
+
+struct Canvas {
+    data: Vec<u32>,
+    /// How long to hold the VNC lock, if contention is injected.
+    lock_hold: Option<Duration>,
+}
+
+pub struct CanvasPublisher {
+    runtime: Handle,
+    width: u32,
+    height: u32,
+    canvas_sender: Option<mpsc::Sender<Canvas>>,
+    debug_window_sender: Option<mpsc::UnboundedSender<DebugWindow>>,
+    task: Option<JoinHandle<()>>,
+    skipped_count: u64,
+    /// Whether the task stopped, which was reported.
+    stopped: bool,
+}
+
+impl CanvasPublisher {
+    pub fn new(runtime: Handle, mut vnc_client: VncClient) -> Self {
+        let (width, height) = (vnc_client.width(), vnc_client.height());
+        let (canvas_sender, mut canvas_receiver) = mpsc::channel::<Canvas>(1);
+        let (debug_window_sender, mut debug_window_receiver) = mpsc::unbounded_channel();
+
+        let task = runtime.spawn_blocking(move || {
+            let mut debug_window: Option<DebugWindow> = None;
+
+            while let Some(canvas) = canvas_receiver.blocking_recv() {
+                while let Ok(value) = debug_window_receiver.try_recv() {
+                    debug_window = Some(value);
+                }
+
+                vnc_client.begin_write();
+                vnc_client.data_u32_mut().copy_from_slice(&canvas.data);
+
+                if let Some(duration) = canvas.lock_hold {
+                    std::thread::sleep(duration);
+                }
+
+                vnc_client.end_write();
+
+                if let Some(window) = &mut debug_window {
+                    match window.show(&canvas.data) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            info!("debug window closed");
+                            debug_window = None;
+                        }
+                        Err(error) => {
+                            warn!("debug window failed"; "error" => %error);
+                            debug_window = None;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            runtime,
+            width,
+            height,
+            canvas_sender: Some(canvas_sender),
+            debug_window_sender: Some(debug_window_sender),
+            task: Some(task),
+            skipped_count: 0,
+            stopped: false,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Also shows the canvas in a local window, starting with the next
+    /// canvas.
+    pub fn set_debug_window(&mut self, value: DebugWindow) {
+        if let Some(sender) = &self.debug_window_sender {
+            let _ = sender.send(value);
+        }
+    }
+
+    /// Copies the canvas to the stage, unless it is still showing the
+    /// previous one.
+    pub fn publish(&mut self, data: &[u32], lock_hold: Option<Duration>) {
+        let sender = match &self.canvas_sender {
+            Some(sender) if !self.stopped => sender,
+            _ => return,
+        };
+
+        match sender.try_reserve() {
+            Ok(permit) => permit.send(Canvas {
+                data: data.to_vec(),
+                lock_hold,
+            }),
+            Err(TrySendError::Full(())) => {
+                self.skipped_count += 1;
+                debug!("display busy, skipping canvas"; "skipped_count" => self.skipped_count);
+            }
+            Err(TrySendError::Closed(())) => {
+                self.stopped = true;
+                warn!("display stage stopped, the debug display is no longer updated");
+            }
+        }
+    }
+}
+
+impl Drop for CanvasPublisher {
+    fn drop(&mut self) {
+        self.canvas_sender = None;
+        self.debug_window_sender = None;
+
+        if let Some(task) = self.task.take() {
+            if let Err(error) = self.runtime.block_on(task) {
+                warn!("display stage failed"; "error" => %error);
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Probing of the runtime environment for diagnostics.
+
+use std::path::Path;
+
+use slog_scope::info;
+
+use crate::{canvas::TextDrawer, ocr_engine::OcrEngine, text_recognizer, vnc};
+
+/// Versions and availability of the libraries and resources used by the
+/// software suite.
+#[derive(Debug)]
+pub struct CapabilityReport {
+    pub tesseract_version: String,
+    pub tesseract_languages: Vec<String>,
+    pub ffmpeg_version: String,
+    pub ffmpeg_configuration: String,
+    pub h264_decoder: bool,
+    pub libvnc_version: String,
+    pub fonts: Vec<String>,
+    pub shm_free_bytes: Option<u64>,
+}
+
+impl CapabilityReport {
+    /// Probes the environment.
+    ///
+    /// Available Tesseract languages are only listed if an initialized
+    /// recognizer is given.
+    pub fn probe(text_recognizer: Option<&dyn OcrEngine>) -> Self {
+        let tesseract_languages = text_recognizer
+            .map(|text_recognizer| text_recognizer.available_languages())
+            .unwrap_or_default();
+        let fonts = TextDrawer::new()
+            .map(|text_drawer| text_drawer.font_names())
+            .unwrap_or_default();
+
+        Self {
+            tesseract_version: text_recognizer::tesseract_version(),
+            tesseract_languages,
+            ffmpeg_version: format!(
+                "avutil {} avcodec {} avformat {}",
+                format_ffmpeg_version(ffmpeg_next::util::version()),
+                format_ffmpeg_version(ffmpeg_next::codec::version()),
+                format_ffmpeg_version(ffmpeg_next::format::version()),
+            ),
+            ffmpeg_configuration: ffmpeg_next::codec::configuration().to_string(),
+            h264_decoder: ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::H264).is_some(),
+            libvnc_version: vnc::libvnc_version(),
+            fonts,
+            shm_free_bytes: free_space("/dev/shm"),
+        }
+    }
+
+    pub fn log(&self) {
+        info!("capability report";
+            "tesseract_version" => &self.tesseract_version,
+            "tesseract_languages" => self.tesseract_languages.join(","),
+            "ffmpeg_version" => &self.ffmpeg_version,
+            "ffmpeg_configuration" => &self.ffmpeg_configuration,
+            "h264_decoder" => self.h264_decoder,
+            "libvnc_version" => &self.libvnc_version,
+            "fonts" => self.fonts.join(","),
+            "shm_free_bytes" => ?self.shm_free_bytes,
+        );
+    }
+}
+
+fn format_ffmpeg_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        version >> 16,
+        (version >> 8) & 0xff,
+        version & 0xff
+    )
+}
+
+fn free_space<P: AsRef<Path>>(path: P) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(path.as_ref()).ok()?;
+
+    Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
@@ -1,21 +1,1733 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use toml::{value::Table, Value};
+
+use crate::{canvas::DEFAULT_FONTS, error::ConfigError, secret::Secret, text_rules::Pattern};
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct ProcessorConfig {
+    /// Regions of the main stream followed by the regions of the `[[stream]]`
+    /// sections.
     pub region: Vec<Region>,
+    /// Additional streams recognized side by side with the main stream, each
+    /// with its own regions. Changes require a restart.
+    #[serde(default)]
+    pub stream: Vec<StreamConfig>,
+    /// Sets of regions active depending on the screen. The first scene is
+    /// active at start.
+    #[serde(default)]
+    pub scene: Vec<SceneConfig>,
+    #[serde(default)]
+    pub scene_detection: SceneDetectionConfig,
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    #[serde(default)]
+    pub catch_up: CatchUpConfig,
+    /// How frames are received from the stream dumper. Changes require a
+    /// restart.
+    #[serde(default)]
+    pub frame_transport: FrameTransport,
+    /// Tesseract variables by name, such as `load_system_dawg = "0"`, set
+    /// when the recognizers are created. Changes require a restart.
+    #[serde(default)]
+    pub tesseract_variables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// Reporting of statistics to a collector when present.
+    #[serde(default)]
+    pub telemetry: Option<TelemetryConfig>,
+    /// Hash chaining of emitted items when present.
+    #[serde(default)]
+    pub integrity: Option<IntegrityConfig>,
+    /// Destinations of emitted items, any number of which run at once.
+    #[serde(default)]
+    pub output: Vec<OutputConfig>,
+    /// Filtering of blocked words before items reach chat outputs when
+    /// present.
+    #[serde(default)]
+    pub content_filter: Option<ContentFilterConfig>,
+    /// Shorthand for a message bus output.
+    #[serde(default)]
+    pub message_bus: Option<MessageBusConfig>,
+    /// Shorthand for a Discord output.
+    #[serde(default)]
+    pub discord: Option<DiscordConfig>,
+    /// Shorthand for an IRC output.
+    #[serde(default)]
+    pub irc: Option<IrcConfig>,
+    /// Shorthand for a Twitch output.
+    #[serde(default)]
+    pub twitch: Option<TwitchConfig>,
+    /// Shorthand for a webhook output.
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+    /// HTTP API for querying recent results when present.
+    #[serde(default)]
+    pub http_api: Option<HttpApiConfig>,
+    /// Session manifest of produced artifacts when present.
+    #[serde(default)]
+    pub manifest: Option<ManifestConfig>,
+    /// Dumping of per-frame results for debugging when present.
+    #[serde(default)]
+    pub frame_dump: Option<FrameDumpConfig>,
+    #[serde(default)]
+    pub frame_quality: FrameQualityConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub overlay: OverlayConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    /// Only used when fault injection is enabled on the command line.
+    #[serde(default)]
+    pub fault_injection: FaultInjectionConfig,
+}
+
+impl ProcessorConfig {
+    /// Loads the config from a file or a directory of fragments.
+    ///
+    /// Fragments are the `.toml` files in the directory applied in file name
+    /// order, so later files take precedence (for example, `00-base.toml`,
+    /// `50-stream.toml`, `90-secrets.toml`). Tables are merged recursively.
+    /// Arrays of tables where every table has a `name` are merged by name;
+    /// other values are replaced.
+    ///
+    /// Fractional region coordinates are resolved against the frame size.
+    pub fn load(path: &Path, frame_width: u32, frame_height: u32) -> Result<Self, ConfigError> {
+        Self::from_value(load_value(path)?, frame_width, frame_height)
+    }
+
+    /// Loads only the `tesseract_variables` table, for tools that recognize
+    /// images of any size without the rest of the config.
+    pub fn load_tesseract_variables(path: &Path) -> Result<BTreeMap<String, String>, ConfigError> {
+        let value = load_value(path)?
+            .get("tesseract_variables")
+            .cloned()
+            .unwrap_or_else(|| Value::Table(Table::new()));
+
+        Ok(value.try_into()?)
+    }
+
+    /// Parses the config from TOML text.
+    pub fn parse(text: &str, frame_width: u32, frame_height: u32) -> Result<Self, ConfigError> {
+        Self::from_value(toml::de::from_str(text)?, frame_width, frame_height)
+    }
+
+    /// Returns the `[[output]]` entries followed by the outputs of the
+    /// shorthand tables such as `[discord]`.
+    pub fn output_configs(&self) -> Vec<OutputConfig> {
+        let mut configs = self.output.clone();
+        configs.extend(self.message_bus.clone().map(OutputConfig::MessageBus));
+        configs.extend(self.discord.clone().map(OutputConfig::Discord));
+        configs.extend(self.irc.clone().map(OutputConfig::Irc));
+        configs.extend(self.twitch.clone().map(OutputConfig::Twitch));
+        configs.extend(self.webhook.clone().map(OutputConfig::Webhook));
+        configs
+    }
+
+    fn from_value(
+        mut value: Value,
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Result<Self, ConfigError> {
+        move_stream_regions(&mut value);
+
+        if let Some(regions) = value.get_mut("region").and_then(Value::as_array_mut) {
+            for region in regions {
+                if let Some(region) = region.as_table_mut() {
+                    resolve_region_coordinates(region, frame_width, frame_height)?;
+                }
+            }
+        }
+
+        let config: Self = value.try_into()?;
+        config.check_streams()?;
+        config.check_region_bounds(frame_width, frame_height)?;
+
+        Ok(config)
+    }
+
+    /// Checks the regions of the main stream fit in its frame. Regions of
+    /// other streams are checked against their own frames once known.
+    fn check_region_bounds(&self, frame_width: u32, frame_height: u32) -> Result<(), ConfigError> {
+        for region in &self.region {
+            if region.stream.is_none() && !region.fits_frame(frame_width, frame_height) {
+                return Err(ConfigError::RegionOutsideFrame {
+                    region: region.name.clone(),
+                    frame_width,
+                    frame_height,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_streams(&self) -> Result<(), ConfigError> {
+        for (index, stream) in self.stream.iter().enumerate() {
+            if self.stream[..index]
+                .iter()
+                .any(|other| other.name == stream.name)
+            {
+                return Err(ConfigError::DuplicateStream {
+                    name: stream.name.clone(),
+                });
+            }
+        }
+
+        for region in &self.region {
+            if let Some(stream_name) = &region.stream {
+                if !self.stream.iter().any(|stream| &stream.name == stream_name) {
+                    return Err(ConfigError::UnknownStream {
+                        region: region.name.clone(),
+                        stream: stream_name.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Moves the regions of the `[[stream]]` sections to the end of the regions,
+/// setting their stream.
+fn move_stream_regions(value: &mut Value) {
+    let mut stream_regions = Vec::new();
+
+    if let Some(streams) = value.get_mut("stream").and_then(Value::as_array_mut) {
+        for stream in streams.iter_mut().filter_map(Value::as_table_mut) {
+            let name = match stream.get("name").and_then(Value::as_str) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            if let Some(Value::Array(regions)) = stream.remove("region") {
+                for mut region in regions {
+                    if let Some(region_table) = region.as_table_mut() {
+                        region_table.insert("stream".to_string(), Value::String(name.clone()));
+                    }
+
+                    stream_regions.push(region);
+                }
+            }
+        }
+    }
+
+    if stream_regions.is_empty() {
+        return;
+    }
+
+    if let Some(table) = value.as_table_mut() {
+        if let Value::Array(regions) = table
+            .entry("region".to_string())
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            regions.extend(stream_regions);
+        }
+    }
+}
+
+/// Stream read from the shared memory of another stream dumper, with the
+/// size of the main stream.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StreamConfig {
+    /// Identifies the stream in emitted items.
+    pub name: String,
+    /// Instance ID of the stream dumper.
+    pub id: u16,
+}
+
+/// Programs launched by the supervisor, read from the `[pipeline]` table of
+/// the processor's config.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PipelineConfig {
+    /// Stream URL passed to the stream dumper.
+    pub input: String,
+    /// Interpret the input as a webpage link to resolve to a stream URL.
+    #[serde(default)]
+    pub get_url: bool,
+    /// Program resolving the link: `yt-dlp`, `streamlink`, or `youtube-dl`.
+    /// The first one installed is used when absent.
+    pub url_resolver: Option<String>,
+    /// Resolution format when resolving the input, or comma-separated formats
+    /// tried in order.
+    pub format: Option<String>,
+    /// Proxy for resolving and reading the input.
+    pub proxy: Option<String>,
+    /// Netscape format cookies file for resolving and reading the input.
+    pub cookies: Option<PathBuf>,
+    /// Headers such as `Referer: https://example.com/` for resolving and
+    /// reading the input.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default = "default_stream_id")]
+    pub stream_id: u16,
+    #[serde(default = "default_stream_width")]
+    pub stream_width: u32,
+    #[serde(default = "default_stream_height")]
+    pub stream_height: u32,
+    /// Number of frames kept in shared memory by the stream dumper.
+    #[serde(default = "default_frame_slots")]
+    pub frame_slots: u32,
+    /// Address the stream dumper sends frames over TCP on, for processors
+    /// on other hosts.
+    pub frame_listen: Option<String>,
+    #[serde(default = "default_vnc_id")]
+    pub vnc_id: u16,
+    #[serde(default = "default_vnc_width")]
+    pub vnc_width: u32,
+    #[serde(default = "default_vnc_height")]
+    pub vnc_height: u32,
+    /// Additional arguments for the OCR processor, such as
+    /// `["--ocr-threads", "2"]`.
+    #[serde(default)]
+    pub processor_args: Vec<String>,
+    /// Namespace of the shared memory and sockets of the programs, for
+    /// running several pipelines with the same IDs on one host.
+    pub ipc_namespace: Option<String>,
+    /// Directory of the programs. Defaults to the supervisor's directory.
+    pub bin_dir: Option<PathBuf>,
+    /// Delay before restarting a program that exited.
+    #[serde(default = "default_restart_delay_secs")]
+    pub restart_delay_secs: f64,
+}
+
+impl PipelineConfig {
+    /// Loads the `[pipeline]` table from a config file or a directory of
+    /// fragments.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let value = load_value(path)?
+            .get("pipeline")
+            .cloned()
+            .unwrap_or_else(|| Value::Table(Table::new()));
+
+        Ok(value.try_into()?)
+    }
+}
+
+/// Screens served by one VNC server process, so setups with several streams
+/// don't need a VNC server per stream.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VncServerConfig {
+    #[serde(default)]
+    pub screen: Vec<VncScreenConfig>,
+    /// Password required from clients. Only the first 8 characters are used
+    /// by VNC authentication.
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Address of the interface the screens listen on. Addresses other than
+    /// loopback require a password.
+    #[serde(default = "default_vnc_listen_address")]
+    pub listen_address: Ipv4Addr,
+    /// Web VNC client served by each screen when present.
+    #[serde(default)]
+    pub web: Option<VncWebConfig>,
+    /// MJPEG streams of the screens served over HTTP when present.
+    #[serde(default)]
+    pub mjpeg: Option<MjpegConfig>,
+}
+
+impl VncServerConfig {
+    /// Loads the `[vnc_server]` table from a config file or a directory of
+    /// fragments.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let value = load_value(path)?
+            .get("vnc_server")
+            .cloned()
+            .unwrap_or_else(|| Value::Table(Table::new()));
+
+        Ok(value.try_into()?)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VncScreenConfig {
+    /// Instance ID number for shared memory and port number.
+    pub id: u16,
+    #[serde(default = "default_vnc_width")]
+    pub width: u32,
+    #[serde(default = "default_vnc_height")]
+    pub height: u32,
+    /// Shows the stream with the regions of a config file for editing
+    /// instead of the processor's debug image when present.
+    #[serde(default)]
+    pub edit: Option<RegionEditConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VncWebConfig {
+    /// Directory of the client's files, such as a noVNC checkout.
+    pub directory: PathBuf,
+    /// Added to the port of each screen for its HTTP port.
+    #[serde(default = "default_http_port_offset")]
+    pub port_offset: u16,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MjpegConfig {
+    /// Address to listen on such as `127.0.0.1:8890`.
+    pub address: String,
+    /// Images sent per second to each client.
+    #[serde(default = "default_mjpeg_fps")]
+    pub fps: f64,
+    /// JPEG quality from 1 to 100.
+    #[serde(default = "default_mjpeg_quality")]
+    pub quality: u8,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegionEditConfig {
+    /// Instance ID number of the stream dumper whose frames are shown. The
+    /// screen size must be the frame size.
+    #[serde(default = "default_stream_id")]
+    pub stream_id: u16,
+    /// Config file whose regions are edited.
+    pub config: PathBuf,
+}
+
+fn default_vnc_listen_address() -> Ipv4Addr {
+    Ipv4Addr::LOCALHOST
+}
+
+fn default_http_port_offset() -> u16 {
+    100
+}
+
+fn default_mjpeg_fps() -> f64 {
+    5.0
+}
+
+fn default_mjpeg_quality() -> u8 {
+    75
+}
+
+fn default_stream_id() -> u16 {
+    8840
+}
+
+fn default_stream_width() -> u32 {
+    1280
+}
+
+fn default_stream_height() -> u32 {
+    720
+}
+
+fn default_frame_slots() -> u32 {
+    crate::frame::DEFAULT_SLOT_COUNT
+}
+
+fn default_vnc_id() -> u16 {
+    8855
+}
+
+fn default_vnc_width() -> u32 {
+    1024
+}
+
+fn default_vnc_height() -> u32 {
+    768
+}
+
+fn default_restart_delay_secs() -> f64 {
+    5.0
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SceneConfig {
+    pub name: String,
+    /// Names of regions recognized only while the scene is active.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Switches to the scene when all of the templates match the frame.
+    #[serde(default)]
+    pub detect: Vec<SceneTemplate>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SceneDetectionConfig {
+    /// Number of consecutive frames a scene must be detected in before
+    /// switching to it.
+    pub confirm_frames: u32,
+}
+
+impl Default for SceneDetectionConfig {
+    fn default() -> Self {
+        Self { confirm_frames: 2 }
+    }
+}
+
+/// Reference image compared against the frame at a location.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SceneTemplate {
+    pub x: u32,
+    pub y: u32,
+    pub image: PathBuf,
+    /// Maximum mean difference per color channel for a match.
+    #[serde(default = "default_scene_template_max_difference")]
+    pub max_difference: f32,
+}
+
+fn default_scene_template_max_difference() -> f32 {
+    10.0
+}
+
+/// Self-imposed resource budget so the service doesn't starve other processes
+/// on the host.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ResourceLimits {
+    /// Maximum CPU usage as a percentage of one core. Frame processing is
+    /// slowed down to stay under this value.
+    pub max_cpu_percent: Option<f32>,
+    /// Maximum resident memory in megabytes. Regions are processed one per
+    /// frame while over this value.
+    pub max_memory_mb: Option<u64>,
+}
+
+/// Skipping of frames with visible compression blocks.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FrameQualityConfig {
+    /// Frames with a higher blockiness are not recognized. Disabled if not
+    /// set.
+    pub max_blockiness: Option<f32>,
+    /// Number of consecutive frames skipped at most.
+    pub max_skipped_frames: u32,
+}
+
+impl Default for FrameQualityConfig {
+    fn default() -> Self {
+        Self {
+            max_blockiness: None,
+            max_skipped_frames: 30,
+        }
+    }
+}
+
+/// How a suspicious but usable config is reported.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum IssueSeverity {
+    Ignore,
+    Warn,
+    Error,
+}
+
+/// Checks of the regions at config load.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Regions that can be active at the same time overlap each other.
+    pub region_overlap: IssueSeverity,
+    /// Regions stacked on the debug display don't fit the VNC screen.
+    pub debug_layout_overflow: IssueSeverity,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            region_overlap: IssueSeverity::Warn,
+            debug_layout_overflow: IssueSeverity::Warn,
+        }
+    }
+}
+
+/// Behavior after processing falls behind the stream.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CatchUpConfig {
+    pub policy: CatchUpPolicy,
+    /// Minimum time between the capture of consecutive frames reported as a
+    /// gap event.
+    pub gap_secs: f64,
+}
+
+impl Default for CatchUpConfig {
+    fn default() -> Self {
+        Self {
+            policy: CatchUpPolicy::JumpToLive,
+            gap_secs: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method")]
+pub enum CatchUpPolicy {
+    /// Frames decoded while busy are dropped so processing resumes at the
+    /// live frame.
+    JumpToLive,
+    /// Frames decoded while busy are processed in order as fast as possible
+    /// afterwards, as long as they are within the bound and still in the
+    /// stream dumper's ring. Older frames are skipped.
+    Backlog { max_frames: u32 },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method")]
+pub enum FrameTransport {
+    /// Ring of frames in shared memory on the same host.
+    SharedMemory,
+    /// Frames streamed over TCP from a stream dumper started with `--listen`.
+    Tcp { address: String },
+}
+
+impl Default for FrameTransport {
+    fn default() -> Self {
+        Self::SharedMemory
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TelemetryConfig {
+    /// Address of the collector such as `collector.example.com:9771`.
+    pub collector: String,
+    /// Name distinguishing this instance in the collector.
+    pub instance: String,
+    #[serde(default = "default_telemetry_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_telemetry_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct IntegrityConfig {
+    /// Key for signing the hashes with HMAC-SHA256.
+    #[serde(default)]
+    pub key: Option<Secret>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MessageBusConfig {
+    /// Address of the bus such as `localhost:9900`.
+    pub address: String,
+    #[serde(default = "default_text_topic")]
+    pub text_topic: String,
+    #[serde(default = "default_event_topic")]
+    pub event_topic: String,
+    /// Case of the payload field names.
+    #[serde(default)]
+    pub field_case: FieldCase,
+}
+
+fn default_text_topic() -> String {
+    "ocr.text".to_string()
+}
+
+fn default_event_topic() -> String {
+    "ocr.event".to_string()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum FieldCase {
+    CamelCase,
+    SnakeCase,
+}
+
+impl Default for FieldCase {
+    fn default() -> Self {
+        Self::CamelCase
+    }
+}
+
+/// Destination of emitted items, selected by `type`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputConfig {
+    /// Appends items as JSON lines to a file.
+    File {
+        path: PathBuf,
+    },
+    MessageBus(MessageBusConfig),
+    Discord(DiscordConfig),
+    Irc(IrcConfig),
+    Twitch(TwitchConfig),
+    Webhook(WebhookConfig),
+}
+
+/// Filtering of items sent to chats such as Discord, IRC, and Twitch.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ContentFilterConfig {
+    /// Files of blocked words, one per line.
+    pub word_lists: Vec<PathBuf>,
+    #[serde(default)]
+    pub action: ContentFilterAction,
+}
+
+/// What happens to an item containing a blocked word.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ContentFilterAction {
+    /// The item isn't sent to chats.
+    Drop,
+    /// The blocked words are replaced by asterisks.
+    Mask,
+}
+
+impl Default for ContentFilterAction {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DiscordConfig {
+    pub webhook_url: Secret,
+    /// Regions whose items are posted. All regions if empty.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Items emitted within this time of the first are posted as one
+    /// message.
+    #[serde(default = "default_discord_batch_secs")]
+    pub batch_secs: f64,
+    /// Name shown instead of the webhook's.
+    pub username: Option<String>,
+}
+
+fn default_discord_batch_secs() -> f64 {
+    2.0
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IrcConfig {
+    /// Address of the server such as `irc.example.com:6667`.
+    pub address: String,
+    /// Connect with TLS, typically on port 6697.
+    #[serde(default)]
+    pub tls: bool,
+    pub nickname: String,
+    /// Channel such as `#tppocr`.
+    pub channel: String,
+    /// Server password.
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Regions whose items are relayed. All regions if empty.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Shortest time between messages, so the server doesn't disconnect
+    /// the client for flooding.
+    #[serde(default = "default_irc_min_interval_secs")]
+    pub min_interval_secs: f64,
+}
+
+fn default_irc_min_interval_secs() -> f64 {
+    2.0
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TwitchConfig {
+    /// Channel name without `#`.
+    pub channel: String,
+    /// Account the messages are posted as.
+    pub username: String,
+    /// OAuth token of the account with the `chat:edit` scope.
+    pub oauth_token: Secret,
+    /// Regions whose items are posted. All regions if empty.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Most messages posted within `window_secs`. Twitch allows 20 messages
+    /// per 30 seconds, or 100 if the account is a moderator of the channel.
+    #[serde(default = "default_twitch_max_messages")]
+    pub max_messages: u32,
+    #[serde(default = "default_twitch_window_secs")]
+    pub window_secs: f64,
+}
+
+fn default_twitch_max_messages() -> u32 {
+    20
+}
+
+fn default_twitch_window_secs() -> f64 {
+    30.0
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    /// URL posted to, a secret since it may contain a token.
+    pub url: Secret,
+    /// Value of the `Authorization` header, such as `Bearer TOKEN`.
+    #[serde(default)]
+    pub authorization: Option<Secret>,
+    /// Other headers sent with each post, such as API keys.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Secret>,
+    /// Most items posted together as a JSON array. With 1, each item is
+    /// posted as a JSON object.
+    #[serde(default = "default_webhook_batch_size")]
+    pub batch_size: usize,
+    /// Longest time an item waits for the batch to fill.
+    #[serde(default = "default_webhook_batch_secs")]
+    pub batch_secs: f64,
+    /// Attempts of posting a body before it is spooled.
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    /// Directory of the spool of bodies that failed to post. Without it,
+    /// they are dropped.
+    #[serde(default)]
+    pub spool_dir: Option<PathBuf>,
+    #[serde(default = "default_webhook_spool_max_bytes")]
+    pub spool_max_bytes: u64,
+}
+
+fn default_webhook_batch_size() -> usize {
+    1
+}
+
+fn default_webhook_batch_secs() -> f64 {
+    1.0
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    3
+}
+
+fn default_webhook_spool_max_bytes() -> u64 {
+    100_000_000
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct HttpApiConfig {
+    /// Address to listen on such as `127.0.0.1:8880`.
+    pub address: String,
+    /// Number of recent items kept for `/results`.
+    #[serde(default = "default_http_api_max_results")]
+    pub max_results: usize,
+}
+
+fn default_http_api_max_results() -> usize {
+    1000
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ManifestConfig {
+    /// Path of the manifest file. `{session_id}` is replaced with the
+    /// session ID.
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FrameDumpConfig {
+    pub output: FrameDumpOutput,
+    /// Dump only every this number of frames.
+    #[serde(default = "default_frame_dump_every_frames")]
+    pub every_frames: u32,
+    /// Include Tesseract's hOCR document of regions recognized in the frame.
+    #[serde(default)]
+    pub hocr: bool,
+    /// Include Tesseract's TSV table of regions recognized in the frame.
+    #[serde(default)]
+    pub tsv: bool,
+}
+
+fn default_frame_dump_every_frames() -> u32 {
+    10
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method")]
+pub enum FrameDumpOutput {
+    /// Appends JSON lines to a file.
+    File { path: PathBuf },
+    /// Sends JSON lines to tools connecting to a TCP address.
+    Socket { address: String },
 }
 
-#[derive(Clone, Deserialize)]
+/// Context attached to every output record.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetadataConfig {
+    /// Static fields such as run name, host, or game.
+    pub fields: BTreeMap<String, String>,
+    /// Start of the run used to compute the day counter.
+    pub run_start: Option<DateTime<Utc>>,
+    /// Title, game, and start time of the channel's stream from the Twitch
+    /// API when present.
+    pub twitch: Option<TwitchMetadataConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TwitchMetadataConfig {
+    /// Channel name.
+    pub channel: String,
+    /// Client ID of an application registered in the Twitch developer
+    /// console.
+    pub client_id: String,
+    pub client_secret: Secret,
+    /// Seconds between queries of the stream.
+    #[serde(default = "default_twitch_metadata_poll_secs")]
+    pub poll_secs: f64,
+}
+
+fn default_twitch_metadata_poll_secs() -> f64 {
+    60.0
+}
+
+/// Probabilities, in range [0.0, 1.0] per frame or per delivery, of
+/// artificial faults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FaultInjectionConfig {
+    pub delay_probability: f32,
+    pub delay_ms: u64,
+    pub drop_frame_probability: f32,
+    pub lock_contention_probability: f32,
+    pub lock_hold_ms: u64,
+    pub sink_failure_probability: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Region {
+    /// Identifies the region in results and when merging config fragments.
+    pub name: String,
+    /// Name of the `[[stream]]` the region is in, which is set for the
+    /// regions of stream sections. None for the main stream.
+    #[serde(default)]
+    pub stream: Option<String>,
     pub x: u32,
     pub y: u32,
     pub width: u32,
     pub height: u32,
     pub processor: ProcessorStrategy,
+    /// Tesseract language code overriding the global language.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Keeps only pixels of the text color before recognition.
+    #[serde(default)]
+    pub color_key: Option<ColorKey>,
+    /// Averages recent crops to reduce compression noise.
+    #[serde(default)]
+    pub temporal_average: Option<TemporalAverage>,
+    /// Converts the region to black and white before recognition.
+    #[serde(default)]
+    pub binarization: Option<Binarization>,
+    /// Integer factor the region is enlarged by before recognition.
+    #[serde(default = "default_scale")]
+    pub scale: u32,
+    #[serde(default)]
+    pub scale_filter: ScaleFilter,
+    /// Recognize the region only every this number of frames.
+    #[serde(default = "default_interval_frames")]
+    pub interval_frames: u32,
+    /// Skips recognition while the perceptual hash of the region differs
+    /// from the last recognized crop by at most this number of bits out of
+    /// 64.
+    #[serde(default)]
+    pub perceptual_hash_threshold: Option<u32>,
+    /// Maximum rate of emitted items before an alert is fired.
+    #[serde(default)]
+    pub quota: Option<EmissionQuota>,
+    /// Switches the recognition language when the text appears to be in
+    /// another language.
+    #[serde(default)]
+    pub language_detection: Option<LanguageDetection>,
+    /// Recognizes the region with ONNX models instead of Tesseract.
+    #[serde(default)]
+    pub onnx: Option<OnnxConfig>,
+    /// Rewrites or drops the text of items before they are emitted, in
+    /// order and before correction.
+    #[serde(default)]
+    pub rules: Vec<TextRule>,
+    /// Corrects near-misses of known words before items are emitted.
+    #[serde(default)]
+    pub correction: Option<CorrectionConfig>,
+    /// Grouping of readings of the FixedLine strategy.
+    #[serde(default)]
+    pub fixed_line: FixedLineConfig,
+    /// Recognition of the cursor of the Menu strategy.
+    #[serde(default)]
+    pub menu: MenuConfig,
+    /// Layers drawn for the region on the debug display.
+    #[serde(default)]
+    pub overlay: RegionOverlayConfig,
+}
+
+impl Region {
+    /// Returns whether the region is entirely within a frame of the size.
+    pub fn fits_frame(&self, frame_width: u32, frame_height: u32) -> bool {
+        u64::from(self.x) + u64::from(self.width) <= u64::from(frame_width)
+            && u64::from(self.y) + u64::from(self.height) <= u64::from(frame_height)
+    }
+}
+
+fn default_scale() -> u32 {
+    1
+}
+
+fn default_interval_frames() -> u32 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FixedLineConfig {
+    /// Jaro-Winkler similarity to the first reading of the line, from 0 to
+    /// 1, below which a reading is a new line. Lower it for lines that
+    /// change by only a few characters, such as banners.
+    #[serde(default = "default_fixed_line_similarity_threshold")]
+    pub similarity_threshold: f64,
+    /// Time after the last reading a line is emitted if no new line
+    /// replaced it.
+    #[serde(default = "default_fixed_line_flush_secs")]
+    pub flush_secs: f64,
+}
+
+impl Default for FixedLineConfig {
+    fn default() -> Self {
+        Self {
+            similarity_threshold: default_fixed_line_similarity_threshold(),
+            flush_secs: default_fixed_line_flush_secs(),
+        }
+    }
+}
+
+fn default_fixed_line_similarity_threshold() -> f64 {
+    0.8
+}
+
+fn default_fixed_line_flush_secs() -> f64 {
+    5.0
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MenuConfig {
+    /// Text the cursor is recognized as at the start of the selected option.
+    #[serde(default = "default_menu_cursors")]
+    pub cursors: Vec<String>,
+}
+
+impl Default for MenuConfig {
+    fn default() -> Self {
+        Self {
+            cursors: default_menu_cursors(),
+        }
+    }
+}
+
+/// Layers of a region on the debug display. Turning layers off saves the CPU
+/// time of drawing them.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RegionOverlayConfig {
+    /// Crop of the stream frame.
+    #[serde(default = "default_overlay_layer")]
+    pub image: bool,
+    #[serde(default = "default_overlay_layer")]
+    pub word_boxes: bool,
+    /// Confidence of each word above its box.
+    #[serde(default = "default_overlay_layer")]
+    pub confidences: bool,
+    /// Recognized text and status lines below the region.
+    #[serde(default = "default_overlay_layer")]
+    pub text: bool,
+}
+
+impl Default for RegionOverlayConfig {
+    fn default() -> Self {
+        Self {
+            image: true,
+            word_boxes: true,
+            confidences: true,
+            text: true,
+        }
+    }
+}
+
+/// Layers of the debug display not belonging to a region.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OverlayConfig {
+    /// Line with the date, frame counter, and latency.
+    #[serde(default = "default_overlay_layer")]
+    pub date: bool,
+    /// Column at the right of the display listing the latest emitted items
+    /// when present.
+    #[serde(default)]
+    pub history: Option<HistoryPanelConfig>,
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            date: true,
+            history: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct HistoryPanelConfig {
+    /// Width of the column in pixels. Regions are drawn to the left of it.
+    #[serde(default = "default_history_panel_width")]
+    pub width: u32,
+    /// Number of items listed, newest at the bottom.
+    #[serde(default = "default_history_panel_item_count")]
+    pub item_count: usize,
+}
+
+fn default_history_panel_width() -> u32 {
+    400
+}
+
+fn default_history_panel_item_count() -> usize {
+    30
 }
 
-#[derive(Clone, Deserialize)]
+fn default_overlay_layer() -> bool {
+    true
+}
+
+/// Fonts and colors of the debug display.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DisplayConfig {
+    /// Fonts tried in order for each character. Each is a path to a font
+    /// file, such as `fonts/unifont.otf`, or a PostScript name or a family
+    /// name of an installed font.
+    #[serde(default = "default_display_fonts")]
+    pub fonts: Vec<String>,
+    /// Size of the text in pixels, which also sets the line spacing.
+    #[serde(default = "default_display_font_size")]
+    pub font_size: f32,
+    #[serde(default)]
+    pub colors: DisplayColors,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            fonts: default_display_fonts(),
+            font_size: default_display_font_size(),
+            colors: DisplayColors::default(),
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// Vertical space below each region for its text and status lines.
+    pub fn region_spacing(&self) -> u32 {
+        (self.font_size * 3.0).ceil() as u32
+    }
+}
+
+/// Colors of the debug display as `[red, green, blue]`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DisplayColors {
+    pub background: [u8; 3],
+    pub date: [u8; 3],
+    pub word_box: [u8; 3],
+    pub confidence: [u8; 3],
+    pub text: [u8; 3],
+    pub status: [u8; 3],
+    pub pause_background: [u8; 3],
+    pub pause_text: [u8; 3],
+    pub history_background: [u8; 3],
+    pub history_text: [u8; 3],
+}
+
+impl Default for DisplayColors {
+    fn default() -> Self {
+        Self {
+            background: [0, 0, 0],
+            date: [255, 255, 255],
+            word_box: [0, 255, 0],
+            confidence: [0, 255, 0],
+            text: [255, 0, 255],
+            status: [128, 128, 128],
+            pause_background: [160, 0, 0],
+            pause_text: [255, 255, 255],
+            history_background: [32, 32, 32],
+            history_text: [255, 255, 255],
+        }
+    }
+}
+
+fn default_display_fonts() -> Vec<String> {
+    DEFAULT_FONTS.iter().map(|name| name.to_string()).collect()
+}
+
+fn default_display_font_size() -> f32 {
+    16.0
+}
+
+fn default_menu_cursors() -> Vec<String> {
+    ["▶", "►", "▸", ">", "»"]
+        .iter()
+        .map(|cursor| cursor.to_string())
+        .collect()
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct EmissionQuota {
+    /// Number of items allowed within the period.
+    pub max_items: u32,
+    #[serde(default = "default_quota_period_secs")]
+    pub period_secs: u64,
+    /// Stop emitting items from the region when the quota is exceeded.
+    #[serde(default)]
+    pub auto_mute: bool,
+}
+
+fn default_quota_period_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct LanguageDetection {
+    /// Tesseract language codes the region may switch between.
+    pub languages: Vec<String>,
+    /// Minimum time between detections, each of which recognizes the region
+    /// once per language.
+    #[serde(default = "default_language_detection_interval_secs")]
+    pub interval_secs: f64,
+    /// Amount the mean word confidence of another language must exceed the
+    /// current language's by to switch to it.
+    #[serde(default = "default_language_detection_min_gain")]
+    pub min_gain: f32,
+}
+
+fn default_language_detection_interval_secs() -> f64 {
+    30.0
+}
+
+fn default_language_detection_min_gain() -> f32 {
+    0.1
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method")]
+pub enum TextRule {
+    /// Replaces every match of the regular expression. The replacement may
+    /// refer to capture groups as `$1` or `${name}`.
+    Replace {
+        pattern: Pattern,
+        #[serde(default)]
+        replacement: String,
+    },
+    /// Drops the item if the regular expression matches its text.
+    Drop { pattern: Pattern },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CorrectionConfig {
+    /// Files of words or names, one per line.
+    pub word_lists: Vec<PathBuf>,
+    /// Maximum number of edited characters of a corrected word or name.
+    #[serde(default = "default_correction_max_distance")]
+    pub max_distance: usize,
+    /// Words shorter than this number of characters are not corrected since
+    /// they are close to too many entries.
+    #[serde(default = "default_correction_min_length")]
+    pub min_length: usize,
+}
+
+fn default_correction_max_distance() -> usize {
+    2
+}
+
+fn default_correction_min_length() -> usize {
+    4
+}
+
+/// Models of the ONNX OCR engine, in the format exported by PaddleOCR.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OnnxConfig {
+    /// Text line recognition model with CTC output.
+    pub recognition_model: PathBuf,
+    /// Characters of the recognition model's classes, one per line.
+    pub dictionary: PathBuf,
+    /// Text detection model finding the lines in the region. Without it,
+    /// the region is recognized as one line.
+    #[serde(default)]
+    pub detection_model: Option<PathBuf>,
+    /// Height in pixels of line images given to the recognition model.
+    #[serde(default = "default_onnx_line_height")]
+    pub line_height: u32,
+    /// Probability above which a pixel of the detection map is text.
+    #[serde(default = "default_onnx_detection_threshold")]
+    pub detection_threshold: f32,
+}
+
+fn default_onnx_line_height() -> u32 {
+    48
+}
+
+fn default_onnx_detection_threshold() -> f32 {
+    0.3
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ScaleFilter {
+    Nearest,
+    Bilinear,
+}
+
+impl Default for ScaleFilter {
+    fn default() -> Self {
+        Self::Nearest
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub enum ProcessorStrategy {
     FixedLine,
     DialogScroll,
+    FullFrameSparse,
+    Ticker,
+    Menu,
+    Numeric,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ColorKey {
+    /// Text color as red, green, blue.
+    pub color: [u8; 3],
+    /// Maximum difference of each channel from the text color.
+    #[serde(default = "default_color_key_tolerance")]
+    pub tolerance: u8,
+}
+
+fn default_color_key_tolerance() -> u8 {
+    32
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TemporalAverage {
+    /// Number of most recent crops averaged together.
+    pub frames: usize,
+    /// Mean difference per channel from the previous crop above which the
+    /// region is considered changed and the history is discarded.
+    #[serde(default = "default_temporal_average_max_difference")]
+    pub max_difference: f32,
+}
+
+fn default_temporal_average_max_difference() -> f32 {
+    8.0
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method")]
+pub enum Binarization {
+    /// Single threshold computed from the histogram of the region.
+    Otsu,
+    /// Per-pixel threshold computed from the mean of the neighbourhood.
+    AdaptiveMean {
+        #[serde(default = "default_adaptive_block_size")]
+        block_size: u32,
+        #[serde(default)]
+        offset: f32,
+    },
+}
+
+fn default_adaptive_block_size() -> u32 {
+    15
+}
+
+/// Returns the latest modification time of a config file or directory of
+/// fragments.
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    let mut modified = std::fs::metadata(path).ok()?.modified().ok()?;
+
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path).ok()? {
+            if let Some(entry_modified) = entry
+                .ok()
+                .and_then(|entry| entry.metadata().ok())
+                .and_then(|metadata| metadata.modified().ok())
+            {
+                modified = modified.max(entry_modified);
+            }
+        }
+    }
+
+    Some(modified)
+}
+
+/// Replaces region coordinates written as fractions of the frame, such as
+/// `width = 0.8`, with pixels.
+pub fn resolve_region_coordinates(
+    region: &mut Table,
+    frame_width: u32,
+    frame_height: u32,
+) -> Result<(), ConfigError> {
+    for (key, frame_size) in &[
+        ("x", frame_width),
+        ("y", frame_height),
+        ("width", frame_width),
+        ("height", frame_height),
+    ] {
+        if let Some(Value::Float(fraction)) = region.get(*key) {
+            let fraction = *fraction;
+
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(ConfigError::InvalidFraction {
+                    key: key.to_string(),
+                    value: fraction,
+                });
+            }
+
+            let pixels = (fraction * *frame_size as f64).round() as i64;
+            region.insert(key.to_string(), Value::Integer(pixels));
+        }
+    }
+
+    Ok(())
+}
+
+fn load_value(path: &Path) -> Result<Value, ConfigError> {
+    if path.is_dir() {
+        load_directory(path)
+    } else {
+        read_toml(path)
+    }
+}
+
+fn read_toml(path: &Path) -> Result<Value, ConfigError> {
+    let config_text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(toml::de::from_str(&config_text)?)
+}
+
+fn load_directory(path: &Path) -> Result<Value, ConfigError> {
+    let read_error = |source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut fragment_paths = Vec::new();
+
+    for entry in std::fs::read_dir(path).map_err(read_error)? {
+        let fragment_path = entry.map_err(read_error)?.path();
+
+        if fragment_path.is_file() && fragment_path.extension() == Some(OsStr::new("toml")) {
+            fragment_paths.push(fragment_path);
+        }
+    }
+
+    fragment_paths.sort();
+
+    let mut merged = Value::Table(Table::new());
+
+    for fragment_path in fragment_paths {
+        merge_values(&mut merged, read_toml(&fragment_path)?);
+    }
+
+    Ok(merged)
+}
+
+/// Merges `overlay` into `base` with `overlay` taking precedence.
+pub fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base_table), Value::Table(overlay_table)) => {
+            for (key, overlay_value) in overlay_table {
+                match base_table.get_mut(&key) {
+                    Some(base_value) => merge_values(base_value, overlay_value),
+                    None => {
+                        base_table.insert(key, overlay_value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base_array), Value::Array(overlay_array))
+            if is_named_table_array(base_array) && is_named_table_array(&overlay_array) =>
+        {
+            for overlay_item in overlay_array {
+                let name = overlay_item.get("name").cloned();
+
+                match base_array
+                    .iter_mut()
+                    .find(|base_item| base_item.get("name") == name.as_ref())
+                {
+                    Some(base_item) => merge_values(base_item, overlay_item),
+                    None => base_array.push(overlay_item),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn is_named_table_array(array: &[Value]) -> bool {
+    array
+        .iter()
+        .all(|item| item.is_table() && item.get("name").is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vnc_server_config() {
+        let value: Value = toml::de::from_str(
+            r#"
+            [[vnc_server.screen]]
+            id = 8855
+
+            [[vnc_server.screen]]
+            id = 8856
+            width = 640
+            height = 480
+            "#,
+        )
+        .unwrap();
+        let config: VncServerConfig = value
+            .get("vnc_server")
+            .cloned()
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert_eq!(config.screen.len(), 2);
+        assert_eq!(
+            (
+                config.screen[0].id,
+                config.screen[0].width,
+                config.screen[0].height
+            ),
+            (8855, 1024, 768)
+        );
+        assert_eq!(
+            (
+                config.screen[1].id,
+                config.screen[1].width,
+                config.screen[1].height
+            ),
+            (8856, 640, 480)
+        );
+    }
+
+    #[test]
+    fn test_merge_values() {
+        let mut base: Value = toml::de::from_str(
+            r#"
+            [limits]
+            max_cpu_percent = 50.0
+            max_memory_mb = 100
+
+            [[region]]
+            name = "dialog"
+            x = 1
+            y = 2
+
+            [[region]]
+            name = "timestamp"
+            x = 3
+            "#,
+        )
+        .unwrap();
+        let overlay: Value = toml::de::from_str(
+            r#"
+            [limits]
+            max_cpu_percent = 25.0
+
+            [[region]]
+            name = "dialog"
+            x = 10
+
+            [[region]]
+            name = "hud"
+            x = 5
+            "#,
+        )
+        .unwrap();
+
+        merge_values(&mut base, overlay);
+
+        assert_eq!(base["limits"]["max_cpu_percent"].as_float(), Some(25.0));
+        assert_eq!(base["limits"]["max_memory_mb"].as_integer(), Some(100));
+
+        let regions = base["region"].as_array().unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[0]["x"].as_integer(), Some(10));
+        assert_eq!(regions[0]["y"].as_integer(), Some(2));
+        assert_eq!(regions[1]["x"].as_integer(), Some(3));
+        assert_eq!(regions[2]["name"].as_str(), Some("hud"));
+    }
+
+    #[test]
+    fn test_resolve_region_coordinates() {
+        let mut region: Table = toml::de::from_str(
+            r#"
+            x = 0.1
+            y = 20
+            width = 0.8
+            height = 0.25
+            "#,
+        )
+        .unwrap();
+
+        resolve_region_coordinates(&mut region, 1280, 720).unwrap();
+
+        assert_eq!(region["x"].as_integer(), Some(128));
+        assert_eq!(region["y"].as_integer(), Some(20));
+        assert_eq!(region["width"].as_integer(), Some(1024));
+        assert_eq!(region["height"].as_integer(), Some(180));
+
+        region.insert("x".to_string(), Value::Float(1.5));
+        assert!(resolve_region_coordinates(&mut region, 1280, 720).is_err());
+    }
+
+    #[test]
+    fn test_overlay_config() {
+        let config = ProcessorConfig::parse(
+            r#"
+            overlay = { date = false }
+
+            [[region]]
+            name = "dialog"
+            x = 0
+            y = 0
+            width = 100
+            height = 20
+            processor = "DialogScroll"
+            overlay = { image = false, confidences = false }
+            "#,
+            1280,
+            720,
+        )
+        .unwrap();
+
+        assert!(!config.overlay.date);
+        assert_eq!(
+            config.region[0].overlay,
+            RegionOverlayConfig {
+                image: false,
+                word_boxes: true,
+                confidences: false,
+                text: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_display_config() {
+        let config = ProcessorConfig::parse(
+            r#"
+            region = []
+
+            [display]
+            fonts = ["/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf", "UnifontMedium"]
+            font_size = 24.0
+            colors = { text = [255, 255, 0] }
+            "#,
+            1280,
+            720,
+        )
+        .unwrap();
+
+        assert_eq!(config.display.fonts.len(), 2);
+        assert_eq!(config.display.region_spacing(), 72);
+        assert_eq!(config.display.colors.text, [255, 255, 0]);
+        assert_eq!(config.display.colors.status, [128, 128, 128]);
+        assert_eq!(
+            ProcessorConfig::parse("region = []", 1280, 720)
+                .unwrap()
+                .display
+                .region_spacing(),
+            48
+        );
+    }
+
+    #[test]
+    fn test_stream_config() {
+        let text = r#"
+            [[region]]
+            name = "dialog"
+            x = 0
+            y = 0
+            width = 100
+            height = 50
+            processor = "DialogScroll"
+
+            [[stream]]
+            name = "red"
+            id = 8841
+
+            [[stream.region]]
+            name = "red_dialog"
+            x = 0
+            y = 0.5
+            width = 100
+            height = 50
+            processor = "DialogScroll"
+            "#;
+        let config = ProcessorConfig::parse(text, 1280, 720).unwrap();
+
+        assert_eq!(config.stream[0].id, 8841);
+        assert_eq!(config.region.len(), 2);
+        assert_eq!(config.region[0].stream, None);
+        assert_eq!(config.region[1].stream.as_deref(), Some("red"));
+        assert_eq!(config.region[1].y, 360);
+
+        // Regions outside stream sections may name their stream, which must
+        // be configured
+        let text = text.replace("[[stream.region]]", "[[region]]").replace(
+            "name = \"red_dialog\"",
+            "name = \"red_dialog\"\nstream = \"blue\"",
+        );
+
+        assert!(matches!(
+            ProcessorConfig::parse(&text, 1280, 720),
+            Err(ConfigError::UnknownStream { .. })
+        ));
+    }
+
+    #[test]
+    fn test_region_outside_frame() {
+        let text = r#"
+            [[region]]
+            name = "dialog"
+            x = 1200
+            y = 0
+            width = 100
+            height = 50
+            processor = "DialogScroll"
+            "#;
+
+        assert!(matches!(
+            ProcessorConfig::parse(text, 1280, 720),
+            Err(ConfigError::RegionOutsideFrame { .. })
+        ));
+        assert!(ProcessorConfig::parse(text, 1300, 720).is_ok());
+
+        let text = text.replace("x = 1200", &format!("x = {}", u32::MAX));
+
+        assert!(matches!(
+            ProcessorConfig::parse(&text, 1280, 720),
+            Err(ConfigError::RegionOutsideFrame { .. })
+        ));
+    }
+
+    #[test]
+    fn test_webhook_config_redacted() {
+        std::env::set_var("TPPOCR_TEST_WEBHOOK_URL", "https://example.com/hook/TOKEN1");
+        std::env::set_var("TPPOCR_TEST_WEBHOOK_KEY", "TOKEN2");
+
+        let config = ProcessorConfig::parse(
+            r#"
+            region = []
+
+            [webhook]
+            url = { env = "TPPOCR_TEST_WEBHOOK_URL" }
+            headers = { "X-Api-Key" = { env = "TPPOCR_TEST_WEBHOOK_KEY" } }
+            "#,
+            1280,
+            720,
+        )
+        .unwrap();
+        let webhook = config.webhook.as_ref().unwrap();
+
+        assert_eq!(webhook.url.expose(), "https://example.com/hook/TOKEN1");
+        assert_eq!(webhook.headers["X-Api-Key"].expose(), "TOKEN2");
+        assert!(!format!("{:?}", config).contains("TOKEN"));
+    }
 }
@@ -0,0 +1,235 @@
+//! Filtering of blocked words before text reaches chat outputs.
+//!
+//! OCR of noisy frames occasionally produces offensive words that the game
+//! never showed. Words are compared after undoing common letter
+//! substitutions such as "3" for "e", removing punctuation, and shortening
+//! stretched letters, so "sh!iit" matches "shit". Whole words are matched so
+//! words containing a blocked word aren't affected.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use crate::{
+    config::{ContentFilterAction, ContentFilterConfig},
+    correction::word_spans,
+};
+
+/// Single-character words in a row of at least this length are also matched
+/// joined, such as "d a r n".
+const MIN_SPACED_LETTERS: usize = 3;
+
+pub struct ContentFilter {
+    config: ContentFilterConfig,
+    /// Normalized blocked words.
+    words: HashSet<String>,
+}
+
+impl ContentFilter {
+    /// Creates a filter with the words of the configured word list files.
+    pub fn load(config: ContentFilterConfig) -> io::Result<Self> {
+        let mut words = Vec::new();
+
+        for path in &config.word_lists {
+            for line in BufReader::new(File::open(path)?).lines() {
+                words.push(line?);
+            }
+        }
+
+        Ok(Self::new(config, words))
+    }
+
+    /// Creates a filter with the words instead of the configured word list
+    /// files.
+    pub fn new(config: ContentFilterConfig, words: Vec<String>) -> Self {
+        let words = words
+            .iter()
+            .map(|word| letters(word.trim()))
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        Self { config, words }
+    }
+
+    pub fn config(&self) -> &ContentFilterConfig {
+        &self.config
+    }
+
+    /// Returns the text with blocked words masked, or None if the text
+    /// contains a blocked word and is to be dropped.
+    pub fn filter(&self, text: &str) -> Option<String> {
+        let spans = word_spans(text);
+        let mut blocked = vec![false; spans.len()];
+
+        for (index, (start, end)) in spans.iter().enumerate() {
+            blocked[index] = self.is_blocked(&text[*start..*end]);
+        }
+
+        // Runs of spaced letters
+        let mut index = 0;
+
+        while index < spans.len() {
+            let run_length = spans[index..]
+                .iter()
+                .take_while(|(start, end)| text[*start..*end].chars().count() == 1)
+                .count();
+
+            if run_length >= MIN_SPACED_LETTERS {
+                let joined: String = spans[index..index + run_length]
+                    .iter()
+                    .map(|(start, end)| &text[*start..*end])
+                    .collect();
+
+                if self.is_blocked(&joined) {
+                    for flag in &mut blocked[index..index + run_length] {
+                        *flag = true;
+                    }
+                }
+            }
+
+            index += run_length.max(1);
+        }
+
+        if !blocked.contains(&true) {
+            return Some(text.to_string());
+        }
+
+        match self.config.action {
+            ContentFilterAction::Drop => None,
+            ContentFilterAction::Mask => {
+                let mut masked = String::with_capacity(text.len());
+                let mut copied_index = 0;
+
+                for ((start, end), blocked) in spans.iter().zip(&blocked) {
+                    if *blocked {
+                        masked.push_str(&text[copied_index..*start]);
+                        masked.extend(text[*start..*end].chars().map(|_| '*'));
+                        copied_index = *end;
+                    }
+                }
+
+                masked.push_str(&text[copied_index..]);
+                Some(masked)
+            }
+        }
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        // Punctuation around the word may be a substitution, as in "$hit", or
+        // not, as in "hell!"
+        let core = word.trim_matches(|character: char| !character.is_alphanumeric());
+
+        [word, core].iter().any(|candidate| {
+            let letters = letters(candidate);
+
+            shortened(&letters, 1)
+                .iter()
+                .chain(&shortened(&letters, 2))
+                .chain(std::iter::once(&letters))
+                .any(|candidate| self.words.contains(candidate))
+        })
+    }
+}
+
+/// Returns the lowercase letters of the word with common substitutions of
+/// digits and symbols undone.
+fn letters(word: &str) -> String {
+    word.chars()
+        .map(|character| match character {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' | '+' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            '|' => 'l',
+            _ => character,
+        })
+        .filter(|character| character.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Returns the word with runs of 3 or more of the same letter shortened to
+/// the length, or None if it has no such runs.
+fn shortened(word: &str, length: usize) -> Option<String> {
+    let characters: Vec<char> = word.chars().collect();
+    let mut result = String::with_capacity(word.len());
+    let mut changed = false;
+    let mut index = 0;
+
+    while index < characters.len() {
+        let run_length = characters[index..]
+            .iter()
+            .take_while(|character| **character == characters[index])
+            .count();
+
+        if run_length >= 3 {
+            changed = true;
+            result.push_str(&characters[index].to_string().repeat(length));
+        } else {
+            result.extend(&characters[index..index + run_length]);
+        }
+
+        index += run_length;
+    }
+
+    if changed {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_filter(action: ContentFilterAction) -> ContentFilter {
+        ContentFilter::new(
+            ContentFilterConfig {
+                word_lists: Vec::new(),
+                action,
+            },
+            vec!["darn".to_string(), "Heck".to_string(), "".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_filter_mask() {
+        let content_filter = content_filter(ContentFilterAction::Mask);
+
+        assert_eq!(
+            content_filter.filter("what a D4RN day").as_deref(),
+            Some("what a **** day")
+        );
+        assert_eq!(
+            content_filter.filter("daaarn, $ir! h3ck!").as_deref(),
+            Some("******* $ir! *****")
+        );
+        assert_eq!(
+            content_filter.filter("go d a r n it").as_deref(),
+            Some("go * * * * it")
+        );
+        // Whole words only
+        assert_eq!(
+            content_filter.filter("darning socks").as_deref(),
+            Some("darning socks")
+        );
+    }
+
+    #[test]
+    fn test_filter_drop() {
+        let content_filter = content_filter(ContentFilterAction::Drop);
+
+        assert_eq!(content_filter.filter("HECK!"), None);
+        assert_eq!(
+            content_filter.filter("Wild PIDGEY appeared!").as_deref(),
+            Some("Wild PIDGEY appeared!")
+        );
+    }
+}
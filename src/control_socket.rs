@@ -0,0 +1,287 @@
+//! Administration of a running processor through a Unix socket.
+//!
+//! Each connection sends one JSON request on a line such as
+//! `{"command": "pause"}` and receives one JSON response on a line.
+//!
+//! Connections are read on threads of their own, so a client that is slow
+//! to send its request doesn't hold up processing or other clients. Only the
+//! commands are handled on the processing thread.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use slog_scope::warn;
+
+use crate::error::IpcError;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    /// Pauses if needed and processes the given number of frames.
+    Step {
+        #[serde(default = "default_step_count")]
+        count: u32,
+    },
+    ReloadConfig,
+    Status,
+    SetScene {
+        name: String,
+    },
+    /// Saves the debug display and/or the current stream frame to image
+    /// files, such as for reports of misrecognized text.
+    Snapshot {
+        #[serde(default)]
+        canvas: Option<PathBuf>,
+        #[serde(default)]
+        frame: Option<PathBuf>,
+    },
+}
+
+fn default_step_count() -> u32 {
+    1
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusReport>,
+}
+
+impl ControlResponse {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(message),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub paused: bool,
+    pub pending_steps: u32,
+    pub frame_counter: u64,
+    pub region_count: usize,
+    pub active_scene: Option<String>,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    pub latency_p99_ms: Option<f64>,
+}
+
+/// Command read by a connection thread and the channel for its response.
+struct Request {
+    command: ControlCommand,
+    response_sender: SyncSender<ControlResponse>,
+}
+
+pub struct ControlServer {
+    path: PathBuf,
+    listener: UnixListener,
+    request_sender: Sender<Request>,
+    request_receiver: Receiver<Request>,
+}
+
+impl ControlServer {
+    /// Listens on the path, replacing a socket file left behind by a process
+    /// that exited without removing it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IpcError> {
+        let path = path.as_ref().to_path_buf();
+
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(IpcError::SocketInUse { path });
+            }
+
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let (request_sender, request_receiver) = mpsc::channel();
+
+        Ok(Self {
+            path,
+            listener,
+            request_sender,
+            request_receiver,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts the pending connections and handles the commands received
+    /// so far without blocking.
+    pub fn poll<F>(&self, mut handler: F)
+    where
+        F: FnMut(ControlCommand) -> ControlResponse,
+    {
+        while let Ok((stream, _address)) = self.listener.accept() {
+            let request_sender = self.request_sender.clone();
+
+            std::thread::spawn(move || {
+                if let Err(error) = handle_connection(stream, &request_sender) {
+                    warn!("control connection error"; "error" => %error);
+                }
+            });
+        }
+
+        while let Ok(request) = self.request_receiver.try_recv() {
+            // The client may have gone away
+            let _ = request.response_sender.send(handler(request.command));
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the request, passes its command to the processing thread, and
+/// writes the response.
+fn handle_connection(stream: UnixStream, request_sender: &Sender<Request>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str(&line) {
+        Ok(command) => {
+            let (response_sender, response_receiver) = mpsc::sync_channel(1);
+            let request = Request {
+                command,
+                response_sender,
+            };
+
+            // Fails if the server was closed before handling the command
+            match request_sender
+                .send(request)
+                .ok()
+                .and_then(|_| response_receiver.recv().ok())
+            {
+                Some(response) => response,
+                None => ControlResponse::error("server closed".to_string()),
+            }
+        }
+        Err(error) => ControlResponse::error(format!("invalid request: {}", error)),
+    };
+
+    let mut writer = &stream;
+    serde_json::to_writer(&mut writer, &response)?;
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_request_response() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("tppocr-control-{}", std::process::id()));
+        std::fs::create_dir_all(&directory)?;
+        let path = directory.join("control.socket");
+        let server = ControlServer::open(&path)?;
+
+        // A client that never sends a request doesn't block others
+        let _idle_client = UnixStream::connect(&path)?;
+        let mut client = UnixStream::connect(&path)?;
+        client.write_all(b"{\"command\": \"set_scene\", \"name\": \"battle\"}\n")?;
+
+        let mut commands = Vec::new();
+
+        for _ in 0..100 {
+            server.poll(|command| {
+                commands.push(command);
+                ControlResponse::ok()
+            });
+
+            if !commands.is_empty() {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut response = String::new();
+        client.read_to_string(&mut response)?;
+
+        assert_eq!(
+            commands,
+            vec![ControlCommand::SetScene {
+                name: "battle".to_string()
+            }]
+        );
+        assert_eq!(response, "{\"ok\":true}\n");
+
+        // The socket of a running server isn't replaced
+        assert!(matches!(
+            ControlServer::open(&path),
+            Err(IpcError::SocketInUse { .. })
+        ));
+        drop(server);
+
+        // A socket file left behind is
+        UnixListener::bind(&path)?;
+        drop(ControlServer::open(&path)?);
+
+        std::fs::remove_dir_all(&directory)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_count() {
+        assert_eq!(
+            serde_json::from_str::<ControlCommand>("{\"command\": \"step\"}").unwrap(),
+            ControlCommand::Step { count: 1 }
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlCommand>("{\"command\": \"step\", \"count\": 5}")
+                .unwrap(),
+            ControlCommand::Step { count: 5 }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_paths() {
+        assert_eq!(
+            serde_json::from_str::<ControlCommand>(
+                "{\"command\": \"snapshot\", \"frame\": \"/tmp/frame.png\"}"
+            )
+            .unwrap(),
+            ControlCommand::Snapshot {
+                canvas: None,
+                frame: Some(PathBuf::from("/tmp/frame.png")),
+            }
+        );
+    }
+}
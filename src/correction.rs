@@ -0,0 +1,254 @@
+//! Correction of recognized text against lists of known words and names.
+//!
+//! Game text is mostly made of a limited vocabulary such as Pokémon species,
+//! move names, and trainer names. A word or phrase within a small edit
+//! distance of exactly one list entry, such as "P1KACHU", is replaced by the
+//! entry.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use eddie::Levenshtein;
+
+use crate::config::CorrectionConfig;
+
+/// How a phrase matched the entries.
+enum PhraseMatch {
+    /// The phrase is an entry, possibly in another case.
+    Exact,
+    /// The phrase with its core replaced by the closest entry.
+    Corrected(String),
+}
+
+/// Corrects text against the entries of the configured word lists.
+pub struct Corrector {
+    config: CorrectionConfig,
+    /// Entries as written in the list and in uppercase, grouped by number
+    /// of words minus 1.
+    entries: Vec<Vec<(String, String)>>,
+    levenshtein: Levenshtein,
+}
+
+impl Corrector {
+    /// Creates a corrector with the entries of the configured word list
+    /// files.
+    pub fn load(config: CorrectionConfig) -> io::Result<Self> {
+        let mut entries = Vec::new();
+
+        for path in &config.word_lists {
+            for line in BufReader::new(File::open(path)?).lines() {
+                entries.push(line?);
+            }
+        }
+
+        Ok(Self::new(config, entries))
+    }
+
+    /// Creates a corrector with the entries instead of the configured word
+    /// list files. Blank entries are ignored.
+    pub fn new(config: CorrectionConfig, entries: Vec<String>) -> Self {
+        let mut grouped_entries: Vec<Vec<(String, String)>> = Vec::new();
+
+        for entry in entries {
+            let entry = entry.trim();
+
+            if entry.is_empty() {
+                continue;
+            }
+
+            let word_count = entry.split_whitespace().count();
+
+            if grouped_entries.len() < word_count {
+                grouped_entries.resize(word_count, Vec::new());
+            }
+
+            grouped_entries[word_count - 1].push((entry.to_string(), entry.to_uppercase()));
+        }
+
+        Self {
+            config,
+            entries: grouped_entries,
+            levenshtein: Levenshtein::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CorrectionConfig {
+        &self.config
+    }
+
+    /// Returns the text with near-misses of entries replaced.
+    ///
+    /// Phrases of as many words as the longest entries are tried first, and
+    /// the words of a phrase that matches an entry, exactly or not, are not
+    /// tried again. Punctuation around a phrase and whitespace between
+    /// phrases are kept.
+    pub fn correct(&self, text: &str) -> String {
+        let words = word_spans(text);
+        let mut corrected = String::with_capacity(text.len());
+        let mut copied_index = 0;
+        let mut word_index = 0;
+
+        while word_index < words.len() {
+            let longest = self.entries.len().min(words.len() - word_index);
+            let phrase_match = (1..=longest).rev().find_map(|word_count| {
+                let start = words[word_index].0;
+                let end = words[word_index + word_count - 1].1;
+
+                self.match_phrase(&text[start..end])
+                    .map(|phrase_match| (word_count, start, end, phrase_match))
+            });
+
+            match phrase_match {
+                Some((word_count, start, end, PhraseMatch::Corrected(replacement))) => {
+                    corrected.push_str(&text[copied_index..start]);
+                    corrected.push_str(&replacement);
+                    copied_index = end;
+                    word_index += word_count;
+                }
+                Some((word_count, _, _, PhraseMatch::Exact)) => word_index += word_count,
+                None => word_index += 1,
+            }
+        }
+
+        corrected.push_str(&text[copied_index..]);
+        corrected
+    }
+
+    /// Matches the phrase's core against the entries of the same number of
+    /// words. Returns None if it is too far from or ambiguous between
+    /// entries.
+    fn match_phrase(&self, phrase: &str) -> Option<PhraseMatch> {
+        let core_start = phrase.find(char::is_alphanumeric)?;
+        let core_end = phrase
+            .rfind(char::is_alphanumeric)
+            .map(|index| index + phrase[index..].chars().next().unwrap().len_utf8())?;
+        let core = &phrase[core_start..core_end];
+        let core_upper = core.to_uppercase();
+        let word_count = core.split_whitespace().count();
+        let length = core_upper.chars().count();
+
+        if length < self.config.min_length {
+            return None;
+        }
+
+        let mut best: Option<(&str, usize)> = None;
+        let mut ambiguous = false;
+
+        for (entry, entry_upper) in self.entries.get(word_count - 1)? {
+            let entry_length = entry_upper.chars().count();
+
+            if length.max(entry_length) - length.min(entry_length) > self.config.max_distance {
+                continue;
+            }
+
+            let distance = self.levenshtein.distance(&core_upper, entry_upper);
+
+            if distance == 0 {
+                return Some(PhraseMatch::Exact);
+            }
+
+            if distance > self.config.max_distance {
+                continue;
+            }
+
+            match best {
+                Some((best_entry, best_distance)) if distance == best_distance => {
+                    ambiguous |= best_entry != entry.as_str();
+                }
+                Some((_, best_distance)) if distance > best_distance => {}
+                _ => {
+                    best = Some((entry, distance));
+                    ambiguous = false;
+                }
+            }
+        }
+
+        let (entry, _) = best?;
+
+        if ambiguous {
+            return None;
+        }
+
+        // Games write names in all caps or as the list does
+        let entry = if core.chars().any(char::is_lowercase) {
+            entry.to_string()
+        } else {
+            entry.to_uppercase()
+        };
+
+        Some(PhraseMatch::Corrected(format!(
+            "{}{}{}",
+            &phrase[..core_start],
+            entry,
+            &phrase[core_end..]
+        )))
+    }
+}
+
+/// Returns the start and end byte indices of the whitespace separated words.
+pub(crate) fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+
+    for (index, character) in text.char_indices() {
+        match (character.is_whitespace(), start) {
+            (true, Some(word_start)) => {
+                spans.push((word_start, index));
+                start = None;
+            }
+            (false, None) => start = Some(index),
+            _ => {}
+        }
+    }
+
+    if let Some(word_start) = start {
+        spans.push((word_start, text.len()));
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correct() {
+        let config = CorrectionConfig {
+            word_lists: Vec::new(),
+            max_distance: 2,
+            min_length: 4,
+        };
+        let entries = [
+            "Pikachu",
+            "Raichu",
+            "Thunderbolt",
+            "Mr. Mime",
+            "Mimic",
+            "Mew",
+            "Tackle",
+            "Tickle",
+        ]
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect();
+        let corrector = Corrector::new(config, entries);
+
+        assert_eq!(
+            corrector.correct("P1KACHU used THUNDERB0LT!"),
+            "PIKACHU used THUNDERBOLT!"
+        );
+        assert_eq!(corrector.correct("Go,  MR. M1ME!"), "Go,  MR. MIME!");
+        // Words of an exact phrase aren't corrected to shorter entries
+        assert_eq!(corrector.correct("Go, MR. MIME!"), "Go, MR. MIME!");
+        assert_eq!(corrector.correct("MIME used MIM1C!"), "MIMIC used MIMIC!");
+        assert_eq!(corrector.correct("Raichv fainted."), "Raichu fainted.");
+        // Too short
+        assert_eq!(corrector.correct("MEV"), "MEV");
+        // Equally close to Tackle and Tickle
+        assert_eq!(corrector.correct("TACKLE T4CKLE"), "TACKLE T4CKLE");
+        assert_eq!(corrector.correct(""), "");
+    }
+}
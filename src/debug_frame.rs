@@ -0,0 +1,398 @@
+//! Interactive tuning of region settings against a single frame, and
+//! checking the regions of a config against one.
+
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use chrono::{Duration, Utc};
+use toml::{value::Table, Value};
+
+use crate::{
+    config::{self, ProcessorConfig, Region},
+    error::{Error, FrameError, OcrError},
+    ocr_engine::{BoxLevel, PageSegmentation},
+    preprocess::{self, Preprocessor},
+    processor::{self, translate_boxes_to_frame},
+    text_recognizer::TextRecognizerSet,
+};
+
+const HELP: &str = "\
+Commands:
+  rect X Y WIDTH HEIGHT   set the region rectangle
+  set KEY = VALUE         set a region option using TOML syntax,
+                          e.g. set binarization = { method = \"Otsu\" }
+  unset KEY               remove a region option
+  psm MODE                set the page segmentation mode (auto, block, line,
+                          word, character, or sparse) instead of the
+                          strategy's mode
+  run                     recognize the region and print the results
+  save PATH               save the preprocessed region image
+  show                    print the region options
+  help                    print this help
+  quit                    exit";
+
+enum Command {
+    Rect(u32, u32, u32, u32),
+    Set(String, Value),
+    Unset(String),
+    PageSegmentationMode(PageSegmentation),
+    Run,
+    Save(String),
+    Show,
+    Help,
+    Quit,
+}
+
+/// Prompt for trying region options on a frame loaded from an image file.
+pub struct DebugSession {
+    frame_data: Vec<u32>,
+    frame_width: u32,
+    frame_height: u32,
+    text_recognizers: TextRecognizerSet,
+    region: Table,
+    page_segmentation_mode: Option<PageSegmentation>,
+}
+
+impl DebugSession {
+    pub fn new<P: AsRef<Path>>(
+        image_path: P,
+        text_recognizers: TextRecognizerSet,
+    ) -> Result<Self, Error> {
+        let image = image::open(image_path)
+            .map_err(FrameError::from)?
+            .into_rgba8();
+        let (frame_width, frame_height) = image.dimensions();
+
+        let mut region = Table::new();
+        region.insert("name".to_string(), Value::String("debug".to_string()));
+        region.insert("x".to_string(), Value::Integer(0));
+        region.insert("y".to_string(), Value::Integer(0));
+        region.insert("width".to_string(), Value::Integer(frame_width as i64));
+        region.insert("height".to_string(), Value::Integer(frame_height as i64));
+        region.insert(
+            "processor".to_string(),
+            Value::String("FixedLine".to_string()),
+        );
+
+        Ok(Self {
+            frame_data: preprocess::to_u32_pixels(&image),
+            frame_width,
+            frame_height,
+            text_recognizers,
+            region,
+            page_segmentation_mode: None,
+        })
+    }
+
+    /// Reads and executes commands from standard input until quit or end of
+    /// input.
+    pub fn run(&mut self) -> std::io::Result<()> {
+        let stdin = std::io::stdin();
+        let mut stdout = std::io::stdout();
+
+        println!(
+            "Loaded frame {}x{}. Type help for commands.",
+            self.frame_width, self.frame_height
+        );
+
+        loop {
+            print!("> ");
+            stdout.flush().ok();
+
+            let mut line = String::new();
+
+            if stdin.lock().read_line(&mut line)? == 0 {
+                break;
+            }
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match parse_command(&line) {
+                Ok(Command::Quit) => break,
+                Ok(command) => {
+                    if let Err(message) = self.execute(command) {
+                        println!("error: {}", message);
+                    }
+                }
+                Err(message) => println!("error: {}", message),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Rect(x, y, width, height) => {
+                let fits = |start: u32, length: u32, limit: u32| {
+                    start.checked_add(length).map_or(false, |end| end <= limit)
+                };
+
+                if !fits(x, width, self.frame_width) || !fits(y, height, self.frame_height) {
+                    return Err("rectangle is outside of the frame".to_string());
+                }
+
+                for (key, value) in &[("x", x), ("y", y), ("width", width), ("height", height)] {
+                    self.region
+                        .insert(key.to_string(), Value::Integer(*value as i64));
+                }
+            }
+            Command::Set(key, value) => {
+                let previous = self.region.insert(key.clone(), value);
+
+                if let Err(message) = self.build_region() {
+                    match previous {
+                        Some(previous) => self.region.insert(key, previous),
+                        None => self.region.remove(&key),
+                    };
+
+                    return Err(message);
+                }
+            }
+            Command::Unset(key) => {
+                self.region.remove(&key);
+            }
+            Command::PageSegmentationMode(mode) => self.page_segmentation_mode = Some(mode),
+            Command::Run => self.recognize()?,
+            Command::Save(path) => {
+                let image = Preprocessor::new(self.build_region()?)
+                    .process(&self.frame_data, self.frame_width);
+                image.save(&path).map_err(|error| error.to_string())?;
+                println!("saved {}", path);
+            }
+            Command::Show => println!("{}", Value::Table(self.region.clone())),
+            Command::Help => println!("{}", HELP),
+            Command::Quit => {}
+        }
+
+        Ok(())
+    }
+
+    fn build_region(&self) -> Result<Region, String> {
+        let mut region = self.region.clone();
+        config::resolve_region_coordinates(&mut region, self.frame_width, self.frame_height)
+            .map_err(|error| error.to_string())?;
+
+        Value::Table(region)
+            .try_into()
+            .map_err(|error: toml::de::Error| error.to_string())
+    }
+
+    fn recognize(&mut self) -> Result<(), String> {
+        let region = self.build_region()?;
+
+        print_recognition(
+            &mut self.text_recognizers,
+            &region,
+            self.page_segmentation_mode,
+            &self.frame_data,
+            self.frame_width,
+        )
+        .map_err(|error| error.to_string())
+    }
+}
+
+/// Runs every region of the config once on a frame loaded from an image file
+/// and prints the results.
+///
+/// Fractional region coordinates are resolved against the image size.
+/// Loading fails for regions outside of the image, while regions of
+/// `[[stream]]` sections that don't fit it are reported and skipped.
+pub fn calibrate<P: AsRef<Path>>(
+    image_path: P,
+    config_path: &Path,
+    mut text_recognizers: TextRecognizerSet,
+) -> Result<(), Error> {
+    let image = image::open(image_path)
+        .map_err(FrameError::from)?
+        .into_rgba8();
+    let (frame_width, frame_height) = image.dimensions();
+    let frame_data = preprocess::to_u32_pixels(&image);
+    let config = ProcessorConfig::load(config_path, frame_width, frame_height)?;
+
+    println!("Loaded frame {}x{}.", frame_width, frame_height);
+
+    for region in &config.region {
+        println!();
+        println!(
+            "region {} ({}, {}) {}x{}",
+            region.name, region.x, region.y, region.width, region.height
+        );
+
+        if !region.fits_frame(frame_width, frame_height) {
+            println!("error: region is outside of the frame");
+            continue;
+        }
+
+        if let Err(error) = print_recognition(
+            &mut text_recognizers,
+            region,
+            None,
+            &frame_data,
+            frame_width,
+        ) {
+            println!("error: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_recognition(
+    text_recognizers: &mut TextRecognizerSet,
+    region: &Region,
+    page_segmentation_mode: Option<PageSegmentation>,
+    frame_data: &[u32],
+    frame_width: u32,
+) -> Result<(), OcrError> {
+    if let Some(language) = &region.language {
+        text_recognizers.load(language)?;
+    }
+
+    if let Some(config) = &region.onnx {
+        text_recognizers.load_onnx(config)?;
+    }
+
+    let image = Preprocessor::new(region.clone()).process(frame_data, frame_width);
+    let text_recognizer = text_recognizers.get_for_region(region, region.language.as_deref());
+
+    text_recognizer.set_page_segmentation_mode(
+        page_segmentation_mode
+            .unwrap_or_else(|| processor::page_segmentation_mode(&region.processor)),
+    );
+    text_recognizer.set_character_whitelist(processor::character_whitelist(&region.processor));
+    text_recognizer.set_image(
+        &preprocess::to_u32_pixels(&image),
+        image.width(),
+        image.height(),
+    );
+    text_recognizer.recognize()?;
+
+    let text = text_recognizer.text();
+    let mut word_boxes = text_recognizer.boxes(BoxLevel::Word);
+    let mut block_boxes = text_recognizer.boxes(BoxLevel::Block);
+
+    let mut lines = text_recognizer.texts(BoxLevel::Line);
+    let mut words = text_recognizer.texts(BoxLevel::Word);
+
+    translate_boxes_to_frame(region, &mut word_boxes);
+    translate_boxes_to_frame(region, &mut block_boxes);
+
+    for line in lines.iter_mut().chain(&mut words) {
+        translate_boxes_to_frame(region, std::slice::from_mut(&mut line.bounding_box));
+    }
+
+    println!("text: {:?}", text);
+
+    for (label, boxes) in &[("word", &word_boxes), ("block", &block_boxes)] {
+        for bounding_box in boxes.iter() {
+            println!(
+                "{} ({}, {})-({}, {}) confidence {:.3}",
+                label,
+                bounding_box.x1,
+                bounding_box.y1,
+                bounding_box.x2,
+                bounding_box.y2,
+                bounding_box.confidence
+            );
+        }
+    }
+
+    // The strategy normally sees many frames, so the item is flushed by
+    // polling as if time had passed.
+    let mut text_processor = processor::text_processor_for(region.clone());
+    let date = Utc::now();
+
+    text_processor.process(&date, &text, &block_boxes);
+    text_processor.process_lines(&date, &lines);
+    text_processor.process_words(&date, &words);
+
+    for text_item in text_processor.poll_result(&(date + Duration::minutes(1))) {
+        println!(
+            "strategy output: {:?} confidence {:.3}",
+            text_item.text, text_item.confidence
+        );
+    }
+
+    Ok(())
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, arguments) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+    let parse_number = |value: &str| {
+        value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid number {:?}", value))
+    };
+
+    match name {
+        "rect" => {
+            let numbers = arguments
+                .split_whitespace()
+                .map(parse_number)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match numbers.as_slice() {
+                [x, y, width, height] => Ok(Command::Rect(*x, *y, *width, *height)),
+                _ => Err("expected X Y WIDTH HEIGHT".to_string()),
+            }
+        }
+        "set" => {
+            let table: Table = toml::de::from_str(arguments)
+                .map_err(|error| format!("expected KEY = VALUE: {}", error))?;
+
+            match table.into_iter().next() {
+                Some((key, value)) => Ok(Command::Set(key, value)),
+                None => Err("expected KEY = VALUE".to_string()),
+            }
+        }
+        "unset" if !arguments.is_empty() => Ok(Command::Unset(arguments.to_string())),
+        "psm" => Ok(Command::PageSegmentationMode(arguments.parse()?)),
+        "run" => Ok(Command::Run),
+        "save" if !arguments.is_empty() => Ok(Command::Save(arguments.to_string())),
+        "show" => Ok(Command::Show),
+        "help" => Ok(Command::Help),
+        "quit" | "exit" => Ok(Command::Quit),
+        _ => Err(format!(
+            "unknown command {:?}, type help for commands",
+            line
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(
+            parse_command("rect 1 2 30 40\n"),
+            Ok(Command::Rect(1, 2, 30, 40))
+        ));
+        assert!(parse_command("rect 1 2").is_err());
+
+        match parse_command(r#"set color_key = { color = [248, 248, 248] }"#) {
+            Ok(Command::Set(key, value)) => {
+                assert_eq!(key, "color_key");
+                assert!(value.is_table());
+            }
+            _ => panic!(),
+        }
+
+        assert!(matches!(
+            parse_command("psm line"),
+            Ok(Command::PageSegmentationMode(PageSegmentation::SingleLine))
+        ));
+        assert!(parse_command("psm 7").is_err());
+        assert!(parse_command("bogus").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Debug display shown in a local X11 window, for running everything on one
+//! desktop without the VNC server.
+//!
+//! The canvas is drawn with the core protocol's PutImage request, so no X
+//! extension is needed. The screen must have a 24-bit or 32-bit TrueColor
+//! visual, which the canvas's BGRA pixels match on little-endian hosts.
+
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        xproto::{
+            Atom, AtomEnum, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask, Gcontext,
+            ImageFormat, PropMode, Window, WindowClass,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+    COPY_DEPTH_FROM_PARENT,
+};
+
+use crate::error::DisplayError;
+
+const WINDOW_TITLE: &[u8] = b"tppocr debug";
+const BYTES_PER_PIXEL: usize = 4;
+/// Space for the fields of a PutImage request before the data.
+const PUT_IMAGE_HEADER_SIZE: usize = 24;
+
+pub struct DebugWindow {
+    connection: RustConnection,
+    window: Window,
+    gc: Gcontext,
+    depth: u8,
+    width: u32,
+    delete_window_atom: Atom,
+}
+
+impl DebugWindow {
+    /// Opens a window of the canvas size on the X server of `$DISPLAY`.
+    pub fn new(width: u32, height: u32) -> Result<Self, DisplayError> {
+        let (connection, screen_number) = RustConnection::connect(None)?;
+        let screen = &connection.setup().roots[screen_number];
+        let root = screen.root;
+        let depth = screen.root_depth;
+        let visual = screen.root_visual;
+        let black_pixel = screen.black_pixel;
+
+        if depth != 24 && depth != 32 {
+            return Err(DisplayError::UnsupportedDepth { depth });
+        }
+
+        let window = connection.generate_id()?;
+        let gc = connection.generate_id()?;
+
+        connection.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            width as u16,
+            height as u16,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            visual,
+            &CreateWindowAux::new()
+                .background_pixel(black_pixel)
+                .event_mask(EventMask::EXPOSURE),
+        )?;
+        connection.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_NAME,
+            AtomEnum::STRING,
+            WINDOW_TITLE,
+        )?;
+
+        // Closing the window is reported as a message instead of the server
+        // disconnecting the processor
+        let protocols_atom = connection
+            .intern_atom(false, b"WM_PROTOCOLS")?
+            .reply()?
+            .atom;
+        let delete_window_atom = connection
+            .intern_atom(false, b"WM_DELETE_WINDOW")?
+            .reply()?
+            .atom;
+        connection.change_property32(
+            PropMode::REPLACE,
+            window,
+            protocols_atom,
+            AtomEnum::ATOM,
+            &[delete_window_atom],
+        )?;
+
+        connection.create_gc(gc, window, &CreateGCAux::new())?;
+        connection.map_window(window)?;
+        connection.flush()?;
+
+        Ok(Self {
+            connection,
+            window,
+            gc,
+            depth,
+            width,
+            delete_window_atom,
+        })
+    }
+
+    /// Draws the canvas pixels in the window, returning false once the window
+    /// was closed.
+    pub fn show(&mut self, pixels: &[u32]) -> Result<bool, DisplayError> {
+        while let Some(event) = self.connection.poll_for_event()? {
+            if let Event::ClientMessage(event) = event {
+                if event.data.as_data32()[0] == self.delete_window_atom {
+                    return Ok(false);
+                }
+            }
+        }
+
+        let row_size = self.width as usize * BYTES_PER_PIXEL;
+        let rows_per_request =
+            ((self.connection.maximum_request_bytes() - PUT_IMAGE_HEADER_SIZE) / row_size).max(1);
+        let data: Vec<u8> = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_le_bytes().to_vec())
+            .collect();
+
+        // Large canvases exceed the request size limit, so they are sent in
+        // strips of rows
+        for (index, strip) in data.chunks(row_size * rows_per_request).enumerate() {
+            self.connection.put_image(
+                ImageFormat::Z_PIXMAP,
+                self.window,
+                self.gc,
+                self.width as u16,
+                (strip.len() / row_size) as u16,
+                0,
+                (index * rows_per_request) as i16,
+                0,
+                self.depth,
+                strip,
+            )?;
+        }
+
+        self.connection.flush()?;
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,124 @@
+//! Self-contained pipeline for checking an installation without a stream.
+//!
+//! Synthetic frames showing lines of dialog in a box are published to a frame
+//! ring in place of the stream dumper, so the processor can recognize them
+//! with the built-in config.
+
+use std::{thread::JoinHandle, time::Duration};
+
+use chrono::Utc;
+use raqote::{Color, DrawOptions, DrawTarget, Point, Source};
+
+use crate::{canvas::TextDrawer, error::Error, frame, frame_ring::FrameRing};
+
+/// Config of the region covering the dialog box, narrow enough for the
+/// default debug display.
+pub const DEMO_CONFIG: &str = r#"
+[[region]]
+name = "demo_dialog"
+x = 0.05
+y = 0.7
+width = 0.75
+height = 0.2
+processor = "FixedLine"
+"#;
+
+pub(crate) const DIALOG_LINES: &[&str] = &[
+    "Hello there! Welcome to the world of OCR!",
+    "This text is drawn on synthetic frames.",
+    "If you can read this, tppocr is working.",
+];
+pub(crate) const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+/// Frames each line is shown for, followed by as many frames of an empty box.
+pub(crate) const LINE_FRAMES: usize = 30;
+
+/// Starts a thread publishing the dialog lines in a loop to the frame ring
+/// with the given ID, drawn with the fonts of the display config.
+///
+/// The ring exists when this function returns.
+pub fn start_frame_generator(
+    id: u32,
+    width: u32,
+    height: u32,
+    fonts: &[String],
+) -> Result<JoinHandle<()>, Error> {
+    let mut text_drawer = TextDrawer::with_fonts(fonts)?;
+    let mut frames = Vec::new();
+
+    for line in DIALOG_LINES {
+        frames.push(render_frame(&mut text_drawer, width, height, line));
+        frames.push(render_frame(&mut text_drawer, width, height, ""));
+    }
+
+    let frame_size = frames[0].len();
+    FrameRing::create(id, frame_size, frame::DEFAULT_SLOT_COUNT)?;
+
+    Ok(std::thread::spawn(move || {
+        let mut frame_ring = FrameRing::open(id).expect("demo frame ring removed");
+
+        for frame in frames.iter().cycle() {
+            for _ in 0..LINE_FRAMES {
+                frame_ring.publish(frame, &Utc::now());
+                std::thread::sleep(FRAME_INTERVAL);
+            }
+        }
+    }))
+}
+
+/// Draws a dialog box like the ones in the games at the position of the
+/// demo region and returns the RGBA pixels.
+pub(crate) fn render_frame(
+    text_drawer: &mut TextDrawer,
+    width: u32,
+    height: u32,
+    text: &str,
+) -> Vec<u8> {
+    let mut canvas = DrawTarget::new(width as i32, height as i32);
+    let options = DrawOptions::new();
+    let (box_x, box_y) = (width as f32 * 0.05, height as f32 * 0.7);
+    let (box_width, box_height) = (width as f32 * 0.75, height as f32 * 0.2);
+
+    canvas.clear(raqote::SolidSource::from_unpremultiplied_argb(
+        255, 64, 64, 64,
+    ));
+    canvas.fill_rect(
+        box_x,
+        box_y,
+        box_width,
+        box_height,
+        &Source::from(Color::new(255, 248, 248, 248)),
+        &options,
+    );
+
+    text_drawer.set_color(Color::new(255, 16, 16, 16));
+    text_drawer.set_font_size(box_height / 4.0);
+    text_drawer.set_position(Point::new(
+        box_x + box_height / 8.0,
+        box_y + box_height / 3.0,
+    ));
+    text_drawer.draw(&mut canvas, text);
+
+    // Gray levels only, so the channel order of the canvas doesn't matter
+    canvas
+        .get_data()
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes().to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorConfig;
+
+    #[test]
+    fn test_demo_config() {
+        let config = ProcessorConfig::parse(DEMO_CONFIG, 1280, 720).unwrap();
+        let region = &config.region[0];
+
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (64, 504, 960, 144)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Posting emitted items to a Discord channel through a webhook, so a
+//! channel can follow the in-game dialog live.
+//!
+//! Items arriving close together are batched into one message, and posting
+//! waits as long as Discord's rate limit headers ask.
+
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use slog_scope::warn;
+
+use crate::{config::DiscordConfig, text_processor::TextItem};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest content of a Discord message.
+const MAX_CONTENT_LENGTH: usize = 2000;
+/// Attempts of posting a message that was rate limited.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Posts the lines of items arriving within the batch time of the first one
+/// together.
+pub struct DiscordSink {
+    config: DiscordConfig,
+    agent: ureq::Agent,
+    /// Lines of the batch not posted yet.
+    lines: Vec<String>,
+    /// When the batch is posted.
+    batch_deadline: Option<Instant>,
+}
+
+impl DiscordSink {
+    pub fn new(config: DiscordConfig) -> Self {
+        Self {
+            config,
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            lines: Vec::new(),
+            batch_deadline: None,
+        }
+    }
+
+    pub fn config(&self) -> &DiscordConfig {
+        &self.config
+    }
+
+    /// Adds the item to the batch unless its region is not posted.
+    pub fn send_item(&mut self, item: &TextItem) {
+        if !self.config.regions.is_empty() && !self.config.regions.contains(&item.region_name) {
+            return;
+        }
+
+        if self.lines.is_empty() {
+            self.batch_deadline =
+                Some(Instant::now() + Duration::from_secs_f64(self.config.batch_secs.max(0.0)));
+        }
+
+        self.lines
+            .push(format!("[{}] {}", item.region_name, item.text.trim()));
+    }
+
+    /// Returns when the batch is due.
+    pub fn batch_deadline(&self) -> Option<Instant> {
+        self.batch_deadline
+    }
+
+    /// Posts the lines of the batch in as few messages as fit.
+    pub fn post_batch(&mut self) {
+        self.batch_deadline = None;
+
+        for content in batch_contents(&std::mem::take(&mut self.lines), MAX_CONTENT_LENGTH) {
+            post(&self.agent, &self.config, &content);
+        }
+    }
+}
+
+fn post(agent: &ureq::Agent, config: &DiscordConfig, content: &str) {
+    // Recognized text may contain mentions such as @everyone, which
+    // shouldn't ping anyone
+    let body = json!({
+        "content": content,
+        "username": config.username,
+        "allowed_mentions": {"parse": []},
+    })
+    .to_string();
+
+    for _ in 0..MAX_ATTEMPTS {
+        let result = agent
+            .post(config.webhook_url.expose())
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+
+        // Errors are logged without the URL since it contains the webhook
+        // token
+        match result {
+            Ok(response) => {
+                if response.header("X-RateLimit-Remaining") == Some("0") {
+                    std::thread::sleep(retry_delay(&response, "X-RateLimit-Reset-After"));
+                }
+
+                return;
+            }
+            Err(ureq::Error::Status(429, response)) => {
+                let delay = retry_delay(&response, "Retry-After");
+                warn!("Discord rate limited"; "delay" => ?delay);
+                std::thread::sleep(delay);
+            }
+            Err(ureq::Error::Status(status, _)) => {
+                warn!("failed to post to Discord"; "status" => status);
+                return;
+            }
+            Err(error) => {
+                warn!("failed to post to Discord"; "error" => %error.kind());
+                return;
+            }
+        }
+    }
+}
+
+fn retry_delay(response: &ureq::Response, header: &str) -> Duration {
+    response
+        .header(header)
+        .and_then(|value| value.parse::<f64>().ok())
+        .map(|seconds| Duration::from_secs_f64(seconds.max(0.0)))
+        .unwrap_or_else(|| Duration::from_secs(1))
+}
+
+/// Joins the lines into as few message contents as fit the length, splitting
+/// lines longer than a message.
+fn batch_contents(lines: &[String], max_length: usize) -> Vec<String> {
+    let mut contents = Vec::new();
+    let mut content = String::new();
+
+    for line in lines {
+        let chars: Vec<char> = line.chars().collect();
+
+        for chunk in chars.chunks(max_length) {
+            let chunk: String = chunk.iter().collect();
+
+            if !content.is_empty()
+                && content.chars().count() + 1 + chunk.chars().count() > max_length
+            {
+                contents.push(std::mem::take(&mut content));
+            }
+
+            if !content.is_empty() {
+                content.push('\n');
+            }
+
+            content.push_str(&chunk);
+        }
+    }
+
+    if !content.is_empty() {
+        contents.push(content);
+    }
+
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_contents() {
+        let lines = vec![
+            "[dialog] Hello".to_string(),
+            "[dialog] World".to_string(),
+            "[dialog] 0123456789".to_string(),
+        ];
+
+        assert_eq!(
+            batch_contents(&lines, 30),
+            vec![
+                "[dialog] Hello\n[dialog] World".to_string(),
+                "[dialog] 0123456789".to_string()
+            ]
+        );
+        assert_eq!(
+            batch_contents(&lines[2..], 10),
+            vec!["[dialog] 0".to_string(), "123456789".to_string()]
+        );
+        assert!(batch_contents(&[], 30).is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+//! Error types returned by the library modules.
+//!
+//! Binaries are expected to wrap these into `anyhow::Error` at the edges.
+
+use std::{ffi::NulError, io, net::Ipv4Addr, path::PathBuf};
+
+use thiserror::Error;
+
+/// Errors related to obtaining, decoding, or transferring stream frames.
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("video stream not found in input")]
+    StreamNotFound,
+
+    #[error("stream URL resolver exit status {status:?}: {message}")]
+    UrlResolver {
+        status: Option<i32>,
+        message: String,
+    },
+
+    #[error("failed to run stream URL resolver")]
+    UrlResolverSpawn(#[source] io::Error),
+
+    #[error("no stream URL resolver installed, install yt-dlp, streamlink, or youtube-dl")]
+    NoUrlResolver,
+
+    #[error("failed to read cookies file {path:?}")]
+    CookieFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write Streamlink config file {path:?}")]
+    StreamlinkConfig {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("stream decoding error")]
+    Decode(#[from] ffmpeg_next::Error),
+
+    #[error("ffmpeg was built without Video4Linux2 capture device support")]
+    CaptureUnsupported,
+
+    #[error("failed to connect to X server")]
+    X11Connect(#[from] x11rb::errors::ConnectError),
+
+    #[error("X server connection error")]
+    X11Connection(#[from] x11rb::errors::ConnectionError),
+
+    #[error("X server request error")]
+    X11Reply(#[from] x11rb::errors::ReplyError),
+
+    #[error("no window with a title containing {name:?}")]
+    WindowNotFound { name: String },
+
+    #[error("crop rectangle {crop:?} is outside of the input")]
+    CropOutside { crop: crate::frame::CropRect },
+
+    #[error("unsupported screen pixel format of depth {depth}")]
+    UnsupportedPixelFormat { depth: u8 },
+
+    #[error("failed to create recording directory {path:?}")]
+    RecordingDirectory {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to load image")]
+    Image(#[from] image::ImageError),
+
+    #[error("failed to read image directory {path:?}")]
+    ImageDirectory {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("no PNG or JPEG images in directory {path:?}")]
+    NoImages { path: PathBuf },
+
+    #[error("stream dumper frame size {frame_size} does not match {width}x{height}")]
+    FrameSize {
+        width: u32,
+        height: u32,
+        frame_size: usize,
+    },
+
+    #[error("image size {width}x{height} does not match frame size {frame_width}x{frame_height}")]
+    ImageSize {
+        width: u32,
+        height: u32,
+        frame_width: u32,
+        frame_height: u32,
+    },
+
+    #[error("no frame from stream dumper within {0:?}")]
+    Timeout(std::time::Duration),
+
+    #[error("frame acquisition stopped unexpectedly")]
+    AcquisitionStopped,
+
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+}
+
+/// Errors returned by the text recognition engine.
+#[derive(Error, Debug)]
+pub enum OcrError {
+    #[error("tesseract initialization returned error code {0}")]
+    Init(i32),
+
+    #[error("tesseract recognize error code {0}")]
+    Recognize(i32),
+
+    #[error("invalid string argument")]
+    InvalidString(#[from] NulError),
+
+    #[error("ONNX models require building with the onnx feature")]
+    OnnxUnavailable,
+
+    #[cfg(feature = "onnx")]
+    #[error("ONNX Runtime error")]
+    Onnx(#[from] ort::Error),
+
+    #[error("unexpected ONNX model output shape {0:?}")]
+    OnnxOutputShape(Vec<i64>),
+
+    #[error("failed to read OCR dictionary {path:?}")]
+    Dictionary {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Errors related to shared memory and message sockets.
+#[derive(Error, Debug)]
+pub enum IpcError {
+    #[error("failed to open shared memory {name:?}")]
+    SharedMemoryOpen {
+        name: PathBuf,
+        #[source]
+        source: nix::Error,
+    },
+
+    #[error("failed to open memory map")]
+    MemoryMap(#[source] nix::Error),
+
+    #[error("shared memory error")]
+    SharedMemory(#[from] nix::Error),
+
+    #[error("couldn't connect to message server socket {id}")]
+    Connect {
+        id: u32,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to initialize semaphore")]
+    Semaphore(#[source] io::Error),
+
+    #[error("shared memory {id} is not a frame ring")]
+    InvalidFrameRing { id: u32 },
+
+    #[error("couldn't connect to frame sender {address}")]
+    FrameConnect {
+        address: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("invalid frame packet of length {length}")]
+    InvalidFramePacket { length: usize },
+
+    #[error("client has no named address")]
+    NoClientAddress,
+
+    #[error("couldn't connect to file descriptor socket {name:?}")]
+    FdConnect {
+        name: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("no file descriptor received from socket {name:?}")]
+    NoFileDescriptor { name: String },
+
+    #[error("socket {path:?} is in use by another process")]
+    SocketInUse { path: PathBuf },
+
+    #[error("process {pid} of user {uid} and group {gid} is not allowed to connect")]
+    PeerNotAllowed { pid: i32, uid: u32, gid: u32 },
+
+    #[error("socket error")]
+    Socket(#[from] io::Error),
+}
+
+/// Errors related to loading the configuration.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to parse config")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("failed to write config file {path:?}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to parse config for editing")]
+    Edit(#[from] toml_edit::TomlError),
+
+    #[error("regions of config file {path:?} are not [[region]] tables")]
+    RegionTables { path: PathBuf },
+
+    #[error("failed to load scene template image {path:?}")]
+    SceneTemplate {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+
+    #[error("region {key} fraction {value} is not in range 0.0 to 1.0")]
+    InvalidFraction { key: String, value: f64 },
+
+    #[error("config validation failed: {0}")]
+    Validation(String),
+
+    #[error("region {region:?} is outside of the {frame_width}x{frame_height} frame")]
+    RegionOutsideFrame {
+        region: String,
+        frame_width: u32,
+        frame_height: u32,
+    },
+
+    #[error("stream {name:?} configured more than once")]
+    DuplicateStream { name: String },
+
+    #[error("region {region:?} is in unknown stream {stream:?}")]
+    UnknownStream { region: String, stream: String },
+
+    #[error("invalid log level {level:?}")]
+    LogLevel { level: String },
+
+    #[error("invalid IPC namespace {namespace:?}, expected letters, digits, and hyphens")]
+    IpcNamespace { namespace: String },
+
+    #[error("environment variable {name} for secret is not set")]
+    SecretEnv { name: String },
+
+    #[error("secret file {path:?} must not be accessible by group or others")]
+    SecretFilePermissions { path: PathBuf },
+}
+
+/// Errors related to drawing and serving the debug display.
+#[derive(Error, Debug)]
+pub enum DisplayError {
+    #[error("font not found")]
+    FontSelection(#[from] font_kit::error::SelectionError),
+
+    #[error("failed to load font")]
+    FontLoading(#[from] font_kit::error::FontLoadingError),
+
+    #[error("font {name} not found")]
+    FontNotFound { name: String },
+
+    #[error("no fonts configured")]
+    NoFonts,
+
+    #[error("get libvnc screen error")]
+    Screen,
+
+    #[error("no VNC screens configured")]
+    NoScreens,
+
+    #[error("VNC screen {port} configured more than once")]
+    DuplicateScreen { port: u16 },
+
+    #[error("VNC password required to listen on {address}")]
+    PasswordRequired { address: Ipv4Addr },
+
+    #[error("VNC password contains a NUL character")]
+    InvalidPassword,
+
+    #[error("web client directory contains a NUL character")]
+    InvalidWebDirectory,
+
+    #[error("HTTP port of VNC screen {port} out of range")]
+    HttpPort { port: u16 },
+
+    #[error("failed to connect to X server")]
+    X11Connect(#[from] x11rb::errors::ConnectError),
+
+    #[error("X server connection error")]
+    X11Connection(#[from] x11rb::errors::ConnectionError),
+
+    #[error("X server request error")]
+    X11Reply(#[from] x11rb::errors::ReplyError),
+
+    #[error("X server ID allocation error")]
+    X11Id(#[from] x11rb::errors::ReplyOrIdError),
+
+    #[error("unsupported X screen depth {depth}, 24 or 32 is required")]
+    UnsupportedDepth { depth: u8 },
+
+    #[error("failed to load regions for editing")]
+    RegionEditor(#[from] ConfigError),
+
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+}
+
+/// Errors related to supervising the pipeline's programs.
+#[derive(Error, Debug)]
+pub enum SupervisorError {
+    #[error("failed to start {name} ({program:?})")]
+    Spawn {
+        name: String,
+        program: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to signal child process")]
+    Signal(#[from] nix::Error),
+
+    #[error("failed to check child process")]
+    Wait(#[source] io::Error),
+}
+
+/// Any of the library errors.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Frame(#[from] FrameError),
+
+    #[error(transparent)]
+    Ocr(#[from] OcrError),
+
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Display(#[from] DisplayError),
+
+    #[error(transparent)]
+    Supervisor(#[from] SupervisorError),
+
+    #[error("failed to start the task runtime")]
+    Runtime(#[source] io::Error),
+}
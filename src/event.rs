@@ -0,0 +1,122 @@
+//! Notable occurrences reported alongside the recognized text.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use slog_scope::{info, warn};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A region emitted more items than its quota allows, which usually
+    /// means the region is misconfigured or the screen layout changed.
+    EmissionQuotaExceeded {
+        date: DateTime<Utc>,
+        region_index: usize,
+        region_name: String,
+        item_count: usize,
+        muted: bool,
+    },
+    /// Frames between the two capture times were not processed.
+    FrameGap {
+        date: DateTime<Utc>,
+        previous_capture_time: DateTime<Utc>,
+        capture_time: DateTime<Utc>,
+    },
+    /// A region's recognition language was switched because another allowed
+    /// language recognized it with higher confidence.
+    LanguageSwitched {
+        date: DateTime<Utc>,
+        region_name: String,
+        previous_language: String,
+        next_language: String,
+        confidence: f32,
+    },
+    /// The options of a menu region or its selected option changed.
+    MenuSelection {
+        date: DateTime<Utc>,
+        region_name: String,
+        options: Vec<String>,
+        /// Index of the option the cursor is at, if the cursor was
+        /// recognized.
+        selected: Option<usize>,
+    },
+    /// The scene detector observed a transition, such as a battle starting.
+    SceneChanged {
+        date: DateTime<Utc>,
+        previous_scene: Option<String>,
+        next_scene: String,
+    },
+}
+
+impl Event {
+    pub fn log(&self) {
+        match self {
+            Event::EmissionQuotaExceeded {
+                date,
+                region_index,
+                region_name,
+                item_count,
+                muted,
+            } => {
+                warn!("emission quota exceeded";
+                    "date" => %date,
+                    "region_index" => region_index,
+                    "region_name" => region_name,
+                    "item_count" => item_count,
+                    "muted" => muted,
+                );
+            }
+            Event::FrameGap {
+                date,
+                previous_capture_time,
+                capture_time,
+            } => {
+                warn!("frame gap";
+                    "date" => %date,
+                    "previous_capture_time" => %previous_capture_time,
+                    "capture_time" => %capture_time,
+                    "gap_secs" => (*capture_time - *previous_capture_time).num_milliseconds() as f64 / 1000.0,
+                );
+            }
+            Event::LanguageSwitched {
+                date,
+                region_name,
+                previous_language,
+                next_language,
+                confidence,
+            } => {
+                info!("language switched";
+                    "date" => %date,
+                    "region_name" => region_name,
+                    "previous_language" => previous_language,
+                    "next_language" => next_language,
+                    "confidence" => confidence,
+                );
+            }
+            Event::MenuSelection {
+                date,
+                region_name,
+                options,
+                selected,
+            } => {
+                info!("menu selection";
+                    "date" => %date,
+                    "region_name" => region_name,
+                    "options" => ?options,
+                    "selected" => ?selected,
+                );
+            }
+            Event::SceneChanged {
+                date,
+                previous_scene,
+                next_scene,
+            } => {
+                info!("scene changed";
+                    "date" => %date,
+                    "previous_scene" => ?previous_scene,
+                    "next_scene" => next_scene,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Artificial failures for rehearsing how the pipeline degrades.
+
+use std::time::Duration;
+
+use rand::Rng;
+use slog_scope::debug;
+
+use crate::config::FaultInjectionConfig;
+
+/// Randomly decides when to inject faults according to the configured
+/// probabilities.
+#[derive(Clone)]
+pub struct FaultInjector {
+    config: FaultInjectionConfig,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultInjectionConfig) -> Self {
+        Self { config }
+    }
+
+    fn roll(&self, probability: f32) -> bool {
+        probability > 0.0 && rand::thread_rng().gen::<f32>() < probability
+    }
+
+    /// Returns a processing delay to sleep for, if one is injected.
+    pub fn delay(&self) -> Option<Duration> {
+        if self.roll(self.config.delay_probability) {
+            debug!("injecting delay"; "delay_ms" => self.config.delay_ms);
+            Some(Duration::from_millis(self.config.delay_ms))
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the current frame should be discarded.
+    pub fn drop_frame(&self) -> bool {
+        let value = self.roll(self.config.drop_frame_probability);
+
+        if value {
+            debug!("injecting dropped frame");
+        }
+
+        value
+    }
+
+    /// Returns how long a shared memory lock should be held, if contention
+    /// is injected.
+    pub fn lock_contention(&self) -> Option<Duration> {
+        if self.roll(self.config.lock_contention_probability) {
+            debug!("injecting lock contention"; "lock_hold_ms" => self.config.lock_hold_ms);
+            Some(Duration::from_millis(self.config.lock_hold_ms))
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether delivering items or an event to an output should
+    /// fail.
+    pub fn sink_failure(&self) -> bool {
+        let value = self.roll(self.config.sink_failure_probability);
+
+        if value {
+            debug!("injecting sink failure");
+        }
+
+        value
+    }
+}
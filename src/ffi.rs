@@ -0,0 +1,261 @@
+//! C interface to `Pipeline`, built with the `cdylib` feature.
+//!
+//! The functions are declared in `include/tppocr.h`. Results are returned as
+//! JSON strings owned by the caller, which frees them with
+//! `tppocr_string_free`. On failure, functions return NULL and
+//! `tppocr_last_error` describes the error of the calling thread.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+};
+
+use image::RgbaImage;
+use serde::Serialize;
+
+use crate::{
+    config::ProcessorConfig, event::Event, pipeline::Pipeline, text_processor::TextItem,
+    text_recognizer::TextRecognizerSet,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Items and events returned by the processing functions.
+#[derive(Serialize)]
+struct Results {
+    items: Vec<TextItem>,
+    events: Vec<Event>,
+}
+
+pub struct TppocrPipeline {
+    pipeline: Pipeline,
+    /// Whether a call panicked, leaving the pipeline in an unknown state.
+    poisoned: bool,
+}
+
+/// Creates a pipeline from TOML config text for frames of the size.
+///
+/// Returns NULL on error.
+///
+/// # Safety
+///
+/// The strings must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn tppocr_pipeline_new(
+    config: *const c_char,
+    tessdata_path: *const c_char,
+    language: *const c_char,
+    width: u32,
+    height: u32,
+) -> *mut TppocrPipeline {
+    call(|| {
+        let config = ProcessorConfig::parse(&string_arg(config)?, width, height)?;
+        let text_recognizers = TextRecognizerSet::new(
+            &string_arg(tessdata_path)?,
+            &string_arg(language)?,
+            config.tesseract_variables.clone(),
+        )?;
+        let pipeline = Pipeline::new(config, text_recognizers, width, height)?;
+
+        Ok(Box::into_raw(Box::new(TppocrPipeline {
+            pipeline,
+            poisoned: false,
+        })))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Frees the pipeline.
+///
+/// # Safety
+///
+/// The pointer must be NULL or returned by `tppocr_pipeline_new` and not
+/// freed before.
+#[no_mangle]
+pub unsafe extern "C" fn tppocr_pipeline_free(pipeline: *mut TppocrPipeline) {
+    if !pipeline.is_null() {
+        drop(Box::from_raw(pipeline));
+    }
+}
+
+/// Recognizes a frame of BGRA pixels, rows `stride` bytes apart, and returns
+/// the emitted items and events as JSON `{"items": [...], "events": [...]}`.
+///
+/// Returns NULL on error. After a panic, the pipeline only returns errors
+/// until it is freed.
+///
+/// # Safety
+///
+/// The pipeline must be valid, and the data must be `stride * height` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tppocr_pipeline_process_bgra(
+    pipeline: *mut TppocrPipeline,
+    data: *const u8,
+    stride: usize,
+    width: u32,
+    height: u32,
+) -> *mut c_char {
+    with_pipeline(pipeline, |pipeline| {
+        if data.is_null() || stride < width as usize * 4 {
+            anyhow::bail!("invalid frame data or stride {}", stride);
+        }
+
+        let length = stride
+            .checked_mul(height as usize)
+            .ok_or_else(|| anyhow::anyhow!("frame size overflows"))?;
+        let data = std::slice::from_raw_parts(data, length);
+        let items = pipeline.process_image(&bgra_to_image(data, stride, width, height))?;
+
+        results_json(pipeline, items)
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Returns the items still held by the strategies as if time had passed,
+/// in the same JSON as `tppocr_pipeline_process_bgra`.
+///
+/// # Safety
+///
+/// The pipeline must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn tppocr_pipeline_flush(pipeline: *mut TppocrPipeline) -> *mut c_char {
+    with_pipeline(pipeline, |pipeline| {
+        let items = pipeline.flush();
+
+        results_json(pipeline, items)
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Returns the message of the calling thread's last error, or NULL. The
+/// string is valid until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn tppocr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Frees a string returned by the library.
+///
+/// # Safety
+///
+/// The string must be NULL or returned by the library and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn tppocr_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Runs the function, storing its error or panic as the last error instead
+/// of unwinding into C.
+fn call<T>(function: impl FnOnce() -> anyhow::Result<T>) -> Option<T> {
+    let result = match panic::catch_unwind(AssertUnwindSafe(function)) {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("panic in tppocr")),
+    };
+
+    match result {
+        Ok(value) => {
+            set_last_error(None);
+            Some(value)
+        }
+        Err(error) => {
+            set_last_error(Some(format!("{:#}", error)));
+            None
+        }
+    }
+}
+
+/// Runs the function on the pipeline like `call`, poisoning the pipeline if
+/// the function panics.
+unsafe fn with_pipeline<T>(
+    pipeline: *mut TppocrPipeline,
+    function: impl FnOnce(&mut Pipeline) -> anyhow::Result<T>,
+) -> Option<T> {
+    call(|| {
+        let handle = pipeline
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("pipeline is NULL"))?;
+
+        if handle.poisoned {
+            anyhow::bail!("pipeline is unusable after a panic");
+        }
+
+        match panic::catch_unwind(AssertUnwindSafe(|| function(&mut handle.pipeline))) {
+            Ok(result) => result,
+            Err(_) => {
+                handle.poisoned = true;
+                anyhow::bail!("panic in tppocr");
+            }
+        }
+    })
+}
+
+fn set_last_error(message: Option<String>) {
+    let message = message.map(|message| CString::new(message.replace('\0', "")).unwrap());
+
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+unsafe fn string_arg(string: *const c_char) -> anyhow::Result<String> {
+    if string.is_null() {
+        anyhow::bail!("string argument is NULL");
+    }
+
+    Ok(CStr::from_ptr(string).to_str()?.to_string())
+}
+
+fn results_json(pipeline: &mut Pipeline, items: Vec<TextItem>) -> anyhow::Result<*mut c_char> {
+    let json = serde_json::to_string(&Results {
+        items,
+        events: pipeline.take_events(),
+    })?;
+
+    Ok(CString::new(json)?.into_raw())
+}
+
+fn bgra_to_image(data: &[u8], stride: usize, width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let index = y as usize * stride + x as usize * 4;
+        let pixel = &data[index..index + 4];
+
+        image::Rgba([pixel[2], pixel[1], pixel[0], pixel[3]])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bgra_to_image() {
+        // 1x2 frame with padding at the end of each row
+        let data = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+        let image = bgra_to_image(&data, 6, 1, 2);
+
+        assert_eq!(image.get_pixel(0, 0).0, [3, 2, 1, 4]);
+        assert_eq!(image.get_pixel(0, 1).0, [7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = CString::new("[[region]").unwrap();
+        let text = CString::new("").unwrap();
+        let pipeline =
+            unsafe { tppocr_pipeline_new(config.as_ptr(), text.as_ptr(), text.as_ptr(), 64, 64) };
+
+        assert!(pipeline.is_null());
+
+        let message = unsafe { CStr::from_ptr(tppocr_last_error()) };
+        assert!(message.to_str().unwrap().contains("failed to parse config"));
+    }
+}
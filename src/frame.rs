@@ -1,65 +1,235 @@
 use std::{
     path::PathBuf,
+    str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use anyhow::Context;
-use ffmpeg_next::{decoder::Video, format::Pixel, frame, media::Type, software::scaling};
+use chrono::{DateTime, Utc};
+use ffmpeg_next::{
+    format::{self, Pixel},
+    frame,
+    media::Type,
+    software::scaling,
+    Dictionary,
+};
+use rand::Rng;
 use slog_scope::{info, warn};
 
 use crate::{
-    message_socket::{MessageClient, MessageServer},
-    shared_memory::SharedMemory,
+    config::CatchUpPolicy,
+    error::FrameError,
+    frame_ring::FrameRing,
+    frame_transport::{FrameReceiver, FrameSender},
+    recording::{RecordingConfig, SegmentRecorder},
 };
 
 const BYTES_PER_PIXEL: u32 = 4;
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// Presentation time discontinuities longer than this restart pacing instead
+/// of sleeping.
+const MAX_PACING_SLEEP: Duration = Duration::from_secs(1);
+
+/// Name of ffmpeg's Video4Linux2 capture device demuxer.
+const V4L2_FORMAT_NAME: &str = "v4l2";
+
+/// Flag of `av_frame_apply_cropping` to crop exactly instead of keeping the
+/// data pointers aligned.
+const AV_FRAME_CROP_UNALIGNED: i32 = 1;
+
+/// Number of frame slots in shared memory by default.
+pub const DEFAULT_SLOT_COUNT: u32 = 4;
+
+/// Returns the URL to open, resolved again for each connection since
+/// resolved stream URLs may expire.
+pub type UrlResolver = Box<dyn FnMut() -> Result<String, FrameError>>;
+
+/// Rectangle of the input kept before scaling to the output size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    /// Returns the part of the rectangle within an input of the given size,
+    /// or None if they don't intersect.
+    pub fn clamp(&self, input_width: u32, input_height: u32) -> Option<CropRect> {
+        let x2 = self.x.saturating_add(self.width).min(input_width);
+        let y2 = self.y.saturating_add(self.height).min(input_height);
+
+        if self.x >= x2 || self.y >= y2 {
+            return None;
+        }
+
+        Some(CropRect {
+            x: self.x,
+            y: self.y,
+            width: x2 - self.x,
+            height: y2 - self.y,
+        })
+    }
+}
+
+impl FromStr for CropRect {
+    type Err = String;
+
+    /// Parses `X,Y,WIDTH,HEIGHT` in input pixels.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let numbers = text
+            .split(',')
+            .map(|number| number.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid crop rectangle {:?}", text))?;
+
+        match numbers.as_slice() {
+            [x, y, width, height] if *width > 0 && *height > 0 => Ok(CropRect {
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+            }),
+            _ => Err(format!(
+                "expected crop rectangle as X,Y,WIDTH,HEIGHT, got {:?}",
+                text
+            )),
+        }
+    }
+}
+
+/// Delays between reconnection attempts after the input fails or ends.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Consecutive attempts without receiving a frame before giving up.
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before the attempt, numbered from 1, without
+    /// jitter.
+    ///
+    /// The delay doubles each attempt up to the maximum.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1).min(31));
+
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+/// How the dumper timestamps the frames it publishes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameTimestamps {
+    /// Wall clock time the frame is published, which lags the stream by the
+    /// decoding and buffering delay.
+    WallClock,
+    /// Stream start plus the frame's presentation time, so results line up
+    /// with a recording of the stream.
+    ///
+    /// Without a start, the start is the wall clock time of a connection's
+    /// first frame minus its presentation time.
+    Presentation { stream_start: Option<DateTime<Utc>> },
+}
+
+impl Default for FrameTimestamps {
+    fn default() -> Self {
+        Self::WallClock
+    }
+}
 
 pub struct FrameDumper {
     url: String,
     output_width: u32,
     output_height: u32,
-    shared_memory: SharedMemory,
-    message_server: MessageServer,
+    frame_ring: FrameRing,
+    frame_sender: Option<FrameSender>,
     previous_presentation_time: f64,
+    timestamps: FrameTimestamps,
+    /// Time of presentation time 0 for the current connection.
+    presentation_origin: Option<DateTime<Utc>>,
+    /// Wall clock time and presentation time of the frame pacing started at.
+    pacing_start: Option<(Instant, f64)>,
     decoded_frame: frame::video::Video,
     rgb_frame: frame::video::Video,
     infinite_loop: bool,
     skip_sleep: bool,
+    url_resolver: Option<UrlResolver>,
+    reconnect_policy: Option<ReconnectPolicy>,
+    device_options: Vec<(String, String)>,
+    input_options: Vec<(String, String)>,
+    crop: Option<CropRect>,
+    recording: Option<RecordingConfig>,
+    published_frame_count: u64,
 }
 
 impl FrameDumper {
+    /// Creates a dumper writing frames to a ring of `slot_count` slots in
+    /// shared memory.
     pub fn new(
         url: String,
         output_port: u16,
         output_width: u32,
         output_height: u32,
-    ) -> anyhow::Result<Self> {
+        slot_count: u32,
+    ) -> Result<Self, FrameError> {
         let data_size = (output_width * output_height * BYTES_PER_PIXEL) as usize;
 
-        let shared_memory = SharedMemory::open_or_create(output_port as u32, data_size)?;
+        let frame_ring = FrameRing::create(output_port as u32, data_size, slot_count)?;
         // Coordinating process should unlink the shared memory
 
-        let message_server = MessageServer::open(output_port as u32)?;
-        message_server.set_nonblocking(true)?;
-
         Ok(Self {
             url,
             output_width,
             output_height,
-            shared_memory,
-            message_server,
+            frame_ring,
+            frame_sender: None,
             previous_presentation_time: 0.0,
+            timestamps: FrameTimestamps::default(),
+            presentation_origin: None,
+            pacing_start: None,
             decoded_frame: frame::video::Video::empty(),
             rgb_frame: frame::video::Video::empty(),
             infinite_loop: false,
             skip_sleep: false,
+            url_resolver: None,
+            reconnect_policy: None,
+            device_options: Vec::new(),
+            input_options: Vec::new(),
+            crop: None,
+            recording: None,
+            published_frame_count: 0,
         })
     }
 
+    pub fn frame_sender(&self) -> Option<&FrameSender> {
+        self.frame_sender.as_ref()
+    }
+
+    /// Sets the sender of frames to processors on other hosts, in addition
+    /// to shared memory.
+    pub fn set_frame_sender(&mut self, value: Option<FrameSender>) {
+        self.frame_sender = value;
+    }
+
     pub fn infinite_loop(&self) -> bool {
         self.infinite_loop
     }
@@ -68,6 +238,71 @@ impl FrameDumper {
         self.infinite_loop = value;
     }
 
+    /// Sets the function returning the URL for each connection instead of
+    /// the URL given at construction.
+    pub fn set_url_resolver(&mut self, value: Option<UrlResolver>) {
+        self.url_resolver = value;
+    }
+
+    pub fn reconnect_policy(&self) -> Option<&ReconnectPolicy> {
+        self.reconnect_policy.as_ref()
+    }
+
+    /// Sets reconnecting when the input fails or ends, instead of returning.
+    pub fn set_reconnect_policy(&mut self, value: Option<ReconnectPolicy>) {
+        self.reconnect_policy = value;
+    }
+
+    pub fn device_options(&self) -> &[(String, String)] {
+        &self.device_options
+    }
+
+    /// Sets ffmpeg options for opening a capture device, such as
+    /// `video_size`, `framerate`, and `input_format`.
+    pub fn set_device_options(&mut self, value: Vec<(String, String)>) {
+        self.device_options = value;
+    }
+
+    pub fn input_options(&self) -> &[(String, String)] {
+        &self.input_options
+    }
+
+    /// Sets ffmpeg options for opening a URL that isn't a capture device,
+    /// such as `http_proxy`, `cookies`, and `headers`.
+    pub fn set_input_options(&mut self, value: Vec<(String, String)>) {
+        self.input_options = value;
+    }
+
+    pub fn crop(&self) -> Option<CropRect> {
+        self.crop
+    }
+
+    /// Sets the rectangle of the decoded frames, in input pixels, that is
+    /// scaled to the output size.
+    ///
+    /// Only the rectangle is converted and published, so the output size
+    /// can be reduced to the rectangle's instead of the whole frame's.
+    pub fn set_crop(&mut self, value: Option<CropRect>) {
+        self.crop = value;
+    }
+
+    pub fn timestamps(&self) -> FrameTimestamps {
+        self.timestamps
+    }
+
+    pub fn set_timestamps(&mut self, value: FrameTimestamps) {
+        self.timestamps = value;
+    }
+
+    pub fn recording(&self) -> Option<&RecordingConfig> {
+        self.recording.as_ref()
+    }
+
+    /// Sets recording the input to segment files while it is decoded.
+    pub fn set_recording(&mut self, value: Option<RecordingConfig>) {
+        self.recording = value;
+    }
+
     pub fn skip_sleep(&self) -> bool {
         self.skip_sleep
     }
@@ -76,68 +311,243 @@ impl FrameDumper {
         self.skip_sleep = value;
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        let mut input = ffmpeg_next::format::input(&PathBuf::from(&self.url))?;
+    pub fn run(&mut self) -> Result<(), FrameError> {
+        let terminate_flag = Arc::new(AtomicBool::new(false));
+        for sig in signal_hook::consts::TERM_SIGNALS {
+            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            let frame_count = self.published_frame_count;
+            let result = self.run_input(&terminate_flag);
+
+            if terminate_flag.load(Ordering::Relaxed) {
+                return result;
+            }
+
+            let policy = match &self.reconnect_policy {
+                Some(policy) => policy.clone(),
+                None => return result,
+            };
+
+            // The budget is for consecutive failures, so it is renewed once
+            // a connection delivers frames
+            if self.published_frame_count > frame_count {
+                attempt = 0;
+            }
+
+            attempt += 1;
+
+            if attempt > policy.max_attempts {
+                warn!("giving up reconnecting"; "attempts" => policy.max_attempts);
+                return result;
+            }
+
+            let delay = policy
+                .delay(attempt)
+                .mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+
+            match &result {
+                Ok(()) => warn!("input ended, reconnecting";
+                    "attempt" => attempt, "delay" => ?delay),
+                Err(error) => warn!("input failed, reconnecting";
+                    "attempt" => attempt, "delay" => ?delay, "error" => %error),
+            }
+
+            let deadline = Instant::now() + delay;
+
+            while Instant::now() < deadline {
+                if terminate_flag.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+
+    fn run_input(&mut self, terminate_flag: &AtomicBool) -> Result<(), FrameError> {
+        let url = match &mut self.url_resolver {
+            Some(url_resolver) => url_resolver()?,
+            None => self.url.clone(),
+        };
+        let mut input = self.open_input(&url)?;
         let video_stream = input
             .streams()
             .best(Type::Video)
-            .ok_or(ffmpeg_next::Error::StreamNotFound)?;
+            .ok_or(FrameError::StreamNotFound)?;
         let video_stream_index = video_stream.index();
         let time_base = video_stream.time_base().numerator() as f64
             / video_stream.time_base().denominator() as f64;
 
         let mut decoder = video_stream.codec().decoder().video()?;
-        let mut scaler = self.make_scaler(&decoder)?;
+        let mut scaler: Option<scaling::context::Context> = None;
+        let mut recorder = self.start_recording(&input)?;
 
-        info!("loop start");
+        self.previous_presentation_time = 0.0;
+        self.presentation_origin = None;
+        self.pacing_start = None;
 
-        let terminate_flag = Arc::new(AtomicBool::new(false));
-        for sig in signal_hook::consts::TERM_SIGNALS {
-            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
-        }
+        info!("loop start");
 
         loop {
             for (stream, packet) in input.packets() {
+                if let Some(current_recorder) = &mut recorder {
+                    // Losing the recording shouldn't interrupt OCR
+                    if let Err(error) = current_recorder.write(&stream, &packet) {
+                        warn!("recording failed, stopping recording"; "error" => %error);
+                        recorder = None;
+                    }
+                }
+
                 if stream.index() == video_stream_index {
                     decoder.send_packet(&packet)?;
 
                     while let Ok(true) = Self::process_receive_frame_result(
                         decoder.receive_frame(&mut self.decoded_frame),
                     ) {
-                        if self.has_frame_format_changed(&scaler) {
-                            warn!("frame format changed");
-                            scaler = self.make_scaler(&decoder)?;
+                        self.apply_crop()?;
+
+                        match &scaler {
+                            Some(current) if !self.has_frame_format_changed(current) => {}
+                            Some(_) => {
+                                warn!("frame format changed");
+                                scaler = Some(self.make_scaler()?);
+                            }
+                            None => scaler = Some(self.make_scaler()?),
                         }
 
-                        self.process_frame(&mut scaler, time_base)?;
+                        if let Some(scaler) = &mut scaler {
+                            self.process_frame(scaler, time_base)?;
+                        }
                     }
                 }
 
                 if terminate_flag.load(Ordering::Relaxed) {
                     info!("stopping");
-                    self.infinite_loop = false;
                     break;
                 }
             }
 
-            if self.infinite_loop {
+            if self.infinite_loop && !terminate_flag.load(Ordering::Relaxed) {
                 input.seek(0, 0..0)?;
+
+                // Timestamps restart with the input, so a new segment is
+                // started instead of continuing the current one
+                Self::finish_recording(recorder.take());
+                recorder = self.start_recording(&input)?;
+
                 self.previous_presentation_time = 0.0;
+                self.presentation_origin = None;
+                self.pacing_start = None;
             } else {
                 break;
             }
         }
 
+        Self::finish_recording(recorder);
+
         info!("loop stop");
 
         Ok(())
     }
 
-    fn make_scaler(&self, decoder: &Video) -> anyhow::Result<scaling::context::Context> {
+    fn start_recording(
+        &self,
+        input: &format::context::Input,
+    ) -> Result<Option<SegmentRecorder>, FrameError> {
+        match &self.recording {
+            Some(config) => Ok(Some(SegmentRecorder::new(config, input)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn finish_recording(recorder: Option<SegmentRecorder>) {
+        if let Some(recorder) = recorder {
+            if let Err(error) = recorder.finish() {
+                warn!("failed to finish recording"; "error" => %error);
+            }
+        }
+    }
+
+    fn open_input(&self, url: &str) -> Result<format::context::Input, FrameError> {
+        if !is_capture_device(url) {
+            if self.input_options.is_empty() {
+                return Ok(format::input(&PathBuf::from(url))?);
+            }
+
+            let mut options = Dictionary::new();
+
+            for (key, value) in &self.input_options {
+                options.set(key, value);
+            }
+
+            return Ok(format::input_with_dictionary(&PathBuf::from(url), options)?);
+        }
+
+        // Capture devices aren't probed by default, so the demuxer is given
+        // explicitly
+        let device_format = ffmpeg_next::device::input::video()
+            .find(|device_format| {
+                device_format
+                    .name()
+                    .split(',')
+                    .any(|name| name == V4L2_FORMAT_NAME)
+            })
+            .ok_or(FrameError::CaptureUnsupported)?;
+        let mut options = Dictionary::new();
+
+        for (key, value) in &self.device_options {
+            options.set(key, value);
+        }
+
+        info!("opening capture device"; "device" => url, "options" => ?self.device_options);
+
+        Ok(format::open_with(
+            &PathBuf::from(url),
+            &format::format::Format::Input(device_format),
+            options,
+        )?
+        .input())
+    }
+
+    /// Crops the decoded frame in place by offsetting its data pointers, so
+    /// no pixels are copied.
+    fn apply_crop(&mut self) -> Result<(), FrameError> {
+        let crop = match self.crop {
+            Some(crop) => crop,
+            None => return Ok(()),
+        };
+        let width = self.decoded_frame.width();
+        let height = self.decoded_frame.height();
+        let rect = crop
+            .clamp(width, height)
+            .ok_or(FrameError::CropOutside { crop })?;
+
+        unsafe {
+            let frame = self.decoded_frame.as_mut_ptr();
+            (*frame).crop_left = rect.x as usize;
+            (*frame).crop_top = rect.y as usize;
+            (*frame).crop_right = (width - rect.x - rect.width) as usize;
+            (*frame).crop_bottom = (height - rect.y - rect.height) as usize;
+
+            if ffmpeg_next::ffi::av_frame_apply_cropping(frame, AV_FRAME_CROP_UNALIGNED) < 0 {
+                return Err(FrameError::CropOutside { crop });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn make_scaler(&self) -> Result<scaling::context::Context, FrameError> {
+        // Made from the decoded frame instead of the decoder since the frame
+        // may be cropped
         Ok(scaling::context::Context::get(
-            decoder.format(),
-            decoder.width(),
-            decoder.height(),
+            self.decoded_frame.format(),
+            self.decoded_frame.width(),
+            self.decoded_frame.height(),
             Pixel::RGBA,
             self.output_width,
             self.output_height,
@@ -147,7 +557,7 @@ impl FrameDumper {
 
     fn process_receive_frame_result(
         result: Result<(), ffmpeg_next::Error>,
-    ) -> anyhow::Result<bool> {
+    ) -> Result<bool, FrameError> {
         match result {
             Ok(()) => Ok(true),
             Err(error) => match error {
@@ -173,66 +583,145 @@ impl FrameDumper {
         &mut self,
         scaler: &mut scaling::context::Context,
         time_base: f64,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), FrameError> {
         let presentation_time = self.decoded_frame.pts().unwrap() as f64 * time_base;
-        let mut message_buffer: [u8; 0] = [0; 0];
 
-        if presentation_time - self.previous_presentation_time > 0.1
-            || self.previous_presentation_time == 0.0
-        {
-            let receive_result = self.message_server.receive(&mut message_buffer);
+        let sample_due = presentation_time - self.previous_presentation_time > 0.1
+            || self.previous_presentation_time == 0.0;
 
-            if receive_result.is_err() {
-                return Ok(());
+        if sample_due {
+            if !self.skip_sleep {
+                self.pace(presentation_time);
             }
 
-            let (_message_size, client_name) = receive_result.unwrap();
-
             scaler.run(&self.decoded_frame, &mut self.rgb_frame)?;
+            let capture_time = self.frame_timestamp(presentation_time);
+            self.frame_ring
+                .publish(self.rgb_frame.data(0), &capture_time);
+
+            if let Some(frame_sender) = &mut self.frame_sender {
+                frame_sender.publish(
+                    &self.rgb_frame.data(0)[..self.frame_ring.frame_size()],
+                    self.output_width,
+                    self.output_height,
+                    &capture_time,
+                );
+            }
+            self.previous_presentation_time = presentation_time;
+            self.published_frame_count += 1;
+        }
 
-            self.shared_memory
-                .data_mut()
-                .copy_from_slice(self.rgb_frame.data(0));
+        Ok(())
+    }
 
-            self.previous_presentation_time = presentation_time;
+    /// Returns the timestamp of a frame published now with the presentation
+    /// time in seconds.
+    fn frame_timestamp(&mut self, presentation_time: f64) -> DateTime<Utc> {
+        let presentation_offset =
+            chrono::Duration::microseconds((presentation_time * 1_000_000.0) as i64);
 
-            let _ = self.message_server.send(&message_buffer, &client_name);
-            // discard error because the client may have disconnected
+        match self.timestamps {
+            FrameTimestamps::WallClock => Utc::now(),
+            FrameTimestamps::Presentation { stream_start } => {
+                let origin = *self.presentation_origin.get_or_insert_with(|| {
+                    stream_start.unwrap_or_else(|| Utc::now() - presentation_offset)
+                });
 
-            if !self.skip_sleep {
-                std::thread::sleep(Duration::from_secs_f32(0.1));
+                origin + presentation_offset
             }
         }
+    }
 
-        Ok(())
+    /// Sleeps until the frame's presentation time so inputs such as files are
+    /// not read faster than real time.
+    fn pace(&mut self, presentation_time: f64) {
+        let now = Instant::now();
+
+        if let Some((start_instant, start_presentation_time)) = self.pacing_start {
+            let offset =
+                Duration::from_secs_f64((presentation_time - start_presentation_time).max(0.0));
+            let due = start_instant + offset;
+
+            if due > now && due - now <= MAX_PACING_SLEEP {
+                std::thread::sleep(due - now);
+                return;
+            } else if due + MAX_PACING_SLEEP >= now {
+                return;
+            }
+        }
+
+        self.pacing_start = Some((now, presentation_time));
     }
 }
 
+/// Returns whether the input is a Video4Linux2 device such as a capture
+/// card, rather than a URL or file for ffmpeg to probe.
+///
+/// Devices are `/dev/videoN` or their persistent links in `/dev/v4l/`.
+pub fn is_capture_device(input: &str) -> bool {
+    let is_video_node = input.strip_prefix("/dev/video").map_or(false, |number| {
+        !number.is_empty() && number.chars().all(|character| character.is_ascii_digit())
+    });
+
+    is_video_node || input.starts_with("/dev/v4l/")
+}
+
+enum FrameSource {
+    Ring {
+        frame_ring: FrameRing,
+        next_frame_number: u64,
+    },
+    Tcp(FrameReceiver),
+}
+
 pub struct FrameReader {
     width: u32,
     height: u32,
-    shared_memory: SharedMemory,
-    message_client: MessageClient,
+    source: FrameSource,
+    buffer: Vec<u32>,
+    capture_time: Option<DateTime<Utc>>,
+    catch_up_policy: CatchUpPolicy,
 }
 
 impl FrameReader {
-    pub fn new(port: u16, width: u32, height: u32) -> anyhow::Result<Self> {
-        let data_size = (width * height * BYTES_PER_PIXEL) as usize;
+    /// Opens the stream dumper's ring in shared memory.
+    pub fn new(port: u16, width: u32, height: u32) -> Result<Self, FrameError> {
+        let frame_ring = FrameRing::open(port as u32)?;
+
+        if frame_ring.frame_size() != (width * height * BYTES_PER_PIXEL) as usize {
+            return Err(FrameError::FrameSize {
+                width,
+                height,
+                frame_size: frame_ring.frame_size(),
+            });
+        }
 
-        let shared_memory = SharedMemory::open_or_create(port as u32, data_size)?;
+        Ok(Self::with_source(
+            FrameSource::Ring {
+                frame_ring,
+                next_frame_number: 0,
+            },
+            width,
+            height,
+        ))
+    }
 
-        let message_client = MessageClient::open(port as u32)?;
-        message_client.set_timeout(Some(Duration::from_secs(2)))?;
+    /// Connects to a stream dumper's frame sender, possibly on another host.
+    pub fn connect(address: &str, width: u32, height: u32) -> Result<Self, FrameError> {
+        let receiver = FrameReceiver::connect(address, width, height, READ_TIMEOUT)?;
 
-        let mut buffer = Vec::new();
-        buffer.resize(data_size, 0);
+        Ok(Self::with_source(FrameSource::Tcp(receiver), width, height))
+    }
 
-        Ok(Self {
+    fn with_source(source: FrameSource, width: u32, height: u32) -> Self {
+        Self {
             width,
             height,
-            shared_memory,
-            message_client,
-        })
+            source,
+            buffer: vec![0; (width * height) as usize],
+            capture_time: None,
+            catch_up_policy: CatchUpPolicy::JumpToLive,
+        }
     }
 
     pub fn width(&self) -> u32 {
@@ -244,20 +733,230 @@ impl FrameReader {
     }
 
     pub fn data(&self) -> &[u8] {
-        self.shared_memory.data()
+        unsafe {
+            std::slice::from_raw_parts(self.buffer.as_ptr() as *const u8, self.buffer.len() * 4)
+        }
     }
 
     pub fn data_u32(&self) -> &[u32] {
-        self.shared_memory.data_32()
+        &self.buffer
     }
 
-    pub fn read(&mut self) -> anyhow::Result<()> {
-        let mut message_buffer = [0u8; 0];
-        self.message_client.send(&message_buffer)?;
-        self.message_client
-            .receive(&mut message_buffer)
-            .with_context(|| "Disconnected or error sending message to message server")?;
+    /// Returns when the stream dumper decoded the current frame.
+    pub fn capture_time(&self) -> Option<DateTime<Utc>> {
+        self.capture_time
+    }
+
+    pub fn catch_up_policy(&self) -> &CatchUpPolicy {
+        &self.catch_up_policy
+    }
+
+    /// Sets which frame is read after the reader was busy.
+    ///
+    /// With a backlog, frames are read in order as long as they are in the
+    /// stream dumper's ring, so the backlog is limited by its slot count.
+    /// Over TCP, frames are always read in order and the sender drops frames
+    /// when its queue of slot count frames is full; jumping to live skips the
+    /// frames that already arrived.
+    pub fn set_catch_up_policy(&mut self, value: CatchUpPolicy) {
+        self.catch_up_policy = value;
+    }
+
+    /// Waits for a frame newer than the current frame and copies it.
+    pub fn read(&mut self) -> Result<(), FrameError> {
+        let buffer = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.buffer.as_mut_ptr() as *mut u8,
+                self.buffer.len() * 4,
+            )
+        };
+
+        let capture_time = match &mut self.source {
+            FrameSource::Ring {
+                frame_ring,
+                next_frame_number,
+            } => read_ring(frame_ring, next_frame_number, &self.catch_up_policy, buffer)?,
+            FrameSource::Tcp(receiver) => {
+                let mut capture_time = receiver.receive(buffer)?;
+
+                if self.catch_up_policy == CatchUpPolicy::JumpToLive {
+                    while receiver.has_pending() {
+                        capture_time = receiver.receive(buffer)?;
+                    }
+                }
+
+                capture_time
+            }
+        };
+
+        self.capture_time = Some(capture_time);
 
         Ok(())
     }
+
+    /// Returns a copy of the current frame.
+    pub fn frame(&self) -> Frame {
+        Frame {
+            width: self.width,
+            height: self.height,
+            data: self.buffer.clone(),
+            capture_time: self.capture_time,
+        }
+    }
+}
+
+/// A frame copied out of a reader, so it can be recognized on other threads
+/// while the reader waits for the next frame.
+#[derive(Clone)]
+pub struct Frame {
+    width: u32,
+    height: u32,
+    data: Vec<u32>,
+    capture_time: Option<DateTime<Utc>>,
+}
+
+impl Frame {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr() as *const u8, self.data.len() * 4) }
+    }
+
+    pub fn data_u32(&self) -> &[u32] {
+        &self.data
+    }
+
+    /// Returns when the stream dumper decoded the frame.
+    pub fn capture_time(&self) -> Option<DateTime<Utc>> {
+        self.capture_time
+    }
+}
+
+fn read_ring(
+    frame_ring: &FrameRing,
+    next_frame_number: &mut u64,
+    catch_up_policy: &CatchUpPolicy,
+    buffer: &mut [u8],
+) -> Result<DateTime<Utc>, FrameError> {
+    loop {
+        let write_count = frame_ring.write_count();
+
+        if write_count < *next_frame_number {
+            // The writer restarted and counts from zero again
+            warn!("frame ring restarted, resynchronizing";
+                "write_count" => write_count, "next_frame_number" => *next_frame_number);
+            *next_frame_number = write_count.saturating_sub(1);
+
+            continue;
+        }
+
+        if write_count <= *next_frame_number {
+            if !frame_ring.wait(READ_TIMEOUT) {
+                return Err(FrameError::Timeout(READ_TIMEOUT));
+            }
+
+            continue;
+        }
+
+        let frame_number = match catch_up_policy {
+            CatchUpPolicy::JumpToLive => write_count - 1,
+            CatchUpPolicy::Backlog { max_frames } => {
+                let frame_number = (*next_frame_number)
+                    .max(write_count.saturating_sub((*max_frames).max(1) as u64))
+                    .max(frame_ring.oldest_readable());
+
+                if frame_number > *next_frame_number && *next_frame_number > 0 {
+                    warn!("backlog full, skipping frames";
+                        "count" => frame_number - *next_frame_number);
+                }
+
+                frame_number
+            }
+        };
+
+        // None if the frame was overwritten while copying
+        if let Some(capture_time) = frame_ring.read(frame_number, buffer) {
+            *next_frame_number = frame_number + 1;
+
+            return Ok(capture_time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+            max_attempts: 5,
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(4), Duration::from_secs(16));
+        assert_eq!(policy.delay(5), Duration::from_secs(30));
+        assert_eq!(policy.delay(100), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_crop_rect() {
+        let crop: CropRect = "0, 480, 1280, 240".parse().unwrap();
+
+        assert_eq!(
+            crop,
+            CropRect {
+                x: 0,
+                y: 480,
+                width: 1280,
+                height: 240
+            }
+        );
+        assert!("0,480,1280".parse::<CropRect>().is_err());
+        assert!("0,480,0,240".parse::<CropRect>().is_err());
+        assert_eq!(
+            crop.clamp(1000, 600),
+            Some(CropRect {
+                x: 0,
+                y: 480,
+                width: 1000,
+                height: 120
+            })
+        );
+        assert_eq!(crop.clamp(1280, 480), None);
+        assert_eq!(
+            CropRect {
+                x: 100,
+                y: 0,
+                width: u32::MAX,
+                height: 10
+            }
+            .clamp(1280, 720),
+            Some(CropRect {
+                x: 100,
+                y: 0,
+                width: 1180,
+                height: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_is_capture_device() {
+        assert!(is_capture_device("/dev/video0"));
+        assert!(is_capture_device("/dev/video12"));
+        assert!(is_capture_device("/dev/v4l/by-id/usb-capture-video-index0"));
+        assert!(!is_capture_device("/dev/video"));
+        assert!(!is_capture_device("/dev/videos/stream.mp4"));
+        assert!(!is_capture_device("https://example.com/dev/video0"));
+    }
 }
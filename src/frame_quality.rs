@@ -0,0 +1,154 @@
+//! Skipping frames mangled by stream compression.
+//!
+//! Right after a scene cut, the encoder often runs out of bitrate and the
+//! frame shows 8x8 compression blocks until the next keyframe or a few
+//! frames later. Such frames are the worst input for recognition.
+
+use slog_scope::debug;
+
+use crate::config::FrameQualityConfig;
+
+const BLOCK_SIZE: u32 = 8;
+
+/// Returns how pronounced the compression block edges of a frame are.
+///
+/// The value is the ratio of the mean luma difference between neighboring
+/// pixels across 8x8 block boundaries to the difference within blocks. It is
+/// around 1.0 for clean frames and grows as blocks become visible.
+pub fn blockiness(frame_data: &[u32], frame_width: u32, frame_height: u32) -> f32 {
+    let luma = |pixel: u32| {
+        let channel_1 = pixel & 0xff;
+        let channel_2 = (pixel >> 8) & 0xff;
+        let channel_3 = (pixel >> 16) & 0xff;
+
+        (channel_1 + 2 * channel_2 + channel_3) / 4
+    };
+    let mut boundary_sum = 0u64;
+    let mut boundary_count = 0u64;
+    let mut inner_sum = 0u64;
+    let mut inner_count = 0u64;
+
+    for y in 0..frame_height {
+        for x in 0..frame_width {
+            let index = (y * frame_width + x) as usize;
+            let value = luma(frame_data[index]);
+
+            if x + 1 < frame_width {
+                let difference = abs_difference(value, luma(frame_data[index + 1]));
+
+                if x % BLOCK_SIZE == BLOCK_SIZE - 1 {
+                    boundary_sum += difference;
+                    boundary_count += 1;
+                } else {
+                    inner_sum += difference;
+                    inner_count += 1;
+                }
+            }
+
+            if y + 1 < frame_height {
+                let difference =
+                    abs_difference(value, luma(frame_data[index + frame_width as usize]));
+
+                if y % BLOCK_SIZE == BLOCK_SIZE - 1 {
+                    boundary_sum += difference;
+                    boundary_count += 1;
+                } else {
+                    inner_sum += difference;
+                    inner_count += 1;
+                }
+            }
+        }
+    }
+
+    if boundary_count == 0 || inner_count == 0 {
+        return 1.0;
+    }
+
+    // Offset so flat frames, such as a black screen, are not counted as blocky
+    let boundary_mean = boundary_sum as f32 / boundary_count as f32 + 1.0;
+    let inner_mean = inner_sum as f32 / inner_count as f32 + 1.0;
+
+    boundary_mean / inner_mean
+}
+
+fn abs_difference(a: u32, b: u32) -> u64 {
+    (a.max(b) - a.min(b)) as u64
+}
+
+/// Decides whether a frame is clean enough to recognize.
+pub struct FrameQualityGate {
+    config: FrameQualityConfig,
+    skipped_frames: u32,
+}
+
+impl FrameQualityGate {
+    pub fn new(config: FrameQualityConfig) -> Self {
+        Self {
+            config,
+            skipped_frames: 0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: FrameQualityConfig) {
+        self.config = config;
+    }
+
+    /// Returns whether the frame should be recognized.
+    ///
+    /// After `max_skipped_frames` consecutive bad frames, a frame is
+    /// recognized regardless so a consistently poor stream is still
+    /// processed.
+    pub fn check(&mut self, frame_data: &[u32], frame_width: u32, frame_height: u32) -> bool {
+        let max_blockiness = match self.config.max_blockiness {
+            Some(value) => value,
+            None => return true,
+        };
+
+        if self.skipped_frames >= self.config.max_skipped_frames {
+            self.skipped_frames = 0;
+            return true;
+        }
+
+        let value = blockiness(frame_data, frame_width, frame_height);
+
+        if value > max_blockiness {
+            debug!("skipping blocky frame"; "blockiness" => value);
+            self.skipped_frames += 1;
+            false
+        } else {
+            self.skipped_frames = 0;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blockiness() {
+        let width = 64;
+        let height = 64;
+        let gradient: Vec<u32> = (0..width * height)
+            .map(|index| {
+                let value = (index % width) * 4;
+                value | value << 8 | value << 16
+            })
+            .collect();
+        // Same gradient quantized to a flat value per block
+        let blocky: Vec<u32> = (0..width * height)
+            .map(|index| {
+                let value = (index % width) / BLOCK_SIZE * BLOCK_SIZE * 4;
+                value | value << 8 | value << 16
+            })
+            .collect();
+
+        let clean_value = blockiness(&gradient, width, height);
+        let blocky_value = blockiness(&blocky, width, height);
+
+        assert!(clean_value < 1.5, "{}", clean_value);
+        assert!(blocky_value > 3.0, "{}", blocky_value);
+        assert!((blockiness(&vec![0; 64 * 64], width, height) - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,228 @@
+//! Full intermediate state of processing one frame, dumped as JSON for
+//! external visualization and tuning tools.
+//!
+//! Unlike emitted text items, a frame result includes every region's raw
+//! text and boxes and why a region was or wasn't recognized.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use slog_scope::{info, warn};
+
+use crate::{
+    config::{FrameDumpConfig, FrameDumpOutput},
+    text_recognizer::BoundingBox,
+};
+
+/// Writes to a connected tool taking longer than this disconnect it.
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FrameResult {
+    pub frame_counter: u64,
+    pub date: DateTime<Utc>,
+    pub capture_time: Option<DateTime<Utc>>,
+    pub scene: Option<String>,
+    /// False when the frame quality gate rejected the frame, in which case
+    /// no region was processed.
+    pub quality_passed: bool,
+    /// Wall time of recognizing all regions.
+    pub recognition_ms: f64,
+    pub regions: Vec<RegionResult>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RegionResult {
+    pub name: String,
+    pub gate: RegionGate,
+    /// Time spent recognizing the region, including preprocessing.
+    pub recognition_ms: f64,
+    /// Latest recognized text, which is from an earlier frame unless the
+    /// region was recognized.
+    pub text: String,
+    pub word_boxes: Vec<BoundingBox>,
+    pub language: Option<String>,
+    pub perceptual_hash: Option<u64>,
+    /// Items emitted by the region's strategy this frame.
+    pub item_count: usize,
+    /// hOCR document when enabled and the region was recognized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hocr: Option<String>,
+    /// TSV table when enabled and the region was recognized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tsv: Option<String>,
+}
+
+/// Decision whether a region was recognized in a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionGate {
+    Recognized,
+    /// Not scheduled this frame because of `interval_frames`.
+    Interval,
+    /// None of the region's scenes is active.
+    InactiveScene,
+    /// Another region was scheduled while over the memory limit.
+    Degraded,
+    /// The pixels are identical to the previous frame.
+    Unchanged,
+    /// The perceptual hash is within the threshold of the last recognized
+    /// crop.
+    PerceptuallySimilar,
+    /// Recognition failed with an error.
+    Failed,
+}
+
+impl Default for RegionGate {
+    fn default() -> Self {
+        Self::Interval
+    }
+}
+
+/// Writes sampled frame results as JSON lines to a file or to the tools
+/// connected to a TCP socket.
+pub struct FrameResultWriter {
+    config: FrameDumpConfig,
+    output: Output,
+}
+
+enum Output {
+    File(File),
+    Socket {
+        listener: TcpListener,
+        clients: Vec<TcpStream>,
+    },
+}
+
+impl FrameResultWriter {
+    pub fn new(config: FrameDumpConfig) -> io::Result<Self> {
+        let output = match &config.output {
+            FrameDumpOutput::File { path } => {
+                Output::File(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            FrameDumpOutput::Socket { address } => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                let local_address = listener.local_addr()?;
+
+                info!("frame dump listening"; "address" => %local_address);
+
+                Output::Socket {
+                    listener,
+                    clients: Vec::new(),
+                }
+            }
+        };
+
+        Ok(Self { config, output })
+    }
+
+    pub fn config(&self) -> &FrameDumpConfig {
+        &self.config
+    }
+
+    /// Returns whether the frame is sampled for dumping.
+    pub fn is_due(&self, frame_counter: u64) -> bool {
+        frame_counter % self.config.every_frames.max(1) as u64 == 0
+    }
+
+    pub fn write(&mut self, frame_result: &FrameResult) {
+        let mut line = match serde_json::to_string(frame_result) {
+            Ok(line) => line,
+            Err(error) => {
+                warn!("failed to serialize frame result"; "error" => %error);
+                return;
+            }
+        };
+        line.push('\n');
+
+        match &mut self.output {
+            Output::File(file) => {
+                if let Err(error) = file.write_all(line.as_bytes()) {
+                    warn!("failed to write frame result"; "error" => %error);
+                }
+            }
+            Output::Socket { listener, clients } => {
+                while let Ok((stream, address)) = listener.accept() {
+                    info!("frame dump client connected"; "address" => %address);
+
+                    if stream.set_nonblocking(false).is_ok()
+                        && stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT)).is_ok()
+                    {
+                        clients.push(stream);
+                    }
+                }
+
+                clients.retain(|mut client| client.write_all(line.as_bytes()).is_ok());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+
+    #[test]
+    fn test_write_socket() -> anyhow::Result<()> {
+        let mut writer = FrameResultWriter::new(FrameDumpConfig {
+            output: FrameDumpOutput::Socket {
+                address: "127.0.0.1:0".to_string(),
+            },
+            every_frames: 5,
+            hocr: false,
+            tsv: false,
+        })?;
+        let address = match &writer.output {
+            Output::Socket { listener, .. } => listener.local_addr()?,
+            Output::File(_) => unreachable!(),
+        };
+        let client = TcpStream::connect(address)?;
+        let frame_result = FrameResult {
+            frame_counter: 10,
+            date: Utc::now(),
+            capture_time: None,
+            scene: None,
+            quality_passed: true,
+            recognition_ms: 1.5,
+            regions: vec![RegionResult {
+                name: "dialog".to_string(),
+                gate: RegionGate::PerceptuallySimilar,
+                recognition_ms: 0.1,
+                text: String::new(),
+                word_boxes: Vec::new(),
+                language: None,
+                perceptual_hash: Some(7),
+                item_count: 0,
+                hocr: None,
+                tsv: Some("level\tpage_num".to_string()),
+            }],
+        };
+
+        assert!(writer.is_due(10));
+        assert!(!writer.is_due(11));
+
+        // The connection is accepted on the next write
+        std::thread::sleep(Duration::from_millis(50));
+        writer.write(&frame_result);
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line)?;
+        let value: serde_json::Value = serde_json::from_str(&line)?;
+
+        assert_eq!(value["frame_counter"], 10);
+        assert_eq!(value["regions"][0]["gate"], "perceptually_similar");
+        assert!(value["regions"][0].get("hocr").is_none());
+        assert_eq!(value["regions"][0]["tsv"], "level\tpage_num");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+//! Ring of frame slots in shared memory.
+//!
+//! The stream dumper writes each sampled frame to the next slot without
+//! waiting for readers, so a reader can always copy the newest complete frame
+//! or, when catching up, older frames still in the ring.
+//!
+//! Layout: a header page with the number of published frames, the ring
+//! dimensions, a semaphore posted for each published frame, and the capture
+//! time of each slot, followed by the slots.
+
+use std::{
+    sync::atomic::{self, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{error::IpcError, shared_memory::SharedMemory};
+
+const MAGIC: u64 = 0x7470_706f_6372_5231; // "tppocrR1"
+const HEADER_SIZE: usize = 4096;
+const WRITE_COUNT_OFFSET: usize = 0;
+const MAGIC_OFFSET: usize = 8;
+const SLOT_COUNT_OFFSET: usize = 16;
+const FRAME_SIZE_OFFSET: usize = 24;
+const SEMAPHORE_OFFSET: usize = 32;
+const SLOT_TABLE_OFFSET: usize = 64;
+const SLOT_ENTRY_SIZE: usize = 8;
+
+/// Maximum number of slots that fit in the header.
+pub const MAX_SLOTS: u32 = ((HEADER_SIZE - SLOT_TABLE_OFFSET) / SLOT_ENTRY_SIZE) as u32;
+
+pub struct FrameRing {
+    shared_memory: SharedMemory,
+    slot_count: u32,
+    frame_size: usize,
+}
+
+impl FrameRing {
+    /// Creates or resets the ring for writing.
+    ///
+    /// The slot count is clamped to 2 to `MAX_SLOTS`.
+    pub fn create(id: u32, frame_size: usize, slot_count: u32) -> Result<Self, IpcError> {
+        let slot_count = slot_count.clamp(2, MAX_SLOTS);
+        let shared_memory =
+            SharedMemory::open_or_create(id, HEADER_SIZE + frame_size * slot_count as usize)?;
+
+        let ring = Self {
+            shared_memory,
+            slot_count,
+            frame_size,
+        };
+
+        ring.atomic(MAGIC_OFFSET).store(0, Ordering::SeqCst);
+
+        debug_assert!(std::mem::size_of::<libc::sem_t>() <= SLOT_TABLE_OFFSET - SEMAPHORE_OFFSET);
+
+        if unsafe { libc::sem_init(ring.semaphore(), 1, 0) } != 0 {
+            return Err(IpcError::Semaphore(std::io::Error::last_os_error()));
+        }
+
+        ring.atomic(WRITE_COUNT_OFFSET).store(0, Ordering::SeqCst);
+        ring.atomic(SLOT_COUNT_OFFSET)
+            .store(slot_count as u64, Ordering::SeqCst);
+        ring.atomic(FRAME_SIZE_OFFSET)
+            .store(frame_size as u64, Ordering::SeqCst);
+        ring.atomic(MAGIC_OFFSET).store(MAGIC, Ordering::SeqCst);
+
+        Ok(ring)
+    }
+
+    /// Opens a ring created by the writer.
+    pub fn open(id: u32) -> Result<Self, IpcError> {
+        let shared_memory = SharedMemory::open_existing(id)?;
+
+        if shared_memory.data_size() < HEADER_SIZE {
+            return Err(IpcError::InvalidFrameRing { id });
+        }
+
+        let mut ring = Self {
+            shared_memory,
+            slot_count: 0,
+            frame_size: 0,
+        };
+
+        if ring.atomic(MAGIC_OFFSET).load(Ordering::SeqCst) != MAGIC {
+            return Err(IpcError::InvalidFrameRing { id });
+        }
+
+        ring.slot_count = ring.atomic(SLOT_COUNT_OFFSET).load(Ordering::SeqCst) as u32;
+        ring.frame_size = ring.atomic(FRAME_SIZE_OFFSET).load(Ordering::SeqCst) as usize;
+
+        if ring.slot_count < 2
+            || ring.slot_count > MAX_SLOTS
+            || ring.shared_memory.data_size()
+                < HEADER_SIZE + ring.frame_size * ring.slot_count as usize
+        {
+            return Err(IpcError::InvalidFrameRing { id });
+        }
+
+        Ok(ring)
+    }
+
+    pub fn slot_count(&self) -> u32 {
+        self.slot_count
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Returns the number of frames published so far.
+    ///
+    /// The newest frame has number `write_count() - 1`.
+    pub fn write_count(&self) -> u64 {
+        self.atomic(WRITE_COUNT_OFFSET).load(Ordering::Acquire)
+    }
+
+    /// Returns the number of the oldest frame that can still be read.
+    ///
+    /// The slot after the newest frame may be in the middle of being
+    /// overwritten, so one slot less than the ring size is readable.
+    pub fn oldest_readable(&self) -> u64 {
+        self.write_count()
+            .saturating_sub(self.slot_count as u64 - 1)
+    }
+
+    /// Writes the frame to the next slot and publishes it.
+    pub fn publish(&mut self, data: &[u8], capture_time: &DateTime<Utc>) -> u64 {
+        let frame_number = self.write_count();
+        let slot = (frame_number % self.slot_count as u64) as usize;
+        let offset = HEADER_SIZE + slot * self.frame_size;
+
+        // Readers that see any of the new contents must also see the write
+        // count that makes the slot's previous frame unreadable
+        atomic::fence(Ordering::Release);
+
+        self.shared_memory.data_mut()[offset..offset + self.frame_size]
+            .copy_from_slice(&data[..self.frame_size]);
+        self.atomic(SLOT_TABLE_OFFSET + slot * SLOT_ENTRY_SIZE)
+            .store(encode_timestamp(capture_time) as u64, Ordering::Relaxed);
+        self.atomic(WRITE_COUNT_OFFSET)
+            .store(frame_number + 1, Ordering::Release);
+
+        unsafe {
+            libc::sem_post(self.semaphore());
+        }
+
+        frame_number
+    }
+
+    /// Waits until a frame is published or the timeout elapses.
+    ///
+    /// Returns false on timeout. Frames published since the last wait,
+    /// including before it, return immediately, so a frame published between
+    /// checking `write_count` and waiting is not missed. Only one reader
+    /// should wait on a ring.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let mut deadline = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_REALTIME, &mut deadline);
+        }
+
+        let nanoseconds = deadline.tv_nsec as u64 + timeout.subsec_nanos() as u64;
+        deadline.tv_sec += (timeout.as_secs() + nanoseconds / 1_000_000_000) as libc::time_t;
+        deadline.tv_nsec = (nanoseconds % 1_000_000_000) as libc::c_long;
+
+        loop {
+            if unsafe { libc::sem_timedwait(self.semaphore(), &deadline) } == 0 {
+                return true;
+            }
+
+            // Retry if interrupted by a signal
+            if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                return false;
+            }
+        }
+    }
+
+    /// Copies the frame into the buffer and returns its capture time.
+    ///
+    /// Returns None if the frame is not published yet or was overwritten,
+    /// possibly while copying, in which case the buffer contents are invalid.
+    /// Frames are also overwritten when the writer restarts and counts from
+    /// zero again.
+    pub fn read(&self, frame_number: u64, buffer: &mut [u8]) -> Option<DateTime<Utc>> {
+        let write_count = self.write_count();
+
+        if !self.is_readable(frame_number, write_count) {
+            return None;
+        }
+
+        let slot = (frame_number % self.slot_count as u64) as usize;
+        let offset = HEADER_SIZE + slot * self.frame_size;
+        let timestamp = self
+            .atomic(SLOT_TABLE_OFFSET + slot * SLOT_ENTRY_SIZE)
+            .load(Ordering::Relaxed);
+
+        buffer[..self.frame_size]
+            .copy_from_slice(&self.shared_memory.data()[offset..offset + self.frame_size]);
+
+        // Pairs with the writer's fence so that overwriting seen while
+        // copying shows in the count read after
+        atomic::fence(Ordering::Acquire);
+
+        let new_write_count = self.atomic(WRITE_COUNT_OFFSET).load(Ordering::Relaxed);
+
+        if new_write_count < write_count || !self.is_readable(frame_number, new_write_count) {
+            return None;
+        }
+
+        decode_timestamp(timestamp as i64)
+    }
+
+    fn is_readable(&self, frame_number: u64, write_count: u64) -> bool {
+        frame_number < write_count
+            && frame_number >= write_count.saturating_sub(self.slot_count as u64 - 1)
+    }
+
+    fn semaphore(&self) -> *mut libc::sem_t {
+        unsafe { self.shared_memory.data().as_ptr().add(SEMAPHORE_OFFSET) as *mut libc::sem_t }
+    }
+
+    fn atomic(&self, offset: usize) -> &AtomicU64 {
+        debug_assert!(offset + 8 <= HEADER_SIZE);
+
+        // The data of the shared memory is 64-byte aligned and the header is
+        // only accessed through atomics.
+        unsafe { &*(self.shared_memory.data().as_ptr().add(offset) as *const AtomicU64) }
+    }
+}
+
+pub(crate) fn encode_timestamp(date: &DateTime<Utc>) -> i64 {
+    date.timestamp() * 1_000_000 + date.timestamp_subsec_micros() as i64
+}
+
+pub(crate) fn decode_timestamp(microseconds: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(
+        microseconds.div_euclid(1_000_000),
+        microseconds.rem_euclid(1_000_000) as u32 * 1000,
+    )
+    .single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        let date = Utc.timestamp(1_600_000_000, 123_456_000);
+
+        assert_eq!(decode_timestamp(encode_timestamp(&date)), Some(date));
+    }
+
+    #[test]
+    fn test_publish_read() -> anyhow::Result<()> {
+        let mut writer = FrameRing::create(124, 16, 3)?;
+        let reader = FrameRing::open(124)?;
+        let mut buffer = [0u8; 16];
+        let date = Utc.timestamp(1_600_000_000, 0);
+
+        assert_eq!(reader.slot_count(), 3);
+        assert_eq!(reader.read(0, &mut buffer), None);
+        assert!(!reader.wait(Duration::from_millis(1)));
+
+        for value in 0..4u8 {
+            writer.publish(
+                &[value; 16],
+                &(date + chrono::Duration::seconds(value as i64)),
+            );
+        }
+
+        assert!(reader.wait(Duration::from_millis(1)));
+        assert_eq!(reader.write_count(), 4);
+        assert_eq!(reader.oldest_readable(), 2);
+        assert_eq!(reader.read(1, &mut buffer), None);
+        assert_eq!(
+            reader.read(3, &mut buffer),
+            Some(date + chrono::Duration::seconds(3))
+        );
+        assert_eq!(buffer, [3u8; 16]);
+
+        // The writer restarts
+        let mut writer = FrameRing::create(124, 16, 3)?;
+        writer.publish(&[5; 16], &date);
+
+        assert_eq!(reader.write_count(), 1);
+        assert_eq!(reader.read(3, &mut buffer), None);
+        assert_eq!(reader.read(0, &mut buffer), Some(date));
+
+        SharedMemory::remove(124)?;
+
+        Ok(())
+    }
+}
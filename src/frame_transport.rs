@@ -0,0 +1,246 @@
+//! Sending frames over TCP so the stream dumper and OCR processor can run on
+//! different hosts.
+//!
+//! Each frame is a packet of a 4-byte big-endian length followed by the
+//! capture time in microseconds (8 bytes), the width and height (4 bytes
+//! each), and the RGBA pixels.
+
+use std::{
+    convert::TryInto,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use slog_scope::{info, warn};
+
+use crate::{
+    error::{FrameError, IpcError},
+    frame_ring,
+};
+
+const LENGTH_SIZE: usize = 4;
+const HEADER_SIZE: usize = 16;
+/// Larger packets are rejected as corrupt.
+const MAX_PACKET_SIZE: usize = 256 * 1024 * 1024;
+
+/// Sends published frames to every connected processor.
+///
+/// Each connection has its own thread and queue. Frames are dropped for a
+/// connection whose queue is full so a slow processor or network doesn't
+/// hold up the stream dumper.
+pub struct FrameSender {
+    listener: TcpListener,
+    queue_size: usize,
+    clients: Vec<SyncSender<Arc<Vec<u8>>>>,
+}
+
+impl FrameSender {
+    pub fn bind(address: &str, queue_size: usize) -> Result<Self, IpcError> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        info!("frame sender listening"; "address" => %local_address);
+
+        Ok(Self {
+            listener,
+            queue_size: queue_size.max(1),
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn publish(&mut self, data: &[u8], width: u32, height: u32, capture_time: &DateTime<Utc>) {
+        self.accept_clients();
+
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let packet = Arc::new(encode_packet(data, width, height, capture_time));
+
+        self.clients
+            .retain(|client| match client.try_send(Arc::clone(&packet)) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    fn accept_clients(&mut self) {
+        while let Ok((stream, address)) = self.listener.accept() {
+            info!("frame receiver connected"; "address" => %address);
+
+            if let Err(error) = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_nodelay(true))
+            {
+                warn!("failed to set up frame receiver connection"; "error" => %error);
+                continue;
+            }
+
+            let (sender, receiver) = mpsc::sync_channel::<Arc<Vec<u8>>>(self.queue_size);
+            let mut stream = stream;
+
+            std::thread::spawn(move || {
+                for packet in receiver {
+                    if let Err(error) = stream.write_all(&packet) {
+                        info!("frame receiver disconnected"; "address" => %address, "error" => %error);
+                        break;
+                    }
+                }
+            });
+
+            self.clients.push(sender);
+        }
+    }
+}
+
+/// Receives frames from a stream dumper's frame sender.
+pub struct FrameReceiver {
+    stream: TcpStream,
+    width: u32,
+    height: u32,
+}
+
+impl FrameReceiver {
+    /// Connects to the sender and waits at most `timeout` for each frame.
+    pub fn connect(
+        address: &str,
+        width: u32,
+        height: u32,
+        timeout: Duration,
+    ) -> Result<Self, IpcError> {
+        let stream = TcpStream::connect(address).map_err(|source| IpcError::FrameConnect {
+            address: address.to_string(),
+            source,
+        })?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        Ok(Self {
+            stream,
+            width,
+            height,
+        })
+    }
+
+    /// Reads the next frame into the buffer and returns its capture time.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<DateTime<Utc>, FrameError> {
+        let mut length = [0u8; LENGTH_SIZE];
+        self.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+
+        if !(HEADER_SIZE..=MAX_PACKET_SIZE).contains(&length) {
+            return Err(IpcError::InvalidFramePacket { length }.into());
+        }
+
+        let mut header = [0u8; HEADER_SIZE];
+        self.read_exact(&mut header)?;
+
+        let timestamp = i64::from_be_bytes(header[0..8].try_into().unwrap());
+        let width = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let height = u32::from_be_bytes(header[12..16].try_into().unwrap());
+        let frame_size = length - HEADER_SIZE;
+
+        if width != self.width || height != self.height || frame_size != buffer.len() {
+            return Err(FrameError::FrameSize {
+                width: self.width,
+                height: self.height,
+                frame_size,
+            });
+        }
+
+        self.read_exact(buffer)?;
+
+        frame_ring::decode_timestamp(timestamp)
+            .ok_or(IpcError::InvalidFramePacket { length }.into())
+    }
+
+    /// Returns whether data of another frame already arrived.
+    pub fn has_pending(&self) -> bool {
+        let mut byte = [0u8; 1];
+
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        let pending = matches!(self.stream.peek(&mut byte), Ok(size) if size > 0);
+        self.stream.set_nonblocking(false).ok();
+
+        pending
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), FrameError> {
+        self.stream
+            .read_exact(buffer)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => FrameError::Timeout(
+                    self.stream
+                        .read_timeout()
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default(),
+                ),
+                _ => IpcError::Socket(error).into(),
+            })
+    }
+}
+
+fn encode_packet(data: &[u8], width: u32, height: u32, capture_time: &DateTime<Utc>) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(LENGTH_SIZE + HEADER_SIZE + data.len());
+
+    packet.extend_from_slice(&((HEADER_SIZE + data.len()) as u32).to_be_bytes());
+    packet.extend_from_slice(&frame_ring::encode_timestamp(capture_time).to_be_bytes());
+    packet.extend_from_slice(&width.to_be_bytes());
+    packet.extend_from_slice(&height.to_be_bytes());
+    packet.extend_from_slice(data);
+
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_send_receive() -> anyhow::Result<()> {
+        let mut sender = FrameSender::bind("127.0.0.1:0", 2)?;
+        let mut receiver = FrameReceiver::connect(
+            &sender.local_addr()?.to_string(),
+            2,
+            1,
+            Duration::from_secs(5),
+        )?;
+        let date = Utc.timestamp(1_600_000_000, 0);
+
+        sender.publish(&[1; 8], 2, 1, &date);
+        sender.publish(&[2; 8], 2, 1, &(date + chrono::Duration::seconds(1)));
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(receiver.receive(&mut buffer)?, date);
+        assert_eq!(buffer, [1; 8]);
+        assert_eq!(
+            receiver.receive(&mut buffer)?,
+            date + chrono::Duration::seconds(1)
+        );
+        assert_eq!(buffer, [2; 8]);
+
+        sender.publish(&[3; 12], 3, 1, &date);
+        assert!(matches!(
+            receiver.receive(&mut buffer),
+            Err(FrameError::FrameSize { .. })
+        ));
+
+        Ok(())
+    }
+}
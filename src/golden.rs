@@ -0,0 +1,235 @@
+//! Regression tests of recognition accuracy on screenshots with known text.
+//!
+//! Enabled with the `golden` feature, since they need Tesseract's tessdata,
+//! by default in `/usr/share/tesseract-ocr/4.00/tessdata/` or else the
+//! `TESSDATA_PREFIX` environment variable, and GNU Unifont for the demo
+//! frames.
+//!
+//! A fixture is a `.toml` file in `tests/golden/`, or the directory of the
+//! `TPPOCR_GOLDEN_DIR` environment variable. It is a processor config whose
+//! `image` key names the screenshot relative to the file, and whose
+//! `[[expect]]` tables list the text each region is expected to emit, in
+//! order:
+//!
+//! ```toml
+//! image = "dialog.png"
+//!
+//! [[region]]
+//! name = "dialog"
+//! x = 32
+//! y = 500
+//! width = 900
+//! height = 150
+//! processor = "FixedLine"
+//!
+//! [[expect]]
+//! region = "dialog"
+//! text = "Hello there!"
+//! ```
+//!
+//! Regions without expectations must emit nothing. The test fails if the
+//! directory can't be read or has no fixtures.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+use crate::{
+    canvas::TextDrawer,
+    config::{ProcessorConfig, Region},
+    demo::{self, DIALOG_LINES},
+    error::OcrError,
+    pipeline::Pipeline,
+    preprocess,
+    processor::{self, RegionProcessor},
+    text_recognizer::TextRecognizerSet,
+};
+
+const DEFAULT_DATA_PATH: &str = "/usr/share/tesseract-ocr/4.00/tessdata/";
+
+#[derive(Deserialize)]
+struct Fixture {
+    image: PathBuf,
+    #[serde(default)]
+    expect: Vec<Expectation>,
+}
+
+#[derive(Deserialize)]
+struct Expectation {
+    region: String,
+    text: String,
+}
+
+fn text_recognizers() -> TextRecognizerSet {
+    let data_path =
+        std::env::var("TESSDATA_PREFIX").unwrap_or_else(|_| DEFAULT_DATA_PATH.to_string());
+
+    TextRecognizerSet::new(&data_path, "eng", BTreeMap::new())
+        .expect("failed to load tessdata, set TESSDATA_PREFIX")
+}
+
+fn fixture_dir() -> PathBuf {
+    match std::env::var_os("TPPOCR_GOLDEN_DIR") {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"),
+    }
+}
+
+/// Recognizes the region as the processor would and returns the text its
+/// strategy emits, flushed as if time had passed.
+fn recognize_texts(
+    text_recognizers: &mut TextRecognizerSet,
+    region: &Region,
+    frame_data: &[u32],
+    frame_width: u32,
+) -> Result<Vec<String>, OcrError> {
+    processor::load_region_engines(
+        std::slice::from_mut(text_recognizers),
+        std::slice::from_ref(region),
+    )?;
+
+    let mut region_processor = RegionProcessor::new(region.clone());
+    let date = Utc::now();
+
+    region_processor.recognize(text_recognizers, frame_data, frame_width, &date)?;
+
+    Ok(region_processor
+        .get_text(&(date + Duration::minutes(1)))
+        .into_iter()
+        .map(|item| item.text.trim().to_string())
+        .collect())
+}
+
+/// Returns a description of each region whose output differs from the
+/// fixture's expectations.
+fn check_fixture(text_recognizers: &mut TextRecognizerSet, path: &Path) -> Vec<String> {
+    let text = std::fs::read_to_string(path).unwrap();
+    let fixture: Fixture = toml::de::from_str(&text).unwrap();
+    let image_path = path.parent().unwrap().join(&fixture.image);
+    let image = image::open(&image_path)
+        .unwrap_or_else(|error| panic!("failed to open {}: {}", image_path.display(), error))
+        .into_rgba8();
+    let (frame_width, frame_height) = image.dimensions();
+    let frame_data = preprocess::to_u32_pixels(&image);
+    let config = ProcessorConfig::parse(&text, frame_width, frame_height).unwrap();
+    let mut failures = Vec::new();
+
+    for expectation in &fixture.expect {
+        if !config
+            .region
+            .iter()
+            .any(|region| region.name == expectation.region)
+        {
+            failures.push(format!(
+                "{}: unknown region {:?}",
+                path.display(),
+                expectation.region
+            ));
+        }
+    }
+
+    for region in &config.region {
+        let expected: Vec<&str> = fixture
+            .expect
+            .iter()
+            .filter(|expectation| expectation.region == region.name)
+            .map(|expectation| expectation.text.as_str())
+            .collect();
+
+        match recognize_texts(text_recognizers, region, &frame_data, frame_width) {
+            Ok(texts) if texts == expected => {}
+            Ok(texts) => failures.push(format!(
+                "{}: region {}: expected {:?}, got {:?}",
+                path.display(),
+                region.name,
+                expected,
+                texts
+            )),
+            Err(error) => failures.push(format!(
+                "{}: region {}: {}",
+                path.display(),
+                region.name,
+                error
+            )),
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn test_demo_frames() {
+    let (width, height) = (1280, 720);
+    let config = ProcessorConfig::parse(demo::DEMO_CONFIG, width, height).unwrap();
+    let mut text_recognizers = text_recognizers();
+    let mut text_drawer = TextDrawer::new().unwrap();
+
+    for line in DIALOG_LINES {
+        let frame: Vec<u32> = demo::render_frame(&mut text_drawer, width, height, line)
+            .chunks_exact(4)
+            .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+            .collect();
+        let texts =
+            recognize_texts(&mut text_recognizers, &config.region[0], &frame, width).unwrap();
+
+        assert_eq!(texts, &[*line]);
+    }
+}
+
+#[test]
+fn test_pipeline() {
+    let (width, height) = (1280, 720);
+    let config = ProcessorConfig::parse(demo::DEMO_CONFIG, width, height).unwrap();
+    let mut pipeline = Pipeline::new(config, text_recognizers(), width, height).unwrap();
+    let mut text_drawer = TextDrawer::new().unwrap();
+    let mut texts = Vec::new();
+
+    for line in DIALOG_LINES {
+        let data = demo::render_frame(&mut text_drawer, width, height, line);
+        let image = image::RgbaImage::from_raw(width, height, data).unwrap();
+        let items = pipeline.process_image(&image).unwrap();
+        texts.extend(items.into_iter().map(|item| item.text.trim().to_string()));
+    }
+
+    texts.extend(
+        pipeline
+            .flush()
+            .into_iter()
+            .map(|item| item.text.trim().to_string()),
+    );
+
+    assert_eq!(texts, DIALOG_LINES);
+    assert!(pipeline
+        .process_image(&image::RgbaImage::new(width / 2, height / 2))
+        .is_err());
+}
+
+#[test]
+fn test_fixtures() {
+    let directory = fixture_dir();
+    let mut text_recognizers = text_recognizers();
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(&directory) {
+        Ok(entries) => entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "toml")
+            })
+            .collect(),
+        Err(error) => panic!("failed to read {}: {}", directory.display(), error),
+    };
+    paths.sort();
+
+    assert!(!paths.is_empty(), "no fixtures in {}", directory.display());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .flat_map(|path| check_fixture(&mut text_recognizers, path))
+        .collect();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
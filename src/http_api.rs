@@ -0,0 +1,323 @@
+//! HTTP API for dashboards to poll recent results of a running processor
+//! without a separate database.
+//!
+//! Endpoints, all returning JSON:
+//!
+//! * `/regions`: the configured regions with their item counts and latest
+//!   item date.
+//! * `/results?since=DATE&limit=N`: recent items emitted after the RFC 3339
+//!   date, oldest first.
+//! * `/latest`: the latest item of each region.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use slog_scope::{info, warn};
+
+use crate::{config::HttpApiConfig, text_processor::TextItem};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the server checks whether it was stopped while no client
+/// connects.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serves the API from a background thread until dropped.
+pub struct HttpApi {
+    config: HttpApiConfig,
+    state: Arc<Mutex<ApiState>>,
+    stop_flag: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct ApiState {
+    max_results: usize,
+    region_names: Vec<String>,
+    results: VecDeque<StoredItem>,
+    latest: BTreeMap<String, StoredItem>,
+    item_counts: BTreeMap<String, u64>,
+}
+
+#[derive(Clone)]
+struct StoredItem {
+    date: DateTime<Utc>,
+    value: Value,
+}
+
+impl HttpApi {
+    pub fn new(config: HttpApiConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(&config.address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        info!("HTTP API listening"; "address" => %local_address);
+
+        let state = Arc::new(Mutex::new(ApiState {
+            max_results: config.max_results,
+            ..Default::default()
+        }));
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_state = Arc::clone(&state);
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        std::thread::spawn(move || serve(listener, &thread_state, &thread_stop_flag));
+
+        Ok(Self {
+            config,
+            state,
+            stop_flag,
+        })
+    }
+
+    pub fn config(&self) -> &HttpApiConfig {
+        &self.config
+    }
+
+    /// Applies a config with the same address. The address can't be
+    /// changed while running.
+    pub fn set_config(&mut self, config: HttpApiConfig) {
+        self.state.lock().unwrap().max_results = config.max_results;
+        self.config = config;
+    }
+
+    /// Sets the regions listed by `/regions`.
+    pub fn set_region_names(&mut self, value: Vec<String>) {
+        self.state.lock().unwrap().region_names = value;
+    }
+
+    pub fn record_item(&mut self, item: &TextItem) {
+        let stored_item = StoredItem {
+            date: item.date,
+            value: serde_json::to_value(item).unwrap_or(Value::Null),
+        };
+        let mut state = self.state.lock().unwrap();
+
+        *state
+            .item_counts
+            .entry(item.region_name.clone())
+            .or_insert(0) += 1;
+        state
+            .latest
+            .insert(item.region_name.clone(), stored_item.clone());
+        state.results.push_back(stored_item);
+
+        while state.results.len() > state.max_results {
+            state.results.pop_front();
+        }
+    }
+}
+
+impl Drop for HttpApi {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+fn serve(listener: TcpListener, state: &Mutex<ApiState>, stop_flag: &AtomicBool) {
+    while !stop_flag.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(error) = serve_request(stream, state) {
+                    warn!("failed to serve HTTP API request"; "error" => %error);
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_INTERVAL)
+            }
+            Err(error) => {
+                warn!("HTTP API stopped"; "error" => %error);
+                return;
+            }
+        }
+    }
+}
+
+fn serve_request(mut stream: TcpStream, state: &Mutex<ApiState>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Headers are read so the client doesn't see the connection reset
+    let mut header = String::new();
+
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => respond(&state.lock().unwrap(), target),
+        _ => (
+            "405 Method Not Allowed",
+            json!({"error": "only GET is supported"}),
+        ),
+    };
+    let body = body.to_string();
+
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn respond(state: &ApiState, target: &str) -> (&'static str, Value) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameters: BTreeMap<String, String> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+        .collect();
+
+    match path {
+        "/regions" => {
+            let regions: Vec<Value> = state
+                .region_names
+                .iter()
+                .map(|name| {
+                    json!({
+                        "name": name,
+                        "item_count": state.item_counts.get(name).copied().unwrap_or(0),
+                        "latest_date": state.latest.get(name).map(|item| item.date),
+                    })
+                })
+                .collect();
+
+            ("200 OK", Value::from(regions))
+        }
+        "/results" => {
+            let since = match parameters
+                .get("since")
+                .map(|value| value.parse::<DateTime<Utc>>())
+            {
+                Some(Ok(since)) => Some(since),
+                Some(Err(_)) => {
+                    return (
+                        "400 Bad Request",
+                        json!({"error": "since must be an RFC 3339 date"}),
+                    )
+                }
+                None => None,
+            };
+            let limit = match parameters.get("limit").map(|value| value.parse::<usize>()) {
+                Some(Ok(limit)) => limit,
+                Some(Err(_)) => return ("400 Bad Request", json!({"error": "invalid limit"})),
+                None => usize::MAX,
+            };
+            let results: Vec<Value> = state
+                .results
+                .iter()
+                .filter(|item| since.map_or(true, |since| item.date > since))
+                .take(limit)
+                .map(|item| item.value.clone())
+                .collect();
+
+            ("200 OK", Value::from(results))
+        }
+        "/latest" => {
+            let latest: serde_json::Map<String, Value> = state
+                .latest
+                .iter()
+                .map(|(name, item)| (name.clone(), item.value.clone()))
+                .collect();
+
+            ("200 OK", Value::Object(latest))
+        }
+        _ => ("404 Not Found", json!({"error": "not found"})),
+    }
+}
+
+/// Decodes `%XX` escapes and `+` in a query component.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                let byte = std::str::from_utf8(&bytes[index + 1..index + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        let date = |text: &str| text.parse::<DateTime<Utc>>().unwrap();
+        let item = |text: &str, region: &str| StoredItem {
+            date: date(text),
+            value: json!({"date": text, "region_name": region}),
+        };
+        let mut state = ApiState {
+            max_results: 10,
+            region_names: vec!["dialog".to_string(), "battle".to_string()],
+            ..Default::default()
+        };
+        state
+            .results
+            .push_back(item("2021-01-10T21:00:00Z", "dialog"));
+        state
+            .results
+            .push_back(item("2021-01-10T21:00:05Z", "dialog"));
+        state
+            .latest
+            .insert("dialog".to_string(), item("2021-01-10T21:00:05Z", "dialog"));
+        state.item_counts.insert("dialog".to_string(), 2);
+
+        let (status, body) = respond(&state, "/results?since=2021-01-10T21%3A00%3A00Z");
+        assert_eq!(status, "200 OK");
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let (_, body) = respond(&state, "/results?limit=1");
+        assert_eq!(body[0]["date"], "2021-01-10T21:00:00Z");
+
+        let (_, body) = respond(&state, "/regions");
+        assert_eq!(body[0]["item_count"], 2);
+        assert_eq!(body[1]["latest_date"], Value::Null);
+
+        let (_, body) = respond(&state, "/latest");
+        assert_eq!(body["dialog"]["date"], "2021-01-10T21:00:05Z");
+
+        assert_eq!(
+            respond(&state, "/results?since=yesterday").0,
+            "400 Bad Request"
+        );
+        assert_eq!(respond(&state, "/missing").0, "404 Not Found");
+    }
+}
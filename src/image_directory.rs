@@ -0,0 +1,220 @@
+//! Frames from a directory of screenshots instead of a stream, for building
+//! and testing region configs offline.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use image::{imageops::FilterType, RgbaImage};
+use slog_scope::{info, warn};
+
+use crate::{error::FrameError, frame_ring::FrameRing, frame_transport::FrameSender};
+
+const BYTES_PER_PIXEL: u32 = 4;
+/// Interval the current image is published at, matching the sampling of
+/// streams so the processor doesn't time out waiting for a frame.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+const EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// Publishes the PNG and JPEG images of a directory in file name order.
+pub struct ImageDirectoryDumper {
+    paths: Vec<PathBuf>,
+    output_width: u32,
+    output_height: u32,
+    frame_ring: FrameRing,
+    frame_sender: Option<FrameSender>,
+    images_per_second: f64,
+    infinite_loop: bool,
+}
+
+impl ImageDirectoryDumper {
+    pub fn new(
+        directory: &Path,
+        output_port: u16,
+        output_width: u32,
+        output_height: u32,
+        slot_count: u32,
+    ) -> Result<Self, FrameError> {
+        let paths = list_images(directory)?;
+
+        if paths.is_empty() {
+            return Err(FrameError::NoImages {
+                path: directory.to_path_buf(),
+            });
+        }
+
+        let data_size = (output_width * output_height * BYTES_PER_PIXEL) as usize;
+        let frame_ring = FrameRing::create(output_port as u32, data_size, slot_count)?;
+
+        Ok(Self {
+            paths,
+            output_width,
+            output_height,
+            frame_ring,
+            frame_sender: None,
+            images_per_second: 1.0,
+            infinite_loop: false,
+        })
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn images_per_second(&self) -> f64 {
+        self.images_per_second
+    }
+
+    /// Sets how many images are shown per second. Each image is published
+    /// repeatedly while shown.
+    pub fn set_images_per_second(&mut self, value: f64) {
+        self.images_per_second = value;
+    }
+
+    pub fn infinite_loop(&self) -> bool {
+        self.infinite_loop
+    }
+
+    pub fn set_infinite_loop(&mut self, value: bool) {
+        self.infinite_loop = value;
+    }
+
+    /// Sets the sender of frames to processors on other hosts, in addition
+    /// to shared memory.
+    pub fn set_frame_sender(&mut self, value: Option<FrameSender>) {
+        self.frame_sender = value;
+    }
+
+    pub fn run(&mut self) -> Result<(), FrameError> {
+        let terminate_flag = Arc::new(AtomicBool::new(false));
+        for sig in signal_hook::consts::TERM_SIGNALS {
+            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        }
+
+        let hold_duration = Duration::from_secs_f64(1.0 / self.images_per_second.max(0.001));
+
+        info!("loop start"; "image_count" => self.paths.len());
+
+        loop {
+            for path in self.paths.clone() {
+                let image = match self.load_image(&path) {
+                    Ok(image) => image,
+                    Err(error) => {
+                        warn!("failed to load image"; "path" => ?path, "error" => %error);
+                        continue;
+                    }
+                };
+
+                info!("showing image"; "path" => ?path);
+
+                let deadline = Instant::now() + hold_duration;
+
+                while Instant::now() < deadline {
+                    if terminate_flag.load(Ordering::Relaxed) {
+                        info!("stopping");
+                        return Ok(());
+                    }
+
+                    self.publish(&image);
+                    std::thread::sleep(PUBLISH_INTERVAL.min(hold_duration));
+                }
+            }
+
+            if !self.infinite_loop {
+                break;
+            }
+        }
+
+        info!("loop stop");
+
+        Ok(())
+    }
+
+    fn load_image(&self, path: &Path) -> Result<RgbaImage, FrameError> {
+        let image = image::open(path)?.into_rgba8();
+
+        if image.dimensions() == (self.output_width, self.output_height) {
+            Ok(image)
+        } else {
+            Ok(image::imageops::resize(
+                &image,
+                self.output_width,
+                self.output_height,
+                FilterType::Triangle,
+            ))
+        }
+    }
+
+    fn publish(&mut self, image: &RgbaImage) {
+        let capture_time = Utc::now();
+
+        self.frame_ring.publish(image.as_raw(), &capture_time);
+
+        if let Some(frame_sender) = &mut self.frame_sender {
+            frame_sender.publish(
+                image.as_raw(),
+                self.output_width,
+                self.output_height,
+                &capture_time,
+            );
+        }
+    }
+}
+
+/// Returns the paths of the images in the directory sorted by file name.
+pub fn list_images(directory: &Path) -> Result<Vec<PathBuf>, FrameError> {
+    let read_error = |source| FrameError::ImageDirectory {
+        path: directory.to_path_buf(),
+        source,
+    };
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(directory).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let is_image = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+            .unwrap_or(false);
+
+        if path.is_file() && is_image {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_images() -> anyhow::Result<()> {
+        let directory =
+            std::env::temp_dir().join(format!("tppocr-image-directory-{}", std::process::id()));
+        std::fs::create_dir_all(&directory)?;
+
+        for name in &["b.PNG", "a.jpg", "notes.txt"] {
+            std::fs::write(directory.join(name), "")?;
+        }
+
+        let paths = list_images(&directory)?;
+        std::fs::remove_dir_all(&directory)?;
+
+        assert_eq!(
+            paths,
+            vec![directory.join("a.jpg"), directory.join("b.PNG")]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Hash chaining and signing of emitted text items so archived transcripts
+//! can be verified as unmodified and without gaps.
+//!
+//! Each item's hash covers the session ID, its sequence number, the previous
+//! item's hash, and the item's date, region name, text, and confidence. With
+//! a key, the hash is also signed with HMAC-SHA256.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{config::IntegrityConfig, manifest, secret::Secret, text_processor::TextItem};
+
+/// Chain position and hashes of an item, all hex encoded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IntegrityStamp {
+    pub session_id: String,
+    pub sequence: u64,
+    pub previous_hash: String,
+    pub hash: String,
+    pub signature: Option<String>,
+}
+
+/// Stamps items of a session in the order they are emitted.
+pub struct IntegrityChain {
+    session_id: String,
+    sequence: u64,
+    previous_hash: String,
+    key: Option<Secret>,
+}
+
+impl IntegrityChain {
+    /// Starts a new session with a random ID.
+    pub fn new(config: IntegrityConfig) -> Self {
+        Self::with_session_id(manifest::generate_session_id(), config)
+    }
+
+    pub fn with_session_id(session_id: String, config: IntegrityConfig) -> Self {
+        Self {
+            session_id,
+            sequence: 0,
+            previous_hash: hex::encode([0u8; 32]),
+            key: config.key,
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn stamp(&mut self, item: &TextItem) -> IntegrityStamp {
+        let hash = item_hash(
+            &self.session_id,
+            self.sequence,
+            &self.previous_hash,
+            &item.date,
+            &item.region_name,
+            &item.text,
+            item.confidence,
+        );
+        let stamp = IntegrityStamp {
+            session_id: self.session_id.clone(),
+            sequence: self.sequence,
+            previous_hash: self.previous_hash.clone(),
+            signature: self.key.as_ref().map(|key| sign(key, &hash)),
+            hash,
+        };
+
+        self.sequence += 1;
+        self.previous_hash = stamp.hash.clone();
+
+        stamp
+    }
+}
+
+/// Checks that the stamp matches the item and follows the previous stamp of
+/// the session.
+///
+/// The first item of a session has no previous stamp. The signature is
+/// checked only if a key is given.
+pub fn verify(
+    item: &TextItem,
+    stamp: &IntegrityStamp,
+    previous_stamp: Option<&IntegrityStamp>,
+    key: Option<&Secret>,
+) -> bool {
+    let chain_ok = match previous_stamp {
+        Some(previous_stamp) => {
+            previous_stamp.session_id == stamp.session_id
+                && previous_stamp.sequence + 1 == stamp.sequence
+                && previous_stamp.hash == stamp.previous_hash
+        }
+        None => stamp.sequence == 0 && stamp.previous_hash == hex::encode([0u8; 32]),
+    };
+
+    let hash = item_hash(
+        &stamp.session_id,
+        stamp.sequence,
+        &stamp.previous_hash,
+        &item.date,
+        &item.region_name,
+        &item.text,
+        item.confidence,
+    );
+
+    let signature_ok = match key {
+        Some(key) => stamp
+            .signature
+            .as_deref()
+            .map_or(false, |signature| verify_signature(key, &hash, signature)),
+        None => true,
+    };
+
+    chain_ok && hash == stamp.hash && signature_ok
+}
+
+fn item_hash(
+    session_id: &str,
+    sequence: u64,
+    previous_hash: &str,
+    date: &DateTime<Utc>,
+    region_name: &str,
+    text: &str,
+    confidence: f32,
+) -> String {
+    // Strings are JSON encoded so fields can't run into each other
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        serde_json::to_string(session_id).unwrap(),
+        sequence,
+        previous_hash,
+        date.to_rfc3339(),
+        serde_json::to_string(region_name).unwrap(),
+        serde_json::to_string(text).unwrap(),
+        confidence
+    );
+
+    hex::encode(Sha256::digest(canonical.as_bytes()))
+}
+
+fn sign(key: &Secret, hash: &str) -> String {
+    hex::encode(hash_mac(key, hash).finalize().into_bytes())
+}
+
+/// Checks the hex signature in constant time, so the time taken doesn't
+/// reveal how much of a forged signature is right.
+fn verify_signature(key: &Secret, hash: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => hash_mac(key, hash).verify(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn hash_mac(key: &Secret, hash: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key.expose().as_bytes()).unwrap();
+    mac.update(hash.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::RecordMetadata;
+
+    use super::*;
+
+    fn text_item(text: &str) -> TextItem {
+        TextItem {
+            date: Utc::now(),
+            region_name: "dialog".to_string(),
+            text: text.to_string(),
+            confidence: 0.9,
+            location: None,
+            value: None,
+            block_box: None,
+            word_boxes: Vec::new(),
+            metadata: RecordMetadata::default(),
+            integrity: None,
+            stream_name: None,
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let key = Secret::new("hunter2".to_string());
+        let mut chain = IntegrityChain::with_session_id(
+            "test".to_string(),
+            IntegrityConfig {
+                key: Some(key.clone()),
+            },
+        );
+        let item_1 = text_item("Hello");
+        let item_2 = text_item("World");
+        let stamp_1 = chain.stamp(&item_1);
+        let stamp_2 = chain.stamp(&item_2);
+
+        assert!(verify(&item_1, &stamp_1, None, Some(&key)));
+        assert!(verify(&item_2, &stamp_2, Some(&stamp_1), Some(&key)));
+
+        // Modified text
+        assert!(!verify(&text_item("Wor1d"), &stamp_2, Some(&stamp_1), None));
+        // Missing item
+        assert!(!verify(&item_2, &stamp_2, None, None));
+        // Wrong key
+        let wrong_key = Secret::new("hunter3".to_string());
+        assert!(!verify(&item_1, &stamp_1, None, Some(&wrong_key)));
+        // Malformed signature
+        let mut forged_stamp = stamp_1.clone();
+        forged_stamp.signature = Some("not hex".to_string());
+        assert!(!verify(&item_1, &forged_stamp, None, Some(&key)));
+    }
+}
@@ -0,0 +1,322 @@
+//! Naming of the shared memory and sockets connecting the programs.
+//!
+//! By default, shared memory is created in `/dev/shm` and sockets in `/tmp`,
+//! which are left behind when a program crashes. Setting the environment
+//! variable `TPPOCR_IPC_MODE=abstract` uses Linux abstract-namespace sockets
+//! instead, and shared memory created with `memfd_create`, whose file
+//! descriptor is passed to other programs over an abstract socket. Both
+//! disappear with the last program using them. All programs of a pipeline
+//! must use the same mode, and the file descriptor is only passed to
+//! programs of the same user and group.
+//!
+//! Names start with `tppocr_`, followed by the namespace of the pipeline if
+//! one is set with `--ipc-namespace` or the `TPPOCR_IPC_NAMESPACE`
+//! environment variable, so independent pipelines on one host can use the
+//! same IDs.
+
+use std::{
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            io::{AsRawFd, RawFd},
+            net::{SocketAddr, UnixListener, UnixStream},
+        },
+    },
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use clap::Arg;
+use lazy_static::lazy_static;
+use nix::{
+    sys::{
+        socket::{sockopt::PeerCredentials, ControlMessage, MsgFlags},
+        uio::IoVec,
+    },
+    unistd::{Gid, Uid},
+};
+use slog_scope::warn;
+
+use crate::error::{ConfigError, IpcError};
+
+const MODE_VAR: &str = "TPPOCR_IPC_MODE";
+pub const NAMESPACE_VAR: &str = "TPPOCR_IPC_NAMESPACE";
+const NAME_PREFIX: &str = "tppocr_";
+/// How often the file descriptor server checks whether it was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref IPC_MODE: IpcMode = IpcMode::from_var(std::env::var(MODE_VAR).ok().as_deref());
+    static ref IPC_NAMESPACE: RwLock<Option<String>> = RwLock::new(namespace_from_var(
+        std::env::var(NAMESPACE_VAR).ok().as_deref()
+    ));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IpcMode {
+    /// Shared memory in `/dev/shm` and sockets in `/tmp`.
+    Filesystem,
+    /// Memfd shared memory and abstract-namespace sockets.
+    Abstract,
+}
+
+impl IpcMode {
+    /// Returns the mode of the `TPPOCR_IPC_MODE` environment variable.
+    pub fn current() -> Self {
+        *IPC_MODE
+    }
+
+    fn from_var(value: Option<&str>) -> Self {
+        match value.map(str::parse) {
+            None => IpcMode::Filesystem,
+            Some(Ok(mode)) => mode,
+            Some(Err(error)) => {
+                warn!("invalid IPC mode"; "variable" => MODE_VAR, "error" => error);
+                IpcMode::Filesystem
+            }
+        }
+    }
+}
+
+impl FromStr for IpcMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "filesystem" => Ok(IpcMode::Filesystem),
+            "abstract" => Ok(IpcMode::Abstract),
+            _ => Err(format!(
+                "expected IPC mode filesystem or abstract, got {:?}",
+                text
+            )),
+        }
+    }
+}
+
+/// Returns the start of shared memory and socket names, `tppocr_` or
+/// `tppocr_NAMESPACE_`.
+pub fn name_prefix() -> String {
+    match &*IPC_NAMESPACE.read().unwrap() {
+        Some(namespace) => format!("{}{}_", NAME_PREFIX, namespace),
+        None => NAME_PREFIX.to_string(),
+    }
+}
+
+/// Sets the namespace of the shared memory and sockets of the pipeline.
+pub fn set_namespace(namespace: &str) -> Result<(), ConfigError> {
+    *IPC_NAMESPACE.write().unwrap() = Some(parse_namespace(namespace)?);
+
+    Ok(())
+}
+
+/// Returns the `--ipc-namespace` option shared by the programs.
+pub fn namespace_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("ipc_namespace")
+        .long("ipc-namespace")
+        .takes_value(true)
+        .value_name("NAME")
+        .help("Namespace of shared memory and socket names, separating independent pipelines on one host")
+}
+
+/// Namespaces are limited to letters, digits, and hyphens, so names such as
+/// `tppocr_NAMESPACE_8840` are unambiguous.
+fn parse_namespace(text: &str) -> Result<String, ConfigError> {
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(text.to_string())
+    } else {
+        Err(ConfigError::IpcNamespace {
+            namespace: text.to_string(),
+        })
+    }
+}
+
+fn namespace_from_var(value: Option<&str>) -> Option<String> {
+    match value.filter(|value| !value.is_empty()).map(parse_namespace) {
+        None => None,
+        Some(Ok(namespace)) => Some(namespace),
+        Some(Err(error)) => {
+            warn!("invalid IPC namespace"; "variable" => NAMESPACE_VAR, "error" => %error);
+            None
+        }
+    }
+}
+
+/// Returns the address in the abstract namespace.
+pub fn abstract_address(name: &str) -> Result<SocketAddr, IpcError> {
+    Ok(SocketAddr::from_abstract_name(name.as_bytes())?)
+}
+
+/// Serves a file descriptor to every connecting process until dropped.
+pub struct FdServer {
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl FdServer {
+    /// Listens on the abstract socket name, taking ownership of the file
+    /// descriptor.
+    pub fn bind(name: &str, fd: RawFd) -> Result<Self, IpcError> {
+        let listener = UnixListener::bind_addr(&abstract_address(name)?)?;
+        listener.set_nonblocking(true)?;
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        std::thread::spawn(move || {
+            serve_fd(&listener, fd, &thread_stop_flag);
+            let _ = nix::unistd::close(fd);
+        });
+
+        Ok(Self { stop_flag })
+    }
+}
+
+impl Drop for FdServer {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+fn serve_fd(listener: &UnixListener, fd: RawFd, stop_flag: &AtomicBool) {
+    while !stop_flag.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(error) = send_fd(&stream, fd) {
+                    warn!("failed to send file descriptor"; "error" => %error);
+                }
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(STOP_CHECK_INTERVAL);
+            }
+            Err(error) => {
+                warn!("file descriptor server error"; "error" => %error);
+                std::thread::sleep(STOP_CHECK_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Sends the file descriptor if the peer runs as the same user and group.
+fn send_fd(stream: &UnixStream, fd: RawFd) -> Result<(), IpcError> {
+    // Abstract sockets have no file permissions, so any process could
+    // connect
+    let credentials = nix::sys::socket::getsockopt(stream.as_raw_fd(), PeerCredentials)?;
+
+    if credentials.uid() != Uid::effective().as_raw()
+        || credentials.gid() != Gid::effective().as_raw()
+    {
+        return Err(IpcError::PeerNotAllowed {
+            pid: credentials.pid(),
+            uid: credentials.uid(),
+            gid: credentials.gid(),
+        });
+    }
+
+    stream.set_nonblocking(false)?;
+    nix::sys::socket::sendmsg(
+        stream.as_raw_fd(),
+        &[IoVec::from_slice(&[0])],
+        &[ControlMessage::ScmRights(&[fd])],
+        MsgFlags::empty(),
+        None,
+    )?;
+
+    Ok(())
+}
+
+/// Returns a file descriptor received from the server of the abstract socket
+/// name.
+pub fn receive_fd(name: &str) -> Result<RawFd, IpcError> {
+    let stream = UnixStream::connect_addr(&abstract_address(name)?).map_err(|source| {
+        IpcError::FdConnect {
+            name: name.to_string(),
+            source,
+        }
+    })?;
+
+    // nix's recvmsg can't parse the empty peer address of stream sockets
+    let mut buffer = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control = [0u64; 8];
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    if unsafe { libc::recvmsg(stream.as_raw_fd(), &mut header, libc::MSG_CMSG_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&header) };
+
+    if !cmsg.is_null()
+        && unsafe {
+            (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        }
+    {
+        return Ok(unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd) });
+    }
+
+    Err(IpcError::NoFileDescriptor {
+        name: name.to_string(),
+    })
+}
+
+/// Returns whether a process serves the abstract socket name.
+pub fn is_served(name: &str) -> bool {
+    match receive_fd(name) {
+        Ok(fd) => {
+            let _ = nix::unistd::close(fd);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipc_mode() {
+        assert_eq!(IpcMode::from_var(None), IpcMode::Filesystem);
+        assert_eq!(IpcMode::from_var(Some("abstract")), IpcMode::Abstract);
+        assert_eq!(IpcMode::from_var(Some("memfd")), IpcMode::Filesystem);
+        assert_eq!(namespace_from_var(Some("run-2")), Some("run-2".to_string()));
+        assert_eq!(namespace_from_var(Some("run_2")), None);
+        assert_eq!(namespace_from_var(Some("")), None);
+    }
+
+    #[test]
+    fn test_fd_server() -> anyhow::Result<()> {
+        let name = format!("tppocr_test-{}.memfd", std::process::id());
+        let memfd_name = std::ffi::CString::new(name.as_str())?;
+        let fd = nix::sys::memfd::memfd_create(
+            &memfd_name,
+            nix::sys::memfd::MemFdCreateFlag::MFD_CLOEXEC,
+        )?;
+        nix::unistd::write(fd, b"frame")?;
+
+        let fd_server = FdServer::bind(&name, fd)?;
+        let received_fd = receive_fd(&name)?;
+        let mut buffer = [0; 5];
+        nix::sys::uio::pread(received_fd, &mut buffer, 0)?;
+        nix::unistd::close(received_fd)?;
+
+        assert_eq!(&buffer, b"frame");
+        assert!(is_served(&name));
+
+        drop(fd_server);
+        std::thread::sleep(STOP_CHECK_INTERVAL * 3);
+        assert!(!is_served(&name));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Finding and removing shared memory and sockets left behind by processes
+//! that crashed before unlinking them.
+
+use std::{
+    collections::HashSet,
+    fmt, io,
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+};
+
+use crate::error::IpcError;
+
+/// Directory where POSIX shared memory is mounted.
+const SHARED_MEMORY_DIR: &str = "/dev/shm";
+/// Directory of message sockets.
+const SOCKET_DIR: &str = "/tmp";
+const SOCKET_EXTENSION: &str = ".socket";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IpcFileKind {
+    SharedMemory,
+    Socket,
+}
+
+impl fmt::Display for IpcFileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcFileKind::SharedMemory => write!(f, "shared memory"),
+            IpcFileKind::Socket => write!(f, "socket"),
+        }
+    }
+}
+
+/// Shared memory segment or socket file no process is using.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaleFile {
+    pub kind: IpcFileKind,
+    pub path: PathBuf,
+}
+
+impl StaleFile {
+    pub fn remove(&self) -> Result<(), IpcError> {
+        match std::fs::remove_file(&self.path) {
+            Ok(_) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Returns the shared memory no process has mapped and the sockets no process
+/// is bound to.
+pub fn find_stale_files() -> Result<Vec<StaleFile>, IpcError> {
+    let mapped = mapped_shared_memory();
+    let prefix = crate::ipc::name_prefix();
+    let mut stale_files = Vec::new();
+
+    for path in list_files(Path::new(SHARED_MEMORY_DIR), |name| {
+        name.starts_with(&prefix)
+    })? {
+        if !mapped.contains(&path) {
+            stale_files.push(StaleFile {
+                kind: IpcFileKind::SharedMemory,
+                path,
+            });
+        }
+    }
+
+    for path in list_files(Path::new(SOCKET_DIR), |name| is_socket_name(name, &prefix))? {
+        if !is_socket_bound(&path) {
+            stale_files.push(StaleFile {
+                kind: IpcFileKind::Socket,
+                path,
+            });
+        }
+    }
+
+    Ok(stale_files)
+}
+
+fn list_files(directory: &Path, filter: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>, IpcError> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+
+        if entry.file_name().to_str().map_or(false, &filter) {
+            paths.push(entry.path());
+        }
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Names start with the prefix of the IPC namespace, so without a namespace
+/// all namespaces are included.
+fn is_socket_name(name: &str, prefix: &str) -> bool {
+    name.starts_with(prefix) && name.ends_with(SOCKET_EXTENSION)
+}
+
+/// Returns whether a process is bound to the datagram socket.
+///
+/// Sockets of other types are reported as bound so they are left alone.
+fn is_socket_bound(path: &Path) -> bool {
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(_) => return true,
+    };
+
+    match socket.connect(path) {
+        Err(error) => error.kind() != io::ErrorKind::ConnectionRefused,
+        Ok(_) => true,
+    }
+}
+
+/// Returns the shared memory files mapped by any process that can be
+/// inspected.
+fn mapped_shared_memory() -> HashSet<PathBuf> {
+    let mut paths = HashSet::new();
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return paths,
+    };
+
+    for entry in entries.flatten() {
+        if let Ok(text) = std::fs::read_to_string(entry.path().join("maps")) {
+            paths.extend(parse_mapped_paths(&text));
+        }
+    }
+
+    paths
+}
+
+/// Returns the shared memory paths of the lines of a `/proc/PID/maps` file.
+fn parse_mapped_paths(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .filter_map(|line| line.splitn(6, char::is_whitespace).nth(5))
+        .map(str::trim)
+        .filter(|path| path.starts_with(SHARED_MEMORY_DIR))
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapped_paths() {
+        let text = "\
+            7f0c1a000000-7f0c1a384000 rw-s 00000000 00:19 12 /dev/shm/tppocr_8840\n\
+            7f0c1b000000-7f0c1b021000 r-xp 00000000 08:01 34 /usr/lib/libc.so.6\n\
+            7ffd2c000000-7ffd2c021000 rw-p 00000000 00:00 0\n";
+
+        assert_eq!(
+            parse_mapped_paths(text),
+            [PathBuf::from("/dev/shm/tppocr_8840")]
+        );
+        assert!(is_socket_name("tppocr_client-8840.socket", "tppocr_"));
+        assert!(is_socket_name("tppocr_run-2_8840.socket", "tppocr_"));
+        assert!(!is_socket_name("tppocr_8840.socket", "tppocr_run-2_"));
+        assert!(!is_socket_name("tppocr-output-123", "tppocr_"));
+    }
+}
@@ -0,0 +1,509 @@
+//! Relaying emitted items to an IRC channel, as the original tppocr fed
+//! recognized dialog into chat tooling.
+//!
+//! Only the parts of the client protocol needed to register, join one
+//! channel, answer pings, and send messages are implemented. The relay is
+//! shared with the Twitch chat sink.
+
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use slog_scope::{info, warn};
+
+use crate::{config::IrcConfig, text_processor::TextItem};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// How long reading waits for a server message.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Time between reading the server's messages, such as pings, while no
+/// lines are sent.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Time the server has to accept the registration.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest text of a message, leaving room in the 512 byte line for the
+/// command and the prefix the server adds when relaying it.
+const MAX_TEXT_LENGTH: usize = 400;
+/// Lines are sent no faster than the configured interval so the server
+/// doesn't disconnect the client for flooding.
+pub struct IrcSink {
+    config: IrcConfig,
+    relay: Relay,
+}
+
+impl IrcSink {
+    pub fn new(config: IrcConfig) -> Self {
+        let relay = Relay::new(
+            Connection {
+                address: config.address.clone(),
+                tls: config.tls,
+                password: config
+                    .password
+                    .as_ref()
+                    .map(|password| password.expose().to_string()),
+                nickname: config.nickname.clone(),
+                channel: config.channel.clone(),
+            },
+            RateLimit::new(
+                1,
+                Duration::from_secs_f64(config.min_interval_secs.max(0.0)),
+            ),
+        );
+
+        Self { config, relay }
+    }
+
+    pub fn config(&self) -> &IrcConfig {
+        &self.config
+    }
+
+    /// Sends a message for each line of the item unless its region is not
+    /// relayed.
+    pub fn send_item(&mut self, item: &TextItem) {
+        if !self.config.regions.is_empty() && !self.config.regions.contains(&item.region_name) {
+            return;
+        }
+
+        for line in message_lines(&item.text) {
+            self.relay.send(&format!("[{}] {}", item.region_name, line));
+        }
+    }
+
+    /// Returns when `poll_connection` is due.
+    pub fn connection_poll_time(&self) -> Instant {
+        self.relay.poll_time()
+    }
+
+    /// Reconnects when due, or answers the server's pings.
+    pub fn poll_connection(&mut self) {
+        self.relay.poll();
+    }
+}
+
+/// Server and channel of a relay.
+pub(crate) struct Connection {
+    /// Address such as `irc.example.com:6667`.
+    pub address: String,
+    pub tls: bool,
+    pub password: Option<String>,
+    pub nickname: String,
+    pub channel: String,
+}
+
+/// Limit of messages sent within a sliding window of time.
+pub(crate) struct RateLimit {
+    max_messages: usize,
+    window: Duration,
+    send_times: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(max_messages: usize, window: Duration) -> Self {
+        Self {
+            max_messages: max_messages.max(1),
+            window,
+            send_times: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether a message can be sent now.
+    pub fn is_ready(&mut self, now: Instant) -> bool {
+        while let Some(send_time) = self.send_times.front() {
+            if now.duration_since(*send_time) >= self.window {
+                self.send_times.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        self.send_times.len() < self.max_messages
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.send_times.push_back(now);
+    }
+}
+
+/// Connection to a channel that lines are sent to, reconnecting when it is
+/// lost.
+pub(crate) struct Relay {
+    connection: Connection,
+    rate_limit: RateLimit,
+    session: Option<Session>,
+    /// When connecting is tried again.
+    retry_time: Instant,
+    /// When the server's messages are read next while connected.
+    idle_poll_time: Instant,
+}
+
+impl Relay {
+    /// Creates a relay that connects on its first poll or line, so creating
+    /// it doesn't wait for the server.
+    pub fn new(connection: Connection, rate_limit: RateLimit) -> Self {
+        Self {
+            connection,
+            rate_limit,
+            session: None,
+            retry_time: Instant::now(),
+            idle_poll_time: Instant::now(),
+        }
+    }
+
+    /// Sends the line once the rate limit allows it.
+    ///
+    /// Lines sent while disconnected are stale by the time the client
+    /// reconnects, so they are dropped.
+    pub fn send(&mut self, line: &str) {
+        if self.session.is_none() && Instant::now() >= self.retry_time {
+            self.connect();
+        }
+
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return,
+        };
+        let rate_limit = &mut self.rate_limit;
+        let channel = &self.connection.channel;
+
+        let result = (|| {
+            while !rate_limit.is_ready(Instant::now()) {
+                session.poll()?;
+            }
+
+            // A line break in the text would end the command and let the
+            // rest be read as another command
+            let line = line.replace(['\r', '\n'], " ");
+            session.send(&format!(
+                "PRIVMSG {} :{}",
+                channel,
+                truncate(&line, MAX_TEXT_LENGTH)
+            ))?;
+            rate_limit.record(Instant::now());
+
+            Ok(())
+        })();
+
+        if let Err(error) = result {
+            self.disconnect(error);
+        }
+    }
+
+    /// Returns when `poll` is due.
+    pub fn poll_time(&self) -> Instant {
+        match self.session {
+            Some(_) => self.idle_poll_time,
+            None => self.retry_time,
+        }
+    }
+
+    /// Connects when due, or reads the server's messages, answering pings.
+    pub fn poll(&mut self) {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => {
+                if Instant::now() >= self.retry_time {
+                    self.connect();
+                }
+                return;
+            }
+        };
+
+        loop {
+            match session.poll() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(error) => {
+                    self.disconnect(error);
+                    return;
+                }
+            }
+        }
+
+        self.idle_poll_time = Instant::now() + IDLE_POLL_INTERVAL;
+    }
+
+    fn connect(&mut self) {
+        match Session::connect(&self.connection) {
+            Ok(session) => {
+                self.session = Some(session);
+                self.idle_poll_time = Instant::now() + IDLE_POLL_INTERVAL;
+            }
+            Err(error) => self.disconnect(error),
+        }
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        warn!("IRC connection failed"; "address" => &self.connection.address,
+            "error" => %error, "retry_interval" => ?RETRY_INTERVAL);
+        self.session = None;
+        self.retry_time = Instant::now() + RETRY_INTERVAL;
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        if let Some(session) = &mut self.session {
+            let _ = session.send("QUIT");
+        }
+    }
+}
+
+trait IrcStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> IrcStream for T {}
+
+struct Session {
+    reader: BufReader<Box<dyn IrcStream>>,
+    /// Bytes of a server line not completely received yet.
+    pending: Vec<u8>,
+}
+
+impl Session {
+    /// Connects, registers, and joins the channel.
+    fn connect(connection: &Connection) -> io::Result<Self> {
+        let socket_address = connection
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not resolved"))?;
+        let stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let stream: Box<dyn IrcStream> = if connection.tls {
+            Box::new(tls_stream(&connection.address, stream)?)
+        } else {
+            Box::new(stream)
+        };
+        let mut session = Self {
+            reader: BufReader::new(stream),
+            pending: Vec::new(),
+        };
+
+        if let Some(password) = &connection.password {
+            session.send(&format!("PASS {}", password))?;
+        }
+
+        session.send(&format!("NICK {}", connection.nickname))?;
+        session.send(&format!("USER {} 0 * :tppocr", connection.nickname))?;
+        session.wait_registered()?;
+        session.send(&format!("JOIN {}", connection.channel))?;
+
+        info!("joined IRC channel"; "address" => &connection.address,
+            "channel" => &connection.channel);
+
+        Ok(session)
+    }
+
+    fn wait_registered(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + REGISTRATION_TIMEOUT;
+
+        while Instant::now() < deadline {
+            if let Some(message) = self.poll()? {
+                match message.command.as_str() {
+                    "001" => return Ok(()),
+                    // Nickname errors
+                    "431" | "432" | "433" | "436" => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("nickname rejected: {}", message.command),
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "registration not accepted",
+        ))
+    }
+
+    /// Reads a server message if one arrives within the poll interval,
+    /// answering pings.
+    fn poll(&mut self) -> io::Result<Option<Message>> {
+        match self.reader.read_until(b'\n', &mut self.pending) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "server closed the connection",
+                ))
+            }
+            Ok(_) => {}
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error),
+        }
+
+        if !self.pending.ends_with(b"\n") {
+            return Ok(None);
+        }
+
+        let line = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+
+        let message = match Message::parse(&line) {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+
+        match message.command.as_str() {
+            "PING" => self.send(&format!("PONG :{}", message.trailing))?,
+            "ERROR" => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    message.trailing,
+                ))
+            }
+            _ => {}
+        }
+
+        Ok(Some(message))
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()
+    }
+}
+
+/// Wraps the connection in TLS, verifying the server's certificate against
+/// the bundled root certificates.
+fn tls_stream(
+    address: &str,
+    stream: TcpStream,
+) -> io::Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>> {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let server_name = rustls::ServerName::try_from(host)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    let client = rustls::ClientConnection::new(Arc::new(config), server_name)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    Ok(rustls::StreamOwned::new(client, stream))
+}
+
+/// Command and last parameter of a server message.
+#[derive(Debug, PartialEq)]
+struct Message {
+    command: String,
+    trailing: String,
+}
+
+impl Message {
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches(&['\r', '\n'][..]);
+
+        if line.starts_with(':') {
+            line = line.split_once(' ')?.1;
+        }
+
+        let (head, trailing) = match line.find(" :") {
+            Some(index) => (&line[..index], &line[index + 2..]),
+            None => match line.rfind(' ') {
+                Some(index) => (&line[..index], &line[index + 1..]),
+                None => (line, ""),
+            },
+        };
+        let command = head
+            .split(' ')
+            .next()
+            .filter(|command| !command.is_empty())?;
+
+        Some(Self {
+            command: command.to_string(),
+            trailing: trailing.to_string(),
+        })
+    }
+}
+
+/// Shortens the text to at most the number of bytes at a character
+/// boundary.
+/// Returns the non-blank lines of the text, trimmed, each sent as its own
+/// message since a message cannot contain line breaks.
+pub(crate) fn message_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+fn truncate(text: &str, max_length: usize) -> &str {
+    let mut end = text.len().min(max_length);
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(
+            Message::parse(":irc.example.com 001 tppocr :Welcome\r\n"),
+            Some(Message {
+                command: "001".to_string(),
+                trailing: "Welcome".to_string()
+            })
+        );
+        assert_eq!(
+            Message::parse("PING :irc.example.com\r\n"),
+            Some(Message {
+                command: "PING".to_string(),
+                trailing: "irc.example.com".to_string()
+            })
+        );
+        assert_eq!(Message::parse(":irc.example.com"), None);
+        assert_eq!(truncate("ポケモン", 7), "ポケ");
+    }
+
+    #[test]
+    fn test_message_lines() {
+        assert_eq!(
+            message_lines(" Hello\r\nQUIT\n\n world \r").collect::<Vec<_>>(),
+            ["Hello", "QUIT", "world"]
+        );
+        assert_eq!(message_lines(" \n ").count(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut rate_limit = RateLimit::new(2, Duration::from_secs(30));
+        let start = Instant::now();
+
+        assert!(rate_limit.is_ready(start));
+        rate_limit.record(start);
+        rate_limit.record(start + Duration::from_secs(10));
+        assert!(!rate_limit.is_ready(start + Duration::from_secs(20)));
+        assert!(rate_limit.is_ready(start + Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,111 @@
+//! Choosing the recognition language of a region from the languages it may
+//! switch between.
+//!
+//! The region's crop is recognized with each allowed language and the
+//! language whose words Tesseract is most confident about is chosen. Unlike
+//! detecting the language of the recognized text, this also works when the
+//! current language can't represent the script at all, such as English
+//! recognizing Japanese.
+
+use crate::{
+    config::LanguageDetection,
+    error::OcrError,
+    ocr_engine::{BoxLevel, PageSegmentation},
+    text_recognizer::{BoundingBox, TextRecognizerSet},
+};
+
+/// Mean word confidence of a language on a crop.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanguageScore {
+    pub language: String,
+    pub confidence: f32,
+}
+
+/// Recognizes the image with each allowed language other than the current
+/// one and returns their scores.
+///
+/// The languages must be loaded in the recognizer set.
+pub fn score_languages(
+    config: &LanguageDetection,
+    current_language: &str,
+    text_recognizers: &TextRecognizerSet,
+    page_segmentation_mode: PageSegmentation,
+    image_data: &[u32],
+    image_width: u32,
+    image_height: u32,
+) -> Result<Vec<LanguageScore>, OcrError> {
+    let mut scores = Vec::new();
+
+    for language in &config.languages {
+        if language == current_language {
+            continue;
+        }
+
+        let text_recognizer = text_recognizers.get(Some(language));
+        text_recognizer.set_page_segmentation_mode(page_segmentation_mode);
+        text_recognizer.set_image(image_data, image_width, image_height);
+        text_recognizer.recognize()?;
+
+        scores.push(LanguageScore {
+            language: language.clone(),
+            confidence: mean_confidence(&text_recognizer.boxes(BoxLevel::Word)),
+        });
+    }
+
+    Ok(scores)
+}
+
+/// Returns the language to switch to, if any language scores better than
+/// the current one by at least the configured gain.
+pub fn choose_language<'a>(
+    config: &LanguageDetection,
+    current_confidence: f32,
+    scores: &'a [LanguageScore],
+) -> Option<&'a str> {
+    scores
+        .iter()
+        .filter(|score| score.confidence >= current_confidence + config.min_gain)
+        .max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())
+        .map(|score| score.language.as_str())
+}
+
+/// Returns the mean confidence of the words, or 0 without words.
+pub fn mean_confidence(word_boxes: &[BoundingBox]) -> f32 {
+    if word_boxes.is_empty() {
+        return 0.0;
+    }
+
+    word_boxes
+        .iter()
+        .map(|bounding_box| bounding_box.confidence)
+        .sum::<f32>()
+        / word_boxes.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_language() {
+        let config = LanguageDetection {
+            languages: vec!["eng".to_string(), "jpn".to_string(), "fra".to_string()],
+            interval_secs: 30.0,
+            min_gain: 0.1,
+        };
+        let score = |language: &str, confidence| LanguageScore {
+            language: language.to_string(),
+            confidence,
+        };
+
+        assert_eq!(
+            choose_language(&config, 0.3, &[score("jpn", 0.9), score("fra", 0.5)]),
+            Some("jpn")
+        );
+        assert_eq!(
+            choose_language(&config, 0.85, &[score("jpn", 0.9), score("fra", 0.5)]),
+            None
+        );
+        assert_eq!(choose_language(&config, 0.0, &[]), None);
+    }
+}
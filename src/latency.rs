@@ -0,0 +1,91 @@
+//! Measurement of the delay between frame capture and output.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+/// Percentiles of recent latencies in milliseconds.
+#[derive(Clone, Debug)]
+pub struct LatencySummary {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// Keeps the most recent latency samples.
+pub struct LatencyTracker {
+    capacity: usize,
+    samples: VecDeque<f64>,
+}
+
+impl LatencyTracker {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records the latency from `start_date` to `end_date`.
+    pub fn record(&mut self, start_date: &DateTime<Utc>, end_date: &DateTime<Utc>) {
+        let milliseconds = (*end_date - *start_date)
+            .num_microseconds()
+            .unwrap_or(i64::MAX) as f64
+            / 1000.0;
+
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(milliseconds.max(0.0));
+    }
+
+    /// Returns the percentiles or None if there are no samples.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let percentile = |value: f64| {
+            let index = ((sorted.len() - 1) as f64 * value).round() as usize;
+            sorted[index]
+        };
+
+        Some(LatencySummary {
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut tracker = LatencyTracker::new(100);
+        let start_date = Utc::now();
+
+        assert!(tracker.summary().is_none());
+
+        for milliseconds in 1..=200 {
+            tracker.record(
+                &start_date,
+                &(start_date + Duration::milliseconds(milliseconds)),
+            );
+        }
+
+        let summary = tracker.summary().unwrap();
+
+        assert_eq!(summary.p50, 151.0);
+        assert_eq!(summary.p95, 195.0);
+        assert_eq!(summary.p99, 199.0);
+    }
+}
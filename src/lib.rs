@@ -1,12 +1,68 @@
+pub mod acquisition;
+pub mod bench;
 mod bindings;
 pub mod canvas;
+pub mod canvas_publisher;
+pub mod capability;
 pub mod config;
+pub mod content_filter;
+pub mod control_socket;
+pub mod correction;
+pub mod debug_frame;
+pub mod debug_window;
+pub mod demo;
+pub mod discord;
+pub mod error;
+pub mod event;
+pub mod fault_injection;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
 pub mod frame;
+pub mod frame_quality;
+pub mod frame_result;
+pub mod frame_ring;
+pub mod frame_transport;
+#[cfg(all(test, feature = "golden"))]
+mod golden;
+pub mod http_api;
+pub mod image_directory;
+pub mod integrity;
+pub mod ipc;
+pub mod ipc_cleanup;
+pub mod irc;
+pub mod language_detection;
+pub mod latency;
 pub mod logging;
+pub mod manifest;
+pub mod message_bus;
 pub mod message_socket;
+pub mod metadata;
+pub mod mjpeg;
+pub mod ocr_engine;
+pub mod onnx_engine;
+pub mod output;
+pub mod perceptual_hash;
+pub mod pipeline;
+pub mod preprocess;
 pub mod processor;
+pub mod quota;
+pub mod recording;
+pub mod region_editor;
+pub mod region_proposal;
+pub mod resource_limit;
+pub mod scene;
+pub mod scene_detector;
+pub mod screen_capture;
+pub mod secret;
 pub mod shared_memory;
 pub mod stream_url;
+pub mod supervisor;
+pub mod telemetry;
 pub mod text_processor;
 pub mod text_recognizer;
+pub mod text_rules;
+pub mod twitch;
+pub mod twitch_api;
+pub mod validation;
 pub mod vnc;
+pub mod webhook;
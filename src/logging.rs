@@ -1,27 +1,278 @@
-use std::sync::{Arc, Mutex};
+//! Logging to the terminal or to rotating files.
+//!
+//! Logging is set up before the command line is parsed and is shared by the
+//! programs started by the supervisor, so it is configured with environment
+//! variables:
+//!
+//! - `TPPOCR_LOG_FORMAT`: `term` (default) for human readable lines or `json`
+//!   for one JSON object per line.
+//! - `TPPOCR_LOG_DIR`: directory of log files, named after the program such as
+//!   `tppocr.log`, in place of the terminal.
+//! - `TPPOCR_LOG_MAX_BYTES`: size at which a log file is rotated (default
+//!   100 MB).
+//! - `TPPOCR_LOG_ROTATE`: `hourly` or `daily` to also rotate log files at the
+//!   start of each hour or day (UTC).
+//! - `TPPOCR_LOG_KEEP`: number of rotated files kept, such as `tppocr.log.1`
+//!   (default 10).
+//! - `TPPOCR_LOG_LEVEL`: minimum level of records, optionally by module, such
+//!   as `info,frame=warning,text_processor=debug` (default `debug`). The
+//!   `--log-level` option of the programs replaces it once the command line is
+//!   parsed.
 
-use slog::{Drain, Level, LevelFilter};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Arg;
+use serde_json::{Map, Value};
+use slog::{Drain, FilterLevel, Key, Never, OwnedKVList, Record, Serializer, KV};
 use slog_async::{Async, OverflowStrategy};
 use slog_scope::{debug, GlobalLoggerGuard};
-use slog_term::{FullFormat, TermDecorator};
+use slog_term::{FullFormat, PlainSyncDecorator, TermDecorator};
+
+use crate::error::ConfigError;
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 100_000_000;
+const DEFAULT_KEEP_FILE_COUNT: usize = 10;
+/// Crate of the library modules, which filters can omit.
+const CRATE_NAME: &str = "tppocr";
+
+lazy_static::lazy_static! {
+    static ref LOG_FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::default());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Terminal,
+    Json,
+}
+
+/// Period at the start of which log files are rotated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+impl RotationInterval {
+    fn seconds(self) -> i64 {
+        match self {
+            RotationInterval::Hourly => 3600,
+            RotationInterval::Daily => 86400,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Directory of log files. Logs go to the terminal when absent.
+    pub directory: Option<PathBuf>,
+    pub max_file_size: u64,
+    pub rotation_interval: Option<RotationInterval>,
+    pub keep_file_count: usize,
+    pub filter: LogFilter,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Terminal,
+            directory: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            rotation_interval: None,
+            keep_file_count: DEFAULT_KEEP_FILE_COUNT,
+            filter: LogFilter::default(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Reads the config from the `TPPOCR_LOG_*` environment variables.
+    ///
+    /// Invalid values are reported on stderr and replaced with defaults
+    /// because there is no logger to report them yet.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars<F>(var: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = Self::default();
+
+        match var("TPPOCR_LOG_FORMAT").as_deref() {
+            None | Some("term") => {}
+            Some("json") => config.format = LogFormat::Json,
+            Some(value) => eprintln!("unknown TPPOCR_LOG_FORMAT {:?}, using term", value),
+        }
+
+        config.directory = var("TPPOCR_LOG_DIR").map(PathBuf::from);
+
+        if let Some(value) = var("TPPOCR_LOG_MAX_BYTES") {
+            match value.parse() {
+                Ok(size) => config.max_file_size = size,
+                Err(_) => eprintln!("invalid TPPOCR_LOG_MAX_BYTES {:?}", value),
+            }
+        }
+
+        match var("TPPOCR_LOG_ROTATE").as_deref() {
+            None => {}
+            Some("hourly") => config.rotation_interval = Some(RotationInterval::Hourly),
+            Some("daily") => config.rotation_interval = Some(RotationInterval::Daily),
+            Some(value) => eprintln!("unknown TPPOCR_LOG_ROTATE {:?}", value),
+        }
+
+        if let Some(value) = var("TPPOCR_LOG_KEEP") {
+            match value.parse() {
+                Ok(count) => config.keep_file_count = count,
+                Err(_) => eprintln!("invalid TPPOCR_LOG_KEEP {:?}", value),
+            }
+        }
+
+        if let Some(value) = var("TPPOCR_LOG_LEVEL") {
+            match LogFilter::parse(&value) {
+                Ok(filter) => config.filter = filter,
+                Err(error) => eprintln!("invalid TPPOCR_LOG_LEVEL: {}", error),
+            }
+        }
+
+        config
+    }
+}
+
+/// Minimum levels of records by module, such as
+/// `info,frame=warning,text_processor=debug`.
+///
+/// A level without a module applies to modules without their own level. The
+/// level of the longest matching module path applies, so `frame` also covers
+/// `frame::reader`. Module paths of the library may omit the crate name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFilter {
+    level: FilterLevel,
+    modules: Vec<(String, FilterLevel)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            level: FilterLevel::Debug,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Result<Self, ConfigError> {
+        let mut filter = Self::default();
+
+        for directive in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (Some(module.trim()), level.trim()),
+                None => (None, directive),
+            };
+            let level = level.parse().map_err(|_| ConfigError::LogLevel {
+                level: level.to_string(),
+            })?;
+
+            match module {
+                Some(module) => filter.modules.push((module.to_string(), level)),
+                None => filter.level = level,
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn accepts(&self, module: &str, level: slog::Level) -> bool {
+        let module = module
+            .strip_prefix(CRATE_NAME)
+            .and_then(|path| path.strip_prefix("::"))
+            .unwrap_or(module);
+        let filter_level = self
+            .modules
+            .iter()
+            .filter(|(prefix, _)| {
+                let prefix = prefix
+                    .strip_prefix(CRATE_NAME)
+                    .and_then(|path| path.strip_prefix("::"))
+                    .unwrap_or(prefix);
+
+                module == prefix
+                    || module
+                        .strip_prefix(prefix)
+                        .map_or(false, |rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |(_, level)| *level);
+
+        level.as_usize() <= filter_level.as_usize()
+    }
+}
+
+/// Replaces the log filter of the programs, such as from `--log-level`.
+pub fn set_log_filter(spec: &str) -> Result<(), ConfigError> {
+    *LOG_FILTER.write().unwrap() = LogFilter::parse(spec)?;
+
+    Ok(())
+}
+
+/// Returns the `--log-level` option shared by the programs.
+pub fn log_level_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("log_level")
+        .long("log-level")
+        .takes_value(true)
+        .value_name("LEVELS")
+        .help("Minimum log level, optionally by module, such as info,frame=warning,text_processor=debug")
+}
+
+/// Drops records below the level of the global filter.
+struct ModuleFilter<D: Drain> {
+    drain: D,
+}
+
+impl<D: Drain> Drain for ModuleFilter<D> {
+    type Ok = ();
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), D::Err> {
+        if LOG_FILTER
+            .read()
+            .unwrap()
+            .accepts(record.module(), record.level())
+        {
+            self.drain.log(record, values)?;
+        }
+
+        Ok(())
+    }
+}
 
 pub fn set_up_logging() {
     lazy_static::lazy_static! {
         static ref GLOBAL_LOGGER_GUARD: Arc<Mutex<Option<GlobalLoggerGuard>>> = Arc::new(Mutex::new(None));
     }
 
-    let decorator = TermDecorator::new().build();
-    let drain = FullFormat::new(decorator)
-        .use_utc_timestamp()
-        .use_original_order()
-        .build()
-        .fuse();
-    let drain = LevelFilter::new(drain, Level::Debug).fuse();
-    let drain = Async::new(drain)
+    let config = LoggingConfig::from_env();
+    *LOG_FILTER.write().unwrap() = config.filter.clone();
+
+    let drain = Async::new(create_drain(&config))
         .chan_size(512)
         .overflow_strategy(OverflowStrategy::Block)
         .build()
         .fuse();
+    // Filtered before the channel so dropped records are never copied to it
+    let drain = ModuleFilter { drain }.fuse();
 
     let logger = slog::Logger::root(drain, slog::o!());
     let guard = slog_scope::set_global_logger(logger);
@@ -29,5 +280,414 @@ pub fn set_up_logging() {
     let mut global_logger = GLOBAL_LOGGER_GUARD.lock().unwrap();
     *global_logger = Some(guard);
 
-    debug!("logging initialized");
+    debug!("logging initialized"; "format" => ?config.format, "directory" => ?config.directory);
+}
+
+type BoxedDrain = Box<dyn Drain<Ok = (), Err = Never> + Send>;
+
+fn create_drain(config: &LoggingConfig) -> BoxedDrain {
+    let directory = match &config.directory {
+        Some(directory) => directory,
+        None => {
+            return match config.format {
+                LogFormat::Terminal => Box::new(
+                    FullFormat::new(TermDecorator::new().build())
+                        .use_utc_timestamp()
+                        .use_original_order()
+                        .build()
+                        .fuse(),
+                ),
+                LogFormat::Json => Box::new(JsonFormat::new(io::stderr()).fuse()),
+            };
+        }
+    };
+
+    let path = directory.join(format!("{}.log", program_name()));
+    let file = match RotatingFile::open(&path, config) {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!("failed to open log file {:?}: {}", path, error);
+
+            return create_drain(&LoggingConfig {
+                directory: None,
+                ..config.clone()
+            });
+        }
+    };
+
+    // A full disk shouldn't stop the programs, so write errors are dropped
+    match config.format {
+        LogFormat::Terminal => Box::new(
+            FullFormat::new(PlainSyncDecorator::new(file))
+                .use_utc_timestamp()
+                .use_original_order()
+                .build()
+                .ignore_res(),
+        ),
+        LogFormat::Json => Box::new(JsonFormat::new(file).ignore_res()),
+    }
+}
+
+fn program_name() -> String {
+    std::env::args_os()
+        .next()
+        .as_ref()
+        .and_then(|arg| Path::new(arg).file_stem())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "tppocr".to_string())
+}
+
+/// Formats records as JSON objects with the time, level, message, and
+/// key-value pairs, one per line.
+struct JsonFormat<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonFormat<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonFormat<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut serializer = JsonSerializer(Map::new());
+        serializer.0.insert(
+            "ts".to_string(),
+            Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+        );
+        serializer
+            .0
+            .insert("level".to_string(), Value::from(record.level().as_str()));
+        serializer
+            .0
+            .insert("msg".to_string(), Value::from(record.msg().to_string()));
+
+        values
+            .serialize(record, &mut serializer)
+            .map_err(io::Error::other)?;
+        record
+            .kv()
+            .serialize(record, &mut serializer)
+            .map_err(io::Error::other)?;
+
+        let mut line = serde_json::to_vec(&serializer.0)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&line)?;
+        writer.flush()
+    }
+}
+
+/// Collects key-value pairs, keeping numbers and booleans as JSON values.
+struct JsonSerializer(Map<String, Value>);
+
+impl JsonSerializer {
+    fn insert<T: Into<Value>>(&mut self, key: Key, value: T) -> slog::Result {
+        self.0.insert(key.to_string(), value.into());
+        Ok(())
+    }
+}
+
+impl Serializer for JsonSerializer {
+    fn emit_arguments(&mut self, key: Key, value: &fmt::Arguments<'_>) -> slog::Result {
+        self.insert(key, value.to_string())
+    }
+
+    fn emit_str(&mut self, key: Key, value: &str) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_bool(&mut self, key: Key, value: bool) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_u32(&mut self, key: Key, value: u32) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_i32(&mut self, key: Key, value: i32) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_u64(&mut self, key: Key, value: u64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_i64(&mut self, key: Key, value: i64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_usize(&mut self, key: Key, value: usize) -> slog::Result {
+        self.insert(key, value as u64)
+    }
+
+    fn emit_isize(&mut self, key: Key, value: isize) -> slog::Result {
+        self.insert(key, value as i64)
+    }
+
+    fn emit_f32(&mut self, key: Key, value: f32) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_f64(&mut self, key: Key, value: f64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+}
+
+/// Log file renamed to `NAME.1`, `NAME.2`, and so on when it grows too large
+/// or a rotation interval starts, like logrotate.
+///
+/// Formats write a record in several parts and flush at its end, so writes
+/// are buffered until flushed and files are only rotated between records.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    record: Vec<u8>,
+    size: u64,
+    opened: DateTime<Utc>,
+    max_size: u64,
+    interval: Option<RotationInterval>,
+    keep_count: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, config: &LoggingConfig) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        // Appending to the file of a previous run continues its period
+        let opened = metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            record: Vec::new(),
+            size: metadata.len(),
+            opened,
+            max_size: config.max_file_size,
+            interval: config.rotation_interval,
+            keep_count: config.keep_file_count,
+        })
+    }
+
+    fn needs_rotation(&self, now: &DateTime<Utc>, write_size: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+
+        if self.size + write_size as u64 > self.max_size {
+            return true;
+        }
+
+        match self.interval {
+            Some(interval) => {
+                let seconds = interval.seconds();
+                now.timestamp().div_euclid(seconds) != self.opened.timestamp().div_euclid(seconds)
+            }
+            None => false,
+        }
+    }
+
+    fn rotate(&mut self, now: &DateTime<Utc>) -> io::Result<()> {
+        let rotated_path = |index: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            PathBuf::from(path)
+        };
+
+        if self.keep_count == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep_count).rev() {
+                let path = rotated_path(index);
+
+                if path.exists() {
+                    fs::rename(&path, rotated_path(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = *now;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.record.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.record.is_empty() {
+            let now = Utc::now();
+            // The record is dropped on errors instead of being written again
+            // with the next one
+            let record = std::mem::take(&mut self.record);
+
+            if self.needs_rotation(&now, record.len()) {
+                self.rotate(&now)?;
+            }
+
+            self.file.write_all(&record)?;
+            self.size += record.len() as u64;
+        }
+
+        self.file.flush()
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = LoggingConfig::from_vars(|name| match name {
+            "TPPOCR_LOG_FORMAT" => Some("json".to_string()),
+            "TPPOCR_LOG_DIR" => Some("/var/log/tppocr".to_string()),
+            "TPPOCR_LOG_ROTATE" => Some("daily".to_string()),
+            "TPPOCR_LOG_KEEP" => Some("x".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(config.directory, Some(PathBuf::from("/var/log/tppocr")));
+        assert_eq!(config.rotation_interval, Some(RotationInterval::Daily));
+        assert_eq!(config.keep_file_count, DEFAULT_KEEP_FILE_COUNT);
+    }
+
+    #[test]
+    fn test_log_filter() -> anyhow::Result<()> {
+        let filter = LogFilter::parse("info, frame=warning, tppocr::frame::reader=debug")?;
+
+        assert!(filter.accepts("tppocr::processor", slog::Level::Info));
+        assert!(!filter.accepts("tppocr::processor", slog::Level::Debug));
+        assert!(!filter.accepts("tppocr::frame", slog::Level::Info));
+        assert!(filter.accepts("tppocr::frame::reader", slog::Level::Debug));
+        assert!(filter.accepts("tppocr::frame_ring", slog::Level::Info));
+        assert!(filter.accepts("stream_dumper", slog::Level::Info));
+
+        assert!(!LogFilter::parse("off")?.accepts("tppocr", slog::Level::Critical));
+        assert!(LogFilter::parse("frame=loud").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotating_file() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("tppocr-logging-{}", std::process::id()));
+        let path = directory.join("test.log");
+        let config = LoggingConfig {
+            max_file_size: 10,
+            rotation_interval: Some(RotationInterval::Hourly),
+            keep_file_count: 2,
+            ..LoggingConfig::default()
+        };
+        let mut file = RotatingFile::open(&path, &config)?;
+
+        file.write_all(b"first\n")?;
+        file.flush()?;
+        // A record written in parts isn't split between files
+        file.write_all(b"sec")?;
+        file.write_all(b"ond\n")?;
+        file.flush()?;
+        file.write_all(b"third\n")?;
+        file.flush()?;
+
+        assert_eq!(fs::read_to_string(&path)?, "third\n");
+        assert_eq!(
+            fs::read_to_string(directory.join("test.log.1"))?,
+            "second\n"
+        );
+        assert_eq!(fs::read_to_string(directory.join("test.log.2"))?, "first\n");
+
+        file.write_all(b"fourth\n")?;
+        file.flush()?;
+
+        assert_eq!(
+            fs::read_to_string(directory.join("test.log.2"))?,
+            "second\n"
+        );
+        assert!(!directory.join("test.log.3").exists());
+
+        file.opened = Utc.ymd(2021, 1, 1).and_hms(10, 59, 0);
+        assert!(!file.needs_rotation(&Utc.ymd(2021, 1, 1).and_hms(10, 59, 59), 0));
+        assert!(file.needs_rotation(&Utc.ymd(2021, 1, 1).and_hms(11, 0, 0), 0));
+
+        fs::remove_dir_all(&directory)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_format() -> anyhow::Result<()> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let drain = JsonFormat::new(SharedBuffer(Arc::clone(&buffer))).fuse();
+        let logger = slog::Logger::root(drain, slog::o!("program" => "test"));
+
+        slog::info!(logger, "frame processed"; "region_name" => "dialog", "count" => 3u64, "muted" => false);
+
+        let line = String::from_utf8(buffer.lock().unwrap().clone())?;
+        let value: Value = serde_json::from_str(&line)?;
+
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["msg"], "frame processed");
+        assert_eq!(value["program"], "test");
+        assert_eq!(value["region_name"], "dialog");
+        assert_eq!(value["count"], 3);
+        assert_eq!(value["muted"], false);
+
+        Ok(())
+    }
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 }
@@ -1,18 +1,33 @@
-use clap::{App, Arg};
+use std::{collections::BTreeMap, path::Path};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use slog_scope::{info, warn};
 use tppocr::{
-    config::ProcessorConfig, frame::FrameReader, processor::Processor,
-    text_recognizer::TextRecognizer, vnc::VncClient,
+    capability::CapabilityReport,
+    config::{FrameTransport, ProcessorConfig},
+    control_socket::ControlServer,
+    debug_frame::{self, DebugSession},
+    debug_window::DebugWindow,
+    demo,
+    fault_injection::FaultInjector,
+    frame::FrameReader,
+    processor::Processor,
+    region_proposal::{self, RegionProposer},
+    shared_memory::SharedMemory,
+    text_recognizer::TextRecognizerSet,
+    vnc::VncClient,
 };
 
 fn main() -> anyhow::Result<()> {
     tppocr::logging::set_up_logging();
 
     let arg_matches = App::new("OCR service")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("config")
                 .takes_value(true)
                 .value_name("CONFIG")
-                .help("Filename of configuration file")
+                .help("Filename of configuration file or directory of configuration fragments")
                 .required(true),
         )
         .arg(
@@ -61,30 +76,285 @@ fn main() -> anyhow::Result<()> {
             Arg::with_name("tesseract_language")
                 .long("tesseract-language")
                 .default_value("eng")
-                .help("Tesseract language codes."),
+                .help("Tesseract language codes used for regions without a language."),
+        )
+        .arg(
+            Arg::with_name("ocr_threads")
+                .long("ocr-threads")
+                .default_value("1")
+                .help("Number of Tesseract instances recognizing regions in parallel."),
+        )
+        .arg(
+            Arg::with_name("control_socket")
+                .long("control-socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Filename of a Unix socket accepting JSON commands such as pause, resume, reload_config, and status"),
+        )
+        .arg(
+            Arg::with_name("window")
+                .long("window")
+                .help("Also show the debug display in a local X11 window, without the VNC server"),
         )
+        .arg(
+            Arg::with_name("fault_injection")
+                .long("fault-injection")
+                .help(
+                "Inject artificial faults configured in the fault_injection section (for testing)",
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("demo")
+                .about("Recognize synthetic frames of dialog without a stream to check the installation")
+                .arg(
+                    Arg::with_name("font")
+                        .long("font")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("FONT")
+                        .help("Font file or installed font name replacing Unifont, repeated for fallbacks"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("debug-frame")
+                .about("Interactively try region options on a single frame")
+                .arg(
+                    Arg::with_name("image")
+                        .takes_value(true)
+                        .value_name("IMAGE")
+                        .help("Filename of the frame image")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("calibrate")
+                .about("Recognize every region of the configuration once on a single frame and print the results")
+                .arg(
+                    Arg::with_name("image")
+                        .takes_value(true)
+                        .value_name("IMAGE")
+                        .help("Filename of the frame image")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("propose-regions")
+                .about("Print candidate regions where text was detected in multiple frames")
+                .arg(
+                    Arg::with_name("min_frames")
+                        .long("min-frames")
+                        .default_value("2")
+                        .help("Minimum number of frames in which text is detected at a location"),
+                )
+                .arg(
+                    Arg::with_name("image")
+                        .takes_value(true)
+                        .value_name("IMAGE")
+                        .help("Filenames of frame images, preferably of different scenes")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .arg(tppocr::logging::log_level_arg())
+        .arg(tppocr::ipc::namespace_arg())
         .get_matches();
 
-    let frame_reader = FrameReader::new(
-        arg_matches.value_of("stream_id").unwrap().parse()?,
-        arg_matches.value_of("stream_width").unwrap().parse()?,
-        arg_matches.value_of("stream_height").unwrap().parse()?,
-    )?;
+    if let Some(spec) = arg_matches.value_of("log_level") {
+        tppocr::logging::set_log_filter(spec)?;
+    }
+
+    if let Some(namespace) = arg_matches.value_of("ipc_namespace") {
+        tppocr::ipc::set_namespace(namespace)?;
+    }
+
+    if let Some(sub_matches) = arg_matches.subcommand_matches("debug-frame") {
+        let text_recognizers = TextRecognizerSet::new(
+            arg_matches.value_of("tesseract_data_path").unwrap(),
+            arg_matches.value_of("tesseract_language").unwrap(),
+            tesseract_variables(&arg_matches)?,
+        )?;
+        let mut session =
+            DebugSession::new(sub_matches.value_of("image").unwrap(), text_recognizers)?;
+        session.run()?;
+
+        return Ok(());
+    }
+
+    if let Some(sub_matches) = arg_matches.subcommand_matches("calibrate") {
+        let text_recognizers = TextRecognizerSet::new(
+            arg_matches.value_of("tesseract_data_path").unwrap(),
+            arg_matches.value_of("tesseract_language").unwrap(),
+            tesseract_variables(&arg_matches)?,
+        )?;
+        debug_frame::calibrate(
+            sub_matches.value_of("image").unwrap(),
+            config_path(&arg_matches)?,
+            text_recognizers,
+        )?;
+
+        return Ok(());
+    }
+
+    if let Some(sub_matches) = arg_matches.subcommand_matches("propose-regions") {
+        let text_recognizers = TextRecognizerSet::new(
+            arg_matches.value_of("tesseract_data_path").unwrap(),
+            arg_matches.value_of("tesseract_language").unwrap(),
+            tesseract_variables(&arg_matches)?,
+        )?;
+        let mut proposer = RegionProposer::new();
+
+        for path in sub_matches.values_of("image").unwrap() {
+            info!("detecting text lines"; "path" => path);
+            proposer.detect(text_recognizers.get(None), &image::open(path)?.into_rgba8())?;
+        }
+
+        let proposals = proposer.proposals(sub_matches.value_of("min_frames").unwrap().parse()?);
+        print!("{}", region_proposal::format_config(&proposals));
+
+        return Ok(());
+    }
+
+    let stream_width = arg_matches.value_of("stream_width").unwrap().parse()?;
+    let stream_height = arg_matches.value_of("stream_height").unwrap().parse()?;
+
+    if let Some(sub_matches) = arg_matches.subcommand_matches("demo") {
+        return run_demo(&arg_matches, sub_matches, stream_width, stream_height);
+    }
+    let config_path = config_path(&arg_matches)?;
+    let config = ProcessorConfig::load(config_path, stream_width, stream_height)?;
+    let text_recognizer_pool =
+        create_text_recognizer_pool(&arg_matches, &config.tesseract_variables)?;
+
+    CapabilityReport::probe(Some(text_recognizer_pool[0].get(None))).log();
+    info!("effective configuration"; "config" => ?config);
+
+    let frame_reader = match &config.frame_transport {
+        FrameTransport::SharedMemory => FrameReader::new(
+            arg_matches.value_of("stream_id").unwrap().parse()?,
+            stream_width,
+            stream_height,
+        )?,
+        FrameTransport::Tcp { address } => {
+            info!("connecting to frame sender"; "address" => address);
+            FrameReader::connect(address, stream_width, stream_height)?
+        }
+    };
     let vnc_client = VncClient::new(
         arg_matches.value_of("vnc_id").unwrap().parse()?,
         arg_matches.value_of("vnc_width").unwrap().parse()?,
         arg_matches.value_of("vnc_height").unwrap().parse()?,
     )?;
-    let text_recognizer = TextRecognizer::new(
-        arg_matches.value_of("tesseract_data_path").unwrap(),
-        arg_matches.value_of("tesseract_language").unwrap(),
-    )?;
 
-    let config_text = std::fs::read_to_string(arg_matches.value_of("config").unwrap())?;
-    let config: ProcessorConfig = toml::de::from_str(&config_text)?;
+    let fault_injection_config = config.fault_injection.clone();
+    let mut processor = Processor::new(frame_reader, vnc_client, text_recognizer_pool, config)?;
+    processor.set_config_path(Some(config_path.to_path_buf()));
+
+    if let Some(path) = arg_matches.value_of("control_socket") {
+        processor.set_control_server(Some(ControlServer::open(path)?));
+    }
+
+    if arg_matches.is_present("window") {
+        processor.set_debug_window(Some(DebugWindow::new(
+            arg_matches.value_of("vnc_width").unwrap().parse()?,
+            arg_matches.value_of("vnc_height").unwrap().parse()?,
+        )?));
+    }
+
+    if arg_matches.is_present("fault_injection") {
+        warn!("fault injection enabled");
+        processor.set_fault_injector(Some(FaultInjector::new(fault_injection_config)));
+    }
 
-    let mut processor = Processor::new(frame_reader, vnc_client, text_recognizer, config);
     processor.run()?;
 
     Ok(())
 }
+
+/// Returns the config path, which subcommands other than `calibrate` do not
+/// require.
+fn config_path<'a>(arg_matches: &'a ArgMatches) -> anyhow::Result<&'a Path> {
+    arg_matches
+        .value_of("config")
+        .map(Path::new)
+        .ok_or_else(|| anyhow::anyhow!("the CONFIG argument is required"))
+}
+
+/// Returns the Tesseract variables of the config if one is given.
+fn tesseract_variables(arg_matches: &ArgMatches) -> anyhow::Result<BTreeMap<String, String>> {
+    match arg_matches.value_of("config") {
+        Some(path) => Ok(ProcessorConfig::load_tesseract_variables(Path::new(path))?),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Creates a recognizer set for each OCR thread.
+fn create_text_recognizer_pool(
+    arg_matches: &ArgMatches,
+    variables: &BTreeMap<String, String>,
+) -> anyhow::Result<Vec<TextRecognizerSet>> {
+    let ocr_threads: usize = arg_matches.value_of("ocr_threads").unwrap().parse()?;
+    let mut text_recognizer_pool = Vec::new();
+
+    for _ in 0..ocr_threads.max(1) {
+        text_recognizer_pool.push(TextRecognizerSet::new(
+            arg_matches.value_of("tesseract_data_path").unwrap(),
+            arg_matches.value_of("tesseract_language").unwrap(),
+            variables.clone(),
+        )?);
+    }
+
+    Ok(text_recognizer_pool)
+}
+
+fn run_demo(
+    arg_matches: &ArgMatches,
+    sub_matches: &ArgMatches,
+    stream_width: u32,
+    stream_height: u32,
+) -> anyhow::Result<()> {
+    let stream_id: u16 = arg_matches.value_of("stream_id").unwrap().parse()?;
+    let vnc_id: u16 = arg_matches.value_of("vnc_id").unwrap().parse()?;
+    let mut config = ProcessorConfig::parse(demo::DEMO_CONFIG, stream_width, stream_height)?;
+
+    if let Some(fonts) = sub_matches.values_of("font") {
+        config.display.fonts = fonts.map(String::from).collect();
+    }
+
+    let text_recognizer_pool =
+        create_text_recognizer_pool(arg_matches, &config.tesseract_variables)?;
+
+    demo::start_frame_generator(
+        stream_id as u32,
+        stream_width,
+        stream_height,
+        &config.display.fonts,
+    )?;
+
+    let frame_reader = FrameReader::new(stream_id, stream_width, stream_height)?;
+    let vnc_client = VncClient::new(
+        vnc_id,
+        arg_matches.value_of("vnc_width").unwrap().parse()?,
+        arg_matches.value_of("vnc_height").unwrap().parse()?,
+    )?;
+
+    let mut processor = Processor::new(frame_reader, vnc_client, text_recognizer_pool, config)?;
+    processor.set_console_output(true);
+
+    if arg_matches.is_present("window") {
+        processor.set_debug_window(Some(DebugWindow::new(
+            arg_matches.value_of("vnc_width").unwrap().parse()?,
+            arg_matches.value_of("vnc_height").unwrap().parse()?,
+        )?));
+    }
+
+    info!("demo started, press Ctrl+C to stop"; "stream_id" => stream_id, "vnc_id" => vnc_id);
+
+    let result = processor.run();
+
+    for id in &[stream_id, vnc_id] {
+        SharedMemory::remove(*id as u32)?;
+    }
+
+    Ok(result?)
+}
@@ -0,0 +1,224 @@
+//! Session manifest listing the artifacts a run produced.
+//!
+//! The manifest is a JSON file rewritten whenever an artifact is added and
+//! finalized at shutdown with the size and SHA-256 hash of each artifact, so
+//! archivists can tell what a run produced and check the files are intact.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Artifact {
+    /// What the artifact is, such as `subtitles` or `jsonl_log`.
+    pub kind: String,
+    pub path: PathBuf,
+    pub created: DateTime<Utc>,
+    pub finalized: Option<DateTime<Utc>>,
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    /// Additional information such as the covered time range.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestDocument {
+    session_id: String,
+    program_version: String,
+    started: DateTime<Utc>,
+    finished: Option<DateTime<Utc>>,
+    config_path: Option<PathBuf>,
+    artifacts: Vec<Artifact>,
+}
+
+pub struct SessionManifest {
+    path: PathBuf,
+    document: ManifestDocument,
+}
+
+impl SessionManifest {
+    /// Creates a manifest for the session.
+    ///
+    /// `{session_id}` in the path is replaced with the session ID.
+    pub fn new(path: &str, session_id: String) -> Self {
+        Self {
+            path: PathBuf::from(path.replace("{session_id}", &session_id)),
+            document: ManifestDocument {
+                session_id,
+                program_version: env!("CARGO_PKG_VERSION").to_string(),
+                started: Utc::now(),
+                finished: None,
+                config_path: None,
+                artifacts: Vec::new(),
+            },
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.document.session_id
+    }
+
+    pub fn artifacts(&self) -> &[Artifact] {
+        &self.document.artifacts
+    }
+
+    pub fn set_config_path(&mut self, value: Option<PathBuf>) {
+        self.document.config_path = value;
+    }
+
+    /// Adds a file produced by the session and rewrites the manifest.
+    ///
+    /// A file already listed, such as one written again after a config
+    /// reload, is kept as it is.
+    pub fn add_artifact<P: Into<PathBuf>>(&mut self, kind: &str, path: P) -> io::Result<()> {
+        let path = path.into();
+
+        if self
+            .document
+            .artifacts
+            .iter()
+            .any(|artifact| artifact.path == path)
+        {
+            return Ok(());
+        }
+
+        self.document.artifacts.push(Artifact {
+            kind: kind.to_string(),
+            path,
+            created: Utc::now(),
+            finalized: None,
+            size: None,
+            sha256: None,
+            details: BTreeMap::new(),
+        });
+
+        self.write()
+    }
+
+    /// Sets additional information of an artifact, such as the last record
+    /// date. The manifest is written at the next change or at finalization.
+    pub fn set_artifact_detail(&mut self, path: &Path, key: &str, value: String) {
+        if let Some(artifact) = self
+            .document
+            .artifacts
+            .iter_mut()
+            .find(|artifact| artifact.path == path)
+        {
+            artifact.details.insert(key.to_string(), value);
+        }
+    }
+
+    /// Hashes the artifacts, marks the session finished, and writes the
+    /// manifest.
+    ///
+    /// Artifacts that no longer exist are listed without a hash.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        let date = Utc::now();
+
+        for artifact in &mut self.document.artifacts {
+            if let Ok((size, hash)) = hash_file(&artifact.path) {
+                artifact.size = Some(size);
+                artifact.sha256 = Some(hash);
+                artifact.finalized = Some(date);
+            }
+        }
+
+        self.document.finished = Some(date);
+
+        self.write()
+    }
+
+    /// Writes the manifest to a temporary file and renames it so a reader
+    /// never sees a partial manifest.
+    pub fn write(&self) -> io::Result<()> {
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(".tmp");
+
+        std::fs::write(
+            &temporary_path,
+            serde_json::to_vec_pretty(&self.document).map_err(io::Error::from)?,
+        )?;
+        std::fs::rename(&temporary_path, &self.path)
+    }
+}
+
+/// Returns a unique ID for a run starting now.
+pub fn generate_session_id() -> String {
+    let random: u64 = rand::thread_rng().gen();
+
+    format!("{}-{:016x}", Utc::now().format("%Y%m%dT%H%M%SZ"), random)
+}
+
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 65536];
+    let mut size = 0;
+
+    loop {
+        let read_size = file.read(&mut buffer)?;
+
+        if read_size == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read_size]);
+        size += read_size as u64;
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finalize() -> anyhow::Result<()> {
+        let directory =
+            std::env::temp_dir().join(format!("tppocr-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&directory)?;
+
+        let artifact_path = directory.join("log.jsonl");
+        std::fs::write(&artifact_path, "abc")?;
+
+        let manifest_path = directory.join("{session_id}.json");
+        let mut manifest =
+            SessionManifest::new(manifest_path.to_str().unwrap(), "test".to_string());
+        manifest.add_artifact("jsonl_log", &artifact_path)?;
+        manifest.add_artifact("jsonl_log", &artifact_path)?;
+        manifest.set_artifact_detail(&artifact_path, "last_frame_counter", "10".to_string());
+        manifest.finalize()?;
+
+        assert_eq!(manifest.artifacts().len(), 1);
+        assert_eq!(manifest.artifacts()[0].details["last_frame_counter"], "10");
+
+        assert_eq!(manifest.path(), directory.join("test.json"));
+        assert_eq!(manifest.artifacts()[0].size, Some(3));
+        assert_eq!(
+            manifest.artifacts()[0].sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+
+        let document: serde_json::Value = serde_json::from_slice(&std::fs::read(manifest.path())?)?;
+        assert_eq!(document["session_id"], "test");
+        assert!(document["finished"].is_string());
+
+        std::fs::remove_dir_all(&directory)?;
+
+        Ok(())
+    }
+}
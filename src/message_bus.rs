@@ -0,0 +1,175 @@
+//! Publishing emitted items and events to the message bus of the other
+//! stream services, so the processor can act as their OCR component.
+//!
+//! Each message is one JSON object on a line sent over TCP, holding the
+//! topic, the send time, and the payload. Field names of the payload are
+//! converted to the case the services expect.
+
+use std::{
+    io::Write,
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use slog_scope::{info, warn};
+
+use crate::{
+    config::{FieldCase, MessageBusConfig},
+    event::Event,
+    text_processor::TextItem,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// While the bus is unreachable, messages are dropped and the connection is
+/// retried periodically.
+pub struct MessageBusSink {
+    config: MessageBusConfig,
+    stream: Option<TcpStream>,
+    /// When connecting is tried again after it failed.
+    retry_time: Instant,
+}
+
+impl MessageBusSink {
+    pub fn new(config: MessageBusConfig) -> Self {
+        Self {
+            config,
+            stream: None,
+            retry_time: Instant::now(),
+        }
+    }
+
+    pub fn config(&self) -> &MessageBusConfig {
+        &self.config
+    }
+
+    pub fn send_item(&mut self, item: &TextItem) {
+        let message = self.message(&self.config.text_topic, item);
+        self.send(message);
+    }
+
+    pub fn send_event(&mut self, event: &Event) {
+        let message = self.message(&self.config.event_topic, event);
+        self.send(message);
+    }
+
+    fn message<T: Serialize>(&self, topic: &str, payload: &T) -> String {
+        let mut message = Map::new();
+        message.insert("topic".to_string(), Value::from(topic));
+        message.insert(
+            "timestamp".to_string(),
+            Value::from(Utc::now().to_rfc3339()),
+        );
+        message.insert(
+            "payload".to_string(),
+            convert_case(
+                serde_json::to_value(payload).unwrap_or(Value::Null),
+                self.config.field_case,
+            ),
+        );
+
+        Value::Object(message).to_string()
+    }
+
+    fn send(&mut self, mut message: String) {
+        let address = &self.config.address;
+
+        if self.stream.is_none() && Instant::now() >= self.retry_time {
+            match connect(address) {
+                Ok(stream) => {
+                    info!("connected to message bus"; "address" => address);
+                    self.stream = Some(stream);
+                }
+                Err(error) => {
+                    warn!("failed to connect to message bus";
+                        "address" => address, "error" => %error);
+                    self.retry_time = Instant::now() + RETRY_INTERVAL;
+                }
+            }
+        }
+
+        if let Some(stream) = &mut self.stream {
+            message.push('\n');
+
+            if let Err(error) = stream.write_all(message.as_bytes()) {
+                warn!("message bus connection lost"; "address" => address, "error" => %error);
+                self.stream = None;
+                self.retry_time = Instant::now();
+            }
+        }
+    }
+}
+
+fn connect(address: &str) -> std::io::Result<TcpStream> {
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "address not resolved"))?;
+    let stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    Ok(stream)
+}
+
+/// Converts the snake case field names of the objects in the value.
+///
+/// Keys of maps with arbitrary keys, such as metadata fields, are converted
+/// too, so such keys should be single words.
+pub fn convert_case(value: Value, field_case: FieldCase) -> Value {
+    match (value, field_case) {
+        (value, FieldCase::SnakeCase) => value,
+        (Value::Object(object), _) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (to_camel_case(&key), convert_case(value, field_case)))
+                .collect(),
+        ),
+        (Value::Array(array), _) => Value::Array(
+            array
+                .into_iter()
+                .map(|value| convert_case(value, field_case))
+                .collect(),
+        ),
+        (value, _) => value,
+    }
+}
+
+fn to_camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper_next = false;
+
+    for character in name.chars() {
+        if character == '_' {
+            upper_next = !result.is_empty();
+        } else if upper_next {
+            result.extend(character.to_uppercase());
+            upper_next = false;
+        } else {
+            result.push(character);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_convert_case() {
+        let value = json!({"region_name": "dialog", "run_day": 3, "lines": [{"x_1": 1}]});
+
+        assert_eq!(
+            convert_case(value.clone(), FieldCase::CamelCase),
+            json!({"regionName": "dialog", "runDay": 3, "lines": [{"x1": 1}]})
+        );
+        assert_eq!(convert_case(value.clone(), FieldCase::SnakeCase), value);
+    }
+}
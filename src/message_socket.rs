@@ -1,104 +1,135 @@
 use std::{
-    os::unix::net::UnixDatagram,
+    os::unix::net::{SocketAddr, UnixDatagram},
     path::{Path, PathBuf},
     time::Duration,
 };
 
-use anyhow::Context;
+use crate::{error::IpcError, ipc::IpcMode};
 
 pub struct MessageServer {
-    path: PathBuf,
+    path: Option<PathBuf>,
     socket: UnixDatagram,
 }
 
 impl MessageServer {
-    pub fn open(id: u32) -> anyhow::Result<Self> {
-        let path = PathBuf::from(format!("/tmp/tppocr_{}.socket", id));
-
-        if path.exists() {
-            std::fs::remove_file(&path)?;
-        }
-
-        let socket = UnixDatagram::bind(&path)?;
+    pub fn open(id: u32) -> Result<Self, IpcError> {
+        let (path, socket) = bind(&Self::path(id))?;
 
         Ok(Self { path, socket })
     }
 
-    pub fn set_nonblocking(&self, value: bool) -> anyhow::Result<()> {
+    /// Returns the filename of the socket of the ID.
+    ///
+    /// In the abstract IPC mode, the filename is the abstract socket name.
+    pub fn path(id: u32) -> PathBuf {
+        PathBuf::from(format!("/tmp/{}{}.socket", crate::ipc::name_prefix(), id))
+    }
+
+    pub fn set_nonblocking(&self, value: bool) -> Result<(), IpcError> {
         Ok(self.socket.set_nonblocking(value)?)
     }
 
-    pub fn set_timeout(&self, value: Option<Duration>) -> anyhow::Result<()> {
+    pub fn set_timeout(&self, value: Option<Duration>) -> Result<(), IpcError> {
         self.socket.set_read_timeout(value)?;
         self.socket.set_write_timeout(value)?;
         Ok(())
     }
 
-    pub fn send(&self, buffer: &[u8], client: &Path) -> anyhow::Result<usize> {
-        Ok(self.socket.send_to(buffer, client)?)
+    pub fn send(&self, buffer: &[u8], client: &SocketAddr) -> Result<usize, IpcError> {
+        Ok(self.socket.send_to_addr(buffer, client)?)
     }
 
-    pub fn receive(&self, buffer: &mut [u8]) -> anyhow::Result<(usize, PathBuf)> {
+    pub fn receive(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), IpcError> {
         let (size, address) = self.socket.recv_from(buffer)?;
 
-        if let Some(path) = address.as_pathname() {
-            Ok((size, path.to_path_buf()))
+        if address.is_unnamed() {
+            Err(IpcError::NoClientAddress)
         } else {
-            Err(anyhow::anyhow!("Client has no named address"))
+            Ok((size, address))
         }
     }
 }
 
 impl Drop for MessageServer {
     fn drop(&mut self) {
-        std::fs::remove_file(&self.path).unwrap();
+        if let Some(path) = &self.path {
+            std::fs::remove_file(path).unwrap();
+        }
     }
 }
 
 pub struct MessageClient {
-    path: PathBuf,
+    path: Option<PathBuf>,
     socket: UnixDatagram,
 }
 
 impl MessageClient {
-    pub fn open(id: u32) -> anyhow::Result<Self> {
-        let path = PathBuf::from(format!("/tmp/tppocr_client-{}.socket", id));
-
-        if path.exists() {
-            std::fs::remove_file(&path)?;
-        }
-
-        let server_path = PathBuf::from(format!("/tmp/tppocr_{}.socket", id));
-        let socket = UnixDatagram::bind(&path)?;
+    pub fn open(id: u32) -> Result<Self, IpcError> {
+        let (path, socket) = bind(Path::new(&format!(
+            "/tmp/{}client-{}.socket",
+            crate::ipc::name_prefix(),
+            id
+        )))?;
         socket
-            .connect(server_path)
-            .with_context(|| format!("Couldn't connect to message server socket {}", id))?;
+            .connect_addr(&address(&MessageServer::path(id))?)
+            .map_err(|source| IpcError::Connect { id, source })?;
 
         Ok(Self { path, socket })
     }
 
-    pub fn set_nonblocking(&self, value: bool) -> anyhow::Result<()> {
+    pub fn set_nonblocking(&self, value: bool) -> Result<(), IpcError> {
         Ok(self.socket.set_nonblocking(value)?)
     }
 
-    pub fn set_timeout(&self, value: Option<Duration>) -> anyhow::Result<()> {
+    pub fn set_timeout(&self, value: Option<Duration>) -> Result<(), IpcError> {
         self.socket.set_read_timeout(value)?;
         self.socket.set_write_timeout(value)?;
         Ok(())
     }
 
-    pub fn send(&self, buffer: &[u8]) -> anyhow::Result<usize> {
+    pub fn send(&self, buffer: &[u8]) -> Result<usize, IpcError> {
         Ok(self.socket.send(buffer)?)
     }
 
-    pub fn receive(&self, buffer: &mut [u8]) -> anyhow::Result<usize> {
+    pub fn receive(&self, buffer: &mut [u8]) -> Result<usize, IpcError> {
         Ok(self.socket.recv(buffer)?)
     }
 }
 
 impl Drop for MessageClient {
     fn drop(&mut self) {
-        std::fs::remove_file(&self.path).unwrap();
+        if let Some(path) = &self.path {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+/// Returns the address of the socket filename in the current IPC mode.
+fn address(path: &Path) -> Result<SocketAddr, IpcError> {
+    match IpcMode::current() {
+        IpcMode::Filesystem => Ok(SocketAddr::from_pathname(path)?),
+        IpcMode::Abstract => crate::ipc::abstract_address(&socket_name(path)),
+    }
+}
+
+fn socket_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Binds a socket, returning the file to remove when closing it, if any.
+fn bind(path: &Path) -> Result<(Option<PathBuf>, UnixDatagram), IpcError> {
+    match IpcMode::current() {
+        IpcMode::Filesystem => {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+
+            Ok((Some(path.to_path_buf()), UnixDatagram::bind(path)?))
+        }
+        IpcMode::Abstract => Ok((None, UnixDatagram::bind_addr(&address(path)?)?)),
     }
 }
 
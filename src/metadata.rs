@@ -0,0 +1,130 @@
+//! Context attached to every output record.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    config::MetadataConfig,
+    twitch_api::{StreamInfo, StreamInfoPoller},
+};
+
+/// Metadata of a record at the time it was produced.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RecordMetadata {
+    /// Static fields from the config such as run name, host, or game.
+    pub fields: BTreeMap<String, String>,
+    /// Day number of the run starting from day 0.
+    pub run_day: Option<i64>,
+    /// Seconds since processing started.
+    pub uptime_secs: i64,
+    /// Twitch stream of the channel when configured and live.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<StreamInfo>,
+}
+
+/// Computes the metadata for records.
+pub struct MetadataProvider {
+    config: MetadataConfig,
+    start_date: DateTime<Utc>,
+    stream_info_poller: Option<StreamInfoPoller>,
+    /// Logger with the stream in its context and the stream it was built
+    /// for.
+    logger: Option<(Option<StreamInfo>, slog::Logger)>,
+}
+
+impl MetadataProvider {
+    pub fn new(config: MetadataConfig) -> Self {
+        Self {
+            stream_info_poller: config.twitch.clone().map(StreamInfoPoller::new),
+            config,
+            start_date: Utc::now(),
+            logger: None,
+        }
+    }
+
+    /// Replaces the config while keeping the uptime.
+    ///
+    /// The stream is polled again only if the Twitch config changed.
+    pub fn set_config(&mut self, config: MetadataConfig) {
+        if self
+            .stream_info_poller
+            .as_ref()
+            .map(StreamInfoPoller::config)
+            != config.twitch.as_ref()
+        {
+            self.stream_info_poller = config.twitch.clone().map(StreamInfoPoller::new);
+        }
+
+        self.config = config;
+    }
+
+    pub fn snapshot(&self, date: &DateTime<Utc>) -> RecordMetadata {
+        RecordMetadata {
+            fields: self.config.fields.clone(),
+            run_day: self
+                .config
+                .run_start
+                .map(|run_start| (*date - run_start).num_days()),
+            uptime_secs: (*date - self.start_date).num_seconds(),
+            stream: self
+                .stream_info_poller
+                .as_ref()
+                .and_then(StreamInfoPoller::stream_info),
+        }
+    }
+
+    /// Returns the global logger with the title, game, and start time of the
+    /// stream added to its context while the channel is live.
+    pub fn logger(&mut self) -> slog::Logger {
+        let stream_info = self
+            .stream_info_poller
+            .as_ref()
+            .and_then(StreamInfoPoller::stream_info);
+
+        match &self.logger {
+            Some((logger_stream_info, logger)) if *logger_stream_info == stream_info => {
+                logger.clone()
+            }
+            _ => {
+                let logger = match &stream_info {
+                    Some(stream_info) => slog_scope::logger().new(slog::o!(
+                        "stream_title" => stream_info.title.clone(),
+                        "stream_game" => stream_info.game.clone(),
+                        "stream_started_at" => stream_info.started_at.to_rfc3339())),
+                    None => slog_scope::logger(),
+                };
+
+                self.logger = Some((stream_info, logger.clone()));
+                logger
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_run_day() {
+        let provider = MetadataProvider::new(MetadataConfig {
+            fields: BTreeMap::new(),
+            run_start: Some(Utc.ymd(2021, 1, 10).and_hms(21, 0, 0)),
+            twitch: None,
+        });
+
+        let metadata = provider.snapshot(&Utc.ymd(2021, 1, 11).and_hms(20, 0, 0));
+        assert_eq!(metadata.run_day, Some(0));
+
+        let metadata = provider.snapshot(&Utc.ymd(2021, 1, 24).and_hms(22, 0, 0));
+        assert_eq!(metadata.run_day, Some(14));
+
+        // Only a live stream is serialized
+        let value = serde_json::to_value(&metadata).unwrap();
+        assert!(value.get("stream").is_none());
+    }
+}
@@ -0,0 +1,250 @@
+//! Debug images served as MJPEG streams over HTTP, which dashboards and
+//! browsers can show in an `<img>` element without a VNC client.
+//!
+//! The debug image of each screen is served at `/ID`, such as `/8855`.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use image::{codecs::jpeg::JpegEncoder, ColorType};
+use slog_scope::{debug, info, warn};
+
+use crate::{
+    config::{MjpegConfig, VncScreenConfig},
+    shared_memory::SharedMemory,
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the server checks whether it was stopped while no client
+/// connects.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+const BOUNDARY: &str = "tppocr-frame";
+
+/// Serves the streams from background threads until dropped.
+pub struct MjpegServer {
+    stop_flag: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Copy)]
+struct Screen {
+    id: u16,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Clone, Copy)]
+struct StreamSettings {
+    interval: Duration,
+    quality: u8,
+}
+
+impl MjpegServer {
+    pub fn new(config: &MjpegConfig, screens: &[VncScreenConfig]) -> io::Result<Self> {
+        let listener = TcpListener::bind(&config.address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        info!("MJPEG server listening"; "address" => %local_address);
+
+        let screens: Vec<Screen> = screens
+            .iter()
+            .map(|screen| Screen {
+                id: screen.id,
+                width: screen.width,
+                height: screen.height,
+            })
+            .collect();
+        let settings = StreamSettings {
+            interval: Duration::from_secs_f64(1.0 / config.fps.max(0.1)),
+            quality: config.quality.clamp(1, 100),
+        };
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        std::thread::spawn(move || serve(listener, &screens, settings, &thread_stop_flag));
+
+        Ok(Self { stop_flag })
+    }
+}
+
+impl Drop for MjpegServer {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+fn serve(
+    listener: TcpListener,
+    screens: &[Screen],
+    settings: StreamSettings,
+    stop_flag: &Arc<AtomicBool>,
+) {
+    while !stop_flag.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                let screens = screens.to_vec();
+                let stop_flag = Arc::clone(stop_flag);
+
+                // Each client is streamed to until it disconnects
+                std::thread::spawn(move || {
+                    if let Err(error) = serve_client(stream, &screens, settings, &stop_flag) {
+                        debug!("MJPEG client disconnected";
+                            "address" => %address, "error" => %error);
+                    }
+                });
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_INTERVAL)
+            }
+            Err(error) => {
+                warn!("MJPEG server stopped"; "error" => %error);
+                return;
+            }
+        }
+    }
+}
+
+fn serve_client(
+    mut stream: TcpStream,
+    screens: &[Screen],
+    settings: StreamSettings,
+    stop_flag: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Headers are read so the client doesn't see the connection reset
+    let mut header = String::new();
+
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let screen = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => find_screen(screens, target),
+        _ => None,
+    };
+    let screen = match screen {
+        Some(screen) => screen,
+        None => {
+            return write!(
+                stream,
+                "HTTP/1.0 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 9\r\n\r\nnot found"
+            );
+        }
+    };
+
+    let data_size = (screen.width * screen.height * 4) as usize;
+    let shared_memory =
+        SharedMemory::open(screen.id as u32, data_size).map_err(io::Error::other)?;
+    let mut rgb = vec![0; (screen.width * screen.height * 3) as usize];
+    let mut jpeg = Vec::new();
+
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-cache\r\n\r\n",
+        BOUNDARY
+    )?;
+
+    while !stop_flag.load(Ordering::Relaxed) {
+        // The previous image is sent again if the processor is drawing
+        if read_rgb(&shared_memory, &mut rgb) {
+            jpeg.clear();
+            JpegEncoder::new_with_quality(&mut jpeg, settings.quality)
+                .encode(&rgb, screen.width, screen.height, ColorType::Rgb8)
+                .map_err(io::Error::other)?;
+        }
+
+        if !jpeg.is_empty() {
+            write!(
+                stream,
+                "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                BOUNDARY,
+                jpeg.len()
+            )?;
+            stream.write_all(&jpeg)?;
+            stream.write_all(b"\r\n")?;
+        }
+
+        std::thread::sleep(settings.interval);
+    }
+
+    Ok(())
+}
+
+fn find_screen(screens: &[Screen], target: &str) -> Option<Screen> {
+    let path = target.split('?').next().unwrap_or(target);
+
+    screens
+        .iter()
+        .find(|screen| path.trim_start_matches('/') == screen.id.to_string())
+        .copied()
+}
+
+/// Copies the debug image as RGB, returning false if the processor was
+/// drawing it.
+fn read_rgb(shared_memory: &SharedMemory, rgb: &mut [u8]) -> bool {
+    let sequence = match shared_memory.begin_read() {
+        Some(sequence) => sequence,
+        None => return false,
+    };
+
+    // The VNC server's default pixel format on little-endian hosts puts red
+    // in the first byte, which shows the RGBA stream frames drawn on the
+    // canvas as they are, so the bytes are read in the same order
+    for (rgba, rgb_pixel) in shared_memory
+        .data()
+        .chunks_exact(4)
+        .zip(rgb.chunks_exact_mut(3))
+    {
+        rgb_pixel.copy_from_slice(&rgba[..3]);
+    }
+
+    shared_memory.validate_read(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_rgb() -> anyhow::Result<()> {
+        let mut shared_memory = SharedMemory::open_or_create(125, 8)?;
+        let screens = [Screen {
+            id: 125,
+            width: 2,
+            height: 1,
+        }];
+
+        assert!(find_screen(&screens, "/125?t=1").is_some());
+        assert!(find_screen(&screens, "/126").is_none());
+
+        shared_memory.begin_write();
+        shared_memory
+            .data_mut()
+            .copy_from_slice(&[1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let mut rgb = vec![0; 6];
+        assert!(!read_rgb(&shared_memory, &mut rgb));
+
+        shared_memory.end_write();
+        assert!(read_rgb(&shared_memory, &mut rgb));
+        assert_eq!(rgb, [1, 2, 3, 4, 5, 6]);
+
+        shared_memory.unlink()?;
+
+        Ok(())
+    }
+}
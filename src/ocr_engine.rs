@@ -0,0 +1,102 @@
+//! Interface of the OCR engines that recognize region images.
+//!
+//! Tesseract, through `TextRecognizer`, is the default engine. Other engines
+//! implement the same trait so the processor, language detection, and tools
+//! don't depend on Tesseract.
+
+use std::str::FromStr;
+
+use crate::{
+    error::OcrError,
+    text_recognizer::{BoundingBox, TextLine},
+};
+
+/// Layout level of recognized elements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxLevel {
+    Block,
+    Line,
+    Word,
+    Symbol,
+}
+
+/// How the engine divides the image into text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSegmentation {
+    /// Blocks of text anywhere in the image.
+    Auto,
+    /// A single block of text of any number of lines.
+    SingleBlock,
+    SingleLine,
+    SingleWord,
+    SingleCharacter,
+    /// As much text as possible in no particular order.
+    SparseText,
+}
+
+impl FromStr for PageSegmentation {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "auto" => Ok(Self::Auto),
+            "block" => Ok(Self::SingleBlock),
+            "line" => Ok(Self::SingleLine),
+            "word" => Ok(Self::SingleWord),
+            "character" => Ok(Self::SingleCharacter),
+            "sparse" => Ok(Self::SparseText),
+            _ => Err(format!(
+                "expected page segmentation auto, block, line, word, character, or sparse, got {:?}",
+                text
+            )),
+        }
+    }
+}
+
+/// An OCR engine instance for one language.
+///
+/// Methods take `&self` like the Tesseract API so one instance can be
+/// borrowed from a recognizer set by the worker that uses it.
+pub trait OcrEngine: Send {
+    /// Sets how the image is divided into text. Engines that segment the
+    /// image themselves ignore it.
+    fn set_page_segmentation_mode(&self, _mode: PageSegmentation) {}
+
+    /// Restricts recognition to the characters, or restores the whitelist of
+    /// the engine's variables if none. Engines that can't restrict characters
+    /// ignore it.
+    fn set_character_whitelist(&self, _characters: Option<&str>) {}
+
+    /// Sets the image as 32-bit pixels to be recognized.
+    fn set_image(&self, data: &[u32], width: u32, height: u32);
+
+    /// Restricts recognition to a rectangle of the image.
+    fn set_rectangle(&self, left: u32, top: u32, width: u32, height: u32);
+
+    fn recognize(&self) -> Result<(), OcrError>;
+
+    /// Returns the recognized text of the image.
+    fn text(&self) -> String;
+
+    /// Returns the bounding boxes of the elements at the level in image
+    /// coordinates.
+    fn boxes(&self, level: BoxLevel) -> Vec<BoundingBox>;
+
+    /// Returns the text of each element at the level with its bounding box.
+    fn texts(&self, level: BoxLevel) -> Vec<TextLine>;
+
+    /// Returns the result as an hOCR document if the engine supports it.
+    fn hocr(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the result as a Tesseract TSV table if the engine supports it.
+    fn tsv(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the language codes the engine can load.
+    fn available_languages(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
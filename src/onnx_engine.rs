@@ -0,0 +1,582 @@
+//! OCR with ONNX models, for fonts Tesseract struggles with such as stylized
+//! battle text.
+//!
+//! Models are expected in the format exported by PaddleOCR. The recognition
+//! model takes a text line image of a fixed height and outputs the
+//! probabilities of each character class per column, decoded with CTC. The
+//! optional detection model outputs a map of text probability per pixel from
+//! which the line boxes are found.
+//!
+//! The engine requires the `onnx` feature. ONNX Runtime is loaded at run time
+//! from the path in the `ORT_DYLIB_PATH` environment variable or the library
+//! search path.
+
+use std::path::Path;
+
+use crate::{
+    config::OnnxConfig, error::OcrError, ocr_engine::OcrEngine, text_recognizer::BoundingBox,
+};
+
+/// Distance relative to a detected box's area over its perimeter that the
+/// box is expanded by, since detection maps are tighter than the text.
+const UNCLIP_RATIO: f32 = 1.5;
+/// Detected boxes narrower or shorter than this in pixels are noise.
+const MIN_BOX_SIZE: i32 = 3;
+
+/// Creates an engine for the models.
+pub fn create_engine(config: &OnnxConfig) -> Result<Box<dyn OcrEngine>, OcrError> {
+    #[cfg(feature = "onnx")]
+    {
+        Ok(Box::new(engine::OnnxEngine::new(config.clone())?))
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    {
+        let _ = config;
+        Err(OcrError::OnnxUnavailable)
+    }
+}
+
+/// Reads the characters of the recognition model's classes.
+///
+/// Class 0 is the CTC blank, so line N of the file is class N + 1. A space
+/// is appended as the last class like PaddleOCR's dictionaries with spaces
+/// enabled.
+pub fn load_dictionary(path: &Path) -> Result<Vec<String>, OcrError> {
+    let text = std::fs::read_to_string(path).map_err(|source| OcrError::Dictionary {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut dictionary: Vec<String> = text
+        .lines()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect();
+    dictionary.push(" ".to_string());
+
+    Ok(dictionary)
+}
+
+/// Character decoded from recognition model output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedSymbol {
+    pub text: String,
+    pub confidence: f32,
+    /// Output column of the character, which is proportional to its
+    /// horizontal position in the line image.
+    pub step: usize,
+}
+
+/// Decodes the class probabilities of each output column with greedy CTC.
+///
+/// The most probable class of each column is taken, then repeated classes
+/// are merged and blanks are removed.
+pub fn ctc_decode(
+    probabilities: &[f32],
+    class_count: usize,
+    dictionary: &[String],
+) -> Vec<DecodedSymbol> {
+    let mut symbols = Vec::new();
+    let mut previous_class = 0;
+
+    for (step, column) in probabilities.chunks_exact(class_count.max(1)).enumerate() {
+        let (class, confidence) = column.iter().enumerate().fold(
+            (0, f32::MIN),
+            |(best_class, best), (class, &probability)| {
+                if probability > best {
+                    (class, probability)
+                } else {
+                    (best_class, best)
+                }
+            },
+        );
+
+        if class != 0 && class != previous_class {
+            if let Some(text) = dictionary.get(class - 1) {
+                symbols.push(DecodedSymbol {
+                    text: text.clone(),
+                    confidence,
+                    step,
+                });
+            }
+        }
+
+        previous_class = class;
+    }
+
+    symbols
+}
+
+/// Finds the boxes of connected areas of the detection map above the
+/// threshold.
+///
+/// The confidence of a box is the mean probability of its pixels. Boxes are
+/// expanded to cover the whole text and clamped to the map.
+pub fn find_text_boxes(map: &[f32], width: u32, height: u32, threshold: f32) -> Vec<BoundingBox> {
+    let width = width as usize;
+    let height = height as usize;
+    let mut visited = vec![false; map.len()];
+    let mut boxes = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..map.len().min(width * height) {
+        if visited[start] || map[start] <= threshold {
+            continue;
+        }
+
+        let (mut x1, mut y1, mut x2, mut y2) = (width, height, 0, 0);
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        visited[start] = true;
+        stack.push(start);
+
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            x1 = x1.min(x);
+            y1 = y1.min(y);
+            x2 = x2.max(x + 1);
+            y2 = y2.max(y + 1);
+            sum += map[index];
+            count += 1;
+
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+
+            for neighbour in neighbours.iter().flatten() {
+                if !visited[*neighbour] && map[*neighbour] > threshold {
+                    visited[*neighbour] = true;
+                    stack.push(*neighbour);
+                }
+            }
+        }
+
+        let box_width = (x2 - x1) as f32;
+        let box_height = (y2 - y1) as f32;
+
+        if box_width < MIN_BOX_SIZE as f32 || box_height < MIN_BOX_SIZE as f32 {
+            continue;
+        }
+
+        let distance =
+            (box_width * box_height * UNCLIP_RATIO / (2.0 * (box_width + box_height))) as i32;
+
+        boxes.push(BoundingBox {
+            confidence: sum / count as f32,
+            x1: (x1 as i32 - distance).max(0),
+            y1: (y1 as i32 - distance).max(0),
+            x2: (x2 as i32 + distance).min(width as i32),
+            y2: (y2 as i32 + distance).min(height as i32),
+        });
+    }
+
+    // In reading order
+    boxes.sort_by_key(|bounding_box| (bounding_box.y1, bounding_box.x1));
+
+    boxes
+}
+
+#[cfg(feature = "onnx")]
+mod engine {
+    use std::cell::RefCell;
+
+    use image::{imageops::FilterType, Rgba, RgbaImage};
+    use ort::{session::Session, value::Tensor};
+
+    use super::*;
+    use crate::{ocr_engine::BoxLevel, text_recognizer::TextLine};
+
+    /// Detection model input sides must be a multiple of this.
+    const DETECTION_ALIGNMENT: u32 = 32;
+    /// Longest side of the detection model input.
+    const MAX_DETECTION_SIDE: u32 = 960;
+    const DETECTION_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+    const DETECTION_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+    pub struct OnnxEngine {
+        config: OnnxConfig,
+        dictionary: Vec<String>,
+        recognition: RefCell<Session>,
+        detection: Option<RefCell<Session>>,
+        state: RefCell<State>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        image: RgbaImage,
+        rectangle: Option<(u32, u32, u32, u32)>,
+        lines: Vec<Line>,
+    }
+
+    struct Line {
+        bounding_box: BoundingBox,
+        /// Characters with their boxes, including spaces.
+        symbols: Vec<TextLine>,
+    }
+
+    impl OnnxEngine {
+        pub fn new(config: OnnxConfig) -> Result<Self, OcrError> {
+            let dictionary = load_dictionary(&config.dictionary)?;
+            let recognition = Session::builder()?.commit_from_file(&config.recognition_model)?;
+            let detection = match &config.detection_model {
+                Some(path) => Some(RefCell::new(Session::builder()?.commit_from_file(path)?)),
+                None => None,
+            };
+
+            Ok(Self {
+                config,
+                dictionary,
+                recognition: RefCell::new(recognition),
+                detection,
+                state: RefCell::default(),
+            })
+        }
+
+        /// Returns the line boxes in the image.
+        fn detect_lines(&self, image: &RgbaImage) -> Result<Vec<BoundingBox>, OcrError> {
+            let detection = match &self.detection {
+                Some(detection) => detection,
+                None => {
+                    return Ok(vec![BoundingBox {
+                        confidence: 1.0,
+                        x1: 0,
+                        y1: 0,
+                        x2: image.width() as i32,
+                        y2: image.height() as i32,
+                    }])
+                }
+            };
+
+            let scale =
+                (MAX_DETECTION_SIDE as f32 / image.width().max(image.height()) as f32).min(1.0);
+            let align = |side: u32| {
+                let side = (side as f32 * scale).round() as u32;
+                ((side + DETECTION_ALIGNMENT / 2) / DETECTION_ALIGNMENT).max(1)
+                    * DETECTION_ALIGNMENT
+            };
+            let (width, height) = (align(image.width()), align(image.height()));
+            let resized = image::imageops::resize(image, width, height, FilterType::Triangle);
+            let input = to_tensor(&resized, |channel, value| {
+                (value / 255.0 - DETECTION_MEAN[channel]) / DETECTION_STD[channel]
+            })?;
+
+            let mut session = detection.borrow_mut();
+            let outputs = session.run(ort::inputs![input])?;
+            let (shape, map) = outputs[0].try_extract_tensor::<f32>()?;
+
+            if shape.len() != 4 || shape[2] != height as i64 || shape[3] != width as i64 {
+                return Err(OcrError::OnnxOutputShape(shape.to_vec()));
+            }
+
+            let x_scale = image.width() as f32 / width as f32;
+            let y_scale = image.height() as f32 / height as f32;
+
+            Ok(
+                find_text_boxes(map, width, height, self.config.detection_threshold)
+                    .into_iter()
+                    .map(|bounding_box| BoundingBox {
+                        x1: (bounding_box.x1 as f32 * x_scale) as i32,
+                        y1: (bounding_box.y1 as f32 * y_scale) as i32,
+                        x2: (bounding_box.x2 as f32 * x_scale).ceil() as i32,
+                        y2: (bounding_box.y2 as f32 * y_scale).ceil() as i32,
+                        ..bounding_box
+                    })
+                    .collect(),
+            )
+        }
+
+        /// Recognizes a line image, returning its characters with boxes
+        /// relative to the line.
+        fn recognize_line(&self, image: &RgbaImage) -> Result<Vec<TextLine>, OcrError> {
+            let height = self.config.line_height.max(1);
+            let width = ((image.width() as f32 * height as f32 / image.height().max(1) as f32)
+                .ceil() as u32)
+                .max(1);
+            let resized = image::imageops::resize(image, width, height, FilterType::Triangle);
+            let input = to_tensor(&resized, |_, value| value / 255.0 * 2.0 - 1.0)?;
+
+            let mut session = self.recognition.borrow_mut();
+            let outputs = session.run(ort::inputs![input])?;
+            let (shape, probabilities) = outputs[0].try_extract_tensor::<f32>()?;
+
+            if shape.len() != 3 || shape[1] <= 0 || shape[2] <= 0 {
+                return Err(OcrError::OnnxOutputShape(shape.to_vec()));
+            }
+
+            let step_count = shape[1] as f32;
+            let step_width = image.width() as f32 / step_count;
+
+            Ok(
+                ctc_decode(probabilities, shape[2] as usize, &self.dictionary)
+                    .into_iter()
+                    .map(|symbol| TextLine {
+                        text: symbol.text,
+                        bounding_box: BoundingBox {
+                            confidence: symbol.confidence,
+                            x1: (symbol.step as f32 * step_width) as i32,
+                            y1: 0,
+                            x2: ((symbol.step + 1) as f32 * step_width).ceil() as i32,
+                            y2: image.height() as i32,
+                        },
+                    })
+                    .collect(),
+            )
+        }
+
+        /// Returns the text and box of each element at the level.
+        fn elements(&self, level: BoxLevel) -> Vec<TextLine> {
+            let state = self.state.borrow();
+
+            match level {
+                BoxLevel::Block => {
+                    let lines: Vec<TextLine> =
+                        state.lines.iter().map(|line| line.to_text_line()).collect();
+
+                    merge(&lines, "\n").into_iter().collect()
+                }
+                BoxLevel::Line => state.lines.iter().map(|line| line.to_text_line()).collect(),
+                BoxLevel::Word => state
+                    .lines
+                    .iter()
+                    .flat_map(|line| {
+                        line.symbols
+                            .split(|symbol| symbol.text.trim().is_empty())
+                            .filter_map(|word| merge(word, ""))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect(),
+                BoxLevel::Symbol => state
+                    .lines
+                    .iter()
+                    .flat_map(|line| &line.symbols)
+                    .filter(|symbol| !symbol.text.trim().is_empty())
+                    .cloned()
+                    .collect(),
+            }
+        }
+    }
+
+    impl Line {
+        fn to_text_line(&self) -> TextLine {
+            let text: String = self
+                .symbols
+                .iter()
+                .map(|symbol| symbol.text.as_str())
+                .collect();
+
+            TextLine {
+                text: text.trim().to_string(),
+                bounding_box: BoundingBox {
+                    confidence: mean_confidence(&self.symbols),
+                    ..self.bounding_box.clone()
+                },
+            }
+        }
+    }
+
+    impl OcrEngine for OnnxEngine {
+        fn set_image(&self, data: &[u32], width: u32, height: u32) {
+            let bytes = data.iter().flat_map(|pixel| pixel.to_ne_bytes()).collect();
+            let mut state = self.state.borrow_mut();
+
+            state.image = RgbaImage::from_raw(width, height, bytes).unwrap_or_default();
+            state.rectangle = None;
+            state.lines.clear();
+        }
+
+        fn set_rectangle(&self, left: u32, top: u32, width: u32, height: u32) {
+            self.state.borrow_mut().rectangle = Some((left, top, width, height));
+        }
+
+        fn recognize(&self) -> Result<(), OcrError> {
+            let (image, left, top) = {
+                let state = self.state.borrow();
+
+                match state.rectangle {
+                    Some((left, top, width, height)) => (
+                        image::imageops::crop_imm(&state.image, left, top, width, height)
+                            .to_image(),
+                        left as i32,
+                        top as i32,
+                    ),
+                    None => (state.image.clone(), 0, 0),
+                }
+            };
+
+            let mut lines = Vec::new();
+
+            if image.width() > 0 && image.height() > 0 {
+                for line_box in self.detect_lines(&image)? {
+                    let line_image = image::imageops::crop_imm(
+                        &image,
+                        line_box.x1 as u32,
+                        line_box.y1 as u32,
+                        (line_box.x2 - line_box.x1) as u32,
+                        (line_box.y2 - line_box.y1) as u32,
+                    )
+                    .to_image();
+                    let mut symbols = self.recognize_line(&line_image)?;
+
+                    for symbol in &mut symbols {
+                        let bounding_box = &mut symbol.bounding_box;
+                        bounding_box.x1 += line_box.x1 + left;
+                        bounding_box.x2 += line_box.x1 + left;
+                        bounding_box.y1 += line_box.y1 + top;
+                        bounding_box.y2 += line_box.y1 + top;
+                    }
+
+                    if !symbols.is_empty() {
+                        lines.push(Line {
+                            bounding_box: BoundingBox {
+                                x1: line_box.x1 + left,
+                                y1: line_box.y1 + top,
+                                x2: line_box.x2 + left,
+                                y2: line_box.y2 + top,
+                                ..line_box
+                            },
+                            symbols,
+                        });
+                    }
+                }
+            }
+
+            self.state.borrow_mut().lines = lines;
+
+            Ok(())
+        }
+
+        fn text(&self) -> String {
+            self.elements(BoxLevel::Line)
+                .iter()
+                .map(|line| format!("{}\n", line.text))
+                .collect()
+        }
+
+        fn boxes(&self, level: BoxLevel) -> Vec<BoundingBox> {
+            self.elements(level)
+                .into_iter()
+                .map(|element| element.bounding_box)
+                .collect()
+        }
+
+        fn texts(&self, level: BoxLevel) -> Vec<TextLine> {
+            self.elements(level)
+        }
+    }
+
+    /// Converts the image to a 1 × 3 × height × width tensor of the
+    /// normalized RGB values.
+    fn to_tensor<F>(image: &RgbaImage, normalize: F) -> Result<Tensor<f32>, OcrError>
+    where
+        F: Fn(usize, f32) -> f32,
+    {
+        let (width, height) = image.dimensions();
+        let plane_size = (width * height) as usize;
+        let mut data = vec![0.0; plane_size * 3];
+
+        for (index, Rgba(pixel)) in image.pixels().enumerate() {
+            for channel in 0..3 {
+                data[channel * plane_size + index] = normalize(channel, pixel[channel] as f32);
+            }
+        }
+
+        Ok(Tensor::from_array((
+            [1, 3, height as usize, width as usize],
+            data,
+        ))?)
+    }
+
+    /// Joins the elements into one enclosing them.
+    fn merge(elements: &[TextLine], separator: &str) -> Option<TextLine> {
+        let first = elements.first()?;
+        let mut bounding_box = first.bounding_box.clone();
+
+        for element in elements {
+            bounding_box.x1 = bounding_box.x1.min(element.bounding_box.x1);
+            bounding_box.y1 = bounding_box.y1.min(element.bounding_box.y1);
+            bounding_box.x2 = bounding_box.x2.max(element.bounding_box.x2);
+            bounding_box.y2 = bounding_box.y2.max(element.bounding_box.y2);
+        }
+
+        bounding_box.confidence = mean_confidence(elements);
+
+        Some(TextLine {
+            text: elements
+                .iter()
+                .map(|element| element.text.as_str())
+                .collect::<Vec<_>>()
+                .join(separator),
+            bounding_box,
+        })
+    }
+
+    fn mean_confidence(elements: &[TextLine]) -> f32 {
+        if elements.is_empty() {
+            return 0.0;
+        }
+
+        elements
+            .iter()
+            .map(|element| element.bounding_box.confidence)
+            .sum::<f32>()
+            / elements.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctc_decode() {
+        let dictionary = vec!["A".to_string(), "B".to_string(), " ".to_string()];
+        #[rustfmt::skip]
+        let probabilities = [
+            0.1, 0.8, 0.1, 0.0,
+            0.2, 0.7, 0.1, 0.0,
+            0.9, 0.0, 0.1, 0.0,
+            0.1, 0.6, 0.3, 0.0,
+            0.1, 0.1, 0.1, 0.7,
+            0.0, 0.0, 0.9, 0.1,
+        ];
+
+        let symbols = ctc_decode(&probabilities, 4, &dictionary);
+        let text: String = symbols.iter().map(|symbol| symbol.text.as_str()).collect();
+
+        assert_eq!(text, "AA B");
+        assert_eq!(symbols[0].step, 0);
+        assert_eq!(symbols[1].step, 3);
+        assert!((symbols[3].confidence - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_find_text_boxes() {
+        let (width, height) = (40, 20);
+        let mut map = vec![0.0; width * height];
+
+        for y in 8..12 {
+            for x in 20..36 {
+                map[y * width + x] = 0.9;
+            }
+            for x in 2..12 {
+                map[y * width + x] = 0.5;
+            }
+        }
+
+        // Noise
+        map[width + 1] = 0.9;
+
+        let boxes = find_text_boxes(&map, width as u32, height as u32, 0.3);
+
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(
+            (boxes[0].x1, boxes[0].y1, boxes[0].x2, boxes[0].y2),
+            (0, 6, 14, 14)
+        );
+        assert!((boxes[0].confidence - 0.5).abs() < 1e-6);
+        assert_eq!(boxes[1].x1, 18);
+    }
+}
@@ -0,0 +1,755 @@
+//! Destinations of emitted items and events.
+//!
+//! Each `[[output]]` entry of the config, and each shorthand table such as
+//! `[discord]`, creates one sink. Items are sent to every sink, so for
+//! example a transcript file, the message bus, and a chat can be fed at
+//! once.
+//!
+//! Sinks relaying text to people in a chat only receive items that pass the
+//! content filter, when one is configured.
+//!
+//! The processor sends items to the sinks through an `OutputWorker`.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, TrySendError},
+        Arc,
+    },
+    time::Instant,
+};
+
+use slog_scope::warn;
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::{
+    config::{ContentFilterConfig, OutputConfig},
+    content_filter::ContentFilter,
+    discord::DiscordSink,
+    event::Event,
+    fault_injection::FaultInjector,
+    irc::IrcSink,
+    message_bus::MessageBusSink,
+    text_processor::TextItem,
+    twitch::TwitchSink,
+    webhook::WebhookSink,
+};
+
+/// Batches of items and events waiting for a sink that may drop them before
+/// new ones are dropped, and the backlog of other sinks that is warned about.
+const QUEUE_SIZE: usize = 1000;
+
+pub trait OutputSink: Send {
+    /// Sends the items a region emitted in a frame.
+    fn emit(&mut self, items: &[TextItem]);
+
+    /// Sends an event. Sinks of only text ignore events.
+    fn emit_event(&mut self, _event: &Event) {}
+
+    /// Whether the sink relays text to people in a chat, so items are
+    /// filtered first.
+    fn is_chat(&self) -> bool {
+        false
+    }
+
+    /// Returns when `poll` is due even if nothing is emitted, such as to
+    /// send a batch or answer a server's pings.
+    fn poll_time(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Does the work that is due. Afterwards, `poll_time` must return a
+    /// later time or None.
+    fn poll(&mut self) {}
+
+    /// Sends what the sink holds back, before it is dropped.
+    fn flush(&mut self) {}
+
+    /// Fails as the sink would if its destination failed, for rehearsing
+    /// failure handling. By default, the sink's task stops as if the sink
+    /// crashed.
+    fn inject_failure(&mut self) {
+        panic!("injected sink failure");
+    }
+}
+
+/// Creates the sink of an output.
+pub fn create_sink(config: &OutputConfig) -> io::Result<Box<dyn OutputSink>> {
+    Ok(match config {
+        OutputConfig::File { path } => Box::new(FileSink::new(path)?),
+        OutputConfig::MessageBus(config) => Box::new(MessageBusSink::new(config.clone())),
+        OutputConfig::Discord(config) => Box::new(DiscordSink::new(config.clone())),
+        OutputConfig::Irc(config) => Box::new(IrcSink::new(config.clone())),
+        OutputConfig::Twitch(config) => Box::new(TwitchSink::new(config.clone())),
+        OutputConfig::Webhook(config) => Box::new(WebhookSink::new(config.clone())),
+    })
+}
+
+/// Runs each sink of the configured outputs on a blocking task of the
+/// runtime, fed through a queue of its own, so a slow sink holds up neither
+/// recognition nor the other sinks.
+///
+/// File and webhook sinks keep every item however far behind they are.
+/// Chats and the message bus drop items while their queue is full, as they
+/// are rate limited anyway. Dropping the worker waits for the queued items
+/// to be sent.
+pub struct OutputWorker {
+    runtime: Handle,
+    workers: Vec<SinkWorker>,
+    /// Tasks of removed sinks still sending their queued items.
+    retired_tasks: Vec<JoinHandle<()>>,
+    content_filter_config: Option<ContentFilterConfig>,
+    /// None if not configured or the word lists couldn't be loaded.
+    content_filter: Option<ContentFilter>,
+    fault_injector: Option<FaultInjector>,
+}
+
+impl OutputWorker {
+    pub fn new(runtime: Handle) -> Self {
+        Self {
+            runtime,
+            workers: Vec::new(),
+            retired_tasks: Vec::new(),
+            content_filter_config: None,
+            content_filter: None,
+            fault_injector: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Applies the configs of the outputs.
+    ///
+    /// Sinks whose config is unchanged are kept so their connections and
+    /// queued items aren't lost, unless their task stopped. Removed sinks
+    /// send their queued items in the background. A sink that can't be
+    /// created is logged and skipped.
+    pub fn set_configs(&mut self, configs: Vec<OutputConfig>) {
+        let mut old_workers = std::mem::take(&mut self.workers);
+
+        for config in configs {
+            match old_workers
+                .iter()
+                .position(|worker| worker.config == config && !worker.stopped)
+            {
+                Some(index) => self.workers.push(old_workers.remove(index)),
+                None => match create_sink(&config) {
+                    Ok(sink) => self.workers.push(SinkWorker::start(
+                        &self.runtime,
+                        config,
+                        sink,
+                        self.fault_injector.clone(),
+                    )),
+                    Err(error) => warn!("failed to create output";
+                        "output" => ?config, "error" => %error),
+                },
+            }
+        }
+
+        self.retired_tasks.retain(|task| !task.is_finished());
+        self.retired_tasks
+            .extend(old_workers.into_iter().map(|worker| worker.task));
+    }
+
+    /// Applies the config of the content filter, loading its word lists if
+    /// it changed.
+    ///
+    /// If the word lists can't be loaded, the error is logged and no items
+    /// are sent to chats rather than unfiltered ones.
+    pub fn set_content_filter_config(&mut self, config: Option<ContentFilterConfig>) {
+        if config == self.content_filter_config {
+            return;
+        }
+
+        self.content_filter = match &config {
+            Some(config) => match ContentFilter::load(config.clone()) {
+                Ok(content_filter) => Some(content_filter),
+                Err(error) => {
+                    warn!("failed to load content filter word lists"; "error" => %error);
+                    None
+                }
+            },
+            None => None,
+        };
+        self.content_filter_config = config;
+    }
+
+    /// Makes deliveries to each sink fail at random, one sink at a time.
+    pub fn set_fault_injector(&mut self, value: Option<FaultInjector>) {
+        for worker in &mut self.workers {
+            worker.send(SinkMessage::FaultInjector(value.clone()));
+        }
+
+        self.fault_injector = value;
+    }
+
+    pub fn emit(&mut self, items: Vec<TextItem>) {
+        if items.is_empty() {
+            return;
+        }
+
+        let items = Arc::new(items);
+        let mut chat_items = None;
+
+        for worker in &mut self.workers {
+            if !worker.is_chat || self.content_filter_config.is_none() {
+                worker.send(SinkMessage::Items(Arc::clone(&items)));
+                continue;
+            }
+
+            let content_filter = &self.content_filter;
+            let chat_items = chat_items
+                .get_or_insert_with(|| Arc::new(filter_items(content_filter.as_ref(), &items)));
+
+            if !chat_items.is_empty() {
+                worker.send(SinkMessage::Items(Arc::clone(chat_items)));
+            }
+        }
+    }
+
+    pub fn emit_event(&mut self, event: Event) {
+        let event = Arc::new(event);
+
+        for worker in &mut self.workers {
+            worker.send(SinkMessage::Event(Arc::clone(&event)));
+        }
+    }
+}
+
+impl Drop for OutputWorker {
+    fn drop(&mut self) {
+        let tasks = self
+            .workers
+            .drain(..)
+            .map(|worker| worker.task)
+            .chain(self.retired_tasks.drain(..))
+            .collect::<Vec<_>>();
+
+        // The queues are closed now, so the tasks finish once they are empty
+        for task in tasks {
+            if let Err(error) = self.runtime.block_on(task) {
+                warn!("output task failed"; "error" => %error);
+            }
+        }
+    }
+}
+
+enum SinkMessage {
+    Items(Arc<Vec<TextItem>>),
+    Event(Arc<Event>),
+    FaultInjector(Option<FaultInjector>),
+}
+
+enum SinkSender {
+    Lossless(mpsc::Sender<SinkMessage>),
+    Lossy(mpsc::SyncSender<SinkMessage>),
+}
+
+/// The task of a sink and its queue.
+struct SinkWorker {
+    config: OutputConfig,
+    is_chat: bool,
+    sender: SinkSender,
+    /// Messages queued for the sink and not yet handled.
+    pending_count: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+    dropped_count: u64,
+    /// Whether the task stopped, such as after the sink panicked, which was
+    /// reported.
+    stopped: bool,
+}
+
+impl SinkWorker {
+    fn start(
+        runtime: &Handle,
+        config: OutputConfig,
+        mut sink: Box<dyn OutputSink>,
+        fault_injector: Option<FaultInjector>,
+    ) -> Self {
+        let is_chat = sink.is_chat();
+        let pending_count = Arc::new(AtomicUsize::new(0));
+        let task_pending_count = Arc::clone(&pending_count);
+
+        let (sender, receiver) = match config {
+            OutputConfig::File { .. } | OutputConfig::Webhook(_) => {
+                let (sender, receiver) = mpsc::channel();
+                (SinkSender::Lossless(sender), receiver)
+            }
+            _ => {
+                let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+                (SinkSender::Lossy(sender), receiver)
+            }
+        };
+        let task = runtime.spawn_blocking(move || {
+            run_sink(
+                sink.as_mut(),
+                &receiver,
+                &task_pending_count,
+                fault_injector,
+            );
+            sink.flush();
+        });
+
+        Self {
+            config,
+            is_chat,
+            sender,
+            pending_count,
+            task,
+            dropped_count: 0,
+            stopped: false,
+        }
+    }
+
+    fn send(&mut self, message: SinkMessage) {
+        if self.stopped {
+            return;
+        }
+
+        let pending_count = self.pending_count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let closed = match &self.sender {
+            SinkSender::Lossless(sender) => {
+                if pending_count > QUEUE_SIZE && pending_count.is_power_of_two() {
+                    warn!("output is falling behind";
+                        "output" => ?self.config, "pending_count" => pending_count);
+                }
+
+                sender.send(message).is_err()
+            }
+            SinkSender::Lossy(sender) => match sender.try_send(message) {
+                Ok(()) => false,
+                Err(TrySendError::Full(_)) => {
+                    self.pending_count.fetch_sub(1, Ordering::Relaxed);
+                    self.dropped_count += 1;
+
+                    if self.dropped_count.is_power_of_two() {
+                        warn!("output queue full, dropping items";
+                            "output" => ?self.config, "dropped_count" => self.dropped_count);
+                    }
+
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => true,
+            },
+        };
+
+        if closed {
+            self.stopped = true;
+            warn!("output stopped, items are no longer sent to it until the config is reloaded";
+                "output" => ?self.config);
+        }
+    }
+}
+
+/// Passes the queued messages to the sink until the queue is closed, polling
+/// it when due.
+fn run_sink(
+    sink: &mut dyn OutputSink,
+    receiver: &Receiver<SinkMessage>,
+    pending_count: &AtomicUsize,
+    mut fault_injector: Option<FaultInjector>,
+) {
+    loop {
+        let message = match sink.poll_time() {
+            Some(poll_time) => {
+                let now = Instant::now();
+
+                if poll_time <= now {
+                    sink.poll();
+                    continue;
+                }
+
+                match receiver.recv_timeout(poll_time - now) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(message) => message,
+                Err(_) => return,
+            },
+        };
+
+        let is_delivery = !matches!(message, SinkMessage::FaultInjector(_));
+
+        if is_delivery
+            && fault_injector
+                .as_ref()
+                .map_or(false, FaultInjector::sink_failure)
+        {
+            sink.inject_failure();
+        }
+
+        match message {
+            SinkMessage::Items(items) => sink.emit(&items),
+            SinkMessage::Event(event) => sink.emit_event(&event),
+            SinkMessage::FaultInjector(value) => fault_injector = value,
+        }
+
+        pending_count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the items with blocked words masked and items that are dropped
+/// removed. All items are removed without a filter.
+fn filter_items(content_filter: Option<&ContentFilter>, items: &[TextItem]) -> Vec<TextItem> {
+    let content_filter = match content_filter {
+        Some(content_filter) => content_filter,
+        None => return Vec::new(),
+    };
+
+    items
+        .iter()
+        .filter_map(|item| match content_filter.filter(&item.text) {
+            Some(text) => Some(TextItem {
+                text,
+                ..item.clone()
+            }),
+            None => {
+                warn!("content filter blocked item"; "region_name" => &item.region_name);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Appends items as JSON lines to a file.
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+        })
+    }
+}
+
+impl OutputSink for FileSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        let mut lines = String::new();
+
+        for item in items {
+            if let Ok(line) = serde_json::to_string(item) {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+        }
+
+        if let Err(error) = self.file.write_all(lines.as_bytes()) {
+            warn!("failed to write output file"; "error" => %error);
+        }
+    }
+}
+
+impl OutputSink for MessageBusSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        for item in items {
+            self.send_item(item);
+        }
+    }
+
+    fn emit_event(&mut self, event: &Event) {
+        self.send_event(event);
+    }
+}
+
+impl OutputSink for DiscordSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        for item in items {
+            self.send_item(item);
+        }
+    }
+
+    fn is_chat(&self) -> bool {
+        true
+    }
+
+    fn poll_time(&self) -> Option<Instant> {
+        self.batch_deadline()
+    }
+
+    fn poll(&mut self) {
+        self.post_batch();
+    }
+
+    fn flush(&mut self) {
+        self.post_batch();
+    }
+}
+
+impl OutputSink for IrcSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        for item in items {
+            self.send_item(item);
+        }
+    }
+
+    fn is_chat(&self) -> bool {
+        true
+    }
+    fn poll_time(&self) -> Option<Instant> {
+        Some(self.connection_poll_time())
+    }
+
+    fn poll(&mut self) {
+        self.poll_connection();
+    }
+}
+
+impl OutputSink for TwitchSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        for item in items {
+            self.send_item(item);
+        }
+    }
+
+    fn is_chat(&self) -> bool {
+        true
+    }
+    fn poll_time(&self) -> Option<Instant> {
+        Some(self.connection_poll_time())
+    }
+
+    fn poll(&mut self) {
+        self.poll_connection();
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn emit(&mut self, items: &[TextItem]) {
+        for item in items {
+            self.send_item(item);
+        }
+    }
+
+    fn poll_time(&self) -> Option<Instant> {
+        self.batch_deadline()
+    }
+
+    fn poll(&mut self) {
+        self.post_batch();
+    }
+
+    fn flush(&mut self) {
+        self.post_batch();
+    }
+
+    fn inject_failure(&mut self) {
+        WebhookSink::inject_failure(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{config::FaultInjectionConfig, metadata::RecordMetadata};
+
+    #[derive(Deserialize)]
+    struct Config {
+        output: Vec<OutputConfig>,
+    }
+
+    #[test]
+    fn test_file_outputs() {
+        let directory = std::env::temp_dir().join(format!("tppocr-output-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let text = format!(
+            r#"
+            [[output]]
+            type = "file"
+            path = "{0}/a.jsonl"
+
+            [[output]]
+            type = "file"
+            path = "{0}/b.jsonl"
+            "#,
+            directory.display()
+        );
+        let config: Config = toml::de::from_str(&text).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut output_worker = OutputWorker::new(runtime.handle().clone());
+        output_worker.set_configs(config.output.clone());
+        let item = test_item();
+
+        output_worker.emit(vec![item.clone()]);
+        output_worker.set_configs(config.output[1..].to_vec());
+
+        assert_eq!(output_worker.len(), 1);
+
+        // Dropping the worker waits for the queued items, also of removed
+        // sinks
+        output_worker.emit(vec![item]);
+        drop(output_worker);
+
+        for (name, line_count) in &[("a.jsonl", 1), ("b.jsonl", 2)] {
+            let contents = std::fs::read_to_string(directory.join(name)).unwrap();
+            let value: serde_json::Value =
+                serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+
+            assert_eq!(value["text"], "Hello");
+            assert_eq!(contents.lines().count(), *line_count);
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    struct PanickingSink;
+
+    impl OutputSink for PanickingSink {
+        fn emit(&mut self, _items: &[TextItem]) {
+            panic!("sink failed");
+        }
+    }
+
+    #[test]
+    fn test_stopped_sink() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut worker = SinkWorker::start(
+            runtime.handle(),
+            OutputConfig::File {
+                path: "unused.jsonl".into(),
+            },
+            Box::new(PanickingSink),
+            None,
+        );
+
+        worker.send(SinkMessage::Items(Arc::new(vec![test_item()])));
+
+        while !worker.task.is_finished() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert!(!worker.stopped);
+        worker.send(SinkMessage::Items(Arc::new(vec![test_item()])));
+        assert!(worker.stopped);
+    }
+
+    /// Holds items back until polled or flushed.
+    struct BatchingSink {
+        batch: Vec<TextItem>,
+        deadline: Option<Instant>,
+        sent: Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+
+    impl OutputSink for BatchingSink {
+        fn emit(&mut self, items: &[TextItem]) {
+            self.batch.extend_from_slice(items);
+            self.deadline
+                .get_or_insert_with(|| Instant::now() + std::time::Duration::from_millis(50));
+        }
+
+        fn poll_time(&self) -> Option<Instant> {
+            self.deadline
+        }
+
+        fn poll(&mut self) {
+            self.flush();
+        }
+
+        fn flush(&mut self) {
+            self.deadline = None;
+
+            if !self.batch.is_empty() {
+                self.sent.lock().unwrap().push(self.batch.len());
+                self.batch.clear();
+            }
+        }
+    }
+
+    #[test]
+    fn test_polled_sink() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut worker = SinkWorker::start(
+            runtime.handle(),
+            OutputConfig::File {
+                path: "unused.jsonl".into(),
+            },
+            Box::new(BatchingSink {
+                batch: Vec::new(),
+                deadline: None,
+                sent: Arc::clone(&sent),
+            }),
+            None,
+        );
+
+        worker.send(SinkMessage::Items(Arc::new(vec![test_item(), test_item()])));
+
+        while sent.lock().unwrap().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // Held back items are sent when the queue is closed
+        worker.send(SinkMessage::Items(Arc::new(vec![test_item()])));
+        drop(worker.sender);
+        runtime.block_on(worker.task).unwrap();
+
+        assert_eq!(*sent.lock().unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_injected_sink_failure() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut worker = SinkWorker::start(
+            runtime.handle(),
+            OutputConfig::File {
+                path: "unused.jsonl".into(),
+            },
+            Box::new(BatchingSink {
+                batch: Vec::new(),
+                deadline: None,
+                sent: Arc::clone(&sent),
+            }),
+            Some(FaultInjector::new(FaultInjectionConfig {
+                sink_failure_probability: 1.0,
+                ..FaultInjectionConfig::default()
+            })),
+        );
+
+        worker.send(SinkMessage::Items(Arc::new(vec![test_item()])));
+
+        assert!(runtime.block_on(worker.task).unwrap_err().is_panic());
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    fn test_item() -> TextItem {
+        TextItem {
+            date: Utc::now(),
+            region_name: "dialog".to_string(),
+            text: "Hello".to_string(),
+            confidence: 0.9,
+            location: None,
+            value: None,
+            block_box: None,
+            word_boxes: Vec::new(),
+            metadata: RecordMetadata::default(),
+            integrity: None,
+            stream_name: None,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Perceptual hashing of region crops for deciding whether the text could
+//! have changed.
+//!
+//! Unlike an exact hash of the pixels, a difference hash (dHash) stays the
+//! same or changes by a few bits under compression noise, so a region is
+//! recognized again only when its image visibly changed.
+
+use crate::{config::Region, preprocess};
+
+const HASH_WIDTH: u32 = 8;
+const HASH_HEIGHT: u32 = 8;
+
+/// Returns the 64-bit difference hash of the region.
+///
+/// The region is shrunk to 9x8 cells of mean luma and each bit is set when a
+/// cell is brighter than the cell to its right.
+pub fn difference_hash(region: &Region, frame_data: &[u32], frame_width: u32) -> u64 {
+    let columns = HASH_WIDTH + 1;
+    let mut cells = [0u64; ((HASH_WIDTH + 1) * HASH_HEIGHT) as usize];
+    let mut counts = [0u64; ((HASH_WIDTH + 1) * HASH_HEIGHT) as usize];
+
+    let rows = preprocess::frame_rows(
+        frame_data,
+        frame_width,
+        region.x,
+        region.y,
+        region.width,
+        region.height,
+    );
+
+    for (y, row) in rows.enumerate() {
+        let cell_y = y as u32 * HASH_HEIGHT / region.height;
+
+        for (x, pixel) in row.iter().enumerate() {
+            let cell_x = x as u32 * columns / region.width;
+            let index = (cell_y * columns + cell_x) as usize;
+
+            cells[index] += luma(*pixel) as u64;
+            counts[index] += 1;
+        }
+    }
+
+    let mean = |cell_x: u32, cell_y: u32| {
+        let index = (cell_y * columns + cell_x) as usize;
+        cells[index] / counts[index].max(1)
+    };
+    let mut hash = 0u64;
+
+    for cell_y in 0..HASH_HEIGHT {
+        for cell_x in 0..HASH_WIDTH {
+            hash <<= 1;
+
+            if mean(cell_x, cell_y) > mean(cell_x + 1, cell_y) {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+/// Returns the number of bits that differ between two hashes.
+pub fn distance(hash_a: u64, hash_b: u64) -> u32 {
+    (hash_a ^ hash_b).count_ones()
+}
+
+fn luma(pixel: u32) -> u32 {
+    let channel_1 = pixel & 0xff;
+    let channel_2 = (pixel >> 8) & 0xff;
+    let channel_3 = (pixel >> 16) & 0xff;
+
+    (channel_1 + 2 * channel_2 + channel_3) / 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(width: u32, height: u32) -> Region {
+        toml::from_str(&format!(
+            "name = \"test\"\nx = 0\ny = 0\nwidth = {}\nheight = {}\nprocessor = \"FixedLine\"",
+            width, height
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_difference_hash() {
+        let region = region(36, 16);
+        // Brightness decreasing to the right sets every bit
+        let gradient: Vec<u32> = (0..16)
+            .flat_map(|_| (0..36).map(|x| 0xff00_0000 | (0x010101 * (250 - x * 6))))
+            .collect();
+        let mut noisy = gradient.clone();
+        noisy[40] ^= 0x0000_0303;
+
+        let hash = difference_hash(&region, &gradient, 36);
+
+        assert_eq!(hash, u64::MAX);
+        assert_eq!(distance(hash, difference_hash(&region, &noisy, 36)), 0);
+        assert_eq!(
+            distance(hash, difference_hash(&region, &vec![0; 36 * 16], 36)),
+            64
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Recognition of regions in images given by the caller.
+//!
+//! `Pipeline` runs the same preprocessing, OCR, strategies, text rules, and
+//! corrections as the processor, but on images passed in directly instead of
+//! frames from the stream dumper, without a debug display or outputs. Other
+//! programs can embed the recognition this way:
+//!
+//! ```no_run
+//! # use std::collections::BTreeMap;
+//! # use tppocr::{config::ProcessorConfig, pipeline::Pipeline, text_recognizer::TextRecognizerSet};
+//! # fn main() -> anyhow::Result<()> {
+//! let image = image::open("frame.png")?.into_rgba8();
+//! let config = ProcessorConfig::load("config.toml".as_ref(), image.width(), image.height())?;
+//! let text_recognizers = TextRecognizerSet::new(
+//!     "/usr/share/tesseract-ocr/4.00/tessdata/",
+//!     "eng",
+//!     config.tesseract_variables.clone(),
+//! )?;
+//! let mut pipeline = Pipeline::new(config, text_recognizers, image.width(), image.height())?;
+//!
+//! pipeline.process_image(&image)?;
+//!
+//! for item in pipeline.flush() {
+//!     println!("{}: {}", item.region_name, item.text);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::{DateTime, Duration, Utc};
+use image::RgbaImage;
+
+use crate::{
+    config::ProcessorConfig,
+    error::{ConfigError, Error, FrameError},
+    event::Event,
+    preprocess,
+    processor::{self, RegionProcessor},
+    scene::SceneSelector,
+    text_processor::TextItem,
+    text_recognizer::TextRecognizerSet,
+};
+
+pub struct Pipeline {
+    text_recognizers: TextRecognizerSet,
+    region_processors: Vec<RegionProcessor>,
+    scene_selector: SceneSelector,
+    frame_width: u32,
+    frame_height: u32,
+    /// Date of the latest image.
+    date: Option<DateTime<Utc>>,
+    events: Vec<Event>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline for images of the frame size the config was loaded
+    /// with.
+    ///
+    /// Regions of `[[stream]]` sections are left out since there is only
+    /// one image.
+    pub fn new(
+        config: ProcessorConfig,
+        mut text_recognizers: TextRecognizerSet,
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Result<Self, Error> {
+        let regions: Vec<_> = config
+            .region
+            .iter()
+            .filter(|region| region.stream.is_none())
+            .cloned()
+            .collect();
+
+        for region in &regions {
+            if !region.fits_frame(frame_width, frame_height) {
+                return Err(ConfigError::RegionOutsideFrame {
+                    region: region.name.clone(),
+                    frame_width,
+                    frame_height,
+                }
+                .into());
+            }
+        }
+
+        processor::load_region_engines(std::slice::from_mut(&mut text_recognizers), &regions)?;
+
+        Ok(Self {
+            text_recognizers,
+            region_processors: regions.into_iter().map(RegionProcessor::new).collect(),
+            scene_selector: SceneSelector::new(
+                &config.scene,
+                config.scene_detection.confirm_frames,
+            )?,
+            frame_width,
+            frame_height,
+            date: None,
+            events: Vec::new(),
+        })
+    }
+
+    /// Recognizes the regions in the image and returns the items emitted,
+    /// dated now.
+    ///
+    /// Strategies emit text once it stops changing, so the text of an image
+    /// is usually returned by a later call or by `flush`.
+    pub fn process_image(&mut self, image: &RgbaImage) -> Result<Vec<TextItem>, Error> {
+        self.process_image_at(image, &Utc::now())
+    }
+
+    /// Recognizes the regions in the image as if it was captured at the
+    /// date, which is also the clock the strategies' timeouts use.
+    pub fn process_image_at(
+        &mut self,
+        image: &RgbaImage,
+        date: &DateTime<Utc>,
+    ) -> Result<Vec<TextItem>, Error> {
+        if image.dimensions() != (self.frame_width, self.frame_height) {
+            return Err(FrameError::ImageSize {
+                width: image.width(),
+                height: image.height(),
+                frame_width: self.frame_width,
+                frame_height: self.frame_height,
+            }
+            .into());
+        }
+
+        let frame_data = preprocess::to_u32_pixels(image);
+        let mut items = Vec::new();
+
+        self.date = Some(*date);
+        self.events.extend(self.scene_selector.update(
+            &frame_data,
+            self.frame_width,
+            self.frame_height,
+        ));
+
+        for (index, region_processor) in self.region_processors.iter_mut().enumerate() {
+            if !self
+                .scene_selector
+                .is_region_active(&region_processor.region().name)
+            {
+                continue;
+            }
+
+            self.events.extend(region_processor.recognize(
+                &self.text_recognizers,
+                &frame_data,
+                self.frame_width,
+                date,
+            )?);
+
+            let text_items = region_processor.get_text(date);
+
+            self.events
+                .extend(region_processor.check_quota(index, text_items.len(), date));
+
+            if !region_processor.is_muted() {
+                self.events.extend(region_processor.get_events(date));
+                items.extend(text_items);
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Returns the items still held by the strategies, as if time had passed
+    /// since the latest image.
+    pub fn flush(&mut self) -> Vec<TextItem> {
+        let date = match self.date {
+            Some(date) => date + Duration::minutes(1),
+            None => return Vec::new(),
+        };
+        let mut items = Vec::new();
+
+        for region_processor in &mut self.region_processors {
+            let text_items = region_processor.get_text(&date);
+
+            if !region_processor.is_muted() {
+                self.events.extend(region_processor.get_events(&date));
+                items.extend(text_items);
+            }
+        }
+
+        self.date = Some(date);
+
+        items
+    }
+
+    /// Returns the events, such as scene changes and menu selections,
+    /// produced since the last call.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+}
@@ -0,0 +1,396 @@
+//! Image processing of region crops before text recognition.
+
+use std::collections::VecDeque;
+
+use image::{imageops::FilterType, GrayImage, Luma, Rgba, RgbaImage};
+
+use crate::config::{Binarization, ColorKey, Region, ScaleFilter, TemporalAverage};
+
+/// Applies the preprocessing steps configured for a region.
+pub struct Preprocessor {
+    region: Region,
+    history: VecDeque<RgbaImage>,
+}
+
+impl Preprocessor {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Returns the processed copy of the region from the frame.
+    pub fn process(&mut self, frame_data: &[u32], frame_width: u32) -> RgbaImage {
+        let mut image = crop_region(frame_data, frame_width, &self.region);
+
+        if let Some(temporal_average) = &self.region.temporal_average {
+            image = self.average(image, temporal_average.clone());
+        }
+
+        if let Some(color_key) = &self.region.color_key {
+            mask_color_key(&mut image, color_key);
+        }
+
+        if self.region.scale > 1 {
+            image = upscale(&image, self.region.scale, self.region.scale_filter);
+        }
+
+        match &self.region.binarization {
+            Some(binarization) => {
+                let mut image = image::imageops::grayscale(&image);
+
+                match binarization {
+                    Binarization::Otsu => binarize_otsu(&mut image),
+                    Binarization::AdaptiveMean { block_size, offset } => {
+                        binarize_adaptive_mean(&mut image, *block_size, *offset)
+                    }
+                }
+
+                normalize_polarity(&mut image);
+                gray_to_rgba(&image)
+            }
+            None => image,
+        }
+    }
+
+    fn average(&mut self, image: RgbaImage, temporal_average: TemporalAverage) -> RgbaImage {
+        // Averaging across a change would produce a blend of the old and new
+        // text, so only average while the region stays the same.
+        if let Some(previous) = self.history.back() {
+            if mean_difference(previous, &image) > temporal_average.max_difference {
+                self.history.clear();
+            }
+        }
+
+        self.history.push_back(image);
+
+        while self.history.len() > temporal_average.frames.max(1) {
+            self.history.pop_front();
+        }
+
+        average_images(self.history.make_contiguous())
+    }
+}
+
+/// Copies the region out of a frame of RGBA pixels.
+pub fn crop_region(frame_data: &[u32], frame_width: u32, region: &Region) -> RgbaImage {
+    crop_rect(
+        frame_data,
+        frame_width,
+        region.x,
+        region.y,
+        region.width,
+        region.height,
+    )
+}
+
+/// Copies a rectangle out of a frame of RGBA pixels.
+///
+/// Pixels of the rectangle outside of the frame are left transparent.
+pub fn crop_rect(
+    frame_data: &[u32],
+    frame_width: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+    let frame_rows = frame_rows(frame_data, frame_width, x, y, width, height);
+
+    for (row, frame_row) in image.rows_mut().zip(frame_rows) {
+        for (pixel, frame_pixel) in row.zip(frame_row) {
+            *pixel = Rgba(frame_pixel.to_ne_bytes());
+        }
+    }
+
+    image
+}
+
+/// Returns the rows of a rectangle of the frame, clipped to the frame.
+///
+/// Regions of `[[stream]]` sections are only checked against the frame size
+/// of the main stream, so they may extend past the frames of their own.
+pub fn frame_rows(
+    frame_data: &[u32],
+    frame_width: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = &[u32]> {
+    let stride = frame_width as usize;
+    let start = (x as usize).min(stride);
+    let end = (x as usize + width as usize).min(stride);
+
+    frame_data
+        .chunks_exact(stride.max(1))
+        .skip(y as usize)
+        .take(height as usize)
+        .map(move |row| &row[start..end])
+}
+
+/// Converts the image to pixels in the layout accepted by the recognizer.
+pub fn to_u32_pixels(image: &RgbaImage) -> Vec<u32> {
+    image
+        .pixels()
+        .map(|pixel| u32::from_ne_bytes(pixel.0))
+        .collect()
+}
+
+/// Turns pixels matching the text color black and every other pixel white.
+///
+/// Textured dialog backgrounds are removed this way. The text is made dark
+/// so light colored text isn't lost against the white background.
+pub fn mask_color_key(image: &mut RgbaImage, color_key: &ColorKey) {
+    for pixel in image.pixels_mut() {
+        let matches = pixel.0[..3]
+            .iter()
+            .zip(&color_key.color)
+            .all(|(value, key_value)| {
+                (*value as i16 - *key_value as i16).abs() <= color_key.tolerance as i16
+            });
+
+        *pixel = if matches {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([255, 255, 255, 255])
+        };
+    }
+}
+
+/// Enlarges the image by an integer factor.
+pub fn upscale(image: &RgbaImage, scale: u32, filter: ScaleFilter) -> RgbaImage {
+    let filter = match filter {
+        ScaleFilter::Nearest => FilterType::Nearest,
+        ScaleFilter::Bilinear => FilterType::Triangle,
+    };
+
+    image::imageops::resize(image, image.width() * scale, image.height() * scale, filter)
+}
+
+/// Returns the mean absolute difference per channel of two images of the
+/// same size.
+pub fn mean_difference(image_a: &RgbaImage, image_b: &RgbaImage) -> f32 {
+    if image_a.dimensions() != image_b.dimensions() {
+        return f32::INFINITY;
+    }
+
+    let sum: u64 = image_a
+        .as_raw()
+        .iter()
+        .zip(image_b.as_raw())
+        .map(|(a, b)| (*a as i16 - *b as i16).abs() as u64)
+        .sum();
+
+    sum as f32 / image_a.as_raw().len().max(1) as f32
+}
+
+/// Returns the per-pixel mean of images of the same size.
+pub fn average_images(images: &[RgbaImage]) -> RgbaImage {
+    let (width, height) = images[0].dimensions();
+    let mut sums = vec![0u32; images[0].as_raw().len()];
+
+    for image in images {
+        for (sum, value) in sums.iter_mut().zip(image.as_raw()) {
+            *sum += *value as u32;
+        }
+    }
+
+    let count = images.len() as u32;
+    let data = sums
+        .into_iter()
+        .map(|sum| ((sum + count / 2) / count) as u8)
+        .collect();
+
+    RgbaImage::from_raw(width, height, data).unwrap()
+}
+
+fn gray_to_rgba(image: &GrayImage) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let value = image.get_pixel(x, y)[0];
+        Rgba([value, value, value, 255])
+    })
+}
+
+/// Returns the threshold that minimizes the intra-class variance of the
+/// black and white pixels.
+pub fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total = (image.width() * image.height()) as f64;
+    let sum_all: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let mut sum_background = 0.0;
+    let mut weight_background = 0.0;
+    let mut best_variance = 0.0;
+    let mut threshold = 0;
+
+    for (value, count) in histogram.iter().enumerate() {
+        weight_background += *count as f64;
+
+        if weight_background == 0.0 {
+            continue;
+        }
+
+        let weight_foreground = total - weight_background;
+
+        if weight_foreground == 0.0 {
+            break;
+        }
+
+        sum_background += value as f64 * *count as f64;
+
+        let mean_background = sum_background / weight_background;
+        let mean_foreground = (sum_all - sum_background) / weight_foreground;
+        let variance =
+            weight_background * weight_foreground * (mean_background - mean_foreground).powi(2);
+
+        if variance > best_variance {
+            best_variance = variance;
+            threshold = value;
+        }
+    }
+
+    threshold as u8
+}
+
+pub fn binarize_otsu(image: &mut GrayImage) {
+    let threshold = otsu_threshold(image);
+
+    for pixel in image.pixels_mut() {
+        pixel[0] = if pixel[0] > threshold { 255 } else { 0 };
+    }
+}
+
+/// Thresholds each pixel against the mean of the `block_size` square
+/// neighbourhood around it minus `offset`.
+pub fn binarize_adaptive_mean(image: &mut GrayImage, block_size: u32, offset: f32) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let radius = (block_size / 2) as usize;
+
+    // Summed-area table with an extra leading row and column of zeros
+    let mut integral = vec![0u64; (width + 1) * (height + 1)];
+
+    for y in 0..height {
+        let mut row_sum = 0u64;
+
+        for x in 0..width {
+            row_sum += image.get_pixel(x as u32, y as u32)[0] as u64;
+            integral[(y + 1) * (width + 1) + x + 1] = integral[y * (width + 1) + x + 1] + row_sum;
+        }
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let x1 = x.saturating_sub(radius);
+            let y1 = y.saturating_sub(radius);
+            let x2 = (x + radius + 1).min(width);
+            let y2 = (y + radius + 1).min(height);
+
+            let sum = integral[y2 * (width + 1) + x2] + integral[y1 * (width + 1) + x1]
+                - integral[y1 * (width + 1) + x2]
+                - integral[y2 * (width + 1) + x1];
+            let mean = sum as f32 / ((x2 - x1) * (y2 - y1)) as f32;
+
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            *pixel = if pixel[0] as f32 > mean - offset {
+                Luma([255])
+            } else {
+                Luma([0])
+            };
+        }
+    }
+}
+
+/// Inverts a binarized image if most pixels are black so the result is
+/// always dark text on a light background.
+pub fn normalize_polarity(image: &mut GrayImage) {
+    let black_count = image.pixels().filter(|pixel| pixel[0] < 128).count();
+
+    if black_count * 2 > (image.width() * image.height()) as usize {
+        image::imageops::invert(image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_otsu_threshold() {
+        let image =
+            GrayImage::from_fn(10, 10, |x, _y| if x < 3 { Luma([20]) } else { Luma([200]) });
+
+        let threshold = otsu_threshold(&image);
+
+        assert!(threshold >= 20 && threshold < 200);
+    }
+
+    #[test]
+    fn test_binarize_light_text_on_dark() {
+        let mut image = GrayImage::from_fn(
+            10,
+            10,
+            |x, _y| if x == 5 { Luma([230]) } else { Luma([30]) },
+        );
+
+        binarize_otsu(&mut image);
+        normalize_polarity(&mut image);
+
+        assert_eq!(image.get_pixel(5, 5)[0], 0);
+        assert_eq!(image.get_pixel(0, 0)[0], 255);
+    }
+
+    #[test]
+    fn test_binarize_adaptive_mean() {
+        // Dark text on a background with a brightness gradient
+        let mut image = GrayImage::from_fn(20, 10, |x, y| {
+            if y == 5 {
+                Luma([x as u8 * 5])
+            } else {
+                Luma([100 + x as u8 * 5])
+            }
+        });
+
+        binarize_adaptive_mean(&mut image, 5, 10.0);
+
+        assert_eq!(image.get_pixel(10, 5)[0], 0);
+        assert_eq!(image.get_pixel(10, 0)[0], 255);
+        assert_eq!(image.get_pixel(19, 9)[0], 255);
+    }
+
+    #[test]
+    fn test_average_images() {
+        let image_a = RgbaImage::from_pixel(2, 2, Rgba([100, 0, 10, 255]));
+        let image_b = RgbaImage::from_pixel(2, 2, Rgba([110, 20, 10, 255]));
+
+        assert_eq!(mean_difference(&image_a, &image_b), 7.5);
+
+        let image = average_images(&[image_a, image_b]);
+
+        assert_eq!(image.get_pixel(1, 1), &Rgba([105, 10, 10, 255]));
+    }
+
+    #[test]
+    fn test_crop_rect_past_frame_edge() {
+        let frame_data: Vec<u32> = (1..=6).collect();
+
+        let image = crop_rect(&frame_data, 3, 2, 1, 2, 2);
+
+        assert_eq!(image.get_pixel(0, 0), &Rgba(6u32.to_ne_bytes()));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(0, 1), &Rgba([0, 0, 0, 0]));
+        assert_eq!(frame_rows(&frame_data, 3, 4, 0, 2, 5).count(), 2);
+    }
+}
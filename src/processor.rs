@@ -1,130 +1,1131 @@
 use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
     convert::TryInto,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
-use chrono::Utc;
-use image::{Bgra, ImageBuffer};
-use raqote::{Color, DrawOptions, DrawTarget, Image, PathBuilder, Point, Source, StrokeStyle};
-use slog_scope::info;
+use chrono::{DateTime, Utc};
+use image::{Bgra, DynamicImage, ImageBuffer, RgbaImage};
+use raqote::{DrawOptions, DrawTarget, Image, PathBuilder, Point, Source, StrokeStyle};
+use slog_scope::{info, warn};
+use tokio::runtime::Runtime;
 
-use crate::{canvas::TextDrawer, config::{ProcessorConfig, ProcessorStrategy, Region}, frame::FrameReader, text_processor::{DialogScrollProcessor, FixedLineProcessor, TextItem, TextProcessor}, text_recognizer::TextRecognizer, vnc::VncClient};
+use crate::{
+    acquisition::{FrameAcquisition, FrameSet},
+    canvas::{rgb_color, TextDrawer},
+    canvas_publisher::CanvasPublisher,
+    config::{
+        self, DisplayColors, DisplayConfig, FrameDumpConfig, FrameDumpOutput, HttpApiConfig,
+        OutputConfig, ProcessorConfig, ProcessorStrategy, Region,
+    },
+    control_socket::{ControlCommand, ControlResponse, ControlServer, StatusReport},
+    correction::Corrector,
+    debug_window::DebugWindow,
+    error::{DisplayError, Error, OcrError},
+    event::Event,
+    fault_injection::FaultInjector,
+    frame::{Frame, FrameReader},
+    frame_quality::FrameQualityGate,
+    frame_result::{FrameResult, FrameResultWriter, RegionGate, RegionResult},
+    http_api::HttpApi,
+    integrity::IntegrityChain,
+    language_detection::{self, LanguageScore},
+    latency::LatencyTracker,
+    manifest::{self, SessionManifest},
+    metadata::MetadataProvider,
+    ocr_engine::{BoxLevel, PageSegmentation},
+    output::OutputWorker,
+    perceptual_hash,
+    preprocess::{self, Preprocessor},
+    quota::EmissionRateTracker,
+    resource_limit::ResourceMonitor,
+    scene::SceneSelector,
+    telemetry::TelemetryReporter,
+    text_processor::{
+        DialogScrollProcessor, FixedLineProcessor, MenuProcessor, NumericProcessor,
+        SparseTextProcessor, TextItem, TextProcessor, TickerProcessor,
+    },
+    text_recognizer::{BoundingBox, TextLine, TextRecognizerSet},
+    text_rules, validation,
+    vnc::VncClient,
+};
+
+/// Minimum time between checks of the config files for changes.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct Processor {
-    frame_reader: FrameReader,
-    vnc_client: VncClient,
-    text_recognizer: TextRecognizer,
+    frame_acquisition: FrameAcquisition,
+    /// Frames being processed.
+    frame_set: Option<Arc<FrameSet>>,
+    canvas_publisher: CanvasPublisher,
+    text_recognizer_pool: Vec<TextRecognizerSet>,
     region_processors: Vec<RegionProcessor>,
+    scene_selector: SceneSelector,
     config: ProcessorConfig,
+    config_path: Option<PathBuf>,
+    config_modified: Option<SystemTime>,
+    config_check_time: Option<Instant>,
     canvas: DrawTarget,
     text_drawer: TextDrawer,
+    resource_monitor: ResourceMonitor,
+    metadata_provider: MetadataProvider,
+    frame_quality_gate: FrameQualityGate,
+    fault_injector: Option<FaultInjector>,
+    integrity_chain: Option<IntegrityChain>,
+    telemetry_reporter: Option<TelemetryReporter>,
+    outputs: OutputWorker,
+    http_api: Option<HttpApi>,
+    frame_result_writer: Option<FrameResultWriter>,
+    session_manifest: Option<SessionManifest>,
+    control_server: Option<ControlServer>,
+    /// Lines of the latest emitted items for the history panel, oldest
+    /// first.
+    text_history: VecDeque<String>,
+    console_output: bool,
+    paused: bool,
+    /// Frames to process while paused.
+    pending_steps: u32,
+    frame_latency: LatencyTracker,
+    delivery_latency: LatencyTracker,
+    latency_log_date: DateTime<Utc>,
+    previous_capture_time: Option<DateTime<Utc>>,
     frame_counter: u64,
+    /// Runs the stages. Declared last so the stages stop before it waits
+    /// for their blocking tasks.
+    runtime: Runtime,
 }
 
 impl Processor {
+    /// Creates a processor that recognizes regions in parallel, one blocking
+    /// task per instance in `text_recognizer_pool`.
+    ///
+    /// Frame acquisition, the debug display and the outputs run as separate
+    /// stages on the processor's runtime.
     pub fn new(
         frame_reader: FrameReader,
         vnc_client: VncClient,
-        text_recognizer: TextRecognizer,
+        mut text_recognizer_pool: Vec<TextRecognizerSet>,
         config: ProcessorConfig,
-    ) -> Self {
+    ) -> Result<Self, Error> {
+        assert!(!text_recognizer_pool.is_empty());
+
         let canvas = DrawTarget::new(
             vnc_client.width().try_into().unwrap(),
             vnc_client.height().try_into().unwrap(),
         );
 
-        let mut region_processors = Vec::new();
+        validation::check(&config, vnc_client.width(), vnc_client.height())?;
+        load_region_engines(&mut text_recognizer_pool, &config.region)?;
 
-        for region in &config.region {
-            region_processors.push(RegionProcessor::new(region.clone()));
+        let region_processors = config
+            .region
+            .iter()
+            .map(|region| RegionProcessor::new(region.clone()))
+            .collect();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .thread_name("processor-stage")
+            .build()
+            .map_err(Error::Runtime)?;
+        let frame_acquisition =
+            FrameAcquisition::start(runtime.handle().clone(), frame_reader, &config)?;
+        let canvas_publisher = CanvasPublisher::new(runtime.handle().clone(), vnc_client);
+        let scene_selector =
+            SceneSelector::new(&config.scene, config.scene_detection.confirm_frames)?;
+        let resource_monitor = ResourceMonitor::new(config.limits.clone());
+        let metadata_provider = MetadataProvider::new(config.metadata.clone());
+        let frame_quality_gate = FrameQualityGate::new(config.frame_quality.clone());
+        let telemetry_reporter = config.telemetry.clone().map(TelemetryReporter::new);
+        let mut outputs = OutputWorker::new(runtime.handle().clone());
+        outputs.set_configs(config.output_configs());
+        outputs.set_content_filter_config(config.content_filter.clone());
+        let http_api = create_http_api(config.http_api.clone(), &config);
+        let frame_result_writer = create_frame_result_writer(config.frame_dump.clone());
+
+        // The manifest and the integrity chain share the session ID so
+        // archived transcripts can be matched with their manifest
+        let session_id = manifest::generate_session_id();
+        let integrity_chain = config.integrity.clone().map(|integrity_config| {
+            IntegrityChain::with_session_id(session_id.clone(), integrity_config)
+        });
+        let session_manifest = config
+            .manifest
+            .as_ref()
+            .map(|manifest_config| SessionManifest::new(&manifest_config.path, session_id.clone()));
+
+        if integrity_chain.is_some() || session_manifest.is_some() {
+            info!("session"; "session_id" => &session_id);
         }
 
-        Self {
-            frame_reader,
-            vnc_client,
-            text_recognizer,
+        if let Some(session_manifest) = &session_manifest {
+            if let Err(error) = session_manifest.write() {
+                warn!("failed to write session manifest";
+                    "path" => ?session_manifest.path(), "error" => %error);
+            }
+        }
+
+        let text_drawer = create_text_drawer(&config.display)?;
+
+        let mut processor = Self {
+            frame_acquisition,
+            frame_set: None,
+            canvas_publisher,
+            text_recognizer_pool,
             region_processors,
+            scene_selector,
             config,
+            config_path: None,
+            config_modified: None,
+            config_check_time: None,
             canvas,
-            text_drawer: TextDrawer::new().unwrap(),
+            text_drawer,
+            resource_monitor,
+            metadata_provider,
+            frame_quality_gate,
+            fault_injector: None,
+            integrity_chain,
+            telemetry_reporter,
+            outputs,
+            http_api,
+            frame_result_writer,
+            session_manifest,
+            control_server: None,
+            text_history: VecDeque::new(),
+            console_output: false,
+            paused: false,
+            pending_steps: 0,
+            frame_latency: LatencyTracker::new(1000),
+            delivery_latency: LatencyTracker::new(1000),
+            latency_log_date: Utc::now(),
+            previous_capture_time: None,
             frame_counter: 0,
+            runtime,
+        };
+        processor.register_artifacts();
+
+        Ok(processor)
+    }
+
+    pub fn config_path(&self) -> Option<&PathBuf> {
+        self.config_path.as_ref()
+    }
+
+    /// Sets the file or directory the config was loaded from.
+    ///
+    /// When set, the config is reloaded when the files are modified or
+    /// SIGHUP is received.
+    pub fn set_config_path(&mut self, value: Option<PathBuf>) {
+        self.config_modified = value.as_deref().and_then(config::modified_time);
+
+        if let Some(session_manifest) = &mut self.session_manifest {
+            session_manifest.set_config_path(value.clone());
         }
+
+        self.config_path = value;
+    }
+
+    pub fn session_manifest(&self) -> Option<&SessionManifest> {
+        self.session_manifest.as_ref()
+    }
+
+    pub fn fault_injector(&self) -> Option<&FaultInjector> {
+        self.fault_injector.as_ref()
+    }
+
+    /// Enables artificial faults for testing.
+    pub fn set_fault_injector(&mut self, value: Option<FaultInjector>) {
+        self.outputs.set_fault_injector(value.clone());
+        self.fault_injector = value;
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
+    pub fn control_server(&self) -> Option<&ControlServer> {
+        self.control_server.as_ref()
+    }
+
+    /// Sets the socket accepting operator commands.
+    pub fn set_control_server(&mut self, value: Option<ControlServer>) {
+        self.control_server = value;
+    }
+
+    /// Sets a local window also showing the debug display.
+    pub fn set_debug_window(&mut self, value: Option<DebugWindow>) {
+        if let Some(debug_window) = value {
+            self.canvas_publisher.set_debug_window(debug_window);
+        }
+    }
+
+    pub fn console_output(&self) -> bool {
+        self.console_output
+    }
+
+    /// Prints emitted items to standard output, one line each.
+    pub fn set_console_output(&mut self, value: bool) {
+        self.console_output = value;
+    }
+
+    pub fn run(&mut self) -> Result<(), Error> {
         let terminate_flag = Arc::new(AtomicBool::new(false));
         for sig in signal_hook::consts::TERM_SIGNALS {
             signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
         }
 
+        let reload_flag = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload_flag)).unwrap();
+
         info!("starting");
 
         while !terminate_flag.load(Ordering::Relaxed) {
-            self.process_frame()?;
-            self.draw_date();
+            if reload_flag.swap(false, Ordering::Relaxed) || self.config_file_changed() {
+                self.reload_config();
+            }
+
+            self.poll_control_server();
 
-            self.vnc_client.lock()?;
-            self.vnc_client
-                .data_u32_mut()
-                .copy_from_slice(self.canvas.get_data());
-            self.vnc_client.unlock()?;
+            if self.paused {
+                if self.pending_steps == 0 {
+                    std::thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+
+                // Frames skipped between steps are not reported as a gap
+                self.pending_steps -= 1;
+                self.previous_capture_time = None;
+            }
+
+            let logger = self.metadata_provider.logger();
+
+            if let Err(error) = slog_scope::scope(&logger, || self.process_frame()) {
+                self.finalize_session_manifest();
+                return Err(error);
+            }
+
+            self.log_latency();
+            self.report_telemetry();
+            if self.config.overlay.date {
+                self.draw_date();
+            }
+
+            self.draw_history();
+
+            if self.paused {
+                self.draw_pause_indicator();
+            }
+
+            self.publish_canvas();
 
             self.frame_counter += 1;
+
+            let sleep_duration = self.resource_monitor.update();
+
+            if sleep_duration > Duration::from_secs(0) {
+                std::thread::sleep(sleep_duration);
+            }
         }
 
         info!("exiting");
+        self.finalize_session_manifest();
 
         Ok(())
     }
 
-    fn process_frame(&mut self) -> anyhow::Result<()> {
-        self.frame_reader.read()?;
+    /// Passes the canvas to the display stage, which skips it while still
+    /// showing the previous one.
+    fn publish_canvas(&mut self) {
+        let lock_hold = self
+            .fault_injector
+            .as_ref()
+            .and_then(|fault_injector| fault_injector.lock_contention());
 
-        self.text_recognizer.set_image(
-            self.frame_reader.data_u32(),
-            self.frame_reader.width(),
-            self.frame_reader.height(),
-        );
+        self.canvas_publisher
+            .publish(self.canvas.get_data(), lock_hold);
+    }
+
+    /// Lists the files of the file outputs and the frame dump in the
+    /// session manifest.
+    fn register_artifacts(&mut self) {
+        let output_paths =
+            self.config.output_configs().into_iter().filter_map(
+                |output_config| match output_config {
+                    OutputConfig::File { path } => Some(("jsonl_log", path)),
+                    _ => None,
+                },
+            );
+        let frame_dump_path = match &self.config.frame_dump {
+            Some(FrameDumpConfig {
+                output: FrameDumpOutput::File { path },
+                ..
+            }) => Some(("frame_dump", path.clone())),
+            _ => None,
+        };
+
+        for (kind, path) in output_paths.chain(frame_dump_path) {
+            self.add_artifact(kind, path);
+        }
+    }
+
+    fn add_artifact(&mut self, kind: &str, path: PathBuf) {
+        if let Some(session_manifest) = &mut self.session_manifest {
+            if let Err(error) = session_manifest.add_artifact(kind, path) {
+                warn!("failed to write session manifest";
+                    "path" => ?session_manifest.path(), "error" => %error);
+            }
+        }
+    }
+
+    fn add_snapshot_artifact(&mut self, kind: &str, path: PathBuf) {
+        self.add_artifact(kind, path.clone());
+
+        if let Some(session_manifest) = &mut self.session_manifest {
+            session_manifest.set_artifact_detail(
+                &path,
+                "frame_counter",
+                self.frame_counter.to_string(),
+            );
+        }
+    }
+
+    fn finalize_session_manifest(&mut self) {
+        if let Some(session_manifest) = &mut self.session_manifest {
+            if let Err(error) = session_manifest.finalize() {
+                warn!("failed to finalize session manifest";
+                    "path" => ?session_manifest.path(), "error" => %error);
+            }
+        }
+    }
+
+    fn poll_control_server(&mut self) {
+        if let Some(control_server) = self.control_server.take() {
+            control_server.poll(|command| self.handle_control_command(command));
+            self.control_server = Some(control_server);
+        }
+    }
+
+    fn handle_control_command(&mut self, command: ControlCommand) -> ControlResponse {
+        info!("control command"; "command" => ?command);
+
+        match command {
+            ControlCommand::Pause => {
+                self.paused = true;
+                self.pending_steps = 0;
+                self.draw_pause_indicator();
+                self.publish_canvas();
+            }
+            ControlCommand::Resume => {
+                // The pause is intended, so it is not reported as a gap
+                self.paused = false;
+                self.pending_steps = 0;
+                self.previous_capture_time = None;
+            }
+            ControlCommand::Step { count } => {
+                if !self.paused {
+                    self.paused = true;
+                    self.draw_pause_indicator();
+                    self.publish_canvas();
+                }
+
+                self.pending_steps = self.pending_steps.saturating_add(count);
+            }
+            ControlCommand::ReloadConfig => self.reload_config(),
+            ControlCommand::Status => {
+                let latency = self.delivery_latency.summary();
+
+                return ControlResponse {
+                    ok: true,
+                    error: None,
+                    status: Some(StatusReport {
+                        paused: self.paused,
+                        pending_steps: self.pending_steps,
+                        frame_counter: self.frame_counter,
+                        region_count: self.region_processors.len(),
+                        active_scene: self.scene_selector.active_scene().map(str::to_string),
+                        latency_p50_ms: latency.as_ref().map(|summary| summary.p50),
+                        latency_p95_ms: latency.as_ref().map(|summary| summary.p95),
+                        latency_p99_ms: latency.as_ref().map(|summary| summary.p99),
+                    }),
+                };
+            }
+            ControlCommand::SetScene { name } => {
+                if !self.scene_selector.has_scene(&name) {
+                    return ControlResponse::error(format!("unknown scene {:?}", name));
+                }
+
+                if let Some(event) = self.scene_selector.set_active_scene(&name) {
+                    self.emit_event(event);
+                }
+            }
+            ControlCommand::Snapshot { canvas, frame } => {
+                if canvas.is_none() && frame.is_none() {
+                    return ControlResponse::error("no canvas or frame path given".to_string());
+                }
+
+                if let Some(path) = canvas {
+                    let data: Vec<u8> = self
+                        .canvas
+                        .get_data()
+                        .iter()
+                        .flat_map(|pixel| pixel.to_ne_bytes().to_vec())
+                        .collect();
+
+                    if let Err(error) = save_bgra_image(
+                        &path,
+                        self.canvas.width() as u32,
+                        self.canvas.height() as u32,
+                        data,
+                    ) {
+                        return ControlResponse::error(format!("failed to save canvas: {}", error));
+                    }
+
+                    self.add_snapshot_artifact("snapshot_canvas", path);
+                }
+
+                if let Some(path) = frame {
+                    let frame = match &self.frame_set {
+                        Some(frame_set) => &frame_set.main,
+                        None => return ControlResponse::error("no frame was read yet".to_string()),
+                    };
+                    let image =
+                        RgbaImage::from_raw(frame.width(), frame.height(), frame.data().to_vec())
+                            .unwrap();
+
+                    if let Err(error) = DynamicImage::ImageRgba8(image).into_rgb8().save(&path) {
+                        return ControlResponse::error(format!("failed to save frame: {}", error));
+                    }
+
+                    self.add_snapshot_artifact("snapshot_frame", path);
+                }
+            }
+        }
+
+        ControlResponse::ok()
+    }
+
+    /// Returns an event if frames were skipped since the previous frame.
+    fn check_frame_gap(&mut self) -> Option<Event> {
+        let capture_time = self.frame_set.as_ref()?.main.capture_time()?;
+        let previous_capture_time = self.previous_capture_time.replace(capture_time)?;
+        let gap = (capture_time - previous_capture_time).num_milliseconds() as f64 / 1000.0;
+
+        if gap >= self.config.catch_up.gap_secs {
+            Some(Event::FrameGap {
+                date: Utc::now(),
+                previous_capture_time,
+                capture_time,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn config_file_changed(&mut self) -> bool {
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return false,
+        };
+
+        // Each fragment of a config directory is a file to stat
+        if self
+            .config_check_time
+            .map_or(false, |time| time.elapsed() < CONFIG_CHECK_INTERVAL)
+        {
+            return false;
+        }
+
+        self.config_check_time = Some(Instant::now());
+
+        let modified = config::modified_time(path);
+
+        if modified != self.config_modified {
+            self.config_modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Loads the config again and rebuilds the regions that changed.
+    ///
+    /// The current config is kept if the new one is invalid.
+    fn reload_config(&mut self) {
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        info!("reloading config"; "path" => ?path);
+
+        let config = match ProcessorConfig::load(
+            path,
+            self.frame_acquisition.width(),
+            self.frame_acquisition.height(),
+        ) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!("config reload failed"; "error" => %error);
+                return;
+            }
+        };
+
+        if config.stream != self.config.stream {
+            warn!("config reload failed"; "error" => "streams changed, which requires a restart");
+            return;
+        }
+
+        if let Err(error) = validation::check(
+            &config,
+            self.canvas_publisher.width(),
+            self.canvas_publisher.height(),
+        ) {
+            warn!("config reload failed"; "error" => %error);
+            return;
+        }
+
+        if let Err(error) = load_region_engines(&mut self.text_recognizer_pool, &config.region) {
+            warn!("config reload failed"; "error" => %error);
+            return;
+        }
+
+        let mut scene_selector =
+            match SceneSelector::new(&config.scene, config.scene_detection.confirm_frames) {
+                Ok(scene_selector) => scene_selector,
+                Err(error) => {
+                    warn!("config reload failed"; "error" => %error);
+                    return;
+                }
+            };
+
+        // Keep the current scene without reporting a transition
+        if let Some(active_scene) = self.scene_selector.active_scene() {
+            scene_selector.set_active_scene(active_scene);
+        }
+
+        self.scene_selector = scene_selector;
+
+        let mut old_region_processors = std::mem::take(&mut self.region_processors);
+        let mut changed_count = 0;
+
+        for region in &config.region {
+            // Unchanged regions keep their state such as pending text
+            match old_region_processors
+                .iter()
+                .position(|region_processor| region_processor.region() == region)
+            {
+                Some(position) => self
+                    .region_processors
+                    .push(old_region_processors.remove(position)),
+                None => {
+                    changed_count += 1;
+                    self.region_processors
+                        .push(RegionProcessor::new(region.clone()));
+                }
+            }
+        }
+
+        self.resource_monitor = ResourceMonitor::new(config.limits.clone());
+        self.frame_acquisition
+            .set_catch_up_policy(config.catch_up.policy.clone());
+
+        self.metadata_provider.set_config(config.metadata.clone());
+
+        match (&mut self.telemetry_reporter, config.telemetry.clone()) {
+            (Some(telemetry_reporter), Some(telemetry_config)) => {
+                telemetry_reporter.set_config(telemetry_config)
+            }
+            (_, telemetry_config) => {
+                self.telemetry_reporter = telemetry_config.map(TelemetryReporter::new)
+            }
+        }
+
+        self.outputs.set_configs(config.output_configs());
+        self.outputs
+            .set_content_filter_config(config.content_filter.clone());
+
+        match (&mut self.http_api, config.http_api.clone()) {
+            (Some(http_api), Some(http_api_config))
+                if http_api.config().address == http_api_config.address =>
+            {
+                http_api.set_config(http_api_config);
+                http_api.set_region_names(region_names(&config));
+            }
+            (_, http_api_config) => {
+                self.http_api = None;
+                self.http_api = create_http_api(http_api_config, &config);
+            }
+        }
+
+        if self
+            .frame_result_writer
+            .as_ref()
+            .map(FrameResultWriter::config)
+            != config.frame_dump.as_ref()
+        {
+            // Release the file or address before opening it again
+            self.frame_result_writer = None;
+            self.frame_result_writer = create_frame_result_writer(config.frame_dump.clone());
+        }
+
+        self.frame_quality_gate
+            .set_config(config.frame_quality.clone());
+
+        if config.display.fonts != self.config.display.fonts {
+            match create_text_drawer(&config.display) {
+                Ok(text_drawer) => self.text_drawer = text_drawer,
+                Err(error) => warn!("failed to load display fonts"; "error" => %error),
+            }
+        }
+
+        self.text_drawer.set_font_size(config.display.font_size);
+        self.config = config;
+        self.register_artifacts();
+
+        info!("config reloaded";
+            "region_count" => self.region_processors.len(),
+            "changed_region_count" => changed_count,
+            "removed_region_count" => old_region_processors.len());
+    }
+
+    fn process_frame(&mut self) -> Result<(), Error> {
+        let frame_set = self.frame_acquisition.next_frames()?;
+        self.frame_set = Some(Arc::clone(&frame_set));
+
+        if let Some(fault_injector) = &self.fault_injector {
+            if let Some(duration) = fault_injector.delay() {
+                std::thread::sleep(duration);
+            }
+
+            if fault_injector.drop_frame() {
+                return Ok(());
+            }
+        }
+
+        let frame = &frame_set.main;
+
+        if let Some(event) =
+            self.scene_selector
+                .update(frame.data_u32(), frame.width(), frame.height())
+        {
+            self.emit_event(event);
+        }
+
+        let frame_date = frame.capture_time().unwrap_or_else(Utc::now);
+
+        if let Some(event) = self.check_frame_gap() {
+            self.emit_event(event);
+        }
+
+        if !self
+            .frame_quality_gate
+            .check(frame.data_u32(), frame.width(), frame.height())
+        {
+            self.dump_frame_result(false, Duration::from_secs(0), &[]);
+            return Ok(());
+        }
+
+        let recognition_start = Instant::now();
+        let mut recognitions = self.recognize_regions(&frame_set);
+        let recognition_duration = recognition_start.elapsed();
+
+        self.frame_latency.record(&frame_date, &Utc::now());
+
+        if let Some(telemetry_reporter) = &mut self.telemetry_reporter {
+            telemetry_reporter.record_frame();
+        }
 
         self.clear_canvas();
 
         let mut draw_offset_y = 0;
+        let mut events = Vec::new();
+        let mut item_counts = vec![0; self.region_processors.len()];
+
+        for (index, region_processor) in self.region_processors.iter_mut().enumerate() {
+            if let Some(recognition) = recognitions[index].take() {
+                let frame = frame_set.stream_frame(region_processor.region.stream.as_deref());
+                // Items of other streams are dated by their own frames
+                let frame_date = frame.capture_time().unwrap_or(frame_date);
+
+                // A failed region is skipped for the frame so the other
+                // regions are still processed
+                let recognition = match recognition {
+                    Ok(recognition) => {
+                        if region_processor.failed {
+                            info!("region recognition recovered";
+                                "region_name" => &region_processor.region.name);
+                            region_processor.failed = false;
+                        }
+
+                        recognition
+                    }
+                    Err(error) => {
+                        if !region_processor.failed {
+                            warn!("region recognition failed";
+                                "region_name" => &region_processor.region.name, "error" => %error);
+                            region_processor.failed = true;
+                        }
+
+                        region_processor.gate = RegionGate::Failed;
+                        None
+                    }
+                };
+
+                events.extend(region_processor.apply_recognition(recognition, &frame_date));
+                region_processor.draw(
+                    frame,
+                    &mut self.canvas,
+                    &mut self.text_drawer,
+                    &self.config.display,
+                    draw_offset_y,
+                );
 
-        for region_processor in &mut self.region_processors {
-            region_processor.process(
-                &self.text_recognizer,
-                &self.frame_reader,
-                &mut self.canvas,
-                draw_offset_y,
-            )?;
+                let text_items = region_processor.get_text(&frame_date);
+                item_counts[index] = text_items.len();
 
-            draw_offset_y += region_processor.region().height as i32 + 48;
+                events.extend(region_processor.check_quota(index, text_items.len(), &frame_date));
 
-            for text_item in region_processor.get_text() {
-                dbg!(text_item.date, text_item.text);
+                if !region_processor.muted {
+                    events.extend(region_processor.get_events(&frame_date));
+
+                    let mut emitted_items = Vec::with_capacity(text_items.len());
+
+                    for mut text_item in text_items {
+                        text_item.metadata = self.metadata_provider.snapshot(&text_item.date);
+                        text_item.stream_name = region_processor.region.stream.clone();
+                        text_item.integrity = self
+                            .integrity_chain
+                            .as_mut()
+                            .map(|integrity_chain| integrity_chain.stamp(&text_item));
+                        self.delivery_latency.record(&text_item.date, &Utc::now());
+
+                        if let Some(telemetry_reporter) = &mut self.telemetry_reporter {
+                            telemetry_reporter.record_item(text_item.confidence);
+                        }
+
+                        if let Some(http_api) = &mut self.http_api {
+                            http_api.record_item(&text_item);
+                        }
+
+                        if let Some(history) = &self.config.overlay.history {
+                            self.text_history.push_back(format!(
+                                "{} [{}] {}",
+                                text_item.date.format("%H:%M:%S"),
+                                item_source(&text_item),
+                                text_item
+                                    .text
+                                    .split_whitespace()
+                                    .collect::<Vec<_>>()
+                                    .join(" ")
+                            ));
+
+                            while self.text_history.len() > history.item_count {
+                                self.text_history.pop_front();
+                            }
+                        }
+
+                        if self.console_output {
+                            println!(
+                                "{} [{}] {}",
+                                text_item.date.format("%H:%M:%S"),
+                                item_source(&text_item),
+                                text_item.text.trim()
+                            );
+                        }
+
+                        emitted_items.push(text_item);
+                    }
+
+                    self.outputs.emit(emitted_items);
+                }
             }
+
+            draw_offset_y +=
+                (region_processor.region().height + self.config.display.region_spacing()) as i32;
         }
 
+        for event in events {
+            self.emit_event(event);
+        }
+
+        self.dump_frame_result(true, recognition_duration, &item_counts);
+
         Ok(())
     }
 
+    /// Writes the state of the regions for this frame when frame dumping is
+    /// enabled and the frame is sampled.
+    fn dump_frame_result(
+        &mut self,
+        quality_passed: bool,
+        recognition_duration: Duration,
+        item_counts: &[usize],
+    ) {
+        let frame_result_writer = match &mut self.frame_result_writer {
+            Some(writer) if writer.is_due(self.frame_counter) => writer,
+            _ => return,
+        };
+
+        let regions = if quality_passed {
+            self.region_processors
+                .iter()
+                .zip(item_counts)
+                .map(|(region_processor, item_count)| region_processor.frame_result(*item_count))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        if let (Some(session_manifest), FrameDumpOutput::File { path }) = (
+            &mut self.session_manifest,
+            &frame_result_writer.config().output,
+        ) {
+            session_manifest.set_artifact_detail(
+                path,
+                "last_frame_counter",
+                self.frame_counter.to_string(),
+            );
+        }
+
+        frame_result_writer.write(&FrameResult {
+            frame_counter: self.frame_counter,
+            date: Utc::now(),
+            capture_time: self
+                .frame_set
+                .as_ref()
+                .and_then(|frame_set| frame_set.main.capture_time()),
+            scene: self.scene_selector.active_scene().map(str::to_string),
+            quality_passed,
+            recognition_ms: recognition_duration.as_secs_f64() * 1000.0,
+            regions,
+        });
+    }
+
+    /// Runs recognition of the regions spread across the recognizer pool,
+    /// one blocking task per recognizer set.
+    ///
+    /// Returns results indexed by region. Regions that were not scheduled
+    /// this frame are None.
+    fn recognize_regions(
+        &mut self,
+        frame_set: &Arc<FrameSet>,
+    ) -> Vec<Option<Result<Option<RegionRecognition>, OcrError>>> {
+        // While over the memory limit, only one region is processed per frame
+        let degraded_index = if self.resource_monitor.memory_exceeded() {
+            Some(self.frame_counter as usize % self.region_processors.len().max(1))
+        } else {
+            None
+        };
+
+        let now = Utc::now();
+        let pool_size = self.text_recognizer_pool.len();
+        let (dump_hocr, dump_tsv) = match &self.frame_result_writer {
+            Some(writer) if writer.is_due(self.frame_counter) => {
+                (writer.config().hocr, writer.config().tsv)
+            }
+            _ => (false, false),
+        };
+        let mut worker_tasks: Vec<Vec<RecognitionTask>> =
+            (0..pool_size).map(|_| Vec::new()).collect();
+
+        for (index, region_processor) in self.region_processors.iter_mut().enumerate() {
+            let interval_frames = region_processor.region.interval_frames.max(1) as u64;
+
+            region_processor.recognition_duration = Duration::from_secs(0);
+            region_processor.hocr = None;
+            region_processor.tsv = None;
+
+            if self.frame_counter % interval_frames != 0 {
+                region_processor.gate = RegionGate::Interval;
+                continue;
+            }
+
+            if !self
+                .scene_selector
+                .is_region_active(&region_processor.region.name)
+            {
+                region_processor.gate = RegionGate::InactiveScene;
+                continue;
+            }
+
+            if degraded_index.map_or(true, |degraded_index| degraded_index == index) {
+                let detect_language = region_processor.language_detection_due(&now);
+                // The preprocessor keeps its history, so it is moved to the
+                // task and back
+                let preprocessor = std::mem::replace(
+                    &mut region_processor.preprocessor,
+                    Preprocessor::new(region_processor.region.clone()),
+                );
+
+                worker_tasks[index % pool_size].push(RecognitionTask {
+                    index,
+                    region: region_processor.region.clone(),
+                    language: region_processor.language.clone(),
+                    detect_language,
+                    previous_pixel_hash: region_processor.previous_pixel_hash,
+                    previous_perceptual_hash: region_processor.previous_perceptual_hash,
+                    perceptual_hash: region_processor.perceptual_hash,
+                    gate: region_processor.gate,
+                    hocr: dump_hocr,
+                    tsv: dump_tsv,
+                    preprocessor,
+                });
+            } else {
+                region_processor.gate = RegionGate::Degraded;
+            }
+        }
+
+        let handles: Vec<_> = std::mem::take(&mut self.text_recognizer_pool)
+            .into_iter()
+            .zip(worker_tasks)
+            .map(|(text_recognizers, tasks)| {
+                let frame_set = Arc::clone(frame_set);
+
+                self.runtime.spawn_blocking(move || {
+                    let outcomes = tasks
+                        .into_iter()
+                        .map(|task| task.run(&text_recognizers, &frame_set))
+                        .collect::<Vec<_>>();
+
+                    (text_recognizers, outcomes)
+                })
+            })
+            .collect();
+
+        let mut recognitions: Vec<_> = self.region_processors.iter().map(|_| None).collect();
+
+        for handle in handles {
+            let (text_recognizers, outcomes) =
+                self.runtime.block_on(handle).unwrap_or_else(|error| {
+                    match error.try_into_panic() {
+                        Ok(payload) => std::panic::resume_unwind(payload),
+                        Err(error) => panic!("recognition task failed: {}", error),
+                    }
+                });
+
+            self.text_recognizer_pool.push(text_recognizers);
+
+            for outcome in outcomes {
+                let region_processor = &mut self.region_processors[outcome.index];
+                region_processor.preprocessor = outcome.preprocessor;
+                region_processor.perceptual_hash = outcome.perceptual_hash;
+                region_processor.gate = outcome.gate;
+                region_processor.recognition_duration = outcome.duration;
+                recognitions[outcome.index] = Some(outcome.result);
+            }
+        }
+
+        recognitions
+    }
+
     fn draw_date(&mut self) {
-        let color = Color::new(255, 255, 255, 255);
-        self.text_drawer.set_color(color);
         self.text_drawer
-            .set_position(Point::new(0.0, self.vnc_client.height() as f32));
+            .set_color(rgb_color(self.config.display.colors.date));
+        self.text_drawer
+            .set_position(Point::new(0.0, self.canvas_publisher.height() as f32));
+
+        let mut status = format!("Date={} FrameCounter={}", Utc::now(), self.frame_counter);
+
+        if let Some(summary) = self.delivery_latency.summary() {
+            status.push_str(&format!(
+                " Latency={:.0}/{:.0}/{:.0}ms",
+                summary.p50, summary.p95, summary.p99
+            ));
+        }
+
+        self.text_drawer.draw(&mut self.canvas, &status);
+    }
 
-        self.text_drawer.draw(
-            &mut self.canvas,
-            &format!("Date={} FrameCounter={}", Utc::now(), self.frame_counter),
+    /// Draws the latest emitted items in a column at the right of the
+    /// canvas, newest at the bottom.
+    fn draw_history(&mut self) {
+        let history = match &self.config.overlay.history {
+            Some(history) => history,
+            None => return,
+        };
+        let colors = &self.config.display.colors;
+        let line_height = self.config.display.font_size;
+        let x = self.canvas.width() as f32 - history.width as f32;
+        let skip_count = self.text_history.len().saturating_sub(history.item_count);
+
+        self.canvas.fill_rect(
+            x,
+            0.0,
+            history.width as f32,
+            self.canvas.height() as f32,
+            &Source::from(rgb_color(colors.history_background)),
+            &DrawOptions::default(),
         );
+        self.text_drawer.set_color(rgb_color(colors.history_text));
+
+        for (index, line) in self.text_history.iter().skip(skip_count).enumerate() {
+            self.text_drawer
+                .set_position(Point::new(x + 4.0, (index + 1) as f32 * line_height));
+            self.text_drawer.draw(&mut self.canvas, line);
+        }
+    }
+
+    /// Marks the debug display as paused so the frozen overlay is not
+    /// mistaken for a stalled stream.
+    fn draw_pause_indicator(&mut self) {
+        let status = format!("PAUSED FrameCounter={}", self.frame_counter);
+        let colors = &self.config.display.colors;
+        let font_size = self.config.display.font_size;
+        let width = self.canvas.width() as f32;
+
+        self.canvas.fill_rect(
+            width - 320.0,
+            0.0,
+            320.0,
+            font_size * 1.5,
+            &Source::from(rgb_color(colors.pause_background)),
+            &DrawOptions::default(),
+        );
+        self.text_drawer.set_color(rgb_color(colors.pause_text));
+        self.text_drawer
+            .set_position(Point::new(width - 312.0, font_size * 1.125));
+        self.text_drawer.draw(&mut self.canvas, &status);
+    }
+
+    /// Logs the event and sends it to the outputs.
+    fn emit_event(&mut self, event: Event) {
+        event.log();
+        self.outputs.emit_event(event);
+    }
+
+    fn report_telemetry(&mut self) {
+        if let Some(telemetry_reporter) = &mut self.telemetry_reporter {
+            telemetry_reporter.poll(&Utc::now(), self.delivery_latency.summary());
+        }
+    }
+
+    fn log_latency(&mut self) {
+        let date = Utc::now();
+
+        if date - self.latency_log_date < chrono::Duration::seconds(60) {
+            return;
+        }
+
+        self.latency_log_date = date;
+
+        for (name, tracker) in &[
+            ("frame", &self.frame_latency),
+            ("delivery", &self.delivery_latency),
+        ] {
+            if let Some(summary) = tracker.summary() {
+                info!("latency";
+                    "stage" => name,
+                    "p50_ms" => summary.p50,
+                    "p95_ms" => summary.p95,
+                    "p99_ms" => summary.p99,
+                );
+            }
+        }
     }
 
     fn clear_canvas(&mut self) {
-        let source = Source::from(Color::new(255, 0, 0, 0));
+        let source = Source::from(rgb_color(self.config.display.colors.background));
         let options = DrawOptions::default();
         self.canvas.fill_rect(
             0.0,
@@ -137,25 +1138,72 @@ impl Processor {
     }
 }
 
-struct RegionProcessor {
+pub(crate) struct RegionProcessor {
     region: Region,
-    text_drawer: TextDrawer,
     text_processor: Box<dyn TextProcessor>,
+    preprocessor: Preprocessor,
+    previous_pixel_hash: Option<u64>,
+    /// Perceptual hash of the last recognized crop.
+    previous_perceptual_hash: Option<u64>,
+    /// Perceptual hash of the latest crop, shown on the debug display.
+    perceptual_hash: Option<u64>,
+    text: String,
+    word_boxes: Vec<BoundingBox>,
+    emission_rate_tracker: Option<EmissionRateTracker>,
+    muted: bool,
+    /// Recognition language, which differs from the region's language after
+    /// language detection switched it. None for the global language.
+    language: Option<String>,
+    language_detection_date: Option<DateTime<Utc>>,
+    /// Whether the region was recognized in the latest frame.
+    gate: RegionGate,
+    /// Whether the latest recognition failed, so the error is logged once.
+    failed: bool,
+    recognition_duration: Duration,
+    /// Stage times of the latest recognition, None if the latest frame
+    /// wasn't recognized.
+    stage_durations: Option<StageDurations>,
+    /// Layout of the latest frame's recognition when frame dumping wants it.
+    hocr: Option<String>,
+    tsv: Option<String>,
+    corrector: Option<Corrector>,
 }
 
 impl RegionProcessor {
     pub fn new(region: Region) -> Self {
+        let corrector =
+            region
+                .correction
+                .clone()
+                .and_then(|config| match Corrector::load(config) {
+                    Ok(corrector) => Some(corrector),
+                    Err(error) => {
+                        warn!("failed to load word lists, correction disabled";
+                        "region_name" => &region.name, "error" => %error);
+                        None
+                    }
+                });
+
         Self {
             region: region.clone(),
-            text_drawer: TextDrawer::new().unwrap(),
-            text_processor: Self::get_text_processor(region),
-        }
-    }
-
-    fn get_text_processor(region: Region) -> Box<dyn TextProcessor> {
-        match region.processor {
-            ProcessorStrategy::FixedLine => Box::new(FixedLineProcessor::new(region)),
-            ProcessorStrategy::DialogScroll => Box::new(DialogScrollProcessor::new(region)),
+            text_processor: text_processor_for(region.clone()),
+            emission_rate_tracker: region.quota.clone().map(EmissionRateTracker::new),
+            muted: false,
+            language: region.language.clone(),
+            language_detection_date: None,
+            gate: RegionGate::default(),
+            failed: false,
+            recognition_duration: Duration::from_secs(0),
+            stage_durations: None,
+            hocr: None,
+            tsv: None,
+            corrector,
+            preprocessor: Preprocessor::new(region),
+            previous_pixel_hash: None,
+            previous_perceptual_hash: None,
+            perceptual_hash: None,
+            text: String::new(),
+            word_boxes: Vec::new(),
         }
     }
 
@@ -163,40 +1211,148 @@ impl RegionProcessor {
         &self.region
     }
 
-    pub fn process(
+    /// Whether the region stopped emitting after exceeding its quota.
+    pub(crate) fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Returns the stage times of the latest recognition, or None if the
+    /// latest frame wasn't recognized.
+    pub(crate) fn stage_durations(&self) -> Option<StageDurations> {
+        self.stage_durations
+    }
+
+    /// Recognizes the region in the frame on this thread and passes the text
+    /// to the text processor. Returns an event if the recognition language
+    /// was switched.
+    pub(crate) fn recognize(
         &mut self,
-        text_recognizer: &TextRecognizer,
-        frame_reader: &FrameReader,
-        canvas: &mut DrawTarget,
-        draw_offset_y: i32,
-    ) -> anyhow::Result<()> {
-        text_recognizer.set_rectangle(
-            self.region.x,
-            self.region.y,
-            self.region.width,
-            self.region.height,
-        );
-        text_recognizer.recognize()?;
+        text_recognizers: &TextRecognizerSet,
+        frame_data: &[u32],
+        frame_width: u32,
+        date: &DateTime<Utc>,
+    ) -> Result<Option<Event>, OcrError> {
+        let mut job = RecognitionJob {
+            region: &self.region,
+            language: self.language.as_deref(),
+            detect_language: self.language_detection_due(date),
+            previous_pixel_hash: self.previous_pixel_hash,
+            previous_perceptual_hash: self.previous_perceptual_hash,
+            perceptual_hash: &mut self.perceptual_hash,
+            gate: &mut self.gate,
+            duration: &mut self.recognition_duration,
+            hocr: false,
+            tsv: false,
+            preprocessor: &mut self.preprocessor,
+        };
+        let start = Instant::now();
+        let recognition = recognize_region(&mut job, text_recognizers, frame_data, frame_width)?;
+        *job.duration = start.elapsed();
 
-        self.draw_image(frame_reader, canvas, draw_offset_y);
-        self.draw_region_bounding_boxes(text_recognizer, canvas, draw_offset_y);
+        Ok(self.apply_recognition(recognition, date))
+    }
 
-        let text = text_recognizer.get_text();
-        let bounding_boxes = text_recognizer.get_block_boxes();
-        let date = Utc::now();
+    /// Returns whether the region should be recognized with the other
+    /// languages it may switch to.
+    fn language_detection_due(&self, date: &DateTime<Utc>) -> bool {
+        let language_detection = match &self.region.language_detection {
+            Some(language_detection) => language_detection,
+            None => return false,
+        };
 
-        self.text_processor.process(&date, &text, &bounding_boxes);
+        match self.language_detection_date {
+            Some(detection_date) => {
+                (*date - detection_date).num_milliseconds() as f64
+                    >= language_detection.interval_secs * 1000.0
+            }
+            None => true,
+        }
+    }
+
+    /// Passes the recognized text to the text processor and returns an
+    /// event if the recognition language was switched.
+    fn apply_recognition(
+        &mut self,
+        recognition: Option<RegionRecognition>,
+        date: &DateTime<Utc>,
+    ) -> Option<Event> {
+        self.stage_durations = None;
 
-        self.draw_text(&text, canvas, draw_offset_y);
+        // None when the region is identical to the previous frame
+        let recognition = recognition?;
+        let start = Instant::now();
 
-        Ok(())
+        self.text_processor
+            .process(date, &recognition.text, &recognition.block_boxes);
+        self.text_processor.process_lines(date, &recognition.lines);
+        self.text_processor.process_words(date, &recognition.words);
+
+        self.stage_durations = Some(StageDurations {
+            preprocess: recognition.preprocess_duration,
+            ocr: recognition.ocr_duration,
+            text_processing: start.elapsed(),
+        });
+
+        self.previous_pixel_hash = Some(recognition.pixel_hash);
+        self.previous_perceptual_hash = recognition.perceptual_hash;
+        self.text = recognition.text;
+        self.word_boxes = recognition.word_boxes;
+        self.hocr = recognition.hocr;
+        self.tsv = recognition.tsv;
+
+        let language_scores = recognition.language_scores?;
+        let language_detection = self.region.language_detection.as_ref()?;
+        self.language_detection_date = Some(*date);
+
+        let current_confidence = language_detection::mean_confidence(&self.word_boxes);
+        let next_language = language_detection::choose_language(
+            language_detection,
+            current_confidence,
+            &language_scores,
+        )?
+        .to_string();
+        let confidence = language_scores
+            .iter()
+            .find(|score| score.language == next_language)
+            .map_or(0.0, |score| score.confidence);
+        let previous_language = self
+            .language
+            .replace(next_language.clone())
+            .unwrap_or(recognition.language);
+
+        Some(Event::LanguageSwitched {
+            date: *date,
+            region_name: self.region.name.clone(),
+            previous_language,
+            next_language,
+            confidence,
+        })
+    }
+
+    fn draw(
+        &self,
+        frame: &Frame,
+        canvas: &mut DrawTarget,
+        text_drawer: &mut TextDrawer,
+        display: &DisplayConfig,
+        draw_offset_y: i32,
+    ) {
+        if self.region.overlay.image {
+            self.draw_image(frame, canvas, draw_offset_y);
+        }
+
+        self.draw_region_bounding_boxes(canvas, text_drawer, &display.colors, draw_offset_y);
+
+        if self.region.overlay.text {
+            self.draw_text(canvas, text_drawer, display, draw_offset_y);
+        }
     }
 
-    fn draw_image(&self, frame_reader: &FrameReader, canvas: &mut DrawTarget, draw_offset_y: i32) {
+    fn draw_image(&self, frame: &Frame, canvas: &mut DrawTarget, draw_offset_y: i32) {
         let image = ImageBuffer::<Bgra<u8>, _>::from_raw(
-            frame_reader.width(),
-            frame_reader.height(),
-            frame_reader.data().to_vec(),
+            frame.width(),
+            frame.height(),
+            frame.data().to_vec(),
         )
         .unwrap();
         let subimage = image::imageops::crop_imm(
@@ -223,47 +1379,521 @@ impl RegionProcessor {
     }
 
     fn draw_region_bounding_boxes(
-        &mut self,
-        text_recognizer: &TextRecognizer,
+        &self,
         canvas: &mut DrawTarget,
+        text_drawer: &mut TextDrawer,
+        colors: &DisplayColors,
         draw_offset_y: i32,
     ) {
-        for bounding_box in text_recognizer.get_word_boxes() {
-            let mut path = PathBuilder::new();
-            path.rect(
-                (bounding_box.x1 - self.region.x as i32) as f32,
-                (bounding_box.y1 - self.region.y as i32 + draw_offset_y) as f32,
-                (bounding_box.x2 - bounding_box.x1) as f32,
-                (bounding_box.y2 - bounding_box.y1) as f32,
-            );
-            let path = path.finish();
-            let source = Source::from(Color::new(255, 0, 255, 0));
-            let style = StrokeStyle::default();
-            let options = DrawOptions::new();
+        for bounding_box in &self.word_boxes {
+            let bounding_box = bounding_box.to_region_space(&self.region);
 
-            canvas.stroke(&path, &source, &style, &options);
+            if self.region.overlay.word_boxes {
+                let mut path = PathBuilder::new();
+                path.rect(
+                    bounding_box.x1 as f32,
+                    (bounding_box.y1 + draw_offset_y) as f32,
+                    (bounding_box.x2 - bounding_box.x1) as f32,
+                    (bounding_box.y2 - bounding_box.y1) as f32,
+                );
+                let path = path.finish();
+                let source = Source::from(rgb_color(colors.word_box));
+                let style = StrokeStyle::default();
+                let options = DrawOptions::new();
 
-            self.text_drawer.set_color(Color::new(255, 0, 255, 0));
-            self.text_drawer.set_position(Point::new(
-                (bounding_box.x1 - self.region.x as i32) as f32,
-                (bounding_box.y1 - self.region.y as i32 + draw_offset_y) as f32,
+                canvas.stroke(&path, &source, &style, &options);
+            }
+
+            if !self.region.overlay.confidences {
+                continue;
+            }
+
+            text_drawer.set_color(rgb_color(colors.confidence));
+            text_drawer.set_position(Point::new(
+                bounding_box.x1 as f32,
+                (bounding_box.y1 + draw_offset_y) as f32,
             ));
-            self.text_drawer
-                .draw(canvas, &format!("{:.3}", bounding_box.confidence));
+            text_drawer.draw(canvas, &format!("{:.3}", bounding_box.confidence));
         }
     }
 
-    fn draw_text(&mut self, text: &str, canvas: &mut DrawTarget, draw_offset_y: i32) {
-        self.text_drawer.set_color(Color::new(255, 255, 0, 255));
-        self.text_drawer.set_position(Point::new(
-            0.0,
-            self.region.height as f32 + draw_offset_y as f32 + 16.0,
-        ));
-        self.text_drawer.draw(canvas, text);
+    fn draw_text(
+        &self,
+        canvas: &mut DrawTarget,
+        text_drawer: &mut TextDrawer,
+        display: &DisplayConfig,
+        draw_offset_y: i32,
+    ) {
+        let text_y = self.region.height as f32 + draw_offset_y as f32 + display.font_size;
+
+        text_drawer.set_color(rgb_color(display.colors.text));
+        text_drawer.set_position(Point::new(0.0, text_y));
+        text_drawer.draw(canvas, &self.text);
+
+        let mut status = Vec::new();
+
+        if let Some(perceptual_hash) = self.perceptual_hash {
+            status.push(format!("dHash={:016x}", perceptual_hash));
+
+            if let Some(previous_perceptual_hash) = self.previous_perceptual_hash {
+                status.push(format!(
+                    "Distance={}",
+                    perceptual_hash::distance(perceptual_hash, previous_perceptual_hash)
+                ));
+            }
+        }
+
+        if self.region.language_detection.is_some() {
+            status.push(format!(
+                "Language={}",
+                self.language.as_deref().unwrap_or("default")
+            ));
+        }
+
+        if !status.is_empty() {
+            text_drawer.set_color(rgb_color(display.colors.status));
+            text_drawer.set_position(Point::new(0.0, text_y + display.font_size));
+            text_drawer.draw(canvas, &status.join(" "));
+        }
     }
 
-    pub fn get_text(&mut self) -> Vec<TextItem> {
-        let date = Utc::now();
-        self.text_processor.poll_result(&date)
+    fn frame_result(&self, item_count: usize) -> RegionResult {
+        RegionResult {
+            name: self.region.name.clone(),
+            gate: self.gate,
+            recognition_ms: self.recognition_duration.as_secs_f64() * 1000.0,
+            text: self.text.clone(),
+            word_boxes: self.word_boxes.clone(),
+            language: self.language.clone(),
+            perceptual_hash: self.perceptual_hash,
+            item_count,
+            hocr: self.hocr.clone(),
+            tsv: self.tsv.clone(),
+        }
+    }
+
+    /// Returns the items the strategy emits as of the frame date, which is
+    /// also the clock its timeouts use.
+    pub fn get_text(&mut self, date: &DateTime<Utc>) -> Vec<TextItem> {
+        let mut text_items = self.text_processor.poll_result(date);
+
+        if !self.region.rules.is_empty() {
+            text_items = text_items
+                .into_iter()
+                .filter_map(|mut text_item| {
+                    text_item.text = text_rules::apply(&self.region.rules, &text_item.text)?;
+                    Some(text_item)
+                })
+                .collect();
+        }
+
+        if let Some(corrector) = &self.corrector {
+            for text_item in &mut text_items {
+                text_item.text = corrector.correct(&text_item.text);
+            }
+        }
+
+        text_items
+    }
+
+    /// Returns the events produced by the strategy, such as menu selections.
+    pub fn get_events(&mut self, date: &DateTime<Utc>) -> Vec<Event> {
+        self.text_processor.poll_events(date)
+    }
+
+    /// Records items emitted at the frame date and returns an alert if the
+    /// region's quota became exceeded, muting the region if configured.
+    ///
+    /// The quota is measured in stream time, so catching up on a backlog
+    /// doesn't count as a burst.
+    pub(crate) fn check_quota(
+        &mut self,
+        region_index: usize,
+        item_count: usize,
+        date: &DateTime<Utc>,
+    ) -> Option<Event> {
+        if self.muted {
+            return None;
+        }
+
+        let tracker = self.emission_rate_tracker.as_mut()?;
+
+        if !tracker.record(date, item_count) {
+            return None;
+        }
+
+        if tracker.quota().auto_mute {
+            self.muted = true;
+        }
+
+        Some(Event::EmissionQuotaExceeded {
+            date: *date,
+            region_index,
+            region_name: self.region.name.clone(),
+            item_count: tracker.count(),
+            muted: self.muted,
+        })
+    }
+}
+
+/// Recognition results of a region from one frame.
+struct RegionRecognition {
+    pixel_hash: u64,
+    perceptual_hash: Option<u64>,
+    text: String,
+    word_boxes: Vec<BoundingBox>,
+    block_boxes: Vec<BoundingBox>,
+    lines: Vec<TextLine>,
+    words: Vec<TextLine>,
+    /// Language the text was recognized with.
+    language: String,
+    /// Scores of the other languages when language detection ran.
+    language_scores: Option<Vec<LanguageScore>>,
+    hocr: Option<String>,
+    tsv: Option<String>,
+    preprocess_duration: Duration,
+    /// Time of recognition, including language detection.
+    ocr_duration: Duration,
+}
+
+/// Times taken by the stages of a region's recognition.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StageDurations {
+    pub preprocess: Duration,
+    pub ocr: Duration,
+    /// Time the strategy took to process the text, not including polling
+    /// it for items.
+    pub text_processing: Duration,
+}
+
+/// Region work moved to a recognition task, with the region state it
+/// updates.
+struct RecognitionTask {
+    index: usize,
+    region: Region,
+    language: Option<String>,
+    detect_language: bool,
+    previous_pixel_hash: Option<u64>,
+    previous_perceptual_hash: Option<u64>,
+    perceptual_hash: Option<u64>,
+    gate: RegionGate,
+    hocr: bool,
+    tsv: bool,
+    preprocessor: Preprocessor,
+}
+
+/// Result of a recognition task, returning the region state it updated.
+struct RecognitionOutcome {
+    index: usize,
+    preprocessor: Preprocessor,
+    perceptual_hash: Option<u64>,
+    gate: RegionGate,
+    duration: Duration,
+    result: Result<Option<RegionRecognition>, OcrError>,
+}
+
+impl RecognitionTask {
+    fn run(
+        mut self,
+        text_recognizers: &TextRecognizerSet,
+        frame_set: &FrameSet,
+    ) -> RecognitionOutcome {
+        let frame = frame_set.stream_frame(self.region.stream.as_deref());
+        let mut duration = Duration::from_secs(0);
+        let mut job = RecognitionJob {
+            region: &self.region,
+            language: self.language.as_deref(),
+            detect_language: self.detect_language,
+            previous_pixel_hash: self.previous_pixel_hash,
+            previous_perceptual_hash: self.previous_perceptual_hash,
+            perceptual_hash: &mut self.perceptual_hash,
+            gate: &mut self.gate,
+            duration: &mut duration,
+            hocr: self.hocr,
+            tsv: self.tsv,
+            preprocessor: &mut self.preprocessor,
+        };
+        let start = Instant::now();
+        let result = recognize_region(&mut job, text_recognizers, frame.data_u32(), frame.width());
+        *job.duration = start.elapsed();
+
+        RecognitionOutcome {
+            index: self.index,
+            preprocessor: self.preprocessor,
+            perceptual_hash: self.perceptual_hash,
+            gate: self.gate,
+            duration,
+            result,
+        }
+    }
+}
+
+/// Region work of a recognition task, borrowing the region's state.
+struct RecognitionJob<'a> {
+    region: &'a Region,
+    language: Option<&'a str>,
+    /// Also recognize the region with the other languages it may switch to.
+    detect_language: bool,
+    previous_pixel_hash: Option<u64>,
+    previous_perceptual_hash: Option<u64>,
+    /// Receives the perceptual hash of the crop when enabled.
+    perceptual_hash: &'a mut Option<u64>,
+    /// Receives whether recognition ran or why it was skipped.
+    gate: &'a mut RegionGate,
+    /// Receives the time taken by the job.
+    duration: &'a mut Duration,
+    /// Also get the hOCR document for the frame dump.
+    hocr: bool,
+    /// Also get the TSV table for the frame dump.
+    tsv: bool,
+    preprocessor: &'a mut Preprocessor,
+}
+
+/// Preprocesses and recognizes the region in the frame.
+///
+/// Returns None without running recognition when the region's pixels are
+/// identical to the previous frame, or when enabled, perceptually similar to
+/// the last recognized crop. Bounding boxes are returned in frame
+/// coordinates. This function is run on the recognition tasks so it must not
+/// touch the canvas.
+fn recognize_region(
+    job: &mut RecognitionJob,
+    text_recognizers: &TextRecognizerSet,
+    frame_data: &[u32],
+    frame_width: u32,
+) -> Result<Option<RegionRecognition>, OcrError> {
+    let region = job.region;
+    let pixel_hash = hash_region_pixels(region, frame_data, frame_width);
+
+    if job.previous_pixel_hash == Some(pixel_hash) {
+        *job.gate = RegionGate::Unchanged;
+        return Ok(None);
+    }
+
+    let perceptual_hash = region
+        .perceptual_hash_threshold
+        .map(|_| perceptual_hash::difference_hash(region, frame_data, frame_width));
+    *job.perceptual_hash = perceptual_hash;
+
+    if let (Some(threshold), Some(hash), Some(previous_hash)) = (
+        region.perceptual_hash_threshold,
+        perceptual_hash,
+        job.previous_perceptual_hash,
+    ) {
+        if perceptual_hash::distance(hash, previous_hash) <= threshold {
+            *job.gate = RegionGate::PerceptuallySimilar;
+            return Ok(None);
+        }
+    }
+
+    *job.gate = RegionGate::Recognized;
+
+    let preprocess_start = Instant::now();
+    let image = job.preprocessor.process(frame_data, frame_width);
+    let image_data = preprocess::to_u32_pixels(&image);
+    let preprocess_duration = preprocess_start.elapsed();
+    let ocr_start = Instant::now();
+    let language = job
+        .language
+        .unwrap_or_else(|| text_recognizers.default_language());
+    let text_recognizer = text_recognizers.get_for_region(region, Some(language));
+
+    text_recognizer.set_page_segmentation_mode(page_segmentation_mode(&region.processor));
+    text_recognizer.set_character_whitelist(character_whitelist(&region.processor));
+    text_recognizer.set_image(&image_data, image.width(), image.height());
+    text_recognizer.recognize()?;
+
+    let mut word_boxes = text_recognizer.boxes(BoxLevel::Word);
+    let mut block_boxes = text_recognizer.boxes(BoxLevel::Block);
+
+    let mut lines = text_recognizer.texts(BoxLevel::Line);
+    let mut words = text_recognizer.texts(BoxLevel::Word);
+
+    translate_boxes_to_frame(region, &mut word_boxes);
+    translate_boxes_to_frame(region, &mut block_boxes);
+
+    for line in lines.iter_mut().chain(&mut words) {
+        translate_boxes_to_frame(region, std::slice::from_mut(&mut line.bounding_box));
+    }
+
+    let text = text_recognizer.text();
+    let hocr = if job.hocr {
+        text_recognizer.hocr()
+    } else {
+        None
+    };
+    let tsv = if job.tsv { text_recognizer.tsv() } else { None };
+
+    let language_scores = match &region.language_detection {
+        Some(language_detection) if job.detect_language => {
+            Some(language_detection::score_languages(
+                language_detection,
+                language,
+                text_recognizers,
+                page_segmentation_mode(&region.processor),
+                &image_data,
+                image.width(),
+                image.height(),
+            )?)
+        }
+        _ => None,
+    };
+    let ocr_duration = ocr_start.elapsed();
+
+    Ok(Some(RegionRecognition {
+        pixel_hash,
+        perceptual_hash,
+        text,
+        word_boxes,
+        block_boxes,
+        lines,
+        words,
+        language: language.to_string(),
+        language_scores,
+        hocr,
+        tsv,
+        preprocess_duration,
+        ocr_duration,
+    }))
+}
+
+/// Returns the text processor implementing the region's strategy.
+pub(crate) fn text_processor_for(region: Region) -> Box<dyn TextProcessor> {
+    match region.processor {
+        ProcessorStrategy::FixedLine => Box::new(FixedLineProcessor::new(region)),
+        ProcessorStrategy::DialogScroll => Box::new(DialogScrollProcessor::new(region)),
+        ProcessorStrategy::FullFrameSparse => Box::new(SparseTextProcessor::new(region)),
+        ProcessorStrategy::Ticker => Box::new(TickerProcessor::new(region)),
+        ProcessorStrategy::Menu => Box::new(MenuProcessor::new(region)),
+        ProcessorStrategy::Numeric => Box::new(NumericProcessor::new(region)),
+    }
+}
+
+/// Returns the page segmentation mode suited for the strategy.
+pub(crate) fn page_segmentation_mode(strategy: &ProcessorStrategy) -> PageSegmentation {
+    match strategy {
+        ProcessorStrategy::FixedLine
+        | ProcessorStrategy::DialogScroll
+        | ProcessorStrategy::Menu => PageSegmentation::SingleBlock,
+        ProcessorStrategy::FullFrameSparse => PageSegmentation::SparseText,
+        ProcessorStrategy::Ticker | ProcessorStrategy::Numeric => PageSegmentation::SingleLine,
+    }
+}
+
+/// Returns the characters recognition is restricted to for the strategy, or
+/// none for the whitelist of the Tesseract variables.
+pub(crate) fn character_whitelist(strategy: &ProcessorStrategy) -> Option<&'static str> {
+    match strategy {
+        ProcessorStrategy::Numeric => Some("0123456789"),
+        _ => None,
+    }
+}
+
+/// Converts boxes relative to the preprocessed region image, which may be
+/// scaled, to frame coordinates.
+pub(crate) fn translate_boxes_to_frame(region: &Region, boxes: &mut [BoundingBox]) {
+    for bounding_box in boxes {
+        *bounding_box = bounding_box.image_to_frame_space(region);
+    }
+}
+
+/// Returns the region name of the item, preceded by its stream name if any.
+fn item_source(text_item: &TextItem) -> String {
+    match &text_item.stream_name {
+        Some(stream_name) => format!("{}/{}", stream_name, text_item.region_name),
+        None => text_item.region_name.clone(),
+    }
+}
+
+fn create_frame_result_writer(config: Option<FrameDumpConfig>) -> Option<FrameResultWriter> {
+    match FrameResultWriter::new(config?) {
+        Ok(writer) => Some(writer),
+        Err(error) => {
+            warn!("failed to open frame dump output"; "error" => %error);
+            None
+        }
+    }
+}
+
+/// Saves pixels in the BGRA order of the canvas to an image file of the format
+/// of the path's extension.
+fn save_bgra_image(path: &Path, width: u32, height: u32, data: Vec<u8>) -> image::ImageResult<()> {
+    let image = ImageBuffer::<Bgra<u8>, _>::from_raw(width, height, data).unwrap();
+
+    DynamicImage::ImageBgra8(image).into_rgb8().save(path)
+}
+
+fn create_text_drawer(display: &DisplayConfig) -> Result<TextDrawer, DisplayError> {
+    let mut text_drawer = TextDrawer::with_fonts(&display.fonts)?;
+    text_drawer.set_font_size(display.font_size);
+
+    Ok(text_drawer)
+}
+
+fn create_http_api(
+    http_api_config: Option<HttpApiConfig>,
+    config: &ProcessorConfig,
+) -> Option<HttpApi> {
+    match HttpApi::new(http_api_config?) {
+        Ok(mut http_api) => {
+            http_api.set_region_names(region_names(config));
+            Some(http_api)
+        }
+        Err(error) => {
+            warn!("failed to start HTTP API"; "error" => %error);
+            None
+        }
     }
 }
+
+fn region_names(config: &ProcessorConfig) -> Vec<String> {
+    config
+        .region
+        .iter()
+        .map(|region| region.name.clone())
+        .collect()
+}
+
+/// Loads the languages and ONNX models used by the regions.
+pub(crate) fn load_region_engines(
+    text_recognizer_pool: &mut [TextRecognizerSet],
+    regions: &[Region],
+) -> Result<(), OcrError> {
+    for region in regions {
+        if let Some(config) = &region.onnx {
+            for text_recognizers in text_recognizer_pool.iter_mut() {
+                text_recognizers.load_onnx(config)?;
+            }
+        }
+
+        let detection_languages = region
+            .language_detection
+            .iter()
+            .flat_map(|language_detection| &language_detection.languages);
+
+        for language in region.language.iter().chain(detection_languages) {
+            for text_recognizers in text_recognizer_pool.iter_mut() {
+                text_recognizers.load(language)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn hash_region_pixels(region: &Region, frame_data: &[u32], frame_width: u32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    for row in preprocess::frame_rows(
+        frame_data,
+        frame_width,
+        region.x,
+        region.y,
+        region.width,
+        region.height,
+    ) {
+        row.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::EmissionQuota;
+
+/// Counts items emitted by a region within a sliding time period.
+pub struct EmissionRateTracker {
+    quota: EmissionQuota,
+    emission_dates: VecDeque<DateTime<Utc>>,
+    exceeded: bool,
+}
+
+impl EmissionRateTracker {
+    pub fn new(quota: EmissionQuota) -> Self {
+        Self {
+            quota,
+            emission_dates: VecDeque::new(),
+            exceeded: false,
+        }
+    }
+
+    pub fn quota(&self) -> &EmissionQuota {
+        &self.quota
+    }
+
+    /// Returns the number of items emitted within the period.
+    pub fn count(&self) -> usize {
+        self.emission_dates.len()
+    }
+
+    /// Records emitted items and returns true if the quota became exceeded.
+    ///
+    /// Only the transition is reported so an alert is fired once per episode.
+    pub fn record(&mut self, date: &DateTime<Utc>, item_count: usize) -> bool {
+        for _ in 0..item_count {
+            self.emission_dates.push_back(*date);
+        }
+
+        let period = Duration::seconds(self.quota.period_secs as i64);
+
+        while let Some(emission_date) = self.emission_dates.front() {
+            if date.signed_duration_since(*emission_date) > period {
+                self.emission_dates.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let exceeded = self.emission_dates.len() > self.quota.max_items as usize;
+        let newly_exceeded = exceeded && !self.exceeded;
+        self.exceeded = exceeded;
+
+        newly_exceeded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut tracker = EmissionRateTracker::new(EmissionQuota {
+            max_items: 2,
+            period_secs: 60,
+            auto_mute: false,
+        });
+        let date = Utc::now();
+
+        assert!(!tracker.record(&date, 2));
+        assert!(tracker.record(&(date + Duration::seconds(1)), 1));
+        assert!(!tracker.record(&(date + Duration::seconds(2)), 1));
+        assert_eq!(tracker.count(), 4);
+
+        assert!(!tracker.record(&(date + Duration::seconds(100)), 1));
+        assert_eq!(tracker.count(), 1);
+    }
+}
@@ -0,0 +1,207 @@
+//! Recording the input stream to segment files alongside OCR, so recognized
+//! text can later be checked against the original footage.
+//!
+//! Packets are remuxed without decoding, so recording costs little besides
+//! disk space. Segments are split by ffmpeg's segment muxer at keyframes
+//! and named by the local time they start at.
+
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use ffmpeg_next::{
+    format::{self, context::Input, context::Output},
+    media::Type,
+    Dictionary, Packet, Rational, Stream,
+};
+use slog_scope::{info, warn};
+
+use crate::error::FrameError;
+
+/// Container of recorded segments.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentFormat {
+    Mp4,
+    /// MPEG transport stream, which stays playable if the dumper is killed
+    /// in the middle of a segment.
+    Ts,
+}
+
+impl SegmentFormat {
+    fn muxer_name(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "mpegts",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "ts",
+        }
+    }
+}
+
+impl FromStr for SegmentFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "mp4" => Ok(Self::Mp4),
+            "ts" => Ok(Self::Ts),
+            _ => Err(format!("expected segment format mp4 or ts, got {:?}", text)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingConfig {
+    pub directory: PathBuf,
+    pub format: SegmentFormat,
+    /// Approximate length of each segment. Segments are split at the first
+    /// keyframe after this length.
+    pub segment_duration: Duration,
+}
+
+impl RecordingConfig {
+    /// Returns the `strftime` pattern of the segment file paths.
+    pub fn path_pattern(&self) -> PathBuf {
+        self.directory
+            .join(format!("%Y-%m-%dT%H-%M-%S.{}", self.format.extension()))
+    }
+}
+
+/// Remuxes the video and audio streams of one input into segments.
+///
+/// The current segment is finished when the recorder is dropped, such as
+/// when an error stops reading the input, if `finish` wasn't called.
+pub struct SegmentRecorder {
+    output: Output,
+    /// Output stream index and time bases of each input stream that is
+    /// recorded.
+    stream_mapping: Vec<Option<(usize, Rational, Rational)>>,
+    finished: bool,
+}
+
+impl SegmentRecorder {
+    /// Starts the first segment for the opened input.
+    pub fn new(config: &RecordingConfig, input: &Input) -> Result<Self, FrameError> {
+        std::fs::create_dir_all(&config.directory).map_err(|source| {
+            FrameError::RecordingDirectory {
+                path: config.directory.clone(),
+                source,
+            }
+        })?;
+
+        let path_pattern = config.path_pattern();
+        let mut output = format::output_as(&path_pattern, "segment")?;
+        let mut input_streams = Vec::new();
+
+        for stream in input.streams() {
+            let medium = stream.parameters().medium();
+
+            if medium != Type::Video && medium != Type::Audio {
+                input_streams.push(None);
+                continue;
+            }
+
+            let mut output_stream =
+                output.add_stream(ffmpeg_next::encoder::find(ffmpeg_next::codec::Id::None))?;
+            output_stream.set_parameters(stream.parameters());
+
+            // The input container's codec tag may not be valid in the output
+            // container, so the muxer chooses one
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+
+            input_streams.push(Some((output_stream.index(), stream.time_base())));
+        }
+
+        let mut options = Dictionary::new();
+        options.set("segment_format", config.format.muxer_name());
+        options.set(
+            "segment_time",
+            &config.segment_duration.as_secs_f64().to_string(),
+        );
+        options.set("strftime", "1");
+        options.set("reset_timestamps", "1");
+
+        output.write_header_with(options)?;
+
+        // Output time bases are chosen by the muxer when writing the header
+        let stream_mapping = input_streams
+            .into_iter()
+            .map(|mapping| {
+                mapping.map(|(index, input_time_base)| {
+                    let output_time_base = output.stream(index).unwrap().time_base();
+                    (index, input_time_base, output_time_base)
+                })
+            })
+            .collect();
+
+        info!("recording segments"; "path" => %path_pattern.display(),
+            "segment_duration" => ?config.segment_duration);
+
+        Ok(Self {
+            output,
+            stream_mapping,
+            finished: false,
+        })
+    }
+
+    /// Writes a packet read from the input, ignoring streams that aren't
+    /// recorded.
+    pub fn write(&mut self, stream: &Stream, packet: &Packet) -> Result<(), FrameError> {
+        let (index, input_time_base, output_time_base) =
+            match self.stream_mapping.get(stream.index()) {
+                Some(Some(mapping)) => *mapping,
+                _ => return Ok(()),
+            };
+
+        let mut packet = packet.clone();
+        packet.rescale_ts(input_time_base, output_time_base);
+        packet.set_stream(index);
+        packet.set_position(-1);
+        packet.write_interleaved(&mut self.output)?;
+
+        Ok(())
+    }
+
+    /// Finishes the current segment.
+    pub fn finish(mut self) -> Result<(), FrameError> {
+        self.finished = true;
+        self.output.write_trailer()?;
+
+        Ok(())
+    }
+}
+
+impl Drop for SegmentRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(error) = self.output.write_trailer() {
+                warn!("failed to finish recording"; "error" => %error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_pattern() {
+        let config = RecordingConfig {
+            directory: PathBuf::from("/var/recordings"),
+            format: "ts".parse().unwrap(),
+            segment_duration: Duration::from_secs(600),
+        };
+
+        assert_eq!(
+            config.path_pattern(),
+            PathBuf::from("/var/recordings/%Y-%m-%dT%H-%M-%S.ts")
+        );
+        assert!("mkv".parse::<SegmentFormat>().is_err());
+    }
+}
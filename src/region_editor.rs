@@ -0,0 +1,488 @@
+//! Editing of region rectangles on a VNC screen showing the stream.
+//!
+//! Dragging inside a region moves it, and dragging elsewhere draws a new
+//! region. The `s` key writes the regions back to the config file, which
+//! the processor reloads, and `r` discards unsaved edits. Only the changed
+//! coordinates are written, so the rest of the file keeps its comments and
+//! formatting.
+
+use std::path::{Path, PathBuf};
+
+use toml::{value::Table, Value};
+use toml_edit::{ArrayOfTables, DocumentMut, Item};
+
+use crate::error::ConfigError;
+
+/// Left button bit of a VNC pointer event's button mask.
+const LEFT_BUTTON: i32 = 1;
+/// Keysyms of the editing keys.
+const KEY_SAVE: u32 = 0x73;
+const KEY_REVERT: u32 = 0x72;
+/// Rectangles smaller than this many pixels on a side are taken as clicks
+/// and don't create a region.
+const MIN_REGION_SIZE: u32 = 4;
+
+const REGION_COLOR: u32 = 0xff00_ff00;
+const DRAG_COLOR: u32 = 0xffff_ffff;
+
+/// Rectangle of a region in frame pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditedRegion {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl EditedRegion {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+
+    fn coordinates(&self) -> [(&'static str, u32); 4] {
+        [
+            ("x", self.x),
+            ("y", self.y),
+            ("width", self.width),
+            ("height", self.height),
+        ]
+    }
+}
+
+enum Drag {
+    /// Moving the region at the index, grabbed at the offset from its top
+    /// left.
+    Move {
+        index: usize,
+        offset_x: u32,
+        offset_y: u32,
+    },
+    /// Drawing a new region from the start point to the current point.
+    Create {
+        start_x: u32,
+        start_y: u32,
+        x: u32,
+        y: u32,
+    },
+}
+
+pub struct RegionEditor {
+    config_path: PathBuf,
+    frame_width: u32,
+    frame_height: u32,
+    document: Value,
+    regions: Vec<EditedRegion>,
+    /// Regions as last loaded or saved, for writing only the changes.
+    saved_regions: Vec<EditedRegion>,
+    drag: Option<Drag>,
+}
+
+impl RegionEditor {
+    /// Loads the regions of a config file for a frame of the size.
+    pub fn load(
+        config_path: &Path,
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Result<Self, ConfigError> {
+        let mut editor = Self {
+            config_path: config_path.to_path_buf(),
+            frame_width,
+            frame_height,
+            document: Value::Table(Table::new()),
+            regions: Vec::new(),
+            saved_regions: Vec::new(),
+            drag: None,
+        };
+        editor.revert()?;
+
+        Ok(editor)
+    }
+
+    pub fn regions(&self) -> &[EditedRegion] {
+        &self.regions
+    }
+
+    /// Discards edits and loads the regions from the config file again.
+    pub fn revert(&mut self) -> Result<(), ConfigError> {
+        let text = self.read_config()?;
+        self.document = toml::de::from_str(&text)?;
+        self.regions = self
+            .document
+            .get("region")
+            .and_then(Value::as_array)
+            .map(|regions| {
+                regions
+                    .iter()
+                    .filter_map(|region| self.parse_region(region))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.saved_regions = self.regions.clone();
+        self.drag = None;
+
+        Ok(())
+    }
+
+    fn read_config(&self) -> Result<String, ConfigError> {
+        std::fs::read_to_string(&self.config_path).map_err(|source| ConfigError::Read {
+            path: self.config_path.clone(),
+            source,
+        })
+    }
+
+    /// Writes the regions to the config file.
+    ///
+    /// Regions are updated by name and new regions are appended with the
+    /// FixedLine strategy. Only the coordinates that changed are replaced,
+    /// and the file is replaced at once so the processor never reads a
+    /// partial config.
+    pub fn save(&mut self) -> Result<(), ConfigError> {
+        let mut document: DocumentMut = self.read_config()?.parse()?;
+        let region_tables = document
+            .entry("region")
+            .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| ConfigError::RegionTables {
+                path: self.config_path.clone(),
+            })?;
+
+        for region in &self.regions {
+            let saved_region = self
+                .saved_regions
+                .iter()
+                .find(|saved_region| saved_region.name == region.name);
+
+            if saved_region == Some(region) {
+                continue;
+            }
+
+            let position = region_tables.iter().position(|region_table| {
+                region_table.get("name").and_then(Item::as_str) == Some(region.name.as_str())
+            });
+            let region_table = match position {
+                Some(index) => region_tables.get_mut(index).unwrap(),
+                None => {
+                    let mut region_table = toml_edit::Table::new();
+                    region_table["name"] = toml_edit::value(region.name.as_str());
+                    region_table["processor"] = toml_edit::value("FixedLine");
+                    region_tables.push(region_table);
+                    region_tables.get_mut(region_tables.len() - 1).unwrap()
+                }
+            };
+            let saved_coordinates = saved_region.map(EditedRegion::coordinates);
+
+            for (index, (key, value)) in region.coordinates().iter().enumerate() {
+                if saved_coordinates.map(|coordinates| coordinates[index].1) != Some(*value) {
+                    region_table[*key] = toml_edit::value(*value as i64);
+                }
+            }
+        }
+
+        let mut temporary_path = self.config_path.clone().into_os_string();
+        temporary_path.push(".tmp");
+
+        std::fs::write(&temporary_path, document.to_string())
+            .and_then(|_| std::fs::rename(&temporary_path, &self.config_path))
+            .map_err(|source| ConfigError::Write {
+                path: self.config_path.clone(),
+                source,
+            })?;
+
+        self.saved_regions = self.regions.clone();
+
+        Ok(())
+    }
+
+    /// Handles a VNC pointer event at a point of the screen.
+    pub fn pointer_event(&mut self, button_mask: i32, x: u32, y: u32) {
+        let x = x.min(self.frame_width);
+        let y = y.min(self.frame_height);
+        let pressed = button_mask & LEFT_BUTTON != 0;
+
+        match (&mut self.drag, pressed) {
+            (None, true) => {
+                self.drag = Some(
+                    match self
+                        .regions
+                        .iter()
+                        .rposition(|region| region.contains(x, y))
+                    {
+                        Some(index) => Drag::Move {
+                            index,
+                            offset_x: x - self.regions[index].x,
+                            offset_y: y - self.regions[index].y,
+                        },
+                        None => Drag::Create {
+                            start_x: x,
+                            start_y: y,
+                            x,
+                            y,
+                        },
+                    },
+                );
+            }
+            (
+                Some(Drag::Move {
+                    index,
+                    offset_x,
+                    offset_y,
+                }),
+                _,
+            ) => {
+                let region = &mut self.regions[*index];
+                region.x = x
+                    .saturating_sub(*offset_x)
+                    .min(self.frame_width.saturating_sub(region.width));
+                region.y = y
+                    .saturating_sub(*offset_y)
+                    .min(self.frame_height.saturating_sub(region.height));
+            }
+            (
+                Some(Drag::Create {
+                    x: end_x, y: end_y, ..
+                }),
+                _,
+            ) => {
+                *end_x = x;
+                *end_y = y;
+            }
+            (None, false) => {}
+        }
+
+        if !pressed {
+            if let Some(Drag::Create { .. }) = self.drag {
+                if let Some(region) = self.drag_rectangle() {
+                    self.regions.push(region);
+                }
+            }
+
+            self.drag = None;
+        }
+    }
+
+    /// Handles a VNC key event, returning whether the regions were saved or
+    /// reverted.
+    pub fn key_event(&mut self, down: bool, key_sym: u32) -> Result<bool, ConfigError> {
+        match (down, key_sym) {
+            (true, KEY_SAVE) => self.save().map(|_| true),
+            (true, KEY_REVERT) => self.revert().map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Outlines the regions and the rectangle being drawn on a frame of
+    /// 32-bit pixels.
+    pub fn draw(&self, pixels: &mut [u32]) {
+        for region in &self.regions {
+            self.draw_rectangle(pixels, region, REGION_COLOR);
+        }
+
+        if let Some(region) = self.drag_rectangle() {
+            self.draw_rectangle(pixels, &region, DRAG_COLOR);
+        }
+    }
+
+    /// Returns the new region being drawn if it is large enough.
+    fn drag_rectangle(&self) -> Option<EditedRegion> {
+        match self.drag {
+            Some(Drag::Create {
+                start_x,
+                start_y,
+                x,
+                y,
+            }) => {
+                let width = start_x.max(x) - start_x.min(x);
+                let height = start_y.max(y) - start_y.min(y);
+
+                if width < MIN_REGION_SIZE || height < MIN_REGION_SIZE {
+                    return None;
+                }
+
+                Some(EditedRegion {
+                    name: self.new_region_name(),
+                    x: start_x.min(x),
+                    y: start_y.min(y),
+                    width,
+                    height,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn new_region_name(&self) -> String {
+        (self.regions.len() + 1..)
+            .map(|number| format!("region_{}", number))
+            .find(|name| !self.regions.iter().any(|region| &region.name == name))
+            .unwrap()
+    }
+
+    /// Outlines the part of the region inside the frame.
+    fn draw_rectangle(&self, pixels: &mut [u32], region: &EditedRegion, color: u32) {
+        if region.width == 0
+            || region.height == 0
+            || region.x >= self.frame_width
+            || region.y >= self.frame_height
+        {
+            return;
+        }
+
+        let x2 = region.x.saturating_add(region.width).min(self.frame_width) - 1;
+        let y2 = region
+            .y
+            .saturating_add(region.height)
+            .min(self.frame_height)
+            - 1;
+        let width = self.frame_width as usize;
+        let mut set_pixel = |x: u32, y: u32| {
+            if let Some(pixel) = pixels.get_mut(y as usize * width + x as usize) {
+                *pixel = color;
+            }
+        };
+
+        for x in region.x..=x2 {
+            set_pixel(x, region.y);
+            set_pixel(x, y2);
+        }
+
+        for y in region.y..=y2 {
+            set_pixel(region.x, y);
+            set_pixel(x2, y);
+        }
+    }
+
+    /// Returns the rectangle of a region table, resolving fractional
+    /// coordinates, or None if it has no name or coordinates.
+    fn parse_region(&self, region: &Value) -> Option<EditedRegion> {
+        let coordinate = |key: &str, size: u32| match region.get(key)? {
+            Value::Integer(value) => Some(*value as u32),
+            Value::Float(value) => Some((value * size as f64).round() as u32),
+            _ => None,
+        };
+
+        Some(EditedRegion {
+            name: region.get("name")?.as_str()?.to_string(),
+            x: coordinate("x", self.frame_width)?,
+            y: coordinate("y", self.frame_height)?,
+            width: coordinate("width", self.frame_width)?,
+            height: coordinate("height", self.frame_height)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_regions() {
+        let path =
+            std::env::temp_dir().join(format!("tppocr-region-editor-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            # Regions of the game
+            [[region]]
+            name = "dialog"
+            x = 10
+            y = 0.5 # middle
+            width = 100 # most of the screen
+            height = 20
+            processor = "DialogScroll"
+
+            [[region]]
+            name = "clock"
+            x = 0
+            y = 0
+            width = 0.25
+            height = 10
+            "#,
+        )
+        .unwrap();
+        let mut editor = RegionEditor::load(&path, 200, 100).unwrap();
+
+        assert_eq!(
+            editor.regions()[0],
+            EditedRegion {
+                name: "dialog".to_string(),
+                x: 10,
+                y: 50,
+                width: 100,
+                height: 20,
+            }
+        );
+
+        // Move the dialog region by (5, -10)
+        editor.pointer_event(LEFT_BUTTON, 20, 55);
+        editor.pointer_event(LEFT_BUTTON, 25, 45);
+        editor.pointer_event(0, 25, 45);
+        // Draw a new region backwards
+        editor.pointer_event(LEFT_BUTTON, 190, 30);
+        editor.pointer_event(LEFT_BUTTON, 150, 10);
+        editor.pointer_event(0, 150, 10);
+        // A click doesn't create a region
+        editor.pointer_event(LEFT_BUTTON, 5, 5);
+        editor.pointer_event(0, 5, 5);
+
+        let mut pixels = vec![0; 200 * 100];
+        editor.draw(&mut pixels);
+        assert_eq!(pixels[40 * 200 + 15], REGION_COLOR);
+
+        // Regions past the frame edge are outlined within it
+        editor.regions.push(EditedRegion {
+            name: "offscreen".to_string(),
+            x: 190,
+            y: 95,
+            width: u32::MAX,
+            height: 20,
+        });
+        editor.draw(&mut pixels);
+        assert_eq!(pixels[99 * 200 + 199], REGION_COLOR);
+        assert!(editor.regions.last().unwrap().contains(u32::MAX - 1, 99));
+        editor.regions.pop();
+
+        assert!(editor.key_event(true, KEY_SAVE).unwrap());
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# Regions of the game"));
+        assert!(text.contains("width = 100 # most of the screen"));
+        assert!(text.contains("width = 0.25"));
+        assert!(!text.contains("y = 0.5"));
+
+        let mut editor = RegionEditor::load(&path, 200, 100).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            editor.regions(),
+            [
+                EditedRegion {
+                    name: "dialog".to_string(),
+                    x: 15,
+                    y: 40,
+                    width: 100,
+                    height: 20,
+                },
+                EditedRegion {
+                    name: "clock".to_string(),
+                    x: 0,
+                    y: 0,
+                    width: 50,
+                    height: 10,
+                },
+                EditedRegion {
+                    name: "region_3".to_string(),
+                    x: 150,
+                    y: 10,
+                    width: 40,
+                    height: 20,
+                },
+            ]
+        );
+        assert_eq!(
+            editor.document["region"][0]["processor"].as_str(),
+            Some("DialogScroll")
+        );
+        assert!(!editor.key_event(false, KEY_SAVE).unwrap());
+    }
+}
@@ -0,0 +1,213 @@
+//! Suggesting regions from text lines detected across frames.
+
+use std::fmt::Write;
+
+use crate::{
+    error::OcrError,
+    ocr_engine::{BoxLevel, OcrEngine, PageSegmentation},
+    preprocess,
+    text_recognizer::BoundingBox,
+};
+
+/// A rectangle where text was detected in multiple frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionProposal {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Number of frames in which text was detected at this location.
+    pub frame_count: u32,
+}
+
+/// Accumulates text lines detected on full frames and groups lines that
+/// overlap into candidate regions.
+pub struct RegionProposer {
+    min_confidence: f32,
+    min_overlap: f32,
+    candidates: Vec<RegionProposal>,
+}
+
+impl RegionProposer {
+    pub fn new() -> Self {
+        Self {
+            min_confidence: 0.5,
+            min_overlap: 0.5,
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Runs text line detection on a frame and records the lines.
+    ///
+    /// The recognizer's page segmentation mode is changed to automatic and
+    /// its character whitelist is reset to the Tesseract variables'.
+    pub fn detect(
+        &mut self,
+        text_recognizer: &dyn OcrEngine,
+        frame: &image::RgbaImage,
+    ) -> Result<(), OcrError> {
+        text_recognizer.set_page_segmentation_mode(PageSegmentation::Auto);
+        text_recognizer.set_character_whitelist(None);
+        text_recognizer.set_image(
+            &preprocess::to_u32_pixels(frame),
+            frame.width(),
+            frame.height(),
+        );
+        text_recognizer.recognize()?;
+
+        self.add_lines(&text_recognizer.boxes(BoxLevel::Line));
+
+        Ok(())
+    }
+
+    /// Records the text lines of one frame.
+    pub fn add_lines(&mut self, line_boxes: &[BoundingBox]) {
+        let mut matched = vec![false; self.candidates.len()];
+
+        for line_box in line_boxes {
+            if line_box.confidence < self.min_confidence
+                || line_box.x2 <= line_box.x1
+                || line_box.y2 <= line_box.y1
+            {
+                continue;
+            }
+
+            let line = RegionProposal {
+                x: line_box.x1.max(0) as u32,
+                y: line_box.y1.max(0) as u32,
+                width: (line_box.x2 - line_box.x1) as u32,
+                height: (line_box.y2 - line_box.y1) as u32,
+                frame_count: 1,
+            };
+
+            let position = self
+                .candidates
+                .iter()
+                .position(|candidate| overlap(candidate, &line) >= self.min_overlap);
+
+            match position {
+                Some(position) => {
+                    let candidate = &mut self.candidates[position];
+                    let x2 = (candidate.x + candidate.width).max(line.x + line.width);
+                    let y2 = (candidate.y + candidate.height).max(line.y + line.height);
+
+                    candidate.x = candidate.x.min(line.x);
+                    candidate.y = candidate.y.min(line.y);
+                    candidate.width = x2 - candidate.x;
+                    candidate.height = y2 - candidate.y;
+
+                    // Several lines in one frame count as one appearance
+                    if position < matched.len() && !matched[position] {
+                        matched[position] = true;
+                        candidate.frame_count += 1;
+                    }
+                }
+                None => self.candidates.push(line),
+            }
+        }
+    }
+
+    /// Returns the candidates detected in at least `min_frame_count` frames
+    /// ordered from top to bottom.
+    pub fn proposals(&self, min_frame_count: u32) -> Vec<RegionProposal> {
+        let mut proposals: Vec<RegionProposal> = self
+            .candidates
+            .iter()
+            .filter(|candidate| candidate.frame_count >= min_frame_count)
+            .cloned()
+            .collect();
+
+        proposals.sort_by_key(|proposal| (proposal.y, proposal.x));
+
+        proposals
+    }
+}
+
+impl Default for RegionProposer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the area of the intersection divided by the area of the smaller
+/// rectangle.
+fn overlap(a: &RegionProposal, b: &RegionProposal) -> f32 {
+    let x1 = a.x.max(b.x);
+    let y1 = a.y.max(b.y);
+    let x2 = (a.x + a.width).min(b.x + b.width);
+    let y2 = (a.y + a.height).min(b.y + b.height);
+
+    if x2 <= x1 || y2 <= y1 {
+        return 0.0;
+    }
+
+    let intersection = ((x2 - x1) * (y2 - y1)) as f32;
+    let smaller = (a.width * a.height).min(b.width * b.height).max(1) as f32;
+
+    intersection / smaller
+}
+
+/// Formats the proposals as config regions to be copied into a config file.
+pub fn format_config(proposals: &[RegionProposal]) -> String {
+    let mut text = String::new();
+
+    for (index, proposal) in proposals.iter().enumerate() {
+        writeln!(
+            text,
+            "# Detected in {} frames\n\
+            [[region]]\n\
+            name = \"proposed_{}\"\n\
+            x = {}\n\
+            y = {}\n\
+            width = {}\n\
+            height = {}\n\
+            processor = \"FixedLine\"\n",
+            proposal.frame_count,
+            index + 1,
+            proposal.x,
+            proposal.y,
+            proposal.width,
+            proposal.height
+        )
+        .unwrap();
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(x1: i32, y1: i32, x2: i32, y2: i32) -> BoundingBox {
+        BoundingBox {
+            confidence: 0.9,
+            x1,
+            y1,
+            x2,
+            y2,
+        }
+    }
+
+    #[test]
+    fn test_proposals() {
+        let mut proposer = RegionProposer::new();
+
+        proposer.add_lines(&[line(10, 500, 600, 530), line(900, 20, 1000, 40)]);
+        proposer.add_lines(&[line(10, 500, 400, 530), line(10, 510, 620, 535)]);
+        proposer.add_lines(&[line(12, 502, 590, 528), line(300, 300, 310, 310)]);
+
+        let proposals = proposer.proposals(3);
+
+        assert_eq!(
+            proposals,
+            vec![RegionProposal {
+                x: 10,
+                y: 500,
+                width: 610,
+                height: 35,
+                frame_count: 3,
+            }]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+use slog_scope::{info, warn};
+
+use crate::config::ResourceLimits;
+
+/// Tracks the process's own CPU and memory usage against configured limits.
+pub struct ResourceMonitor {
+    limits: ResourceLimits,
+    previous_cpu_time: Duration,
+    previous_wall_time: Instant,
+    memory_exceeded: bool,
+}
+
+impl ResourceMonitor {
+    pub fn new(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            previous_cpu_time: process_cpu_time(),
+            previous_wall_time: Instant::now(),
+            memory_exceeded: false,
+        }
+    }
+
+    /// Returns whether the memory ceiling was exceeded at the last update.
+    ///
+    /// Callers should reduce their work while this is true.
+    pub fn memory_exceeded(&self) -> bool {
+        self.memory_exceeded
+    }
+
+    /// Samples usage since the previous call and returns how long the caller
+    /// should sleep to stay under the CPU budget.
+    pub fn update(&mut self) -> Duration {
+        self.update_memory();
+
+        let cpu_time = process_cpu_time();
+        let wall_time = Instant::now();
+        let mut sleep_duration = Duration::from_secs(0);
+
+        if let Some(max_cpu_percent) = self.limits.max_cpu_percent {
+            let cpu_elapsed = cpu_time
+                .checked_sub(self.previous_cpu_time)
+                .unwrap_or_default();
+            let wall_elapsed = wall_time.duration_since(self.previous_wall_time);
+            let target_wall_elapsed = cpu_elapsed.as_secs_f32() / (max_cpu_percent / 100.0);
+
+            if target_wall_elapsed > wall_elapsed.as_secs_f32() {
+                sleep_duration =
+                    Duration::from_secs_f32(target_wall_elapsed - wall_elapsed.as_secs_f32());
+            }
+        }
+
+        self.previous_cpu_time = cpu_time;
+        self.previous_wall_time = wall_time + sleep_duration;
+
+        sleep_duration
+    }
+
+    fn update_memory(&mut self) {
+        let max_memory_mb = match self.limits.max_memory_mb {
+            Some(value) => value,
+            None => return,
+        };
+        let resident_mb = match resident_memory() {
+            Some(value) => value / 1024 / 1024,
+            None => return,
+        };
+        let exceeded = resident_mb > max_memory_mb;
+
+        if exceeded && !self.memory_exceeded {
+            warn!("memory limit exceeded, degrading";
+                "resident_mb" => resident_mb, "max_memory_mb" => max_memory_mb);
+        } else if !exceeded && self.memory_exceeded {
+            info!("memory usage back under limit"; "resident_mb" => resident_mb);
+        }
+
+        self.memory_exceeded = exceeded;
+    }
+}
+
+/// Returns the user and system CPU time consumed by all threads of the process.
+fn process_cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::from_secs(0);
+    }
+
+    let user = Duration::new(
+        usage.ru_utime.tv_sec as u64,
+        usage.ru_utime.tv_usec as u32 * 1000,
+    );
+    let system = Duration::new(
+        usage.ru_stime.tv_sec as u64,
+        usage.ru_stime.tv_usec as u32 * 1000,
+    );
+
+    user + system
+}
+
+/// Returns the resident set size in bytes.
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    if page_size > 0 {
+        Some(resident_pages * page_size as u64)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,126 @@
+//! Switching between sets of regions depending on what is on screen.
+
+use chrono::Utc;
+
+use crate::{config::SceneConfig, error::ConfigError, event::Event, scene_detector::SceneDetector};
+
+/// Tracks the active scene and which regions belong to it.
+///
+/// Regions that are not listed in any scene are always active.
+pub struct SceneSelector {
+    scenes: Vec<SceneConfig>,
+    detector: SceneDetector,
+    active_scene: Option<String>,
+}
+
+impl SceneSelector {
+    /// Loads the template images of the scenes.
+    pub fn new(scene_configs: &[SceneConfig], confirm_frames: u32) -> Result<Self, ConfigError> {
+        Ok(Self {
+            scenes: scene_configs.to_vec(),
+            detector: SceneDetector::load(scene_configs, confirm_frames)?,
+            active_scene: scene_configs.first().map(|config| config.name.clone()),
+        })
+    }
+
+    pub fn active_scene(&self) -> Option<&str> {
+        self.active_scene.as_deref()
+    }
+
+    pub fn has_scene(&self, name: &str) -> bool {
+        self.scenes.iter().any(|scene| scene.name == name)
+    }
+
+    /// Switches the scene, for example, by an operator command.
+    ///
+    /// Returns an event if the scene changed. Unknown names are ignored.
+    pub fn set_active_scene(&mut self, name: &str) -> Option<Event> {
+        if self.has_scene(name) {
+            self.switch(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_region_active(&self, region_name: &str) -> bool {
+        let mut listed = false;
+
+        for scene in &self.scenes {
+            if scene.regions.iter().any(|name| name == region_name) {
+                if self.active_scene.as_deref() == Some(scene.name.as_str()) {
+                    return true;
+                }
+
+                listed = true;
+            }
+        }
+
+        !listed
+    }
+
+    /// Runs scene detection on the frame and switches to the detected scene.
+    ///
+    /// The active scene is kept if no scene is detected. Returns an event if
+    /// the scene changed.
+    pub fn update(
+        &mut self,
+        frame_data: &[u32],
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Option<Event> {
+        let name = self
+            .detector
+            .update(frame_data, frame_width, frame_height)?;
+
+        self.switch(&name)
+    }
+
+    fn switch(&mut self, name: &str) -> Option<Event> {
+        if self.active_scene.as_deref() == Some(name) {
+            return None;
+        }
+
+        let previous_scene = self.active_scene.replace(name.to_string());
+
+        Some(Event::SceneChanged {
+            date: Utc::now(),
+            previous_scene,
+            next_scene: name.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_config(name: &str, regions: &[&str]) -> SceneConfig {
+        SceneConfig {
+            name: name.to_string(),
+            regions: regions.iter().map(|name| name.to_string()).collect(),
+            detect: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_is_region_active() {
+        let mut selector = SceneSelector::new(
+            &[
+                scene_config("overworld", &["dialog"]),
+                scene_config("battle", &["dialog", "hp"]),
+            ],
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(selector.active_scene(), Some("overworld"));
+        assert!(selector.is_region_active("dialog"));
+        assert!(!selector.is_region_active("hp"));
+        assert!(selector.is_region_active("timestamp"));
+
+        assert!(selector.set_active_scene("battle").is_some());
+        assert!(selector.set_active_scene("battle").is_none());
+        assert!(selector.is_region_active("hp"));
+        assert!(!selector.has_scene("evolution"));
+    }
+}
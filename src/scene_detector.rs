@@ -0,0 +1,213 @@
+//! Classification of the game state by comparing reference image patches
+//! against the frame.
+
+use std::path::Path;
+
+use image::RgbaImage;
+
+use crate::{
+    config::{SceneConfig, SceneTemplate},
+    error::ConfigError,
+    preprocess,
+};
+
+struct SceneTemplates {
+    name: String,
+    templates: Vec<(SceneTemplate, RgbaImage)>,
+}
+
+/// Detects which scene is on screen.
+///
+/// A scene matches when all of its templates match. When several scenes
+/// match, the closest one is chosen. A scene is reported only after it is
+/// detected in consecutive frames so a single noisy frame does not cause a
+/// transition.
+pub struct SceneDetector {
+    scenes: Vec<SceneTemplates>,
+    confirm_frames: u32,
+    candidate: Option<String>,
+    candidate_count: u32,
+}
+
+impl SceneDetector {
+    pub fn new(confirm_frames: u32) -> Self {
+        Self {
+            scenes: Vec::new(),
+            confirm_frames: confirm_frames.max(1),
+            candidate: None,
+            candidate_count: 0,
+        }
+    }
+
+    /// Creates a detector with the template images of the scenes loaded.
+    pub fn load(scene_configs: &[SceneConfig], confirm_frames: u32) -> Result<Self, ConfigError> {
+        let mut detector = Self::new(confirm_frames);
+
+        for config in scene_configs {
+            let mut templates = Vec::new();
+
+            for template in &config.detect {
+                templates.push((template.clone(), load_template(&template.image)?));
+            }
+
+            detector.add_scene(&config.name, templates);
+        }
+
+        Ok(detector)
+    }
+
+    /// Adds a scene. Scenes without templates are never detected.
+    pub fn add_scene(&mut self, name: &str, templates: Vec<(SceneTemplate, RgbaImage)>) {
+        if !templates.is_empty() {
+            self.scenes.push(SceneTemplates {
+                name: name.to_string(),
+                templates,
+            });
+        }
+    }
+
+    /// Returns the name of the closest matching scene in the frame.
+    pub fn classify(
+        &self,
+        frame_data: &[u32],
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Option<&str> {
+        let mut best_scene = None;
+        let mut best_score = f32::INFINITY;
+        let mut best_template_count = 0;
+
+        for scene in &self.scenes {
+            // Worst template difference relative to its threshold
+            let score = scene
+                .templates
+                .iter()
+                .map(|(template, image)| {
+                    template_score(frame_data, frame_width, frame_height, template, image)
+                })
+                .fold(0.0, f32::max);
+
+            // Ties go to the more specific scene
+            if score <= 1.0
+                && (score < best_score
+                    || (score == best_score && scene.templates.len() > best_template_count))
+            {
+                best_score = score;
+                best_template_count = scene.templates.len();
+                best_scene = Some(scene.name.as_str());
+            }
+        }
+
+        best_scene
+    }
+
+    /// Classifies the frame and returns the scene once it has been detected
+    /// in enough consecutive frames.
+    ///
+    /// The scene is returned again on every frame while it is detected.
+    pub fn update(
+        &mut self,
+        frame_data: &[u32],
+        frame_width: u32,
+        frame_height: u32,
+    ) -> Option<String> {
+        let scene = self
+            .classify(frame_data, frame_width, frame_height)
+            .map(str::to_string);
+
+        if scene.is_some() && scene == self.candidate {
+            self.candidate_count = self.candidate_count.saturating_add(1);
+        } else {
+            self.candidate = scene;
+            self.candidate_count = 1;
+        }
+
+        if self.candidate_count >= self.confirm_frames {
+            self.candidate.clone()
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the mean difference of the template from the frame divided by
+/// the template's maximum difference.
+fn template_score(
+    frame_data: &[u32],
+    frame_width: u32,
+    frame_height: u32,
+    template: &SceneTemplate,
+    image: &RgbaImage,
+) -> f32 {
+    if template.x + image.width() > frame_width || template.y + image.height() > frame_height {
+        return f32::INFINITY;
+    }
+
+    let patch = preprocess::crop_rect(
+        frame_data,
+        frame_width,
+        template.x,
+        template.y,
+        image.width(),
+        image.height(),
+    );
+
+    preprocess::mean_difference(&patch, image) / template.max_difference.max(f32::EPSILON)
+}
+
+fn load_template(path: &Path) -> Result<RgbaImage, ConfigError> {
+    Ok(image::open(path)
+        .map_err(|source| ConfigError::SceneTemplate {
+            path: path.to_path_buf(),
+            source,
+        })?
+        .into_rgba8())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn template(x: u32, y: u32, color: [u8; 4]) -> (SceneTemplate, RgbaImage) {
+        (
+            SceneTemplate {
+                x,
+                y,
+                image: "unused.png".into(),
+                max_difference: 10.0,
+            },
+            RgbaImage::from_pixel(2, 2, Rgba(color)),
+        )
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut detector = SceneDetector::new(2);
+        detector.add_scene("black", vec![template(0, 0, [0, 0, 0, 255])]);
+        detector.add_scene(
+            "battle",
+            vec![
+                template(0, 0, [0, 0, 0, 255]),
+                template(4, 0, [255, 255, 255, 255]),
+            ],
+        );
+
+        let black = u32::from_ne_bytes([0, 0, 0, 255]);
+        let white = u32::from_ne_bytes([255, 255, 255, 255]);
+        let mut frame = vec![black; 8 * 4];
+
+        assert_eq!(detector.classify(&frame, 8, 4), Some("black"));
+
+        for y in 0..2 {
+            frame[y * 8 + 4] = white;
+            frame[y * 8 + 5] = white;
+        }
+
+        // Both scenes match, but battle is more specific
+        assert_eq!(detector.classify(&frame, 8, 4), Some("battle"));
+        assert_eq!(detector.update(&frame, 8, 4), None);
+        assert_eq!(detector.update(&frame, 8, 4), Some("battle".to_string()));
+    }
+}
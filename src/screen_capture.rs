@@ -0,0 +1,265 @@
+//! Frames captured from the X11 screen or a window, such as a locally
+//! running emulator, instead of a stream.
+//!
+//! Pixels are read with the core protocol's GetImage request, so neither
+//! ffmpeg nor any X extension is needed. A captured window must be mapped
+//! and not covered by other windows.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use image::{imageops::FilterType, RgbaImage};
+use slog_scope::{info, warn};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{Atom, AtomEnum, ConnectionExt, ImageFormat, ImageOrder, Window},
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    error::FrameError, frame::CropRect, frame_ring::FrameRing, frame_transport::FrameSender,
+};
+
+const BYTES_PER_PIXEL: u32 = 4;
+/// Longest window name read when searching for the window.
+const MAX_NAME_LENGTH: u32 = 1024;
+
+/// Publishes captures of the screen or a window, optionally cropped, at a
+/// fixed rate.
+pub struct ScreenCaptureDumper {
+    connection: RustConnection,
+    root: Window,
+    window_name: Option<String>,
+    window: Option<Window>,
+    crop: Option<CropRect>,
+    output_width: u32,
+    output_height: u32,
+    frame_ring: FrameRing,
+    frame_sender: Option<FrameSender>,
+    frames_per_second: f64,
+}
+
+impl ScreenCaptureDumper {
+    /// Connects to the X server of `$DISPLAY`.
+    ///
+    /// The window whose title contains `window_name` is captured, or the
+    /// whole screen if None.
+    pub fn new(
+        window_name: Option<String>,
+        output_port: u16,
+        output_width: u32,
+        output_height: u32,
+        slot_count: u32,
+    ) -> Result<Self, FrameError> {
+        let (connection, screen_number) = RustConnection::connect(None)?;
+        let root = connection.setup().roots[screen_number].root;
+
+        let data_size = (output_width * output_height * BYTES_PER_PIXEL) as usize;
+        let frame_ring = FrameRing::create(output_port as u32, data_size, slot_count)?;
+
+        Ok(Self {
+            connection,
+            root,
+            window_name,
+            window: None,
+            crop: None,
+            output_width,
+            output_height,
+            frame_ring,
+            frame_sender: None,
+            frames_per_second: 10.0,
+        })
+    }
+
+    pub fn crop(&self) -> Option<CropRect> {
+        self.crop
+    }
+
+    /// Sets the rectangle of the screen or window, in its pixels, that is
+    /// captured.
+    pub fn set_crop(&mut self, value: Option<CropRect>) {
+        self.crop = value;
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+
+    pub fn set_frames_per_second(&mut self, value: f64) {
+        self.frames_per_second = value;
+    }
+
+    /// Sets the sender of frames to processors on other hosts, in addition
+    /// to shared memory.
+    pub fn set_frame_sender(&mut self, value: Option<FrameSender>) {
+        self.frame_sender = value;
+    }
+
+    pub fn run(&mut self) -> Result<(), FrameError> {
+        let terminate_flag = Arc::new(AtomicBool::new(false));
+        for sig in signal_hook::consts::TERM_SIGNALS {
+            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / self.frames_per_second.max(0.001));
+
+        info!("loop start"; "window_name" => &self.window_name, "crop" => ?self.crop);
+
+        while !terminate_flag.load(Ordering::Relaxed) {
+            let deadline = Instant::now() + interval;
+
+            match self.capture() {
+                Ok(image) => self.publish(&image),
+                Err(error) => {
+                    // The window may be closed while the emulator restarts,
+                    // so it is searched for again
+                    warn!("screen capture failed"; "error" => %error);
+                    self.window = None;
+                }
+            }
+
+            let now = Instant::now();
+
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            }
+        }
+
+        info!("loop stop");
+
+        Ok(())
+    }
+
+    fn capture(&mut self) -> Result<RgbaImage, FrameError> {
+        let window = match (self.window, &self.window_name) {
+            (Some(window), _) => window,
+            (None, None) => self.root,
+            (None, Some(window_name)) => {
+                let net_wm_name = self
+                    .connection
+                    .intern_atom(false, b"_NET_WM_NAME")?
+                    .reply()?
+                    .atom;
+                let window = self
+                    .find_window(self.root, net_wm_name, window_name)?
+                    .ok_or_else(|| FrameError::WindowNotFound {
+                        name: window_name.clone(),
+                    })?;
+                info!("capturing window"; "window" => window, "name" => window_name);
+                self.window = Some(window);
+                window
+            }
+        };
+
+        let geometry = self.connection.get_geometry(window)?.reply()?;
+        let full_rect = CropRect {
+            x: 0,
+            y: 0,
+            width: geometry.width as u32,
+            height: geometry.height as u32,
+        };
+        let rect = match self.crop {
+            Some(crop) => crop
+                .clamp(full_rect.width, full_rect.height)
+                .ok_or(FrameError::CropOutside { crop })?,
+            None => full_rect,
+        };
+
+        let reply = self
+            .connection
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                window,
+                rect.x as i16,
+                rect.y as i16,
+                rect.width as u16,
+                rect.height as u16,
+                !0,
+            )?
+            .reply()?;
+
+        if reply.data.len() != (rect.width * rect.height * BYTES_PER_PIXEL) as usize {
+            return Err(FrameError::UnsupportedPixelFormat { depth: reply.depth });
+        }
+
+        let lsb_first = self.connection.setup().image_byte_order == ImageOrder::LSB_FIRST;
+        let image = RgbaImage::from_fn(rect.width, rect.height, |x, y| {
+            let offset = ((y * rect.width + x) * BYTES_PER_PIXEL) as usize;
+            let pixel = &reply.data[offset..offset + BYTES_PER_PIXEL as usize];
+
+            if lsb_first {
+                image::Rgba([pixel[2], pixel[1], pixel[0], 255])
+            } else {
+                image::Rgba([pixel[1], pixel[2], pixel[3], 255])
+            }
+        });
+
+        if image.dimensions() == (self.output_width, self.output_height) {
+            Ok(image)
+        } else {
+            Ok(image::imageops::resize(
+                &image,
+                self.output_width,
+                self.output_height,
+                FilterType::Triangle,
+            ))
+        }
+    }
+
+    /// Returns the first window, depth first, whose title contains the
+    /// name.
+    fn find_window(
+        &self,
+        parent: Window,
+        net_wm_name: Atom,
+        name: &str,
+    ) -> Result<Option<Window>, FrameError> {
+        for window in self.connection.query_tree(parent)?.reply()?.children {
+            for property in &[net_wm_name, AtomEnum::WM_NAME.into()] {
+                let title = self
+                    .connection
+                    .get_property(
+                        false,
+                        window,
+                        *property,
+                        AtomEnum::ANY,
+                        0,
+                        MAX_NAME_LENGTH / 4,
+                    )?
+                    .reply()?
+                    .value;
+
+                if String::from_utf8_lossy(&title).contains(name) {
+                    return Ok(Some(window));
+                }
+            }
+
+            if let Some(window) = self.find_window(window, net_wm_name, name)? {
+                return Ok(Some(window));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn publish(&mut self, image: &RgbaImage) {
+        let capture_time = Utc::now();
+
+        self.frame_ring.publish(image.as_raw(), &capture_time);
+
+        if let Some(frame_sender) = &mut self.frame_sender {
+            frame_sender.publish(
+                image.as_raw(),
+                self.output_width,
+                self.output_height,
+                &capture_time,
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Credentials referenced from the configuration without storing them in it.
+
+use std::{convert::TryFrom, fmt, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::ConfigError;
+
+/// Where the value of a secret is read from.
+///
+/// In the config, a secret is written as `{ env = "VARIABLE_NAME" }` or
+/// `{ file = "/path/to/file" }`. Files must not be accessible by group or
+/// others, and a trailing newline is removed.
+///
+/// The variants are told apart by their key rather than as a tagged enum,
+/// since configs deserialized from a `toml::Value` only support unit
+/// variants.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum SecretSource {
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+/// A credential resolved at config load.
+///
+/// The value is redacted when formatted so it never appears in logs.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(try_from = "SecretSource")]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<SecretSource> for Secret {
+    type Error = ConfigError;
+
+    fn try_from(source: SecretSource) -> Result<Self, Self::Error> {
+        match source {
+            SecretSource::Env { env: name } => match std::env::var(&name) {
+                Ok(value) => Ok(Self(value)),
+                Err(_) => Err(ConfigError::SecretEnv { name }),
+            },
+            SecretSource::File { file: path } => {
+                let read_error = |source| ConfigError::Read {
+                    path: path.clone(),
+                    source,
+                };
+                let metadata = std::fs::metadata(&path).map_err(read_error)?;
+
+                if metadata.permissions().mode() & 0o077 != 0 {
+                    return Err(ConfigError::SecretFilePermissions { path });
+                }
+
+                let value = std::fs::read_to_string(&path).map_err(read_error)?;
+
+                Ok(Self(value.trim_end_matches(&['\r', '\n'][..]).to_string()))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Config {
+        token: Secret,
+    }
+
+    #[test]
+    fn test_env_secret() {
+        std::env::set_var("TPPOCR_TEST_SECRET", "hunter2");
+
+        let config: Config =
+            toml::de::from_str(r#"token = { env = "TPPOCR_TEST_SECRET" }"#).unwrap();
+
+        assert_eq!(config.token.expose(), "hunter2");
+        assert!(!format!("{:?}", config).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_secret_from_value() {
+        std::env::set_var("TPPOCR_TEST_SECRET_VALUE", "hunter3");
+
+        let value: toml::Value =
+            toml::de::from_str(r#"token = { env = "TPPOCR_TEST_SECRET_VALUE" }"#).unwrap();
+        let config: Config = value.try_into().unwrap();
+
+        assert_eq!(config.token.expose(), "hunter3");
+
+        let value: toml::Value = toml::de::from_str(r#"token = { environment = "X" }"#).unwrap();
+
+        assert!(value.try_into::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_missing_env_secret() {
+        let result: Result<Config, _> =
+            toml::de::from_str(r#"token = { env = "TPPOCR_TEST_SECRET_MISSING" }"#);
+
+        assert!(result.is_err());
+    }
+}
@@ -1,47 +1,140 @@
 use std::path::PathBuf;
-use std::{ffi::c_void, os::unix::io::RawFd, path::Path};
+use std::{
+    ffi::{c_void, CString},
+    os::unix::io::RawFd,
+    path::Path,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
 
-use anyhow::Context;
 use nix::{
-    fcntl::{FlockArg, OFlag},
+    fcntl::OFlag,
     sys::{
+        memfd::MemFdCreateFlag,
         mman::{MapFlags, ProtFlags},
         stat::Mode,
     },
 };
+use slog_scope::warn;
+
+use crate::{
+    error::IpcError,
+    ipc::{FdServer, IpcMode},
+};
 
+/// Space before the data for the sequence counter, keeping the data aligned.
+const HEADER_SIZE: usize = 64;
+
+/// Shared memory with a single writer and any number of readers.
+///
+/// Access is coordinated with a sequence lock: the writer makes the sequence
+/// counter odd while writing and even afterwards. A reader copies the data and
+/// then checks the counter didn't change, otherwise the copy may be torn and
+/// should be retried. Readers never block the writer, and a writer that died
+/// while writing can't leave anything locked.
+///
+/// In the abstract IPC mode, the shared memory is a memfd served to other
+/// processes by the process that created it.
 pub struct SharedMemory {
     data_size: usize,
     shared_memory_name: PathBuf,
-    shared_memory_fd: RawFd,
     shared_memory: *mut c_void,
     unlink_on_drop: bool,
+    /// Server of the memfd, stopped when dropped.
+    _fd_server: Option<FdServer>,
 }
 
+// The mapping belongs to the process, so it may be moved to another thread,
+// such as the processor's frame acquisition and canvas publishing stages.
+unsafe impl Send for SharedMemory {}
+
 impl SharedMemory {
-    pub fn open_or_create(id: u32, data_size: usize) -> anyhow::Result<Self> {
+    pub fn open_or_create(id: u32, data_size: usize) -> Result<Self, IpcError> {
         Self::open_(id, data_size, true)
     }
 
-    pub fn open(id: u32, data_size: usize) -> anyhow::Result<Self> {
+    pub fn open(id: u32, data_size: usize) -> Result<Self, IpcError> {
         Self::open_(id, data_size, false)
     }
 
-    fn open_(id: u32, data_size: usize, create: bool) -> anyhow::Result<Self> {
-        // File is mounted to /dev/shm/
-        let shared_memory_name = PathBuf::from(format!("/tppocr_{}", id));
-        let (shared_memory_fd, shared_memory) =
+    /// Opens shared memory created by another process with its current size.
+    pub fn open_existing(id: u32) -> Result<Self, IpcError> {
+        let shared_memory_name = Self::name(id);
+        let shared_memory_fd = match IpcMode::current() {
+            IpcMode::Filesystem => Self::open_file_descriptor(&shared_memory_name, OFlag::O_RDWR)?,
+            IpcMode::Abstract => {
+                crate::ipc::receive_fd(&Self::fd_server_name(&shared_memory_name))?
+            }
+        };
+        let data_size =
+            (nix::sys::stat::fstat(shared_memory_fd)?.st_size as usize).saturating_sub(HEADER_SIZE);
+        let shared_memory = Self::map(shared_memory_fd, data_size)?;
+        nix::unistd::close(shared_memory_fd)?;
+
+        Ok(Self {
+            data_size,
+            shared_memory_name,
+            shared_memory,
+            unlink_on_drop: false,
+            _fd_server: None,
+        })
+    }
+
+    fn open_(id: u32, data_size: usize, create: bool) -> Result<Self, IpcError> {
+        let shared_memory_name = Self::name(id);
+        let (shared_memory, fd_server) =
             Self::open_shared_memory(data_size, &shared_memory_name, create)?;
 
         Ok(Self {
             data_size,
             shared_memory_name,
-            shared_memory_fd,
             shared_memory,
             unlink_on_drop: false,
+            _fd_server: fd_server,
         })
     }
 
+    fn name(id: u32) -> PathBuf {
+        // File is mounted to /dev/shm/
+        PathBuf::from(format!("/{}{}", crate::ipc::name_prefix(), id))
+    }
+
+    /// Returns the abstract socket name serving the memfd of the shared memory.
+    fn fd_server_name(shared_memory_name: &Path) -> String {
+        format!(
+            "{}.memfd",
+            shared_memory_name.to_string_lossy().trim_start_matches('/')
+        )
+    }
+
+    /// Returns whether a process created the shared memory of the ID.
+    pub fn exists(id: u32) -> bool {
+        match IpcMode::current() {
+            IpcMode::Filesystem => Path::new("/dev/shm")
+                .join(Self::name(id).strip_prefix("/").unwrap())
+                .exists(),
+            IpcMode::Abstract => crate::ipc::is_served(&Self::fd_server_name(&Self::name(id))),
+        }
+    }
+
+    /// Removes the shared memory of the ID if it exists.
+    ///
+    /// Processes that still have it open keep their mapping. In the abstract
+    /// IPC mode, there is nothing to remove.
+    pub fn remove(id: u32) -> Result<(), IpcError> {
+        if IpcMode::current() == IpcMode::Abstract {
+            return Ok(());
+        }
+
+        match nix::sys::mman::shm_unlink(&Self::name(id)) {
+            Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::ENOENT)) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
     pub fn data(&self) -> &[u8] {
         unsafe { std::slice::from_raw_parts(self.shared_memory as *const u8, self.data_size) }
     }
@@ -72,50 +165,140 @@ impl SharedMemory {
         self.unlink_on_drop = value;
     }
 
-    pub fn lock(&self) -> anyhow::Result<()> {
-        nix::fcntl::flock(self.shared_memory_fd, FlockArg::LockExclusive)?;
+    /// Marks the start of writing.
+    ///
+    /// If a previous writer died while writing, the counter stays odd.
+    pub fn begin_write(&self) {
+        let sequence = self.sequence().load(Ordering::Relaxed);
 
-        Ok(())
+        self.sequence()
+            .store(sequence + 1 + sequence % 2, Ordering::Relaxed);
+        fence(Ordering::Release);
     }
 
-    pub fn unlock(&self) -> anyhow::Result<()> {
-        nix::fcntl::flock(self.shared_memory_fd, FlockArg::Unlock)?;
+    /// Marks the end of writing.
+    pub fn end_write(&self) {
+        let sequence = self.sequence().load(Ordering::Relaxed);
 
-        Ok(())
+        self.sequence().store(sequence + 1, Ordering::Release);
+    }
+
+    /// Returns the sequence counter to pass to `validate_read` after reading,
+    /// or None if the writer is writing.
+    pub fn begin_read(&self) -> Option<u64> {
+        let sequence = self.sequence().load(Ordering::Acquire);
+
+        if sequence % 2 == 0 {
+            Some(sequence)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the data read since `begin_read` is complete.
+    pub fn validate_read(&self, sequence: u64) -> bool {
+        fence(Ordering::Acquire);
+
+        self.sequence().load(Ordering::Relaxed) == sequence
+    }
+
+    fn sequence(&self) -> &AtomicU64 {
+        unsafe { &*((self.shared_memory as *const u8).sub(HEADER_SIZE) as *const AtomicU64) }
     }
 
     fn open_shared_memory(
         data_size: usize,
         shared_memory_name: &Path,
         create: bool,
-    ) -> anyhow::Result<(RawFd, *mut c_void)> {
-        let shm_flags = if create {
-            OFlag::O_RDWR | OFlag::O_CREAT
-        } else {
-            OFlag::O_RDWR
+    ) -> Result<(*mut c_void, Option<FdServer>), IpcError> {
+        let (fd, fd_server) = match IpcMode::current() {
+            IpcMode::Filesystem => {
+                let shm_flags = if create {
+                    OFlag::O_RDWR | OFlag::O_CREAT
+                } else {
+                    OFlag::O_RDWR
+                };
+
+                (
+                    Self::open_file_descriptor(shared_memory_name, shm_flags)?,
+                    None,
+                )
+            }
+            IpcMode::Abstract => Self::open_memfd(shared_memory_name, create)?,
         };
+
+        nix::unistd::ftruncate(fd, (HEADER_SIZE + data_size) as i64)?;
+        let pointer = Self::map(fd, data_size)?;
+
+        // The mapping stays valid after closing
+        nix::unistd::close(fd)?;
+
+        Ok((pointer, fd_server))
+    }
+
+    /// Receives the memfd from the process that created it, otherwise creates
+    /// it and serves it to other processes.
+    fn open_memfd(
+        shared_memory_name: &Path,
+        create: bool,
+    ) -> Result<(RawFd, Option<FdServer>), IpcError> {
+        let server_name = Self::fd_server_name(shared_memory_name);
+
+        match crate::ipc::receive_fd(&server_name) {
+            Ok(fd) => Ok((fd, None)),
+            Err(IpcError::FdConnect { .. }) if create => {
+                let memfd_name = CString::new(server_name.as_str()).unwrap();
+                let fd = nix::sys::memfd::memfd_create(&memfd_name, MemFdCreateFlag::MFD_CLOEXEC)
+                    .map_err(|source| IpcError::SharedMemoryOpen {
+                    name: shared_memory_name.to_path_buf(),
+                    source,
+                })?;
+                let fd_server = FdServer::bind(&server_name, nix::unistd::dup(fd)?)?;
+
+                Ok((fd, Some(fd_server)))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn open_file_descriptor(
+        shared_memory_name: &Path,
+        shm_flags: OFlag,
+    ) -> Result<RawFd, IpcError> {
         let mode_flags = Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IRGRP | Mode::S_IWGRP;
-        let fd = nix::sys::mman::shm_open(shared_memory_name, shm_flags, mode_flags)
-            .with_context(|| format!("Failed to open shared memory {:?}", shared_memory_name))?;
 
-        nix::unistd::ftruncate(fd, data_size as i64)?;
+        nix::sys::mman::shm_open(shared_memory_name, shm_flags, mode_flags).map_err(|source| {
+            IpcError::SharedMemoryOpen {
+                name: shared_memory_name.to_path_buf(),
+                source,
+            }
+        })
+    }
 
+    /// Maps the header and data, returning a pointer to the data.
+    fn map(fd: RawFd, data_size: usize) -> Result<*mut c_void, IpcError> {
         let pointer = unsafe {
             nix::sys::mman::mmap(
                 std::ptr::null_mut(),
-                data_size,
+                HEADER_SIZE + data_size,
                 ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
                 MapFlags::MAP_SHARED,
                 fd,
                 0,
             )
-            .context("Failed to open memory map")?
+            .map_err(IpcError::MemoryMap)?
         };
 
-        Ok((fd, pointer))
+        Ok(unsafe { (pointer as *mut u8).add(HEADER_SIZE) as *mut c_void })
     }
 
-    pub fn unlink(self) -> anyhow::Result<()> {
+    pub fn unlink(mut self) -> Result<(), IpcError> {
+        self.unlink_on_drop = false;
+
+        if IpcMode::current() == IpcMode::Abstract {
+            return Ok(());
+        }
+
         nix::sys::mman::shm_unlink(&self.shared_memory_name)?;
 
         Ok(())
@@ -124,8 +307,11 @@ impl SharedMemory {
 
 impl Drop for SharedMemory {
     fn drop(&mut self) {
-        if self.unlink_on_drop {
-            nix::sys::mman::shm_unlink(&self.shared_memory_name).unwrap();
+        if self.unlink_on_drop && IpcMode::current() == IpcMode::Filesystem {
+            if let Err(error) = nix::sys::mman::shm_unlink(&self.shared_memory_name) {
+                warn!("failed to unlink shared memory";
+                    "name" => ?self.shared_memory_name, "error" => %error);
+            }
         }
     }
 }
@@ -138,10 +324,19 @@ mod tests {
     fn test_read_write() -> anyhow::Result<()> {
         let mut shared_memory = SharedMemory::open_or_create(123, 100)?;
 
-        shared_memory.lock()?;
+        let reader = SharedMemory::open_existing(123)?;
+        assert_eq!(reader.data_size(), 100);
+
+        let sequence = reader.begin_read().unwrap();
+        shared_memory.begin_write();
+        assert_eq!(reader.begin_read(), None);
         shared_memory.data_mut()[4] = 2;
-        assert_eq!(shared_memory.data()[4], 2);
-        shared_memory.unlock()?;
+        shared_memory.end_write();
+        assert_eq!(reader.data()[4], 2);
+        assert!(!reader.validate_read(sequence));
+
+        let sequence = reader.begin_read().unwrap();
+        assert!(reader.validate_read(sequence));
         shared_memory.unlink()?;
 
         Ok(())
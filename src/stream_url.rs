@@ -1,19 +1,484 @@
-use anyhow::bail;
-use slog_scope::error;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-pub fn get_stream_url(webpage_link: &str, quality_format: &str) -> anyhow::Result<String> {
-    let output = std::process::Command::new("youtube-dl")
-        .arg("--format")
-        .arg(quality_format)
-        .arg("--get-url")
-        .arg(webpage_link)
-        .output()?;
+use slog_scope::{error, info, warn};
+
+use crate::error::FrameError;
+
+/// Proxy, cookies, and headers for region-locked or subscriber-only streams,
+/// used when resolving the link and when reading the stream.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpOptions {
+    /// Proxy URL such as `http://127.0.0.1:3128`.
+    pub proxy: Option<String>,
+    /// Cookies file in the Netscape format exported by browser extensions.
+    pub cookie_file: Option<PathBuf>,
+    /// Headers as `Name: Value`.
+    pub headers: Vec<String>,
+}
+
+impl HttpOptions {
+    /// Returns the options of ffmpeg's HTTP and HLS demuxers.
+    pub fn ffmpeg_options(&self) -> Result<Vec<(String, String)>, FrameError> {
+        let mut options = Vec::new();
+
+        if let Some(proxy) = &self.proxy {
+            options.push(("http_proxy".to_string(), proxy.clone()));
+        }
+
+        if let Some(path) = &self.cookie_file {
+            let cookies: String = read_cookie_file(path)?
+                .iter()
+                .map(|cookie| {
+                    format!(
+                        "{}={}; path={}; domain={};\n",
+                        cookie.name, cookie.value, cookie.path, cookie.domain
+                    )
+                })
+                .collect();
+            options.push(("cookies".to_string(), cookies));
+        }
+
+        if !self.headers.is_empty() {
+            let headers: String = self
+                .headers
+                .iter()
+                .map(|header| format!("{}\r\n", header))
+                .collect();
+            options.push(("headers".to_string(), headers));
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cookie {
+    domain: String,
+    path: String,
+    name: String,
+    value: String,
+}
+
+fn read_cookie_file(path: &Path) -> Result<Vec<Cookie>, FrameError> {
+    let text = std::fs::read_to_string(path).map_err(|source| FrameError::CookieFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(parse_cookies(&text))
+}
+
+/// Parses the tab-separated lines of domain, subdomain flag, path, secure
+/// flag, expiry, name, and value.
+fn parse_cookies(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+
+            match fields.as_slice() {
+                [domain, _, path, _, _, name, value] => Some(Cookie {
+                    domain: domain.to_string(),
+                    path: path.to_string(),
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Streamlink config file with the cookies, removed when dropped.
+///
+/// Streamlink takes cookies one by one instead of a file, and passing them
+/// as arguments would show them to other users in the process list.
+struct StreamlinkCookieConfig {
+    path: PathBuf,
+}
+
+impl StreamlinkCookieConfig {
+    fn write(cookies: &[Cookie]) -> Result<Self, FrameError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "tppocr-streamlink-{}-{}.conf",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|source| FrameError::StreamlinkConfig {
+                path: path.clone(),
+                source,
+            })?;
+        let config = Self { path };
+
+        let text: String = cookies
+            .iter()
+            .map(|cookie| format!("http-cookie={}={}\n", cookie.name, cookie.value))
+            .collect();
+        file.write_all(text.as_bytes())
+            .map_err(|source| FrameError::StreamlinkConfig {
+                path: config.path.clone(),
+                source,
+            })?;
+
+        Ok(config)
+    }
+}
+
+impl Drop for StreamlinkCookieConfig {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Program resolving webpage links to stream URLs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UrlResolver {
+    YtDlp,
+    Streamlink,
+    YoutubeDl,
+}
+
+impl UrlResolver {
+    /// Resolvers in order of preference when detecting an installed one.
+    pub const ALL: [UrlResolver; 3] = [
+        UrlResolver::YtDlp,
+        UrlResolver::Streamlink,
+        UrlResolver::YoutubeDl,
+    ];
+
+    pub fn program(self) -> &'static str {
+        match self {
+            UrlResolver::YtDlp => "yt-dlp",
+            UrlResolver::Streamlink => "streamlink",
+            UrlResolver::YoutubeDl => "youtube-dl",
+        }
+    }
+
+    /// Returns the first installed resolver.
+    pub fn detect() -> Result<Self, FrameError> {
+        let resolver = Self::ALL
+            .iter()
+            .copied()
+            .find(|resolver| resolver.is_installed())
+            .ok_or(FrameError::NoUrlResolver)?;
+
+        info!("detected stream URL resolver"; "program" => resolver.program());
+
+        Ok(resolver)
+    }
+
+    fn is_installed(self) -> bool {
+        Command::new(self.program())
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+    }
+
+    /// Arguments printing the URL of the link's stream in the quality format,
+    /// such as `720p60` or `best`. Streamlink reads the cookies from the
+    /// config file instead of the options' cookies file.
+    fn args(
+        self,
+        webpage_link: &str,
+        quality_format: &str,
+        http_options: &HttpOptions,
+        streamlink_config: Option<&Path>,
+    ) -> Vec<String> {
+        let mut args = Vec::new();
+
+        match self {
+            UrlResolver::YtDlp | UrlResolver::YoutubeDl => {
+                if let Some(proxy) = &http_options.proxy {
+                    args.extend(["--proxy".to_string(), proxy.clone()]);
+                }
+
+                if let Some(path) = &http_options.cookie_file {
+                    args.extend(["--cookies".to_string(), path.to_string_lossy().into_owned()]);
+                }
+
+                for header in &http_options.headers {
+                    args.extend(["--add-header".to_string(), header.clone()]);
+                }
+
+                args.extend([
+                    "--format".to_string(),
+                    quality_format.to_string(),
+                    "--get-url".to_string(),
+                    webpage_link.to_string(),
+                ]);
+            }
+            UrlResolver::Streamlink => {
+                if let Some(proxy) = &http_options.proxy {
+                    args.extend(["--http-proxy".to_string(), proxy.clone()]);
+                }
+
+                if let Some(path) = streamlink_config {
+                    args.extend(["--config".to_string(), path.to_string_lossy().into_owned()]);
+                }
+
+                for header in &http_options.headers {
+                    let (name, value) = header.split_once(':').unwrap_or((header, ""));
+                    args.extend([
+                        "--http-header".to_string(),
+                        format!("{}={}", name.trim(), value.trim()),
+                    ]);
+                }
+
+                args.extend([
+                    "--stream-url".to_string(),
+                    webpage_link.to_string(),
+                    quality_format.to_string(),
+                ]);
+            }
+        }
+
+        args
+    }
+}
+
+impl FromStr for UrlResolver {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|resolver| resolver.program() == text)
+            .ok_or_else(|| {
+                format!(
+                    "expected URL resolver yt-dlp, streamlink, or youtube-dl, got {:?}",
+                    text
+                )
+            })
+    }
+}
+
+/// Returns the stream URL of the link in the first quality format that is
+/// offered, trying the comma-separated formats such as `720p60,720p,480p` in
+/// order.
+pub fn get_stream_url(
+    resolver: UrlResolver,
+    webpage_link: &str,
+    quality_formats: &str,
+    http_options: &HttpOptions,
+) -> Result<String, FrameError> {
+    let quality_formats = split_quality_formats(quality_formats);
+    let mut last_error = None;
+
+    for (index, quality_format) in quality_formats.iter().enumerate() {
+        match resolve(resolver, webpage_link, quality_format, http_options) {
+            Ok(url) => {
+                if index > 0 {
+                    info!("using fallback stream quality"; "format" => quality_format);
+                }
+
+                return Ok(url);
+            }
+            Err(error @ FrameError::UrlResolver { .. }) => {
+                warn!("stream quality not resolved"; "format" => quality_format);
+                last_error = Some(error);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Err(last_error.unwrap_or(FrameError::UrlResolver {
+        status: None,
+        message: "no quality format given".to_string(),
+    }))
+}
+
+fn split_quality_formats(text: &str) -> Vec<&str> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|quality_format| !quality_format.is_empty())
+        .collect()
+}
+
+fn resolve(
+    resolver: UrlResolver,
+    webpage_link: &str,
+    quality_format: &str,
+    http_options: &HttpOptions,
+) -> Result<String, FrameError> {
+    let streamlink_config = match (resolver, &http_options.cookie_file) {
+        (UrlResolver::Streamlink, Some(path)) => {
+            Some(StreamlinkCookieConfig::write(&read_cookie_file(path)?)?)
+        }
+        _ => None,
+    };
+    let output = Command::new(resolver.program())
+        .args(
+            resolver.args(
+                webpage_link,
+                quality_format,
+                http_options,
+                streamlink_config
+                    .as_ref()
+                    .map(|config| config.path.as_path()),
+            ),
+        )
+        .output()
+        .map_err(FrameError::UrlResolverSpawn)?;
 
     if !output.status.success() {
-        let error_message = std::str::from_utf8(&output.stderr)?;
-        error!("youtube-dl error"; "error" => &error_message);
-        bail!("youtube-dl exit status {}", output.status.code().unwrap());
+        // Some Streamlink versions report errors on stdout
+        let mut error_message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+        if error_message.is_empty() {
+            error_message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        }
+
+        error!("stream URL resolver error";
+            "program" => resolver.program(), "format" => quality_format,
+            "error" => &error_message);
+        return Err(FrameError::UrlResolver {
+            status: output.status.code(),
+            message: error_message,
+        });
     }
 
-    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolver_args() -> anyhow::Result<()> {
+        assert_eq!("yt-dlp".parse(), Ok(UrlResolver::YtDlp));
+        assert!("youtube_dl".parse::<UrlResolver>().is_err());
+        assert_eq!(
+            split_quality_formats("720p60, 720p,,480p"),
+            ["720p60", "720p", "480p"]
+        );
+
+        let http_options = HttpOptions::default();
+
+        assert_eq!(
+            UrlResolver::YtDlp.args("https://example.com/stream", "720p60", &http_options, None),
+            [
+                "--format",
+                "720p60",
+                "--get-url",
+                "https://example.com/stream"
+            ]
+        );
+        assert_eq!(
+            UrlResolver::Streamlink.args(
+                "https://example.com/stream",
+                "720p60",
+                &http_options,
+                None
+            ),
+            ["--stream-url", "https://example.com/stream", "720p60"]
+        );
+
+        let http_options = HttpOptions {
+            proxy: Some("http://127.0.0.1:3128".to_string()),
+            cookie_file: None,
+            headers: vec!["Referer: https://example.com/".to_string()],
+        };
+
+        assert_eq!(
+            UrlResolver::Streamlink.args(
+                "https://example.com/stream",
+                "best",
+                &http_options,
+                Some(Path::new("/tmp/cookies.conf"))
+            ),
+            [
+                "--http-proxy",
+                "http://127.0.0.1:3128",
+                "--config",
+                "/tmp/cookies.conf",
+                "--http-header",
+                "Referer=https://example.com/",
+                "--stream-url",
+                "https://example.com/stream",
+                "best"
+            ]
+        );
+        assert_eq!(
+            http_options.ffmpeg_options()?,
+            [
+                (
+                    "http_proxy".to_string(),
+                    "http://127.0.0.1:3128".to_string()
+                ),
+                (
+                    "headers".to_string(),
+                    "Referer: https://example.com/\r\n".to_string()
+                )
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cookies() {
+        let cookies = parse_cookies(
+            "# Netscape HTTP Cookie File\n\
+            .example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+            #HttpOnly_.example.com\tTRUE\t/\tTRUE\t0\ttoken\txyz\n",
+        );
+
+        assert_eq!(
+            cookies,
+            [
+                Cookie {
+                    domain: ".example.com".to_string(),
+                    path: "/".to_string(),
+                    name: "session".to_string(),
+                    value: "abc".to_string(),
+                },
+                Cookie {
+                    domain: ".example.com".to_string(),
+                    path: "/".to_string(),
+                    name: "token".to_string(),
+                    value: "xyz".to_string(),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_streamlink_cookie_config() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cookies = parse_cookies(".example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n");
+        let config = StreamlinkCookieConfig::write(&cookies)?;
+        let path = config.path.clone();
+
+        assert_eq!(std::fs::read_to_string(&path)?, "http-cookie=session=abc\n");
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        drop(config);
+        assert!(!path.exists());
+
+        Ok(())
+    }
 }
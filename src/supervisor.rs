@@ -0,0 +1,330 @@
+//! Launching and monitoring the stream dumper, VNC server, and OCR processor
+//! as child processes.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use nix::{sys::signal::Signal, unistd::Pid};
+use slog_scope::{info, warn};
+
+use crate::{
+    config::PipelineConfig,
+    error::{Error, SupervisorError},
+    shared_memory::SharedMemory,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A program restarted whenever it exits.
+struct ManagedProcess {
+    name: &'static str,
+    program: PathBuf,
+    args: Vec<String>,
+    /// Shared memory IDs of other programs that must exist before starting.
+    required_shared_memory: Vec<u32>,
+    child: Option<Child>,
+    start_after: Instant,
+    restart_count: u64,
+}
+
+impl ManagedProcess {
+    fn new(name: &'static str, program: PathBuf, args: Vec<String>) -> Self {
+        Self {
+            name,
+            program,
+            args,
+            required_shared_memory: Vec::new(),
+            child: None,
+            start_after: Instant::now(),
+            restart_count: 0,
+        }
+    }
+
+    fn is_ready_to_start(&self) -> bool {
+        self.child.is_none()
+            && Instant::now() >= self.start_after
+            && self
+                .required_shared_memory
+                .iter()
+                .all(|id| SharedMemory::exists(*id))
+    }
+
+    fn start(&mut self) -> Result<(), SupervisorError> {
+        info!("starting program"; "name" => self.name, "program" => ?self.program,
+            "restart_count" => self.restart_count);
+
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .spawn()
+            .map_err(|source| SupervisorError::Spawn {
+                name: self.name.to_string(),
+                program: self.program.clone(),
+                source,
+            })?;
+        self.child = Some(child);
+
+        Ok(())
+    }
+
+    /// Checks whether the program exited and schedules its restart.
+    fn check_exited(&mut self, restart_delay: Duration) -> Result<(), SupervisorError> {
+        if let Some(child) = &mut self.child {
+            if let Some(status) = child.try_wait().map_err(SupervisorError::Wait)? {
+                warn!("program exited"; "name" => self.name, "status" => %status,
+                    "restart_delay" => ?restart_delay);
+
+                self.child = None;
+                self.start_after = Instant::now() + restart_delay;
+                self.restart_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn signal(&self, signal: Signal) -> Result<(), SupervisorError> {
+        if let Some(child) = &self.child {
+            nix::sys::signal::kill(Pid::from_raw(child.id() as i32), signal)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the programs of the pipeline configured in one config file.
+///
+/// Programs that exit are restarted after a delay. The OCR processor is
+/// started only once the stream dumper and VNC server created their shared
+/// memory.
+/// Shared memory left behind by the programs is removed at start and at exit
+/// since the programs don't remove it themselves. SIGHUP is forwarded to the
+/// OCR processor to reload its config.
+pub struct Supervisor {
+    config: PipelineConfig,
+    processes: Vec<ManagedProcess>,
+}
+
+impl Supervisor {
+    pub fn new(config_path: &Path, config: PipelineConfig, bin_dir: PathBuf) -> Self {
+        let processes = vec![
+            ManagedProcess::new(
+                "stream_dumper",
+                bin_dir.join("stream_dumper"),
+                stream_dumper_args(&config),
+            ),
+            ManagedProcess::new(
+                "vnc_server",
+                bin_dir.join("vnc_server"),
+                vec![
+                    "--id".to_string(),
+                    config.vnc_id.to_string(),
+                    "--width".to_string(),
+                    config.vnc_width.to_string(),
+                    "--height".to_string(),
+                    config.vnc_height.to_string(),
+                ],
+            ),
+            ManagedProcess {
+                required_shared_memory: vec![config.stream_id as u32, config.vnc_id as u32],
+                ..ManagedProcess::new(
+                    "tppocr",
+                    bin_dir.join("tppocr"),
+                    processor_args(config_path, &config),
+                )
+            },
+        ];
+
+        Self { config, processes }
+    }
+
+    pub fn run(&mut self) -> Result<(), Error> {
+        let terminate_flag = Arc::new(AtomicBool::new(false));
+        for sig in signal_hook::consts::TERM_SIGNALS {
+            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        }
+
+        let reload_flag = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload_flag)).unwrap();
+
+        self.remove_shared_memory();
+
+        let restart_delay = Duration::from_secs_f64(self.config.restart_delay_secs.max(0.0));
+        let mut result = Ok(());
+
+        while !terminate_flag.load(Ordering::Relaxed) {
+            result = self.poll(restart_delay, reload_flag.swap(false, Ordering::Relaxed));
+
+            if result.is_err() {
+                break;
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        info!("stopping programs");
+        self.stop();
+        self.remove_shared_memory();
+
+        result
+    }
+
+    fn poll(&mut self, restart_delay: Duration, reload: bool) -> Result<(), Error> {
+        for process in &mut self.processes {
+            process.check_exited(restart_delay)?;
+
+            if process.is_ready_to_start() {
+                process.start()?;
+            }
+
+            if reload && process.name == "tppocr" {
+                info!("forwarding reload signal");
+                process.signal(Signal::SIGHUP)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Terminates the programs in reverse order, killing those that don't
+    /// exit in time.
+    fn stop(&mut self) {
+        for process in self.processes.iter().rev() {
+            if let Err(error) = process.signal(Signal::SIGTERM) {
+                warn!("failed to terminate program"; "name" => process.name, "error" => %error);
+            }
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        for process in self.processes.iter_mut().rev() {
+            if let Some(mut child) = process.child.take() {
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(_)) | Err(_) => break,
+                        Ok(None) if Instant::now() >= deadline => {
+                            warn!("killing program"; "name" => process.name);
+                            child.kill().ok();
+                            child.wait().ok();
+                            break;
+                        }
+                        Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove_shared_memory(&self) {
+        for id in &[self.config.stream_id, self.config.vnc_id] {
+            if let Err(error) = SharedMemory::remove(*id as u32) {
+                warn!("failed to remove shared memory"; "id" => id, "error" => %error);
+            }
+        }
+    }
+}
+
+fn stream_dumper_args(config: &PipelineConfig) -> Vec<String> {
+    let mut args = vec![
+        config.input.clone(),
+        "--id".to_string(),
+        config.stream_id.to_string(),
+        "--width".to_string(),
+        config.stream_width.to_string(),
+        "--height".to_string(),
+        config.stream_height.to_string(),
+        "--slots".to_string(),
+        config.frame_slots.to_string(),
+    ];
+
+    if config.get_url {
+        args.push("--get-url".to_string());
+    }
+
+    if let Some(url_resolver) = &config.url_resolver {
+        args.push("--url-resolver".to_string());
+        args.push(url_resolver.clone());
+    }
+
+    if let Some(address) = &config.frame_listen {
+        args.push("--listen".to_string());
+        args.push(address.clone());
+    }
+
+    if let Some(format) = &config.format {
+        args.push("--format".to_string());
+        args.push(format.clone());
+    }
+
+    if let Some(proxy) = &config.proxy {
+        args.push("--proxy".to_string());
+        args.push(proxy.clone());
+    }
+
+    if let Some(path) = &config.cookies {
+        args.push("--cookies".to_string());
+        args.push(path.to_string_lossy().to_string());
+    }
+
+    for header in &config.headers {
+        args.push("--header".to_string());
+        args.push(header.clone());
+    }
+
+    args
+}
+
+fn processor_args(config_path: &Path, config: &PipelineConfig) -> Vec<String> {
+    let mut args = vec![
+        config_path.to_string_lossy().to_string(),
+        "--stream-id".to_string(),
+        config.stream_id.to_string(),
+        "--stream-width".to_string(),
+        config.stream_width.to_string(),
+        "--stream-height".to_string(),
+        config.stream_height.to_string(),
+        "--vnc-id".to_string(),
+        config.vnc_id.to_string(),
+        "--vnc-width".to_string(),
+        config.vnc_width.to_string(),
+        "--vnc-height".to_string(),
+        config.vnc_height.to_string(),
+    ];
+    args.extend(config.processor_args.iter().cloned());
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_processor_args() {
+        let config: PipelineConfig = toml::from_str(
+            r#"
+            input = "https://example.com/stream"
+            get_url = true
+            processor_args = ["--ocr-threads", "2"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stream_dumper_args(&config)[..3],
+            ["https://example.com/stream", "--id", "8840"]
+        );
+        assert!(stream_dumper_args(&config).contains(&"--get-url".to_string()));
+
+        let args = processor_args(Path::new("config.toml"), &config);
+        assert_eq!(args[0], "config.toml");
+        assert_eq!(args[args.len() - 2..], ["--ocr-threads", "2"]);
+    }
+}
@@ -0,0 +1,337 @@
+//! Opt-in reporting of aggregate statistics to a collector run by the
+//! operator, for watching several instances in one place.
+//!
+//! Reports contain only counts, confidences, and latencies, never recognized
+//! text. Each report is one JSON object on a line sent over TCP. The
+//! collector keeps the latest report of each instance and serves them in the
+//! Prometheus text format for dashboards.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use slog_scope::{info, warn};
+
+use crate::{config::TelemetryConfig, latency::LatencySummary};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type MetricValue = fn(&TelemetryReport) -> Option<f64>;
+
+/// Statistics of one instance over one reporting interval.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TelemetryReport {
+    pub instance: String,
+    pub date: DateTime<Utc>,
+    pub interval_secs: f64,
+    pub frame_count: u64,
+    pub item_count: u64,
+    /// Mean recognition confidence of the emitted items.
+    pub mean_confidence: Option<f32>,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+}
+
+/// Accumulates statistics and sends them to the collector periodically.
+///
+/// A report finished while the previous one is still being sent is dropped.
+pub struct TelemetryReporter {
+    config: TelemetryConfig,
+    interval_start: DateTime<Utc>,
+    frame_count: u64,
+    item_count: u64,
+    confidence_sum: f64,
+    /// Collector address and report for the sending thread.
+    report_sender: SyncSender<(String, TelemetryReport)>,
+}
+
+impl TelemetryReporter {
+    pub fn new(config: TelemetryConfig) -> Self {
+        let (report_sender, report_receiver) = mpsc::sync_channel(1);
+
+        std::thread::spawn(move || send_reports(report_receiver));
+
+        Self {
+            report_sender,
+            config,
+            interval_start: Utc::now(),
+            frame_count: 0,
+            item_count: 0,
+            confidence_sum: 0.0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: TelemetryConfig) {
+        self.config = config;
+    }
+
+    pub fn record_frame(&mut self) {
+        self.frame_count += 1;
+    }
+
+    pub fn record_item(&mut self, confidence: f32) {
+        self.item_count += 1;
+        self.confidence_sum += confidence as f64;
+    }
+
+    /// Queues a report for sending if the interval elapsed.
+    ///
+    /// Failures are logged and the statistics of the interval are discarded
+    /// so an unreachable collector doesn't affect processing. A report is
+    /// also discarded if the previous one is still being sent.
+    pub fn poll(&mut self, date: &DateTime<Utc>, latency: Option<LatencySummary>) {
+        let elapsed = *date - self.interval_start;
+
+        if elapsed < chrono::Duration::seconds(self.config.interval_secs as i64) {
+            return;
+        }
+
+        let report = TelemetryReport {
+            instance: self.config.instance.clone(),
+            date: *date,
+            interval_secs: elapsed.num_milliseconds() as f64 / 1000.0,
+            frame_count: self.frame_count,
+            item_count: self.item_count,
+            mean_confidence: if self.item_count > 0 {
+                Some((self.confidence_sum / self.item_count as f64) as f32)
+            } else {
+                None
+            },
+            latency_p50_ms: latency.as_ref().map(|summary| summary.p50),
+            latency_p95_ms: latency.as_ref().map(|summary| summary.p95),
+        };
+
+        self.interval_start = *date;
+        self.frame_count = 0;
+        self.item_count = 0;
+        self.confidence_sum = 0.0;
+
+        match self
+            .report_sender
+            .try_send((self.config.collector.clone(), report))
+        {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!("telemetry report dropped while sending the previous one";
+                    "collector" => &self.config.collector);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("telemetry sending thread stopped");
+            }
+        }
+    }
+}
+
+/// Sends the queued reports until the reporter is dropped.
+fn send_reports(report_receiver: Receiver<(String, TelemetryReport)>) {
+    for (address, report) in report_receiver {
+        if let Err(error) = send_report(&address, &report) {
+            warn!("failed to send telemetry report";
+                "collector" => &address, "error" => %error);
+        }
+    }
+}
+
+fn send_report(address: &str, report: &TelemetryReport) -> std::io::Result<()> {
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "address not resolved"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let mut line = serde_json::to_string(report)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+/// Receives reports from instances and serves them for dashboards.
+pub struct TelemetryCollector {
+    reports: Arc<Mutex<BTreeMap<String, TelemetryReport>>>,
+}
+
+impl Default for TelemetryCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TelemetryCollector {
+    pub fn new() -> Self {
+        Self {
+            reports: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Accepts reports on `report_address` and serves metrics over HTTP on
+    /// `metrics_address`. Returns only if binding fails.
+    pub fn run(&self, report_address: &str, metrics_address: &str) -> std::io::Result<()> {
+        let report_listener = TcpListener::bind(report_address)?;
+        let metrics_listener = TcpListener::bind(metrics_address)?;
+
+        info!("collector started";
+            "report_address" => report_address, "metrics_address" => metrics_address);
+
+        let reports = Arc::clone(&self.reports);
+        std::thread::spawn(move || {
+            for stream in metrics_listener.incoming() {
+                if let Err(error) = stream.and_then(|stream| serve_metrics(stream, &reports)) {
+                    warn!("failed to serve metrics"; "error" => %error);
+                }
+            }
+        });
+
+        for stream in report_listener.incoming() {
+            if let Err(error) = stream.and_then(|stream| self.receive_report(stream)) {
+                warn!("failed to receive telemetry report"; "error" => %error);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive_report(&self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+
+        let report: TelemetryReport = serde_json::from_str(&line)?;
+        self.reports
+            .lock()
+            .unwrap()
+            .insert(report.instance.clone(), report);
+
+        Ok(())
+    }
+}
+
+fn serve_metrics(
+    mut stream: TcpStream,
+    reports: &Mutex<BTreeMap<String, TelemetryReport>>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+    // Any request gets the metrics
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let body = format_metrics(reports.lock().unwrap().values());
+
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+/// Formats the latest reports in the Prometheus text format.
+pub fn format_metrics<'a, I>(reports: I) -> String
+where
+    I: IntoIterator<Item = &'a TelemetryReport>,
+{
+    let reports: Vec<&TelemetryReport> = reports.into_iter().collect();
+    let mut text = String::new();
+    let metrics: &[(&str, &str, MetricValue)] = &[
+        (
+            "tppocr_frames_per_second",
+            "Frames processed per second",
+            |report| Some(report.frame_count as f64 / report.interval_secs.max(1.0)),
+        ),
+        (
+            "tppocr_items_per_minute",
+            "Text items emitted per minute",
+            |report| Some(report.item_count as f64 * 60.0 / report.interval_secs.max(1.0)),
+        ),
+        (
+            "tppocr_mean_confidence",
+            "Mean recognition confidence of emitted items",
+            |report| report.mean_confidence.map(f64::from),
+        ),
+        (
+            "tppocr_latency_p50_milliseconds",
+            "Median latency from frame capture to text emission",
+            |report| report.latency_p50_ms,
+        ),
+        (
+            "tppocr_latency_p95_milliseconds",
+            "95th percentile latency from frame capture to text emission",
+            |report| report.latency_p95_ms,
+        ),
+        (
+            "tppocr_last_report_timestamp_seconds",
+            "Time of the latest report",
+            |report| Some(report.date.timestamp() as f64),
+        ),
+    ];
+
+    for (name, help, value) in metrics {
+        text.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} gauge\n",
+            name, help, name
+        ));
+
+        for report in &reports {
+            if let Some(value) = value(report) {
+                text.push_str(&format!(
+                    "{}{{instance=\"{}\"}} {}\n",
+                    name,
+                    escape_label_value(&report.instance),
+                    value
+                ));
+            }
+        }
+    }
+
+    text
+}
+
+/// Escapes backslashes, double quotes, and line feeds as the Prometheus text
+/// format requires in label values.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_format_metrics() {
+        let report = TelemetryReport {
+            instance: "ocr-1".to_string(),
+            date: Utc.timestamp(1_600_000_000, 0),
+            interval_secs: 60.0,
+            frame_count: 600,
+            item_count: 30,
+            mean_confidence: Some(0.5),
+            latency_p50_ms: None,
+            latency_p95_ms: None,
+        };
+        let text = format_metrics(&[report]);
+
+        assert!(text.contains("tppocr_frames_per_second{instance=\"ocr-1\"} 10\n"));
+        assert!(text.contains("tppocr_items_per_minute{instance=\"ocr-1\"} 30\n"));
+        assert!(text.contains("tppocr_mean_confidence{instance=\"ocr-1\"} 0.5\n"));
+        assert!(!text.contains("tppocr_latency_p50_milliseconds{"));
+
+        assert_eq!(escape_label_value("ocr \"1\"\\\n"), "ocr \\\"1\\\"\\\\\\n");
+    }
+}
@@ -2,18 +2,68 @@ use std::collections::VecDeque;
 
 use chrono::{DateTime, Utc};
 use eddie::JaroWinkler;
+use serde::Serialize;
 
-use crate::{config::Region, text_recognizer::BoundingBox};
+use crate::{
+    config::Region,
+    event::Event,
+    integrity::IntegrityStamp,
+    metadata::RecordMetadata,
+    text_recognizer::{BoundingBox, TextLine},
+};
 
 pub trait TextProcessor {
     fn process(&mut self, date: &DateTime<Utc>, text: &str, block_bounding_boxes: &[BoundingBox]);
+
+    /// Processes the individual lines of the recognition result.
+    ///
+    /// Called after `process` with the same result. Lines are ignored by
+    /// default.
+    fn process_lines(&mut self, _date: &DateTime<Utc>, _lines: &[TextLine]) {}
+
+    /// Processes the individual words of the recognition result.
+    ///
+    /// Called after `process_lines` with the same result. Words are ignored
+    /// by default.
+    fn process_words(&mut self, _date: &DateTime<Utc>, _words: &[TextLine]) {}
+
     fn poll_result(&mut self, date: &DateTime<Utc>) -> Vec<TextItem>;
+
+    /// Returns events produced instead of or in addition to text, such as
+    /// menu selections. None by default.
+    fn poll_events(&mut self, _date: &DateTime<Utc>) -> Vec<Event> {
+        Vec::new()
+    }
 }
 
+#[derive(Clone, Serialize)]
 pub struct TextItem {
     pub date: DateTime<Utc>,
+    /// Name of the region the text was recognized in.
+    pub region_name: String,
     pub text: String,
     pub confidence: f32,
+    /// Location in the frame for strategies that search for text.
+    pub location: Option<BoundingBox>,
+    /// Value of the text for strategies that read numbers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<i64>,
+    /// Box of the text block in the frame, and boxes of its words, for
+    /// strategies emitting the text of one recognition. Strategies joining
+    /// text of several frames, such as DialogScroll and Ticker, leave them
+    /// empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_box: Option<BoundingBox>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub word_boxes: Vec<BoundingBox>,
+    /// Filled in by the processor before the item is emitted.
+    pub metadata: RecordMetadata,
+    /// Filled in by the processor if integrity stamping is enabled.
+    pub integrity: Option<IntegrityStamp>,
+    /// Name of the `[[stream]]` of the region, filled in by the processor.
+    /// None for the main stream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_name: Option<String>,
 }
 
 struct InputTextItem {
@@ -21,6 +71,8 @@ struct InputTextItem {
     pub text: String,
     pub confidence: f32,
     pub previous_similarity: Option<f64>, // [0.0, 1.0]
+    pub block_box: Option<BoundingBox>,
+    pub word_boxes: Vec<BoundingBox>,
 }
 
 /// Processes text recognition results for region focused on a line of text
@@ -57,12 +109,20 @@ impl FixedLineProcessor {
             }
         }
 
-        let best_item = &self.input_buffer[best_index];
+        let best_item = self.input_buffer.swap_remove(best_index);
 
         self.output_buffer.push_back(TextItem {
             date: best_item.date,
-            text: best_item.text.clone(),
+            region_name: self.region.name.clone(),
+            text: best_item.text,
             confidence: best_item.confidence,
+            location: None,
+            value: None,
+            block_box: best_item.block_box,
+            word_boxes: best_item.word_boxes,
+            metadata: RecordMetadata::default(),
+            integrity: None,
+            stream_name: None,
         });
 
         self.input_buffer.clear();
@@ -81,7 +141,7 @@ impl TextProcessor for FixedLineProcessor {
 
                 previous_similarity = Some(similarity);
 
-                if similarity < 0.8 {
+                if similarity < self.region.fixed_line.similarity_threshold {
                     self.flush_input_to_output_buffer();
                 }
             }
@@ -91,13 +151,26 @@ impl TextProcessor for FixedLineProcessor {
                 date: date.to_owned(),
                 confidence: block_bounding_boxes.first().unwrap().confidence,
                 previous_similarity,
+                block_box: block_bounding_boxes.first().cloned(),
+                word_boxes: Vec::new(),
             });
         }
     }
 
+    fn process_words(&mut self, date: &DateTime<Utc>, words: &[TextLine]) {
+        if let Some(item) = self.input_buffer.last_mut() {
+            if item.date == *date {
+                item.word_boxes = word_boxes(words, item.block_box.as_ref());
+            }
+        }
+    }
+
     fn poll_result(&mut self, date: &DateTime<Utc>) -> Vec<TextItem> {
+        let flush_duration =
+            chrono::Duration::milliseconds((self.region.fixed_line.flush_secs * 1000.0) as i64);
+
         if let Some(item) = self.input_buffer.last() {
-            if date.signed_duration_since(item.date) > chrono::Duration::seconds(5) {
+            if date.signed_duration_since(item.date) > flush_duration {
                 self.flush_input_to_output_buffer();
             }
         }
@@ -112,34 +185,830 @@ impl TextProcessor for FixedLineProcessor {
     }
 }
 
+/// Words followed by a period that doesn't end a sentence.
+const ABBREVIATIONS: [&str; 8] = ["MR", "MRS", "MS", "DR", "PROF", "ST", "JR", "VS"];
+
 /// Processes text recognition results for a region focused on a fixed-size
 /// dialog box in which text is revealed glyph-by-glyph and lines may shift up
 /// (scroll) to reveal subsequent lines.
+///
+/// Lines of the box are joined, removing hyphenation at line ends, and each
+/// complete sentence is emitted. A line is added once it is finished, which
+/// is when a line appears below it, it scrolls up, the box is cleared or
+/// shows another page, or the box stays unchanged for a while. Text not
+/// ending a sentence is emitted when the box is cleared or stays unchanged.
 pub struct DialogScrollProcessor {
     region: Region,
+    /// Lines currently in the box.
+    box_lines: Vec<DialogLine>,
+    /// Number of leading box lines already added to the pending text.
+    added_line_count: usize,
+    /// Joined text of added lines not yet emitted.
+    pending: Option<InputTextItem>,
+    /// Date the box last changed.
+    change_date: Option<DateTime<Utc>>,
+    output_buffer: VecDeque<TextItem>,
+    similarity_calculator: JaroWinkler,
+}
+
+struct DialogLine {
+    date: DateTime<Utc>,
+    text: String,
+    confidence: f32,
 }
 
 impl DialogScrollProcessor {
     pub fn new(region: Region) -> Self {
-        Self { region }
+        Self {
+            region,
+            box_lines: Vec::new(),
+            added_line_count: 0,
+            pending: None,
+            change_date: None,
+            output_buffer: VecDeque::new(),
+            similarity_calculator: JaroWinkler::new(),
+        }
+    }
+
+    /// Returns whether the text is the same line as earlier text, allowing
+    /// for more glyphs having been revealed.
+    fn is_same_line(&self, earlier: &str, text: &str) -> bool {
+        let length = earlier.chars().count().min(text.chars().count());
+
+        if length == 0 {
+            return false;
+        }
+
+        let earlier: String = earlier.chars().take(length).collect();
+        let text: String = text.chars().take(length).collect();
+
+        self.similarity_calculator.similarity(&earlier, &text) >= 0.8
+    }
+
+    /// Returns the number of lines the box scrolled up by from the previous
+    /// lines to the lines, or None if the box shows another page.
+    fn scrolled_line_count(&self, lines: &[DialogLine]) -> Option<usize> {
+        let first_line = lines.first()?;
+
+        (0..self.box_lines.len()).find(|offset| {
+            self.box_lines[*offset..]
+                .iter()
+                .zip(lines)
+                .all(|(earlier, line)| self.is_same_line(&earlier.text, &line.text))
+                && (*offset > 0 || self.is_same_line(&self.box_lines[0].text, &first_line.text))
+        })
+    }
+
+    /// Adds the box lines up to the index to the pending text.
+    fn add_lines(&mut self, end: usize) {
+        while self.added_line_count < end {
+            let line = &self.box_lines[self.added_line_count];
+
+            match &mut self.pending {
+                Some(pending) => {
+                    pending.text = join_lines(&pending.text, &line.text);
+                    pending.confidence = pending.confidence.min(line.confidence);
+                }
+                None => {
+                    self.pending = Some(InputTextItem {
+                        date: line.date,
+                        text: line.text.clone(),
+                        confidence: line.confidence,
+                        previous_similarity: None,
+                        block_box: None,
+                        word_boxes: Vec::new(),
+                    })
+                }
+            }
+
+            self.added_line_count += 1;
+        }
+
+        self.flush_sentences(false);
+    }
+
+    /// Moves complete sentences, or all text if `all`, from the pending text
+    /// to the output buffer.
+    fn flush_sentences(&mut self, all: bool) {
+        let pending = match &mut self.pending {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let end = if all {
+            pending.text.len()
+        } else {
+            match last_sentence_end(&pending.text) {
+                Some(end) => end,
+                None => return,
+            }
+        };
+
+        for sentence in split_sentences(&pending.text[..end]) {
+            self.output_buffer.push_back(TextItem {
+                date: pending.date,
+                region_name: self.region.name.clone(),
+                text: sentence.to_string(),
+                confidence: pending.confidence,
+                location: None,
+                value: None,
+                block_box: None,
+                word_boxes: Vec::new(),
+                metadata: RecordMetadata::default(),
+                integrity: None,
+                stream_name: None,
+            });
+        }
+
+        let remaining = pending.text[end..].trim();
+
+        if remaining.is_empty() {
+            self.pending = None;
+        } else {
+            pending.text = remaining.to_string();
+        }
+    }
+
+    /// Adds all box lines and emits the pending text.
+    fn flush_box(&mut self) {
+        self.add_lines(self.box_lines.len());
+        self.flush_sentences(true);
     }
 }
 
 impl TextProcessor for DialogScrollProcessor {
-    fn process(&mut self, date: &DateTime<Utc>, text: &str, block_bounding_boxes: &[BoundingBox]) {
-        // TODO!
+    fn process(
+        &mut self,
+        _date: &DateTime<Utc>,
+        _text: &str,
+        _block_bounding_boxes: &[BoundingBox],
+    ) {
+    }
+
+    fn process_lines(&mut self, date: &DateTime<Utc>, lines: &[TextLine]) {
+        let lines: Vec<DialogLine> = lines
+            .iter()
+            .filter(|line| line.bounding_box.confidence >= 0.6)
+            .map(|line| DialogLine {
+                date: *date,
+                text: line.text.trim().to_string(),
+                confidence: line.bounding_box.confidence,
+            })
+            .filter(|line| !line.text.is_empty())
+            .collect();
+
+        let changed = lines.len() != self.box_lines.len()
+            || lines
+                .iter()
+                .zip(&self.box_lines)
+                .any(|(line, box_line)| line.text != box_line.text);
+
+        if !changed {
+            return;
+        }
+
+        self.change_date = Some(*date);
+
+        match self.scrolled_line_count(&lines) {
+            Some(offset) => {
+                // Lines scrolled out of the box are finished
+                self.add_lines(offset);
+                self.added_line_count -= offset;
+                self.box_lines.drain(..offset);
+
+                self.box_lines
+                    .truncate(lines.len().max(self.added_line_count));
+
+                for (index, line) in lines.into_iter().enumerate() {
+                    match self.box_lines.get_mut(index) {
+                        // Lines keep the date they first appeared
+                        Some(box_line) => {
+                            box_line.text = line.text;
+                            box_line.confidence = line.confidence;
+                        }
+                        None => self.box_lines.push(line),
+                    }
+                }
+            }
+            None => {
+                self.flush_box();
+                self.box_lines = lines;
+                self.added_line_count = 0;
+            }
+        }
+
+        // Lines with a line below them are finished
+        self.add_lines(self.box_lines.len().saturating_sub(1));
+    }
+
+    fn poll_result(&mut self, date: &DateTime<Utc>) -> Vec<TextItem> {
+        if let Some(change_date) = self.change_date {
+            if date.signed_duration_since(change_date) > chrono::Duration::seconds(5) {
+                self.flush_box();
+                self.change_date = None;
+            }
+        }
+
+        self.output_buffer.drain(..).collect()
+    }
+}
+
+/// Joins the text of a line to the text of the lines above it, removing
+/// hyphenation of a word split across the lines.
+fn join_lines(text: &str, line: &str) -> String {
+    let mut characters = text.chars().rev();
+    let hyphenated = characters.next() == Some('-')
+        && characters.next().map_or(false, char::is_alphabetic)
+        && line.chars().next().map_or(false, char::is_alphabetic);
+
+    if hyphenated {
+        format!("{}{}", &text[..text.len() - 1], line)
+    } else {
+        format!("{} {}", text, line)
+    }
+}
+
+/// Returns the byte index after the last sentence in the text.
+fn last_sentence_end(text: &str) -> Option<usize> {
+    sentence_ends(text).last().copied()
+}
+
+/// Returns the trimmed sentences of the text. Text after the last sentence
+/// ending punctuation is returned as a sentence.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+
+    for end in sentence_ends(text).into_iter().chain(Some(text.len())) {
+        let sentence = text[start..end].trim();
+
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+
+        start = end;
+    }
+
+    sentences
+}
+
+/// Returns whether the text ends with a title abbreviated with a period,
+/// such as the "PROF" of "PROF. OAK".
+fn is_abbreviation(text: &str) -> bool {
+    let word = text
+        .rsplit(|character: char| !character.is_alphabetic())
+        .next()
+        .unwrap_or_default();
+
+    ABBREVIATIONS
+        .iter()
+        .any(|abbreviation| word.eq_ignore_ascii_case(abbreviation))
+}
+
+/// Returns the byte indices after runs of sentence ending punctuation and
+/// closing quotes that are followed by whitespace or the end of the text.
+fn sentence_ends(text: &str) -> Vec<usize> {
+    let is_ending = |character: char| matches!(character, '.' | '!' | '?' | '…');
+    let is_closing = |character: char| matches!(character, '"' | '\'' | '”' | '’' | ')');
+    let mut ends = Vec::new();
+    let mut characters = text.char_indices().peekable();
+
+    while let Some((index, character)) = characters.next() {
+        if !is_ending(character) {
+            continue;
+        }
+
+        if character == '.' && is_abbreviation(&text[..index]) {
+            continue;
+        }
+
+        let mut end = text.len();
+
+        while let Some((index, character)) = characters.peek() {
+            if is_ending(*character) || is_closing(*character) {
+                characters.next();
+            } else {
+                end = *index;
+                break;
+            }
+        }
+
+        if text[end..].chars().next().map_or(true, char::is_whitespace) {
+            ends.push(end);
+        }
+    }
+
+    ends
+}
+
+/// Processes text recognition results for a region covering the whole frame
+/// in which any text may appear anywhere.
+///
+/// Each line recognized with high confidence is emitted with its location.
+/// A line is not emitted again while it remains on screen.
+pub struct SparseTextProcessor {
+    region: Region,
+    output_buffer: VecDeque<TextItem>,
+    previous_texts: Vec<String>,
+}
+
+impl SparseTextProcessor {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            output_buffer: VecDeque::new(),
+            previous_texts: Vec::new(),
+        }
+    }
+}
+
+impl TextProcessor for SparseTextProcessor {
+    fn process(
+        &mut self,
+        _date: &DateTime<Utc>,
+        _text: &str,
+        _block_bounding_boxes: &[BoundingBox],
+    ) {
+    }
+
+    fn process_lines(&mut self, date: &DateTime<Utc>, lines: &[TextLine]) {
+        let mut texts = Vec::new();
+
+        for line in lines {
+            if line.bounding_box.confidence < 0.8 || line.text.chars().count() < 3 {
+                continue;
+            }
+
+            if !self.previous_texts.contains(&line.text) {
+                self.output_buffer.push_back(TextItem {
+                    date: *date,
+                    region_name: self.region.name.clone(),
+                    text: line.text.clone(),
+                    confidence: line.bounding_box.confidence,
+                    location: Some(line.bounding_box.clone()),
+                    value: None,
+                    block_box: None,
+                    word_boxes: Vec::new(),
+                    metadata: RecordMetadata::default(),
+                    integrity: None,
+                    stream_name: None,
+                });
+            }
+
+            texts.push(line.text.clone());
+        }
+
+        self.previous_texts = texts;
+    }
+
+    fn process_words(&mut self, date: &DateTime<Utc>, words: &[TextLine]) {
+        for item in &mut self.output_buffer {
+            if item.date == *date {
+                item.word_boxes = word_boxes(words, item.location.as_ref());
+            }
+        }
+    }
+
+    fn poll_result(&mut self, _date: &DateTime<Utc>) -> Vec<TextItem> {
+        self.output_buffer.drain(..).collect()
+    }
+}
+
+/// Words within this number of pixels of the left or right edge of a ticker
+/// region are partly cut off and ignored.
+const TICKER_EDGE_MARGIN: i32 = 2;
+
+/// Processes text recognition results for a region focused on a single line
+/// of text scrolling horizontally, such as a news ticker or marquee.
+///
+/// Words fully inside the region are matched to the words of the previous
+/// frame, which are at the same or a position further right, and words
+/// entering from the right are appended to the text of the pass. A pass
+/// ends when the region is empty, the first words of the pass come around
+/// again, or the region stays unchanged for a while. The text of each pass
+/// is emitted once, unless it is the same as the previous pass.
+pub struct TickerProcessor {
+    region: Region,
+    /// Words of the current pass.
+    words: Vec<TickerWord>,
+    /// Indices into `words` and left positions of the words visible in the
+    /// previous frame.
+    visible_words: Vec<(usize, i32)>,
+    pass_date: Option<DateTime<Utc>>,
+    /// Date the visible words last changed.
+    change_date: Option<DateTime<Utc>>,
+    previous_pass_text: Option<String>,
+    output_buffer: VecDeque<TextItem>,
+    similarity_calculator: JaroWinkler,
+}
+
+struct TickerWord {
+    text: String,
+    confidence: f32,
+}
+
+impl TickerProcessor {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            words: Vec::new(),
+            visible_words: Vec::new(),
+            pass_date: None,
+            change_date: None,
+            previous_pass_text: None,
+            output_buffer: VecDeque::new(),
+            similarity_calculator: JaroWinkler::new(),
+        }
+    }
+
+    fn is_same_word(&self, first: &str, second: &str) -> bool {
+        self.similarity_calculator.similarity(first, second) >= 0.8
+    }
+
+    /// Returns the index into the previous frame's visible words of the
+    /// first of the words, or None if the words don't continue them.
+    ///
+    /// Of the positions where the words match the rest of the previous
+    /// words, the one needing the least movement is used.
+    fn align(&self, words: &[&TextLine]) -> Option<usize> {
+        let first_word = words.first()?;
+
+        (0..self.visible_words.len())
+            .filter(|start| {
+                let (_, x1) = self.visible_words[*start];
+
+                // Text only moves left
+                first_word.bounding_box.x1 <= x1 + TICKER_EDGE_MARGIN
+                    && self.visible_words[*start..]
+                        .iter()
+                        .zip(words)
+                        .all(|((index, _), word)| {
+                            self.is_same_word(&self.words[*index].text, &word.text)
+                        })
+            })
+            .min_by_key(|start| self.visible_words[*start].1 - first_word.bounding_box.x1)
+    }
+
+    /// Returns the index of a word after the first where the first words of
+    /// the pass appear again.
+    fn find_repeat(&self) -> Option<usize> {
+        let length = self.words.len().min(3);
+
+        if length == 0 {
+            return None;
+        }
+
+        (1..=self.words.len() - length).find(|start| {
+            self.words[..length]
+                .iter()
+                .zip(&self.words[*start..])
+                .all(|(first, word)| self.is_same_word(&first.text, &word.text))
+        })
+    }
+
+    /// Emits the words of the pass before the index and removes them. The
+    /// remaining words start a pass at the date.
+    fn end_pass(&mut self, end: usize, date: &DateTime<Utc>) {
+        let pass_words: Vec<TickerWord> = self.words.drain(..end).collect();
+
+        for (index, _) in &mut self.visible_words {
+            *index = index.saturating_sub(end);
+        }
+
+        let pass_date = std::mem::replace(
+            &mut self.pass_date,
+            if self.words.is_empty() {
+                None
+            } else {
+                Some(*date)
+            },
+        );
+
+        let date = match pass_date {
+            Some(date) if !pass_words.is_empty() => date,
+            _ => return,
+        };
+
+        let text = pass_words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        if self.previous_pass_text.as_ref() == Some(&text) {
+            return;
+        }
+
+        self.output_buffer.push_back(TextItem {
+            date,
+            region_name: self.region.name.clone(),
+            text: text.clone(),
+            confidence: pass_words
+                .iter()
+                .map(|word| word.confidence)
+                .fold(1.0, f32::min),
+            location: None,
+            value: None,
+            block_box: None,
+            word_boxes: Vec::new(),
+            metadata: RecordMetadata::default(),
+            integrity: None,
+            stream_name: None,
+        });
+        self.previous_pass_text = Some(text);
+    }
+}
+
+impl TextProcessor for TickerProcessor {
+    fn process(
+        &mut self,
+        _date: &DateTime<Utc>,
+        _text: &str,
+        _block_bounding_boxes: &[BoundingBox],
+    ) {
+    }
+
+    fn process_words(&mut self, date: &DateTime<Utc>, words: &[TextLine]) {
+        let left = self.region.x as i32 + TICKER_EDGE_MARGIN;
+        let right = (self.region.x + self.region.width) as i32 - TICKER_EDGE_MARGIN;
+        let mut words: Vec<&TextLine> = words
+            .iter()
+            .filter(|word| {
+                word.bounding_box.confidence >= 0.6
+                    && word.bounding_box.x1 > left
+                    && word.bounding_box.x2 < right
+                    && !word.text.trim().is_empty()
+            })
+            .collect();
+        words.sort_by_key(|word| word.bounding_box.x1);
+
+        let moved = words.len() != self.visible_words.len()
+            || words
+                .iter()
+                .zip(&self.visible_words)
+                .any(|(word, (_, x1))| word.bounding_box.x1 != *x1);
+
+        if moved {
+            self.change_date = Some(*date);
+        }
+
+        if words.is_empty() {
+            // Gap between passes
+            self.end_pass(self.words.len(), date);
+            self.visible_words.clear();
+            return;
+        }
+
+        // Words matched to the previous frame, then new words on the right
+        let (mut index, matched_count) = match self.align(&words) {
+            Some(start) => (
+                self.visible_words[start].0,
+                (self.visible_words.len() - start).min(words.len()),
+            ),
+            None => (self.words.len(), 0),
+        };
+
+        if self.pass_date.is_none() {
+            self.pass_date = Some(*date);
+        }
+
+        let mut visible_words = Vec::with_capacity(words.len());
+
+        for (position, word) in words.iter().enumerate() {
+            let ticker_word = TickerWord {
+                text: word.text.trim().to_string(),
+                confidence: word.bounding_box.confidence,
+            };
+
+            if position < matched_count {
+                // Keep the most confident reading
+                if ticker_word.confidence > self.words[index].confidence {
+                    self.words[index] = ticker_word;
+                }
+            } else {
+                index = self.words.len();
+                self.words.push(ticker_word);
+            }
+
+            visible_words.push((index, word.bounding_box.x1));
+            index += 1;
+        }
+
+        self.visible_words = visible_words;
+
+        if let Some(end) = self.find_repeat() {
+            self.end_pass(end, date);
+        }
     }
 
     fn poll_result(&mut self, date: &DateTime<Utc>) -> Vec<TextItem> {
-        // TODO!
+        if let Some(change_date) = self.change_date {
+            if date.signed_duration_since(change_date) > chrono::Duration::seconds(5) {
+                self.end_pass(self.words.len(), date);
+                self.visible_words.clear();
+                self.change_date = None;
+            }
+        }
+
+        self.output_buffer.drain(..).collect()
+    }
+}
+
+/// Processes text recognition results for a region focused on a menu, a
+/// column of options with a cursor at the selected one.
+///
+/// Each line is an option, and the line starting with one of the configured
+/// cursors is the selected one. Instead of text, a `MenuSelection` event is
+/// emitted when the options or the selection change and stay the same for two
+/// recognitions in a row, which filters out frames where the menu is being
+/// drawn.
+pub struct MenuProcessor {
+    region: Region,
+    /// Options and selected index of the previous recognition.
+    previous_menu: Option<(Vec<String>, Option<usize>)>,
+    /// Options and selected index last emitted.
+    emitted_menu: Option<(Vec<String>, Option<usize>)>,
+    output_buffer: VecDeque<Event>,
+}
+
+impl MenuProcessor {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            previous_menu: None,
+            emitted_menu: None,
+            output_buffer: VecDeque::new(),
+        }
+    }
+
+    /// Returns the option text without the cursor, or None if it doesn't
+    /// start with a cursor.
+    fn strip_cursor<'a>(&self, text: &'a str) -> Option<&'a str> {
+        self.region
+            .menu
+            .cursors
+            .iter()
+            .find_map(|cursor| text.strip_prefix(cursor.as_str()))
+            .map(str::trim_start)
+    }
+}
+
+impl TextProcessor for MenuProcessor {
+    fn process(
+        &mut self,
+        _date: &DateTime<Utc>,
+        _text: &str,
+        _block_bounding_boxes: &[BoundingBox],
+    ) {
+    }
+
+    fn process_lines(&mut self, date: &DateTime<Utc>, lines: &[TextLine]) {
+        let mut lines: Vec<&TextLine> = lines
+            .iter()
+            .filter(|line| line.bounding_box.confidence >= 0.6 && !line.text.trim().is_empty())
+            .collect();
+        lines.sort_by_key(|line| line.bounding_box.y1);
+
+        let mut options = Vec::with_capacity(lines.len());
+        let mut selected = None;
+
+        for line in lines {
+            let text = line.text.trim();
+
+            match self.strip_cursor(text) {
+                Some(option) if selected.is_none() => {
+                    selected = Some(options.len());
+                    options.push(option.to_string());
+                }
+                _ => options.push(text.to_string()),
+            }
+        }
+
+        let menu = if options.is_empty() {
+            None
+        } else {
+            Some((options, selected))
+        };
+
+        if menu == self.previous_menu && menu != self.emitted_menu {
+            // A closed menu isn't reported but is emitted again when opened
+            if let Some((options, selected)) = &menu {
+                self.output_buffer.push_back(Event::MenuSelection {
+                    date: *date,
+                    region_name: self.region.name.clone(),
+                    options: options.clone(),
+                    selected: *selected,
+                });
+            }
+
+            self.emitted_menu = menu.clone();
+        }
+
+        self.previous_menu = menu;
+    }
+
+    fn poll_result(&mut self, _date: &DateTime<Utc>) -> Vec<TextItem> {
         Vec::new()
     }
+
+    fn poll_events(&mut self, _date: &DateTime<Utc>) -> Vec<Event> {
+        self.output_buffer.drain(..).collect()
+    }
+}
+
+/// Processes text recognition results for a region focused on a number,
+/// such as an HP, money, or level counter.
+///
+/// Only digits are recognized. Unlike `FixedLineProcessor`, readings are not
+/// grouped by similarity since a changed digit is a new value. An item with
+/// the parsed value is emitted each time the value changes.
+pub struct NumericProcessor {
+    region: Region,
+    previous_value: Option<i64>,
+    output_buffer: VecDeque<TextItem>,
+}
+
+impl NumericProcessor {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            previous_value: None,
+            output_buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl TextProcessor for NumericProcessor {
+    fn process(&mut self, date: &DateTime<Utc>, text: &str, block_bounding_boxes: &[BoundingBox]) {
+        if !is_text_block_confidence_ok(0.6, block_bounding_boxes) {
+            return;
+        }
+
+        let digits: String = text.chars().filter(char::is_ascii_digit).collect();
+        let value = match digits.parse::<i64>() {
+            Ok(value) => value,
+            // Empty or too long
+            Err(_) => return,
+        };
+
+        if self.previous_value == Some(value) {
+            return;
+        }
+
+        self.previous_value = Some(value);
+        self.output_buffer.push_back(TextItem {
+            date: *date,
+            region_name: self.region.name.clone(),
+            text: digits,
+            confidence: block_bounding_boxes.first().unwrap().confidence,
+            location: None,
+            value: Some(value),
+            block_box: block_bounding_boxes.first().cloned(),
+            word_boxes: Vec::new(),
+            metadata: RecordMetadata::default(),
+            integrity: None,
+            stream_name: None,
+        });
+    }
+
+    fn process_words(&mut self, date: &DateTime<Utc>, words: &[TextLine]) {
+        if let Some(item) = self.output_buffer.back_mut() {
+            if item.date == *date {
+                item.word_boxes = word_boxes(words, item.block_box.as_ref());
+            }
+        }
+    }
+
+    fn poll_result(&mut self, _date: &DateTime<Utc>) -> Vec<TextItem> {
+        self.output_buffer.drain(..).collect()
+    }
+}
+
+/// Returns the boxes of the words whose center is inside the box, or of all
+/// words without a box.
+fn word_boxes(words: &[TextLine], bounding_box: Option<&BoundingBox>) -> Vec<BoundingBox> {
+    words
+        .iter()
+        .map(|word| &word.bounding_box)
+        .filter(|word_box| match bounding_box {
+            Some(bounding_box) => {
+                let center_x = (word_box.x1 + word_box.x2) / 2;
+                let center_y = (word_box.y1 + word_box.y2) / 2;
+
+                (bounding_box.x1..=bounding_box.x2).contains(&center_x)
+                    && (bounding_box.y1..=bounding_box.y2).contains(&center_y)
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
 }
 
 fn is_text_block_top_left(region: &Region, block_bounding_boxes: &[BoundingBox]) -> bool {
     if let Some(bounding_box) = block_bounding_boxes.first() {
-        let ratio_x = (region.x as f32 - bounding_box.x1 as f32) / region.width as f32;
-        let ratio_y = (region.y as f32 - bounding_box.y1 as f32) / region.height as f32;
+        let bounding_box = bounding_box.to_region_space(region);
+        let ratio_x = bounding_box.x1 as f32 / region.width as f32;
+        let ratio_y = bounding_box.y1 as f32 / region.height as f32;
 
         ratio_x <= 0.2 && ratio_y <= 0.2
     } else {
@@ -154,3 +1023,388 @@ fn is_text_block_confidence_ok(threshold: f32, block_bounding_boxes: &[BoundingB
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_text_block_top_left() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "dialog"
+            x = 100
+            y = 200
+            width = 300
+            height = 100
+            processor = "DialogScroll"
+            "#,
+        )
+        .unwrap();
+        let block = |x1, y1| BoundingBox {
+            confidence: 0.9,
+            x1,
+            y1,
+            x2: x1 + 50,
+            y2: y1 + 20,
+        };
+
+        assert!(is_text_block_top_left(&region, &[block(110, 205)]));
+        assert!(!is_text_block_top_left(&region, &[block(250, 205)]));
+        assert!(!is_text_block_top_left(&region, &[block(110, 270)]));
+        assert!(!is_text_block_top_left(&region, &[]));
+    }
+
+    #[test]
+    fn test_dialog_scroll() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "dialog"
+            x = 0
+            y = 0
+            width = 300
+            height = 100
+            processor = "DialogScroll"
+            "#,
+        )
+        .unwrap();
+        let mut processor = DialogScrollProcessor::new(region);
+        let start_date = Utc::now();
+        let mut texts = Vec::new();
+        let frames: &[&[&str]] = &[
+            &["PROF. OAK: Hel"],
+            &["PROF. OAK: Hello there! Wel-"],
+            &["PROF. OAK: Hello there! Wel-", "come to the world"],
+            // Scrolled up
+            &["come to the world", "of POKéMON! My name"],
+            &["of POKéMON! My name", "is OAK"],
+            // Next page
+            &["People call me the"],
+            &["People call me the", "POKéMON PROF!"],
+            &[],
+            &["Oh, hi"],
+        ];
+
+        for (index, frame) in frames.iter().enumerate() {
+            let date = start_date + chrono::Duration::seconds(index as i64);
+            let lines: Vec<TextLine> = frame
+                .iter()
+                .map(|text| TextLine {
+                    text: text.to_string(),
+                    bounding_box: BoundingBox {
+                        confidence: 0.9,
+                        x1: 0,
+                        y1: 0,
+                        x2: 100,
+                        y2: 20,
+                    },
+                })
+                .collect();
+
+            processor.process_lines(&date, &lines);
+            texts.extend(
+                processor
+                    .poll_result(&date)
+                    .into_iter()
+                    .map(|item| item.text),
+            );
+        }
+
+        let date = start_date + chrono::Duration::seconds(60);
+        texts.extend(
+            processor
+                .poll_result(&date)
+                .into_iter()
+                .map(|item| item.text),
+        );
+
+        assert_eq!(
+            texts,
+            [
+                "PROF. OAK: Hello there!",
+                "Welcome to the world of POKéMON!",
+                // Ended by the next page
+                "My name is OAK",
+                "People call me the POKéMON PROF!",
+                "Oh, hi",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ticker() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "ticker"
+            x = 0
+            y = 0
+            width = 200
+            height = 20
+            processor = "Ticker"
+            "#,
+        )
+        .unwrap();
+        let mut processor = TickerProcessor::new(region);
+        let start_date = Utc::now();
+        let mut texts = Vec::new();
+        // Without gaps, the second time around ends the first pass, and the
+        // third pass is the same as the second
+        let message = "RED used SURF! It's super effective!";
+        let passes = format!("{0}  {0}  {0}  ", message);
+        let word_starts: Vec<(usize, &str)> = passes
+            .split(' ')
+            .scan(0, |start, word| {
+                let word_start = *start;
+                *start += word.len() + 1;
+                Some((word_start, word))
+            })
+            .filter(|(_, word)| !word.is_empty())
+            .collect();
+
+        for frame_index in 0..200 {
+            let date = start_date + chrono::Duration::seconds(frame_index);
+            // Scrolls 6 pixels a frame with characters 8 pixels wide
+            let offset = 200 - frame_index as i32 * 6;
+            let words: Vec<TextLine> = word_starts
+                .iter()
+                .map(|(start, word)| {
+                    let x1 = offset + *start as i32 * 8;
+
+                    TextLine {
+                        text: word.to_string(),
+                        bounding_box: BoundingBox {
+                            confidence: 0.9,
+                            x1,
+                            y1: 2,
+                            x2: x1 + word.len() as i32 * 8,
+                            y2: 18,
+                        },
+                    }
+                })
+                .filter(|word| word.bounding_box.x2 > 0 && word.bounding_box.x1 < 200)
+                .collect();
+
+            processor.process_words(&date, &words);
+            texts.extend(
+                processor
+                    .poll_result(&date)
+                    .into_iter()
+                    .map(|item| item.text),
+            );
+        }
+
+        assert_eq!(texts, [message]);
+
+        // A gap ends the pass, which is emitted if it differs
+        let date = start_date + chrono::Duration::seconds(300);
+        let word = |text: &str, x1| TextLine {
+            text: text.to_string(),
+            bounding_box: BoundingBox {
+                confidence: 0.9,
+                x1,
+                y1: 2,
+                x2: x1 + 40,
+                y2: 18,
+            },
+        };
+
+        processor.process_words(&date, &[]);
+        processor.process_words(&date, &[word("NEW", 100), word("GAME", 150)]);
+        processor.process_words(&date, &[word("NEW", 90), word("GAME", 140)]);
+        processor.process_words(&date, &[]);
+
+        let texts: Vec<String> = processor
+            .poll_result(&date)
+            .into_iter()
+            .map(|item| item.text)
+            .collect();
+        assert_eq!(texts, ["NEW GAME"]);
+    }
+
+    #[test]
+    fn test_menu() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "menu"
+            x = 0
+            y = 0
+            width = 100
+            height = 100
+            processor = "Menu"
+            "#,
+        )
+        .unwrap();
+        let mut processor = MenuProcessor::new(region);
+        let date = Utc::now();
+        let lines = |texts: &[&str]| -> Vec<TextLine> {
+            texts
+                .iter()
+                .enumerate()
+                .map(|(index, text)| TextLine {
+                    text: text.to_string(),
+                    bounding_box: BoundingBox {
+                        confidence: 0.9,
+                        x1: 0,
+                        y1: index as i32 * 20,
+                        x2: 80,
+                        y2: index as i32 * 20 + 16,
+                    },
+                })
+                .collect()
+        };
+        let mut events = Vec::new();
+        let frames: &[&[&str]] = &[
+            &["FIGHT", "PKMN"],
+            &["> FIGHT", "PKMN", "ITEM", "RUN"],
+            &["> FIGHT", "PKMN", "ITEM", "RUN"],
+            &["> FIGHT", "PKMN", "ITEM", "RUN"],
+            &["FIGHT", "PKMN", "▶ITEM", "RUN"],
+            &["FIGHT", "PKMN", "▶ITEM", "RUN"],
+            &[],
+            &[],
+            &["FIGHT", "PKMN", "▶ITEM", "RUN"],
+            &["FIGHT", "PKMN", "▶ITEM", "RUN"],
+        ];
+
+        for frame in frames {
+            processor.process_lines(&date, &lines(frame));
+            assert!(processor.poll_result(&date).is_empty());
+            events.extend(processor.poll_events(&date));
+        }
+
+        let menus: Vec<(Vec<String>, Option<usize>)> = events
+            .into_iter()
+            .map(|event| match event {
+                Event::MenuSelection {
+                    options, selected, ..
+                } => (options, selected),
+                _ => unreachable!(),
+            })
+            .collect();
+        let options: Vec<String> = ["FIGHT", "PKMN", "ITEM", "RUN"]
+            .iter()
+            .map(|option| option.to_string())
+            .collect();
+
+        assert_eq!(
+            menus,
+            [
+                (options.clone(), Some(0)),
+                (options.clone(), Some(2)),
+                (options, Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numeric() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "money"
+            x = 0
+            y = 0
+            width = 100
+            height = 20
+            processor = "Numeric"
+            "#,
+        )
+        .unwrap();
+        let mut processor = NumericProcessor::new(region);
+        let date = Utc::now();
+        let block = |confidence| BoundingBox {
+            confidence,
+            x1: 0,
+            y1: 0,
+            x2: 50,
+            y2: 16,
+        };
+        let mut values = Vec::new();
+
+        for (text, confidence) in &[
+            ("3000\n", 0.9),
+            ("3000", 0.9),
+            ("3 500", 0.9),
+            ("8500", 0.3),
+            ("", 0.9),
+            ("99999999999999999999", 0.9),
+            ("3000", 0.9),
+        ] {
+            processor.process(&date, text, &[block(*confidence)]);
+            values.extend(
+                processor
+                    .poll_result(&date)
+                    .into_iter()
+                    .map(|item| (item.text, item.value)),
+            );
+        }
+
+        assert_eq!(
+            values,
+            [
+                ("3000".to_string(), Some(3000)),
+                ("3500".to_string(), Some(3500)),
+                ("3000".to_string(), Some(3000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixed_line_config() {
+        let region: Region = toml::from_str(
+            r#"
+            name = "banner"
+            x = 0
+            y = 0
+            width = 300
+            height = 100
+            processor = "FixedLine"
+            fixed_line = { similarity_threshold = 0.99, flush_secs = 0.5 }
+            "#,
+        )
+        .unwrap();
+        let mut processor = FixedLineProcessor::new(region);
+        let start_date = Utc::now();
+        let block = |confidence| BoundingBox {
+            confidence,
+            x1: 0,
+            y1: 0,
+            x2: 50,
+            y2: 16,
+        };
+
+        // Similar enough for the default threshold but not this one
+        processor.process(&start_date, "SCORE 1200", &[block(0.8)]);
+        processor.process(&start_date, "SCORE 1300", &[block(0.9)]);
+        processor.process_words(
+            &start_date,
+            &[("SCORE", 0), ("1300", 30), ("LIVES", 100)]
+                .iter()
+                .map(|(text, x1)| TextLine {
+                    text: text.to_string(),
+                    bounding_box: BoundingBox {
+                        confidence: 0.9,
+                        x1: *x1,
+                        y1: 0,
+                        x2: x1 + 20,
+                        y2: 16,
+                    },
+                })
+                .collect::<Vec<TextLine>>(),
+        );
+
+        let texts: Vec<String> = processor
+            .poll_result(&start_date)
+            .into_iter()
+            .map(|item| item.text)
+            .collect();
+        assert_eq!(texts, ["SCORE 1200"]);
+
+        let date = start_date + chrono::Duration::seconds(1);
+        let items = processor.poll_result(&date);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].text, "SCORE 1300");
+        assert_eq!(items[0].block_box, Some(block(0.9)));
+        // The word outside the block is left out
+        assert_eq!(items[0].word_boxes.len(), 2);
+    }
+}
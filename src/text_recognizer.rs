@@ -1,28 +1,75 @@
-use std::ffi::{CStr, CString};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{CStr, CString},
+    os::raw::c_char,
+};
 
-use anyhow::bail;
+use serde::Serialize;
 use tesseract_sys::TessBaseAPI;
 
+use crate::{
+    config::{OnnxConfig, Region},
+    error::OcrError,
+    ocr_engine::{BoxLevel, OcrEngine, PageSegmentation},
+    onnx_engine,
+};
+
 pub struct TextRecognizer {
     api: *mut TessBaseAPI,
+    /// Whitelist of the Tesseract variables, restored for regions that don't
+    /// restrict characters.
+    character_whitelist: String,
 }
 
 impl TextRecognizer {
-    pub fn new(data_path: &str, language: &str) -> anyhow::Result<Self> {
+    /// Creates an instance with Tesseract variables set by name.
+    ///
+    /// Variables are given at initialization instead of with
+    /// `TessBaseAPISetVariable` afterwards because some, such as
+    /// `load_system_dawg`, only take effect while the language data is
+    /// loaded.
+    pub fn new(
+        data_path: &str,
+        language: &str,
+        variables: &BTreeMap<String, String>,
+    ) -> Result<Self, OcrError> {
+        let c_names = variables
+            .keys()
+            .map(|name| CString::new(name.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let c_values = variables
+            .values()
+            .map(|value| CString::new(value.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut name_pointers: Vec<*mut c_char> = c_names
+            .iter()
+            .map(|name| name.as_ptr() as *mut c_char)
+            .collect();
+        let mut value_pointers: Vec<*mut c_char> = c_values
+            .iter()
+            .map(|value| value.as_ptr() as *mut c_char)
+            .collect();
+
         let api = unsafe {
             let api = tesseract_sys::TessBaseAPICreate();
             let c_data_path = CString::new(data_path)?;
             let c_language = CString::new(language)?;
 
-            let result = tesseract_sys::TessBaseAPIInit2(
+            let result = tesseract_sys::TessBaseAPIInit4(
                 api,
                 c_data_path.as_ptr(),
                 c_language.as_ptr(),
                 tesseract_sys::TessOcrEngineMode_OEM_LSTM_ONLY,
+                std::ptr::null_mut(),
+                0,
+                name_pointers.as_mut_ptr(),
+                value_pointers.as_mut_ptr(),
+                name_pointers.len() as _,
+                0,
             );
 
             if result != 0 {
-                bail!("tesseract initialization returned error code {}", result);
+                return Err(OcrError::Init(result));
             }
 
             tesseract_sys::TessBaseAPISetPageSegMode(
@@ -33,10 +80,191 @@ impl TextRecognizer {
             api
         };
 
-        Ok(Self { api })
+        Ok(Self {
+            api,
+            character_whitelist: variables
+                .get("tessedit_char_whitelist")
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn get_text(&self) -> String {
+        unsafe { take_text(tesseract_sys::TessBaseAPIGetUTF8Text(self.api)) }
+    }
+
+    /// Returns the recognition result as an hOCR document, which keeps the
+    /// page layout, boxes, and confidences of each element.
+    pub fn get_hocr(&self) -> String {
+        unsafe { take_text(tesseract_sys::TessBaseAPIGetHOCRText(self.api, 0)) }
+    }
+
+    /// Returns the recognition result as tab-separated values with a row
+    /// per layout element.
+    pub fn get_tsv(&self) -> String {
+        unsafe { take_text(tesseract_sys::TessBaseAPIGetTsvText(self.api, 0)) }
+    }
+
+    fn get_boxes(&self, level: tesseract_sys::TessPageIteratorLevel) -> Vec<BoundingBox> {
+        let mut boxes = Vec::new();
+
+        self.for_each_result(level, |_iterator, bounding_box| boxes.push(bounding_box));
+
+        boxes
     }
 
-    pub fn set_image(&self, data: &[u32], width: u32, height: u32) {
+    /// Calls the function with the iterator and bounding box of each result
+    /// at the level.
+    fn for_each_result<F>(&self, level: tesseract_sys::TessPageIteratorLevel, mut callback: F)
+    where
+        F: FnMut(*mut tesseract_sys::TessResultIterator, BoundingBox),
+    {
+        unsafe {
+            let iterator = tesseract_sys::TessBaseAPIGetIterator(self.api);
+
+            if iterator.is_null() {
+                return;
+            }
+
+            let page_iterator = tesseract_sys::TessResultIteratorGetPageIterator(iterator);
+
+            loop {
+                let confidence = tesseract_sys::TessResultIteratorConfidence(iterator, level);
+                let mut x1 = 0;
+                let mut y1 = 0;
+                let mut x2 = 0;
+                let mut y2 = 0;
+                tesseract_sys::TessPageIteratorBoundingBox(
+                    page_iterator,
+                    level,
+                    &mut x1,
+                    &mut y1,
+                    &mut x2,
+                    &mut y2,
+                );
+
+                callback(
+                    iterator,
+                    BoundingBox {
+                        confidence: confidence / 100.0,
+                        x1,
+                        y1,
+                        x2,
+                        y2,
+                    },
+                );
+
+                if tesseract_sys::TessResultIteratorNext(iterator, level) == 0 {
+                    break;
+                }
+            }
+
+            tesseract_sys::TessResultIteratorDelete(iterator);
+        }
+    }
+
+    pub fn get_block_boxes(&self) -> Vec<BoundingBox> {
+        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_BLOCK)
+    }
+
+    pub fn get_line_boxes(&self) -> Vec<BoundingBox> {
+        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_TEXTLINE)
+    }
+
+    pub fn get_word_boxes(&self) -> Vec<BoundingBox> {
+        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_WORD)
+    }
+
+    pub fn get_symbol_boxes(&self) -> Vec<BoundingBox> {
+        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_SYMBOL)
+    }
+
+    /// Returns the text of each line with its bounding box.
+    pub fn get_lines(&self) -> Vec<TextLine> {
+        self.get_texts(tesseract_sys::TessPageIteratorLevel_RIL_TEXTLINE)
+    }
+
+    /// Returns each recognized character with its bounding box.
+    pub fn get_symbols(&self) -> Vec<TextLine> {
+        self.get_texts(tesseract_sys::TessPageIteratorLevel_RIL_SYMBOL)
+    }
+
+    /// Returns the text of each result at the level with its bounding box.
+    fn get_texts(&self, level: tesseract_sys::TessPageIteratorLevel) -> Vec<TextLine> {
+        let mut lines = Vec::new();
+
+        self.for_each_result(level, |iterator, bounding_box| unsafe {
+            let raw_c_string = tesseract_sys::TessResultIteratorGetUTF8Text(iterator, level);
+
+            if raw_c_string.is_null() {
+                return;
+            }
+
+            let text = CStr::from_ptr(raw_c_string)
+                .to_string_lossy()
+                .trim()
+                .to_string();
+            tesseract_sys::TessDeleteText(raw_c_string);
+
+            lines.push(TextLine { text, bounding_box });
+        });
+
+        lines
+    }
+}
+
+impl OcrEngine for TextRecognizer {
+    /// Returns the language codes found in the data path.
+    fn available_languages(&self) -> Vec<String> {
+        let mut languages = Vec::new();
+
+        unsafe {
+            let array = tesseract_sys::TessBaseAPIGetAvailableLanguagesAsVector(self.api);
+
+            if !array.is_null() {
+                let mut index = 0;
+
+                while !(*array.offset(index)).is_null() {
+                    let c_string = CStr::from_ptr(*array.offset(index));
+                    languages.push(c_string.to_string_lossy().to_string());
+                    index += 1;
+                }
+
+                tesseract_sys::TessDeleteTextArray(array);
+            }
+        }
+
+        languages
+    }
+
+    fn set_page_segmentation_mode(&self, mode: PageSegmentation) {
+        let mode = match mode {
+            PageSegmentation::Auto => tesseract_sys::TessPageSegMode_PSM_AUTO,
+            PageSegmentation::SingleBlock => tesseract_sys::TessPageSegMode_PSM_SINGLE_BLOCK,
+            PageSegmentation::SingleLine => tesseract_sys::TessPageSegMode_PSM_SINGLE_LINE,
+            PageSegmentation::SingleWord => tesseract_sys::TessPageSegMode_PSM_SINGLE_WORD,
+            PageSegmentation::SingleCharacter => tesseract_sys::TessPageSegMode_PSM_SINGLE_CHAR,
+            PageSegmentation::SparseText => tesseract_sys::TessPageSegMode_PSM_SPARSE_TEXT,
+        };
+
+        unsafe {
+            tesseract_sys::TessBaseAPISetPageSegMode(self.api, mode);
+        }
+    }
+
+    fn set_character_whitelist(&self, characters: Option<&str>) {
+        let c_name = CString::new("tessedit_char_whitelist").unwrap();
+        let characters = characters.unwrap_or(&self.character_whitelist);
+
+        // Characters can't contain nul
+        if let Ok(c_value) = CString::new(characters) {
+            unsafe {
+                tesseract_sys::TessBaseAPISetVariable(self.api, c_name.as_ptr(), c_value.as_ptr());
+            }
+        }
+    }
+
+    fn set_image(&self, data: &[u32], width: u32, height: u32) {
         unsafe {
             tesseract_sys::TessBaseAPISetImage(
                 self.api,
@@ -51,7 +279,7 @@ impl TextRecognizer {
         }
     }
 
-    pub fn set_rectangle(&self, left: u32, top: u32, width: u32, height: u32) {
+    fn set_rectangle(&self, left: u32, top: u32, width: u32, height: u32) {
         unsafe {
             tesseract_sys::TessBaseAPISetRectangle(
                 self.api,
@@ -63,77 +291,50 @@ impl TextRecognizer {
         }
     }
 
-    pub fn recognize(&self) -> anyhow::Result<()> {
+    fn recognize(&self) -> Result<(), OcrError> {
         let result = unsafe { tesseract_sys::TessBaseAPIRecognize(self.api, std::ptr::null_mut()) };
 
         if result != 0 {
-            bail!("tesseract recognize error code {}", result);
+            Err(OcrError::Recognize(result))
         } else {
             Ok(())
         }
     }
 
-    pub fn get_text(&self) -> String {
-        unsafe {
-            let raw_c_string = tesseract_sys::TessBaseAPIGetUTF8Text(self.api);
-            let c_string = CStr::from_ptr(raw_c_string);
-            let string = c_string.to_string_lossy().to_string();
-            tesseract_sys::TessDeleteText(raw_c_string);
-
-            string
-        }
+    fn text(&self) -> String {
+        self.get_text()
     }
 
-    fn get_boxes(&self, level: tesseract_sys::TessPageIteratorLevel) -> Vec<BoundingBox> {
-        let mut boxes = Vec::new();
-
-        unsafe {
-            let iterator = tesseract_sys::TessBaseAPIGetIterator(self.api);
-            let page_iterator = tesseract_sys::TessResultIteratorGetPageIterator(iterator);
-
-            if !iterator.is_null() {
-                loop {
-                    let confidence = tesseract_sys::TessResultIteratorConfidence(iterator, level);
-                    let mut x1 = 0;
-                    let mut y1 = 0;
-                    let mut x2 = 0;
-                    let mut y2 = 0;
-                    tesseract_sys::TessPageIteratorBoundingBox(
-                        page_iterator,
-                        level,
-                        &mut x1,
-                        &mut y1,
-                        &mut x2,
-                        &mut y2,
-                    );
-
-                    boxes.push(BoundingBox {
-                        confidence: confidence / 100.0,
-                        x1,
-                        y1,
-                        x2,
-                        y2,
-                    });
+    fn boxes(&self, level: BoxLevel) -> Vec<BoundingBox> {
+        self.get_boxes(iterator_level(level))
+    }
 
-                    if tesseract_sys::TessResultIteratorNext(iterator, level) == 0 {
-                        break;
-                    }
-                }
-            }
-        }
+    fn texts(&self, level: BoxLevel) -> Vec<TextLine> {
+        self.get_texts(iterator_level(level))
+    }
 
-        boxes
+    fn hocr(&self) -> Option<String> {
+        Some(self.get_hocr())
     }
 
-    pub fn get_block_boxes(&self) -> Vec<BoundingBox> {
-        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_BLOCK)
+    fn tsv(&self) -> Option<String> {
+        Some(self.get_tsv())
     }
+}
 
-    pub fn get_word_boxes(&self) -> Vec<BoundingBox> {
-        self.get_boxes(tesseract_sys::TessPageIteratorLevel_RIL_WORD)
+fn iterator_level(level: BoxLevel) -> tesseract_sys::TessPageIteratorLevel {
+    match level {
+        BoxLevel::Block => tesseract_sys::TessPageIteratorLevel_RIL_BLOCK,
+        BoxLevel::Line => tesseract_sys::TessPageIteratorLevel_RIL_TEXTLINE,
+        BoxLevel::Word => tesseract_sys::TessPageIteratorLevel_RIL_WORD,
+        BoxLevel::Symbol => tesseract_sys::TessPageIteratorLevel_RIL_SYMBOL,
     }
 }
 
+// The Tesseract API instance may be moved to another thread as long as it is
+// used by one thread at a time.
+unsafe impl Send for TextRecognizer {}
+
 impl Drop for TextRecognizer {
     fn drop(&mut self) {
         unsafe {
@@ -142,6 +343,131 @@ impl Drop for TextRecognizer {
     }
 }
 
+pub fn tesseract_version() -> String {
+    unsafe {
+        CStr::from_ptr(tesseract_sys::TessVersion())
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// Copies and frees a string allocated by Tesseract. A null pointer, returned
+/// when there is no result, is an empty string.
+unsafe fn take_text(raw_c_string: *mut c_char) -> String {
+    if raw_c_string.is_null() {
+        return String::new();
+    }
+
+    let string = CStr::from_ptr(raw_c_string).to_string_lossy().to_string();
+    tesseract_sys::TessDeleteText(raw_c_string);
+
+    string
+}
+
+/// OCR engine instances keyed by language code.
+pub struct TextRecognizerSet {
+    data_path: String,
+    default_language: String,
+    variables: BTreeMap<String, String>,
+    recognizers: HashMap<String, Box<dyn OcrEngine>>,
+    /// ONNX engines of regions that use them instead of Tesseract.
+    onnx_engines: Vec<(OnnxConfig, Box<dyn OcrEngine>)>,
+}
+
+impl TextRecognizerSet {
+    /// Creates the set with an instance for the default language. The
+    /// Tesseract variables apply to every instance.
+    pub fn new(
+        data_path: &str,
+        default_language: &str,
+        variables: BTreeMap<String, String>,
+    ) -> Result<Self, OcrError> {
+        let mut recognizers: HashMap<String, Box<dyn OcrEngine>> = HashMap::new();
+        recognizers.insert(
+            default_language.to_string(),
+            Box::new(TextRecognizer::new(
+                data_path,
+                default_language,
+                &variables,
+            )?),
+        );
+
+        Ok(Self {
+            data_path: data_path.to_string(),
+            default_language: default_language.to_string(),
+            variables,
+            recognizers,
+            onnx_engines: Vec::new(),
+        })
+    }
+
+    pub fn default_language(&self) -> &str {
+        &self.default_language
+    }
+
+    /// Creates an instance for the language if one is not already loaded.
+    pub fn load(&mut self, language: &str) -> Result<(), OcrError> {
+        if !self.recognizers.contains_key(language) {
+            let recognizer = TextRecognizer::new(&self.data_path, language, &self.variables)?;
+            self.recognizers
+                .insert(language.to_string(), Box::new(recognizer));
+        }
+
+        Ok(())
+    }
+
+    /// Creates an ONNX engine for the models if one is not already loaded.
+    pub fn load_onnx(&mut self, config: &OnnxConfig) -> Result<(), OcrError> {
+        if !self
+            .onnx_engines
+            .iter()
+            .any(|(engine_config, _)| engine_config == config)
+        {
+            let engine = onnx_engine::create_engine(config)?;
+            self.onnx_engines.push((config.clone(), engine));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the region's ONNX engine if it has one, otherwise the
+    /// Tesseract instance for the language.
+    ///
+    /// Panics if the engine or language was not loaded.
+    pub fn get_for_region(&self, region: &Region, language: Option<&str>) -> &dyn OcrEngine {
+        match &region.onnx {
+            Some(config) => self
+                .onnx_engines
+                .iter()
+                .find(|(engine_config, _)| engine_config == config)
+                .map(|(_, engine)| engine.as_ref())
+                .unwrap_or_else(|| panic!("ONNX engine of region {} not loaded", region.name)),
+            None => self.get(language),
+        }
+    }
+
+    /// Returns the instance for the language or the default language if None.
+    ///
+    /// Panics if the language was not loaded.
+    pub fn get(&self, language: Option<&str>) -> &dyn OcrEngine {
+        let language = language.unwrap_or(&self.default_language);
+
+        self.recognizers
+            .get(language)
+            .unwrap_or_else(|| panic!("language {} not loaded", language))
+            .as_ref()
+    }
+}
+
+/// Rectangle of recognized text.
+///
+/// Boxes returned by `TextRecognizer` are in the pixel coordinates of the
+/// image given to `set_image`, which for regions is the preprocessed and
+/// possibly scaled region image. The processor converts them to frame
+/// coordinates with `image_to_frame_space`, so boxes in recognition results
+/// and `TextItem` locations are always in frame coordinates. Use
+/// `to_region_space` for coordinates relative to the region's top left.
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct BoundingBox {
     pub confidence: f32, // in range [0.0, 1.0] where 1.0 is 100% confidence
     pub x1: i32,
@@ -149,3 +475,38 @@ pub struct BoundingBox {
     pub x2: i32,
     pub y2: i32,
 }
+
+impl BoundingBox {
+    /// Converts a box from the region's recognition image to the frame.
+    pub fn image_to_frame_space(&self, region: &Region) -> BoundingBox {
+        let scale = region.scale.max(1) as i32;
+
+        BoundingBox {
+            confidence: self.confidence,
+            x1: self.x1 / scale + region.x as i32,
+            y1: self.y1 / scale + region.y as i32,
+            x2: self.x2 / scale + region.x as i32,
+            y2: self.y2 / scale + region.y as i32,
+        }
+    }
+
+    /// Converts a box from the frame to be relative to the region's top left,
+    /// in frame pixels.
+    pub fn to_region_space(&self, region: &Region) -> BoundingBox {
+        BoundingBox {
+            confidence: self.confidence,
+            x1: self.x1 - region.x as i32,
+            y1: self.y1 - region.y as i32,
+            x2: self.x2 - region.x as i32,
+            y2: self.y2 - region.y as i32,
+        }
+    }
+}
+
+/// Recognized text of a line, or of a smaller element such as a symbol, and
+/// its bounding box.
+#[derive(Clone, Debug)]
+pub struct TextLine {
+    pub text: String,
+    pub bounding_box: BoundingBox,
+}
@@ -0,0 +1,112 @@
+//! Regex rules rewriting or dropping recognized text before it is emitted,
+//! such as stripping stray punctuation or dropping known UI artifacts.
+
+use std::{convert::TryFrom, fmt};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::TextRule;
+
+/// Regular expression compiled when the config is parsed, so an invalid
+/// pattern is reported as a config error.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(Self(Regex::new(&value)?))
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+/// Applies the rules in order.
+///
+/// Returns None if a rule drops the text or the rewritten text is blank.
+pub fn apply(rules: &[TextRule], text: &str) -> Option<String> {
+    let mut text = text.to_string();
+
+    for rule in rules {
+        match rule {
+            TextRule::Replace {
+                pattern,
+                replacement,
+            } => {
+                text = pattern
+                    .regex()
+                    .replace_all(&text, replacement.as_str())
+                    .into_owned();
+            }
+            TextRule::Drop { pattern } => {
+                if pattern.regex().is_match(&text) {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        rules: Vec<TextRule>,
+    }
+
+    #[test]
+    fn test_apply() {
+        let config: Config = toml::de::from_str(
+            r#"
+            rules = [
+                { method = "Replace", pattern = "[|_~]+", replacement = "" },
+                { method = "Replace", pattern = "\\s{2,}", replacement = " " },
+                { method = "Drop", pattern = "^\\s*(PRESS START|▼)\\s*$" },
+                { method = "Replace", pattern = "POKé", replacement = "POKÉ" },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            apply(&config.rules, "|Wild  PIDGEY_ appeared!"),
+            Some("Wild PIDGEY appeared!".to_string())
+        );
+        assert_eq!(apply(&config.rules, "~PRESS START~"), None);
+        assert_eq!(apply(&config.rules, "|__|"), None);
+        assert_eq!(
+            apply(&config.rules, "POKé BALL"),
+            Some("POKÉ BALL".to_string())
+        );
+
+        let error = toml::de::from_str::<Config>(r#"rules = [{ method = "Drop", pattern = "(" }]"#);
+        assert!(error.is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Announcing emitted items in a Twitch channel's chat.
+//!
+//! Twitch chat is IRC over TLS authenticated with an OAuth token, so the
+//! IRC relay is used with Twitch's server and rate limit.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::TwitchConfig,
+    irc::{self, Connection, RateLimit, Relay},
+    text_processor::TextItem,
+};
+
+const TWITCH_ADDRESS: &str = "irc.chat.twitch.tv:6697";
+
+pub struct TwitchSink {
+    config: TwitchConfig,
+    relay: Relay,
+}
+
+impl TwitchSink {
+    pub fn new(config: TwitchConfig) -> Self {
+        let token = config.oauth_token.expose();
+        let relay = Relay::new(
+            Connection {
+                address: TWITCH_ADDRESS.to_string(),
+                tls: true,
+                password: Some(format!(
+                    "oauth:{}",
+                    token.strip_prefix("oauth:").unwrap_or(token)
+                )),
+                // Twitch only accepts lowercase login names
+                nickname: config.username.to_lowercase(),
+                channel: format!("#{}", config.channel.to_lowercase()),
+            },
+            RateLimit::new(
+                config.max_messages as usize,
+                Duration::from_secs_f64(config.window_secs.max(0.0)),
+            ),
+        );
+
+        Self { config, relay }
+    }
+
+    pub fn config(&self) -> &TwitchConfig {
+        &self.config
+    }
+
+    /// Sends a message for each line of the item unless its region is not
+    /// posted.
+    pub fn send_item(&mut self, item: &TextItem) {
+        if !self.config.regions.is_empty() && !self.config.regions.contains(&item.region_name) {
+            return;
+        }
+
+        for line in irc::message_lines(&item.text) {
+            self.relay.send(line);
+        }
+    }
+
+    /// Returns when `poll_connection` is due.
+    pub fn connection_poll_time(&self) -> Instant {
+        self.relay.poll_time()
+    }
+
+    /// Reconnects when due, or answers the server's pings.
+    pub fn poll_connection(&mut self) {
+        self.relay.poll();
+    }
+}
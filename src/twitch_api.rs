@@ -0,0 +1,240 @@
+//! Polling of the Twitch Helix API for the title, game, and start time of
+//! the channel's stream, which are attached to emitted items.
+//!
+//! The API is queried with an app access token from the client credentials
+//! flow, so no user has to authorize the application.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use slog_scope::{info, warn};
+
+use crate::config::TwitchMetadataConfig;
+
+const TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
+const STREAMS_URL: &str = "https://api.twitch.tv/helix/streams";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the polling thread checks whether it was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Live stream of the channel.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StreamInfo {
+    pub title: String,
+    pub game: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Polls the stream from a background thread until dropped.
+pub struct StreamInfoPoller {
+    config: TwitchMetadataConfig,
+    stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl StreamInfoPoller {
+    pub fn new(config: TwitchMetadataConfig) -> Self {
+        let stream_info = Arc::new(Mutex::new(None));
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_config = config.clone();
+        let thread_stream_info = Arc::clone(&stream_info);
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        std::thread::spawn(move || {
+            poll_streams(&thread_config, &thread_stream_info, &thread_stop_flag)
+        });
+
+        Self {
+            config,
+            stream_info,
+            stop_flag,
+        }
+    }
+
+    pub fn config(&self) -> &TwitchMetadataConfig {
+        &self.config
+    }
+
+    /// Returns the stream of the latest poll, or None while the channel is
+    /// offline or before the first poll.
+    pub fn stream_info(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}
+
+impl Drop for StreamInfoPoller {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+fn poll_streams(
+    config: &TwitchMetadataConfig,
+    stream_info: &Mutex<Option<StreamInfo>>,
+    stop_flag: &AtomicBool,
+) {
+    let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+    let interval = Duration::from_secs_f64(config.poll_secs.max(1.0));
+    let mut access_token = None;
+
+    while !stop_flag.load(Ordering::Relaxed) {
+        let poll_start = Instant::now();
+
+        if let Some(new_stream_info) = get_stream_info(&agent, config, &mut access_token) {
+            let mut stream_info = stream_info.lock().unwrap();
+
+            if *stream_info != new_stream_info {
+                match &new_stream_info {
+                    Some(new_stream_info) => info!("Twitch stream changed";
+                        "channel" => &config.channel,
+                        "title" => &new_stream_info.title,
+                        "game" => &new_stream_info.game,
+                        "started_at" => %new_stream_info.started_at),
+                    None => info!("Twitch stream offline"; "channel" => &config.channel),
+                }
+
+                *stream_info = new_stream_info;
+            }
+        }
+
+        while poll_start.elapsed() < interval && !stop_flag.load(Ordering::Relaxed) {
+            std::thread::sleep(STOP_CHECK_INTERVAL.min(interval));
+        }
+    }
+}
+
+/// Returns the channel's stream, or None on errors, which are logged.
+fn get_stream_info(
+    agent: &ureq::Agent,
+    config: &TwitchMetadataConfig,
+    access_token: &mut Option<String>,
+) -> Option<Option<StreamInfo>> {
+    let token = match access_token {
+        Some(token) => token.clone(),
+        None => {
+            let token = get_access_token(agent, config)?;
+            *access_token = Some(token.clone());
+            token
+        }
+    };
+
+    let response = agent
+        .get(STREAMS_URL)
+        .query("user_login", &config.channel)
+        .set("Client-Id", &config.client_id)
+        .set("Authorization", &format!("Bearer {}", token))
+        .call();
+
+    match response {
+        Ok(response) => {
+            let text = response.into_string().ok()?;
+            let value = serde_json::from_str(&text).ok()?;
+            let stream_info = parse_streams(&value);
+
+            if stream_info.is_none() && !is_empty_stream_list(&value) {
+                warn!("unexpected Twitch streams response"; "channel" => &config.channel);
+                return None;
+            }
+
+            Some(stream_info)
+        }
+        Err(ureq::Error::Status(401, _)) => {
+            // App access tokens expire, so a new one is requested next time
+            *access_token = None;
+            None
+        }
+        Err(ureq::Error::Status(status, _)) => {
+            warn!("Twitch streams request failed"; "status" => status);
+            None
+        }
+        Err(error) => {
+            warn!("Twitch streams request failed"; "error" => %error.kind());
+            None
+        }
+    }
+}
+
+fn get_access_token(agent: &ureq::Agent, config: &TwitchMetadataConfig) -> Option<String> {
+    let response = agent.post(TOKEN_URL).send_form(&[
+        ("client_id", &config.client_id),
+        ("client_secret", config.client_secret.expose()),
+        ("grant_type", "client_credentials"),
+    ]);
+
+    match response {
+        Ok(response) => {
+            let text = response.into_string().ok()?;
+            let value: Value = serde_json::from_str(&text).ok()?;
+
+            value["access_token"].as_str().map(str::to_string)
+        }
+        Err(ureq::Error::Status(status, _)) => {
+            warn!("Twitch token request failed"; "status" => status);
+            None
+        }
+        Err(error) => {
+            warn!("Twitch token request failed"; "error" => %error.kind());
+            None
+        }
+    }
+}
+
+/// Returns the first stream of a Get Streams response.
+fn parse_streams(value: &Value) -> Option<StreamInfo> {
+    let stream = value["data"].as_array()?.first()?;
+
+    Some(StreamInfo {
+        title: stream["title"].as_str()?.to_string(),
+        game: stream["game_name"].as_str()?.to_string(),
+        started_at: stream["started_at"].as_str()?.parse().ok()?,
+    })
+}
+
+fn is_empty_stream_list(value: &Value) -> bool {
+    value["data"].as_array().map_or(false, Vec::is_empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_streams() {
+        let value = json!({
+            "data": [{
+                "user_login": "twitchplayspokemon",
+                "game_name": "Pokémon Crystal",
+                "type": "live",
+                "title": "Twitch Plays Pokémon",
+                "started_at": "2021-01-10T21:00:00Z",
+            }],
+            "pagination": {},
+        });
+
+        assert_eq!(
+            parse_streams(&value),
+            Some(StreamInfo {
+                title: "Twitch Plays Pokémon".to_string(),
+                game: "Pokémon Crystal".to_string(),
+                started_at: Utc.ymd(2021, 1, 10).and_hms(21, 0, 0),
+            })
+        );
+
+        let value = json!({ "data": [], "pagination": {} });
+
+        assert_eq!(parse_streams(&value), None);
+        assert!(is_empty_stream_list(&value));
+        assert!(!is_empty_stream_list(&json!({ "error": "Unauthorized" })));
+    }
+}
@@ -0,0 +1,258 @@
+//! Checks for region configs that load fine but are probably mistakes.
+//!
+//! Regions outside of the frame are not among them since they fail to load.
+
+use std::fmt;
+
+use slog_scope::warn;
+
+use crate::{
+    config::{IssueSeverity, ProcessorConfig, ProcessorStrategy, Region},
+    error::ConfigError,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigIssue {
+    /// Two regions that can be active at the same time overlap.
+    RegionOverlap { first: String, second: String },
+    /// The regions stacked on the debug display need a larger VNC screen.
+    DebugLayoutOverflow {
+        required_width: u32,
+        required_height: u32,
+        canvas_width: u32,
+        canvas_height: u32,
+    },
+}
+
+impl ConfigIssue {
+    fn severity(&self, config: &ProcessorConfig) -> IssueSeverity {
+        match self {
+            ConfigIssue::RegionOverlap { .. } => config.validation.region_overlap,
+            ConfigIssue::DebugLayoutOverflow { .. } => config.validation.debug_layout_overflow,
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigIssue::RegionOverlap { first, second } => {
+                write!(f, "regions {} and {} overlap", first, second)
+            }
+            ConfigIssue::DebugLayoutOverflow {
+                required_width,
+                required_height,
+                canvas_width,
+                canvas_height,
+            } => write!(
+                f,
+                "debug display of regions needs {}x{} but the VNC screen is {}x{}; \
+                use --vnc-width {} --vnc-height {}",
+                required_width,
+                required_height,
+                canvas_width,
+                canvas_height,
+                (*required_width).max(*canvas_width),
+                (*required_height).max(*canvas_height)
+            ),
+        }
+    }
+}
+
+/// Returns the issues found in the regions.
+///
+/// Regions of different streams, or of scenes that are never active
+/// together, are not considered overlapping. FullFrameSparse regions are
+/// meant to cover other regions and are skipped.
+pub fn find_issues(
+    config: &ProcessorConfig,
+    canvas_width: u32,
+    canvas_height: u32,
+) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+
+    for (index, first) in config.region.iter().enumerate() {
+        for second in &config.region[index + 1..] {
+            if first.stream == second.stream
+                && first.processor != ProcessorStrategy::FullFrameSparse
+                && second.processor != ProcessorStrategy::FullFrameSparse
+                && rectangles_overlap(first, second)
+                && can_be_active_together(config, &first.name, &second.name)
+            {
+                issues.push(ConfigIssue::RegionOverlap {
+                    first: first.name.clone(),
+                    second: second.name.clone(),
+                });
+            }
+        }
+    }
+
+    let history_width = config
+        .overlay
+        .history
+        .as_ref()
+        .map_or(0, |history| history.width);
+    let required_width = config
+        .region
+        .iter()
+        .map(|region| region.width)
+        .max()
+        .unwrap_or(0)
+        + history_width;
+    let region_spacing = config.display.region_spacing();
+    let required_height = config
+        .region
+        .iter()
+        .map(|region| region.height + region_spacing)
+        .sum();
+
+    if required_width > canvas_width || required_height > canvas_height {
+        issues.push(ConfigIssue::DebugLayoutOverflow {
+            required_width,
+            required_height,
+            canvas_width,
+            canvas_height,
+        });
+    }
+
+    issues
+}
+
+/// Logs the issues as configured and returns an error if any of them is
+/// configured as an error.
+pub fn check(
+    config: &ProcessorConfig,
+    canvas_width: u32,
+    canvas_height: u32,
+) -> Result<(), ConfigError> {
+    let mut errors = Vec::new();
+
+    for issue in find_issues(config, canvas_width, canvas_height) {
+        match issue.severity(config) {
+            IssueSeverity::Ignore => {}
+            IssueSeverity::Warn => warn!("config issue"; "issue" => %issue),
+            IssueSeverity::Error => errors.push(issue.to_string()),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Validation(errors.join("; ")))
+    }
+}
+
+fn rectangles_overlap(first: &Region, second: &Region) -> bool {
+    let end = |start: u32, size: u32| u64::from(start) + u64::from(size);
+
+    u64::from(first.x) < end(second.x, second.width)
+        && u64::from(second.x) < end(first.x, first.width)
+        && u64::from(first.y) < end(second.y, second.height)
+        && u64::from(second.y) < end(first.y, first.height)
+}
+
+fn can_be_active_together(config: &ProcessorConfig, first: &str, second: &str) -> bool {
+    let scenes_of = |name: &str| {
+        config
+            .scene
+            .iter()
+            .filter(|scene| scene.regions.iter().any(|region| region == name))
+            .map(|scene| scene.name.as_str())
+            .collect::<Vec<_>>()
+    };
+    let first_scenes = scenes_of(first);
+    let second_scenes = scenes_of(second);
+
+    // Regions not listed in any scene are always active
+    first_scenes.is_empty()
+        || second_scenes.is_empty()
+        || first_scenes
+            .iter()
+            .any(|scene| second_scenes.contains(scene))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HistoryPanelConfig;
+
+    #[test]
+    fn test_find_issues() {
+        let config: ProcessorConfig = toml::from_str(
+            r#"
+            [[region]]
+            name = "dialog"
+            x = 0
+            y = 400
+            width = 600
+            height = 200
+            processor = "DialogScroll"
+
+            [[region]]
+            name = "battle_menu"
+            x = 500
+            y = 500
+            width = 200
+            height = 100
+            processor = "FixedLine"
+
+            [[region]]
+            name = "nickname"
+            x = 500
+            y = 500
+            width = 200
+            height = 100
+            processor = "FixedLine"
+
+            [[scene]]
+            name = "battle"
+            regions = ["battle_menu"]
+
+            [[scene]]
+            name = "naming"
+            regions = ["nickname"]
+            "#,
+        )
+        .unwrap();
+
+        let issues = find_issues(&config, 1024, 768);
+
+        assert_eq!(
+            issues,
+            vec![
+                ConfigIssue::RegionOverlap {
+                    first: "dialog".to_string(),
+                    second: "battle_menu".to_string()
+                },
+                ConfigIssue::RegionOverlap {
+                    first: "dialog".to_string(),
+                    second: "nickname".to_string()
+                }
+            ]
+        );
+
+        assert!(matches!(
+            find_issues(&config, 640, 480).last(),
+            Some(ConfigIssue::DebugLayoutOverflow {
+                required_width: 600,
+                required_height: 544,
+                ..
+            })
+        ));
+
+        // The history panel is drawn to the right of the regions
+        let mut config = config;
+        config.overlay.history = Some(HistoryPanelConfig {
+            width: 500,
+            item_count: 10,
+        });
+
+        assert!(matches!(
+            find_issues(&config, 1024, 768).last(),
+            Some(ConfigIssue::DebugLayoutOverflow {
+                required_width: 1100,
+                ..
+            })
+        ));
+    }
+}
@@ -1,25 +1,255 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    collections::hash_map::DefaultHasher,
+    ffi::CString,
+    hash::{Hash, Hasher},
+    net::Ipv4Addr,
+    os::{
+        raw::{c_char, c_int, c_void},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
-use anyhow::{bail, Context};
-use slog_scope::info;
+use slog_scope::{debug, info, warn};
 
-use crate::{bindings::vnc, shared_memory::SharedMemory};
+use crate::{
+    bindings::vnc,
+    config::{RegionEditConfig, VncServerConfig},
+    error::{DisplayError, IpcError},
+    frame_ring::FrameRing,
+    region_editor::RegionEditor,
+    shared_memory::SharedMemory,
+};
 
 const BYTES_PER_PIXEL: u32 = 4;
+const MAX_READ_ATTEMPTS: u32 = 50;
+/// Side of the square tiles compared between updates, in pixels.
+const TILE_SIZE: u32 = 32;
+
+pub fn libvnc_version() -> String {
+    String::from_utf8_lossy(vnc::LIBVNCSERVER_VERSION)
+        .trim_end_matches('\0')
+        .to_string()
+}
 
+/// Serves the debug images of one or more processors, each as a screen on its
+/// own port.
+///
+/// All screens share one event loop so several streams don't need a process
+/// each.
 pub struct VncServer {
+    screens: Vec<VncScreen>,
+    listen_address: Ipv4Addr,
+    password: Option<PasswordList>,
+    web_client: Option<WebClient>,
+}
+
+/// Null-terminated list of passwords in the form libvnc checks them.
+struct PasswordList {
+    _passwords: Vec<CString>,
+    pointers: Vec<*mut c_char>,
+}
+
+/// Web VNC client served over HTTP by each screen.
+struct WebClient {
+    directory: CString,
+    port_offset: u16,
+}
+
+impl VncServer {
+    pub fn new(port: u16, width: u32, height: u32) -> Result<Self, DisplayError> {
+        let mut server = Self::without_screens();
+        server.add_screen(port, width, height)?;
+
+        Ok(server)
+    }
+
+    pub fn from_config(config: &VncServerConfig) -> Result<Self, DisplayError> {
+        if config.screen.is_empty() {
+            return Err(DisplayError::NoScreens);
+        }
+
+        let mut server = Self::without_screens();
+
+        if let Some(password) = &config.password {
+            server.set_password(password.expose())?;
+        }
+
+        server.set_listen_address(config.listen_address)?;
+
+        if let Some(web) = &config.web {
+            server.set_web_client(&web.directory, web.port_offset)?;
+        }
+
+        for screen in &config.screen {
+            server.add_screen(screen.id, screen.width, screen.height)?;
+
+            if let Some(edit) = &screen.edit {
+                server.screens.last_mut().unwrap().set_region_edit(edit)?;
+            }
+        }
+
+        Ok(server)
+    }
+
+    fn without_screens() -> Self {
+        Self {
+            screens: Vec::new(),
+            listen_address: Ipv4Addr::LOCALHOST,
+            password: None,
+            web_client: None,
+        }
+    }
+
+    /// Adds a screen showing the shared memory of the given ID on the port
+    /// of the same number.
+    pub fn add_screen(&mut self, port: u16, width: u32, height: u32) -> Result<(), DisplayError> {
+        if self.screens.iter().any(|screen| screen.port == port) {
+            return Err(DisplayError::DuplicateScreen { port });
+        }
+
+        self.screens.push(VncScreen::new(port, width, height)?);
+
+        Ok(())
+    }
+
+    /// Requires clients to authenticate with the password.
+    pub fn set_password(&mut self, password: &str) -> Result<(), DisplayError> {
+        let passwords = vec![CString::new(password).map_err(|_| DisplayError::InvalidPassword)?];
+        let mut pointers: Vec<*mut c_char> = passwords
+            .iter()
+            .map(|password| password.as_ptr() as *mut c_char)
+            .collect();
+        pointers.push(std::ptr::null_mut());
+
+        self.password = Some(PasswordList {
+            _passwords: passwords,
+            pointers,
+        });
+
+        Ok(())
+    }
+
+    /// Sets the address of the interface the screens listen on.
+    ///
+    /// A password must be set first to listen on an address other than
+    /// loopback.
+    pub fn set_listen_address(&mut self, address: Ipv4Addr) -> Result<(), DisplayError> {
+        if !address.is_loopback() && self.password.is_none() {
+            return Err(DisplayError::PasswordRequired { address });
+        }
+
+        self.listen_address = address;
+
+        Ok(())
+    }
+
+    /// Serves the files of a web VNC client such as noVNC over HTTP on the
+    /// port of each screen plus the offset.
+    ///
+    /// The client connects with a WebSocket to the port of the screen, which
+    /// libvnc accepts alongside VNC connections.
+    pub fn set_web_client(
+        &mut self,
+        directory: &Path,
+        port_offset: u16,
+    ) -> Result<(), DisplayError> {
+        let directory = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|_| DisplayError::InvalidWebDirectory)?;
+
+        self.web_client = Some(WebClient {
+            directory,
+            port_offset,
+        });
+
+        Ok(())
+    }
+
+    pub fn screen_count(&self) -> usize {
+        self.screens.len()
+    }
+
+    pub fn run(&mut self) -> Result<(), DisplayError> {
+        if let Some(web_client) = &self.web_client {
+            for screen in &self.screens {
+                if screen.port.checked_add(web_client.port_offset).is_none() {
+                    return Err(DisplayError::HttpPort { port: screen.port });
+                }
+            }
+        }
+
+        for screen in &mut self.screens {
+            let screen_info = screen.create_screen()?;
+            screen.set_up_screen(
+                screen_info,
+                self.listen_address,
+                self.password.as_mut(),
+                self.web_client.as_ref(),
+            );
+        }
+
+        info!("loop start"; "screen_count" => self.screens.len(),
+            "listen_address" => %self.listen_address,
+            "password" => self.password.is_some(),
+            "web_client" => self.web_client.is_some());
+
+        let terminate_flag = Arc::new(AtomicBool::new(false));
+        for sig in signal_hook::consts::TERM_SIGNALS {
+            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        }
+
+        let screen_count = self.screens.len() as i64;
+
+        while self.screens.iter().any(VncScreen::is_active) {
+            for screen in self.screens.iter_mut().filter(|screen| screen.is_active()) {
+                // Split the wait between the screens so each is updated as
+                // often as a single screen would be
+                screen.update(screen_count);
+            }
+
+            if terminate_flag.load(Ordering::Relaxed) {
+                info!("server shutdown");
+
+                for screen in self.screens.iter_mut().filter(|screen| screen.is_active()) {
+                    screen.shut_down();
+                }
+            }
+        }
+
+        info!("loop stop");
+
+        Ok(())
+    }
+}
+
+struct VncScreen {
     port: u16,
     width: u32,
     height: u32,
     shared_memory: SharedMemory,
     frame_buffer: Vec<u32>,
+    screen_info: Option<vnc::rfbScreenInfoPtr>,
+    region_edit: Option<RegionEdit>,
+    tile_hashes: TileHashes,
 }
 
-impl VncServer {
-    pub fn new(port: u16, width: u32, height: u32) -> anyhow::Result<Self> {
+/// State of a screen showing the stream for editing regions.
+struct RegionEdit {
+    stream_id: u16,
+    /// Opened once the stream dumper has created it.
+    frame_ring: Option<FrameRing>,
+    frame_data: Vec<u8>,
+    /// Boxed so libvnc's input callbacks can keep a pointer to it.
+    editor: Box<Mutex<RegionEditor>>,
+}
+
+impl VncScreen {
+    fn new(port: u16, width: u32, height: u32) -> Result<Self, DisplayError> {
         let pixel_count = (width * height) as usize;
         let data_size = (width * height * BYTES_PER_PIXEL) as usize;
 
@@ -35,57 +265,134 @@ impl VncServer {
             height,
             shared_memory,
             frame_buffer,
+            screen_info: None,
+            region_edit: None,
+            tile_hashes: TileHashes::new(width, height),
         })
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        let screen_info = self.create_screen()?;
-        self.set_up_screen(screen_info);
+    /// Shows the stream with the regions of the config file instead of the
+    /// debug image, and handles input to edit them.
+    fn set_region_edit(&mut self, config: &RegionEditConfig) -> Result<(), DisplayError> {
+        let editor = RegionEditor::load(&config.config, self.width, self.height)?;
 
-        info!("loop start");
+        self.region_edit = Some(RegionEdit {
+            stream_id: config.stream_id,
+            frame_ring: None,
+            frame_data: vec![0; self.frame_buffer.len() * BYTES_PER_PIXEL as usize],
+            editor: Box::new(Mutex::new(editor)),
+        });
 
-        let terminate_flag = Arc::new(AtomicBool::new(false));
-        for sig in signal_hook::consts::TERM_SIGNALS {
-            signal_hook::flag::register(*sig, Arc::clone(&terminate_flag)).unwrap();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        match self.screen_info {
+            Some(screen_info) => unsafe { vnc::rfbIsActive(screen_info) != 0 },
+            None => false,
+        }
+    }
+
+    fn update(&mut self, screen_count: i64) {
+        let screen_info = match self.screen_info {
+            Some(screen_info) => screen_info,
+            None => return,
+        };
+
+        if self.region_edit.is_some() {
+            self.copy_stream_frame();
+        } else {
+            self.copy_frame_buffer();
         }
 
-        while unsafe { vnc::rfbIsActive(screen_info) != 0 } {
-            self.shared_memory.lock()?;
-            // let rect = self.get_change_rect();
-            self.frame_buffer
-                .copy_from_slice(self.shared_memory.data_32());
-            self.shared_memory.unlock()?;
+        unsafe {
+            for (x1, y1, x2, y2) in self.tile_hashes.update(&self.frame_buffer) {
+                vnc::rfbMarkRectAsModified(screen_info, x1, y1, x2, y2);
+            }
+
+            vnc::rfbProcessEvents(
+                screen_info,
+                (*screen_info).deferUpdateTime as i64 * 1000 / screen_count.max(1),
+            );
+        }
+    }
+
+    fn shut_down(&mut self) {
+        if let Some(screen_info) = self.screen_info {
+            debug!("screen shutdown"; "port" => self.port);
 
-            // if let Some((x1, y1, x2, y2)) = rect {
-            //     unsafe {
-            //         vnc::rfbMarkRectAsModified(screen_info, x1, y1, x2, y2);
-            //     }
-            // }
             unsafe {
-                vnc::rfbMarkRectAsModified(
-                    screen_info,
-                    0,
-                    0,
-                    self.width as i32,
-                    self.height as i32,
-                );
-                vnc::rfbProcessEvents(screen_info, (*screen_info).deferUpdateTime as i64 * 1000);
+                vnc::rfbShutdownServer(screen_info, 1);
             }
+        }
+    }
 
-            if terminate_flag.load(Ordering::Relaxed) {
-                info!("server shutdown");
-                unsafe {
-                    vnc::rfbShutdownServer(screen_info, 1);
+    /// Copies the image drawn by the processor, retrying if it was being
+    /// drawn at the same time.
+    fn copy_frame_buffer(&mut self) {
+        for _ in 0..MAX_READ_ATTEMPTS {
+            if let Some(sequence) = self.shared_memory.begin_read() {
+                self.frame_buffer
+                    .copy_from_slice(self.shared_memory.data_32());
+
+                if self.shared_memory.validate_read(sequence) {
+                    return;
                 }
             }
+
+            std::thread::sleep(Duration::from_millis(1));
         }
 
-        info!("loop stop");
+        // The processor is stuck or died while drawing; a torn image is
+        // better than none for debugging
+        debug!("showing incomplete image"; "port" => self.port);
+        self.frame_buffer
+            .copy_from_slice(self.shared_memory.data_32());
+    }
 
-        Ok(())
+    /// Copies the newest stream frame and outlines the edited regions.
+    fn copy_stream_frame(&mut self) {
+        let port = self.port;
+        let region_edit = match &mut self.region_edit {
+            Some(region_edit) => region_edit,
+            None => return,
+        };
+
+        if region_edit.frame_ring.is_none() {
+            match FrameRing::open(region_edit.stream_id as u32) {
+                Ok(frame_ring) if frame_ring.frame_size() == region_edit.frame_data.len() => {
+                    region_edit.frame_ring = Some(frame_ring);
+                }
+                Ok(frame_ring) => debug!("stream frame size doesn't match screen";
+                    "port" => port, "frame_size" => frame_ring.frame_size()),
+                Err(error) => debug!("stream not available";
+                    "port" => port, "error" => %error),
+            }
+        }
+
+        if let Some(frame_ring) = &region_edit.frame_ring {
+            if let Some(frame_number) = frame_ring.write_count().checked_sub(1) {
+                if frame_ring
+                    .read(frame_number, &mut region_edit.frame_data)
+                    .is_some()
+                {
+                    for (pixel, bytes) in self
+                        .frame_buffer
+                        .iter_mut()
+                        .zip(region_edit.frame_data.chunks_exact(4))
+                    {
+                        *pixel = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    }
+                }
+            }
+        }
+
+        if let Ok(editor) = region_edit.editor.lock() {
+            editor.draw(&mut self.frame_buffer);
+        }
     }
 
-    pub fn create_screen(&self) -> anyhow::Result<vnc::rfbScreenInfoPtr> {
+    fn create_screen(&self) -> Result<vnc::rfbScreenInfoPtr, DisplayError> {
         let mut argc = 0;
         let screen_info = unsafe {
             vnc::rfbGetScreen(
@@ -100,14 +407,22 @@ impl VncServer {
         };
 
         if screen_info.is_null() {
-            bail!("get libvnc screen error");
+            Err(DisplayError::Screen)
         } else {
             Ok(screen_info)
         }
     }
 
-    fn set_up_screen(&mut self, screen_info: vnc::rfbScreenInfoPtr) {
+    fn set_up_screen(
+        &mut self,
+        screen_info: vnc::rfbScreenInfoPtr,
+        listen_address: Ipv4Addr,
+        password: Option<&mut PasswordList>,
+        web_client: Option<&WebClient>,
+    ) {
         unsafe {
+            // The buffer is owned by this screen and never resized, so the
+            // pointer stays valid while the screen exists
             (*screen_info).frameBuffer = self.frame_buffer.as_mut_ptr() as *mut i8;
             (*screen_info).alwaysShared = 1;
             (*screen_info).deferUpdateTime = 200;
@@ -115,43 +430,144 @@ impl VncServer {
             (*screen_info).port = self.port as i32;
             (*screen_info).ipv6port = 0; // disable IPv6
 
-            // Bind to a loopback interface, not public, for security good practices.
-            // A HTTP reverse proxy server can forward a Novnc websocket.
-            (*screen_info).listenInterface = 0x7f00_0001u32.to_be();
+            if let Some(region_edit) = &self.region_edit {
+                (*screen_info).screenData =
+                    &*region_edit.editor as *const Mutex<RegionEditor> as *mut c_void;
+                (*screen_info).ptrAddEvent = Some(edit_pointer_event);
+                (*screen_info).kbdAddEvent = Some(edit_key_event);
+            }
+
+            // Bind to a loopback interface by default, not public, for
+            // security good practices. Novnc can connect directly with a
+            // websocket or through a HTTP reverse proxy server.
+            (*screen_info).listenInterface = u32::from(listen_address).to_be();
+
+            // The list is owned by the server, which outlives the screens
+            if let Some(password) = password {
+                (*screen_info).authPasswdData = password.pointers.as_mut_ptr() as *mut c_void;
+                (*screen_info).passwordCheck = Some(vnc::rfbCheckPasswordByList);
+            }
+
+            // Owned by the server like the password list. libvnc doesn't
+            // modify or free it.
+            if let Some(web_client) = web_client {
+                (*screen_info).httpDir = web_client.directory.as_ptr() as *mut c_char;
+                (*screen_info).httpPort = (self.port + web_client.port_offset) as i32;
+                (*screen_info).http6Port = 0; // disable IPv6
+            }
 
             // Have to call rfbInitServer() with the expanded macro
             vnc::rfbInitServerWithPthreadsAndZRLE(screen_info);
         }
+
+        self.screen_info = Some(screen_info);
     }
+}
 
-    fn _get_change_rect(&self) -> Option<(i32, i32, i32, i32)> {
-        let pixel_count = (self.width * self.height) as usize;
+/// Hashes of the tiles of a screen for finding the parts that changed since
+/// the previous update.
+///
+/// Comparing hashes avoids keeping a second copy of the screen, and libvnc
+/// only sends the marked tiles instead of the whole screen.
+struct TileHashes {
+    width: u32,
+    height: u32,
+    columns: u32,
+    /// None until the tile is first hashed so the first update marks the
+    /// whole screen.
+    hashes: Vec<Option<u64>>,
+}
+
+impl TileHashes {
+    fn new(width: u32, height: u32) -> Self {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        Self {
+            width,
+            height,
+            columns,
+            hashes: vec![None; (columns * rows) as usize],
+        }
+    }
 
-        let mut min_x: i32 = i32::MAX;
-        let mut max_x: i32 = i32::MIN;
-        let mut min_y: i32 = i32::MAX;
-        let mut max_y: i32 = i32::MIN;
-        let mut has_changes = false;
+    /// Hashes the tiles of the pixels and returns the rectangles of the
+    /// changed tiles as (x1, y1, x2, y2). Adjacent changed tiles of a row are
+    /// joined into one rectangle.
+    fn update(&mut self, pixels: &[u32]) -> Vec<(i32, i32, i32, i32)> {
+        let mut rects = Vec::new();
+
+        for (row, row_hashes) in self.hashes.chunks_mut(self.columns as usize).enumerate() {
+            let y1 = row as u32 * TILE_SIZE;
+            let y2 = (y1 + TILE_SIZE).min(self.height);
+            let mut changed_start = None;
+
+            for (column, tile_hash) in row_hashes.iter_mut().enumerate() {
+                let x1 = column as u32 * TILE_SIZE;
+                let x2 = (x1 + TILE_SIZE).min(self.width);
+                let mut hasher = DefaultHasher::new();
+
+                for y in y1..y2 {
+                    let row_start = (y * self.width) as usize;
+                    pixels[row_start + x1 as usize..row_start + x2 as usize].hash(&mut hasher);
+                }
 
-        for pixel_index in 0..pixel_count {
-            let new_pixel = self.shared_memory.data_32()[pixel_index];
-            let old_pixel = self.frame_buffer[pixel_index];
-            let x = pixel_index as u32 % self.width;
-            let y = pixel_index as u32 / self.width;
+                let hash = Some(hasher.finish());
+                let changed = *tile_hash != hash;
+                *tile_hash = hash;
+
+                match (changed, changed_start) {
+                    (true, None) => changed_start = Some(x1),
+                    (false, Some(start)) => {
+                        rects.push((start as i32, y1 as i32, x1 as i32, y2 as i32));
+                        changed_start = None;
+                    }
+                    _ => {}
+                }
+            }
 
-            if new_pixel != old_pixel {
-                min_x = min_x.min(x as i32);
-                max_x = max_x.max(x as i32);
-                min_y = min_y.min(y as i32);
-                max_y = max_y.max(y as i32);
-                has_changes = true;
+            if let Some(start) = changed_start {
+                rects.push((start as i32, y1 as i32, self.width as i32, y2 as i32));
             }
         }
 
-        if has_changes {
-            Some((min_x, min_y, max_x + 1, max_y + 1))
-        } else {
-            None
+        rects
+    }
+}
+
+/// Returns the region editor of the screen of a client.
+///
+/// # Safety
+///
+/// The screen's data must be set to the editor by `set_up_screen`.
+unsafe fn client_editor<'a>(client: vnc::rfbClientPtr) -> &'a Mutex<RegionEditor> {
+    &*((*(*client).screen).screenData as *const Mutex<RegionEditor>)
+}
+
+unsafe extern "C" fn edit_pointer_event(
+    button_mask: c_int,
+    x: c_int,
+    y: c_int,
+    client: vnc::rfbClientPtr,
+) {
+    if let Ok(mut editor) = client_editor(client).lock() {
+        editor.pointer_event(button_mask, x.max(0) as u32, y.max(0) as u32);
+    }
+
+    // Keeps the cursor shown to other clients up to date
+    vnc::rfbDefaultPtrAddEvent(button_mask, x, y, client);
+}
+
+unsafe extern "C" fn edit_key_event(
+    down: vnc::rfbBool,
+    key_sym: vnc::rfbKeySym,
+    client: vnc::rfbClientPtr,
+) {
+    if let Ok(mut editor) = client_editor(client).lock() {
+        match editor.key_event(down != 0, key_sym) {
+            Ok(true) => info!("region edit key applied"; "key_sym" => key_sym),
+            Ok(false) => {}
+            Err(error) => warn!("region edit failed"; "error" => %error),
         }
     }
 }
@@ -163,7 +579,7 @@ pub struct VncClient {
 }
 
 impl VncClient {
-    pub fn new(port: u16, width: u32, height: u32) -> anyhow::Result<Self> {
+    pub fn new(port: u16, width: u32, height: u32) -> Result<Self, IpcError> {
         let data_size = (width * height * BYTES_PER_PIXEL) as usize;
 
         let shared_memory = SharedMemory::open_or_create(port as u32, data_size)?;
@@ -199,15 +615,41 @@ impl VncClient {
         self.shared_memory.data_32_mut()
     }
 
-    pub fn lock(&self) -> anyhow::Result<()> {
-        self.shared_memory
-            .lock()
-            .context("Failed to lock shared memory")
+    /// Marks the start of drawing so the server doesn't show a partial image.
+    pub fn begin_write(&self) {
+        self.shared_memory.begin_write()
+    }
+
+    pub fn end_write(&self) {
+        self.shared_memory.end_write()
     }
+}
 
-    pub fn unlock(&self) -> anyhow::Result<()> {
-        self.shared_memory
-            .unlock()
-            .context("Failed to unlock shared memory")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_hashes() {
+        let (width, height) = (100, 40);
+        let mut pixels = vec![0; (width * height) as usize];
+        let mut tile_hashes = TileHashes::new(width, height);
+
+        assert_eq!(
+            tile_hashes.update(&pixels),
+            [(0, 0, 100, 32), (0, 32, 100, 40)]
+        );
+        assert!(tile_hashes.update(&pixels).is_empty());
+
+        // Changes in the second and third tiles of the first row and the
+        // last tile of the second row
+        pixels[(10 * width + 40) as usize] = 1;
+        pixels[(10 * width + 70) as usize] = 1;
+        pixels[(35 * width + 99) as usize] = 1;
+
+        assert_eq!(
+            tile_hashes.update(&pixels),
+            [(32, 0, 96, 32), (96, 32, 100, 40)]
+        );
     }
 }
@@ -0,0 +1,325 @@
+//! Posting emitted items as JSON to an HTTP endpoint.
+//!
+//! Failed posts are retried with backoff. If the receiver stays down, the
+//! bodies are kept in a bounded spool file on disk. The spooled bodies are
+//! posted again, in order, before any newer body.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use slog_scope::{info, warn};
+
+use crate::{config::WebhookConfig, text_processor::TextItem};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const SPOOL_FILE_NAME: &str = "webhook_spool.jsonl";
+
+/// Posts items in batches of up to the configured size, sending a partial
+/// batch once the batch time has passed since its first item.
+pub struct WebhookSink {
+    config: WebhookConfig,
+    agent: ureq::Agent,
+    spool: Option<Spool>,
+    /// Items of the batch not posted yet.
+    batch: Vec<Value>,
+    /// When the batch is posted even if it isn't full.
+    batch_deadline: Option<Instant>,
+    /// Whether every attempt of the next post fails, for rehearsing retries
+    /// and spooling.
+    injected_failure: bool,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Self {
+        let spool = config
+            .spool_dir
+            .as_ref()
+            .map(|directory| Spool::new(directory, config.spool_max_bytes));
+
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            spool,
+            config,
+            batch: Vec::new(),
+            batch_deadline: None,
+            injected_failure: false,
+        }
+    }
+
+    pub fn config(&self) -> &WebhookConfig {
+        &self.config
+    }
+
+    /// Adds the item to the batch, posting the batch once it is full.
+    pub fn send_item(&mut self, item: &TextItem) {
+        if self.batch.is_empty() {
+            self.batch_deadline =
+                Some(Instant::now() + Duration::from_secs_f64(self.config.batch_secs.max(0.0)));
+        }
+
+        self.batch
+            .push(serde_json::to_value(item).unwrap_or(Value::Null));
+
+        if self.batch.len() >= self.config.batch_size.max(1) {
+            self.post_batch();
+        }
+    }
+
+    /// Returns when the batch is due.
+    pub fn batch_deadline(&self) -> Option<Instant> {
+        self.batch_deadline
+    }
+
+    /// Makes every attempt of the next post fail.
+    pub fn inject_failure(&mut self) {
+        self.injected_failure = true;
+    }
+
+    /// Posts the items of the batch, spooling the body if the receiver
+    /// can't be reached.
+    pub fn post_batch(&mut self) {
+        self.batch_deadline = None;
+
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let mut items = std::mem::take(&mut self.batch);
+
+        // Batches are arrays, so a receiver that gets single items doesn't
+        // need to handle both
+        let body = if self.config.batch_size <= 1 {
+            items.pop().unwrap().to_string()
+        } else {
+            Value::Array(items).to_string()
+        };
+
+        let (agent, config) = (&self.agent, &self.config);
+        let injected_failure = std::mem::take(&mut self.injected_failure);
+        let post = |body: &str| post(agent, config, body, injected_failure);
+
+        match &mut self.spool {
+            Some(spool) => {
+                // The body waits behind spooled bodies the receiver is
+                // still missing
+                if !spool.replay(|body| post(body) != PostResult::Failed)
+                    || post(&body) == PostResult::Failed
+                {
+                    spool.push(&body);
+                }
+            }
+            None => {
+                if post(&body) == PostResult::Failed {
+                    warn!("dropping webhook body");
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of posting a body, after retries.
+#[derive(Debug, PartialEq)]
+enum PostResult {
+    Delivered,
+    /// The receiver rejected the body, so posting it again won't help.
+    Rejected,
+    /// The receiver couldn't be reached or failed.
+    Failed,
+}
+
+/// Posts the body, retrying with backoff. With `injected_failure`, every
+/// attempt fails without sending.
+fn post(
+    agent: &ureq::Agent,
+    config: &WebhookConfig,
+    body: &str,
+    injected_failure: bool,
+) -> PostResult {
+    let mut delay = INITIAL_RETRY_DELAY;
+
+    for attempt in 1..=config.max_attempts.max(1) {
+        if injected_failure {
+            warn!("webhook post failed"; "attempt" => attempt, "error" => "injected failure");
+        } else if let Some(result) = try_post(agent, config, body, attempt) {
+            return result;
+        }
+
+        if attempt < config.max_attempts {
+            std::thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+
+    PostResult::Failed
+}
+
+/// Makes one attempt of posting the body. Returns None if it failed in a
+/// way that is retried.
+fn try_post(
+    agent: &ureq::Agent,
+    config: &WebhookConfig,
+    body: &str,
+    attempt: u32,
+) -> Option<PostResult> {
+    let mut request = agent
+        .post(config.url.expose())
+        .set("Content-Type", "application/json");
+
+    if let Some(authorization) = &config.authorization {
+        request = request.set("Authorization", authorization.expose());
+    }
+
+    for (name, value) in &config.headers {
+        request = request.set(name, value.expose());
+    }
+
+    match request.send_string(body) {
+        Ok(_) => Some(PostResult::Delivered),
+        Err(ureq::Error::Status(status, _)) if status < 500 && status != 429 => {
+            warn!("webhook rejected body"; "status" => status);
+            Some(PostResult::Rejected)
+        }
+        Err(ureq::Error::Status(status, _)) => {
+            warn!("webhook post failed"; "attempt" => attempt, "status" => status);
+            None
+        }
+        Err(error) => {
+            warn!("webhook post failed"; "attempt" => attempt, "error" => %error.kind());
+            None
+        }
+    }
+}
+
+/// File of bodies, one per line, waiting to be posted again.
+struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl Spool {
+    fn new(directory: &Path, max_bytes: u64) -> Self {
+        if let Err(error) = std::fs::create_dir_all(directory) {
+            warn!("failed to create webhook spool directory"; "path" => %directory.display(),
+                "error" => %error);
+        }
+
+        Self {
+            path: directory.join(SPOOL_FILE_NAME),
+            max_bytes,
+        }
+    }
+
+    /// Appends the body unless the spool is full.
+    fn push(&mut self, body: &str) {
+        let size = std::fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+
+        if size + body.len() as u64 + 1 > self.max_bytes {
+            warn!("webhook spool full, dropping body"; "path" => %self.path.display());
+            return;
+        }
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", body));
+
+        if let Err(error) = result {
+            warn!("failed to write webhook spool"; "path" => %self.path.display(),
+                "error" => %error);
+        }
+    }
+
+    /// Posts the spooled bodies in order until posting one fails, keeping
+    /// the rest.
+    ///
+    /// Returns whether the spool is empty afterwards. An unreadable spool
+    /// counts as empty so that new bodies are still posted.
+    fn replay<F>(&mut self, mut post: F) -> bool
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let bodies = match self.read() {
+            Ok(bodies) => bodies,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    warn!("failed to read webhook spool"; "path" => %self.path.display(),
+                        "error" => %error);
+                }
+                return true;
+            }
+        };
+
+        let posted_count = bodies.iter().take_while(|body| post(body)).count();
+
+        info!("replayed webhook spool"; "posted_count" => posted_count,
+            "remaining_count" => bodies.len() - posted_count);
+
+        if let Err(error) = self.write(&bodies[posted_count..]) {
+            warn!("failed to write webhook spool"; "path" => %self.path.display(),
+                "error" => %error);
+        }
+
+        posted_count == bodies.len()
+    }
+
+    fn read(&self) -> io::Result<Vec<String>> {
+        BufReader::new(File::open(&self.path)?).lines().collect()
+    }
+
+    fn write(&self, bodies: &[String]) -> io::Result<()> {
+        if bodies.is_empty() {
+            return std::fs::remove_file(&self.path);
+        }
+
+        // Replaced atomically so a crash doesn't lose the spool
+        let temp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+
+        for body in bodies {
+            writeln!(file, "{}", body)?;
+        }
+
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool() {
+        let directory = std::env::temp_dir()
+            .join(format!("tppocr-spool-{}", std::process::id()))
+            .join("webhook");
+        let mut spool = Spool::new(&directory, 20);
+
+        spool.push(r#"{"a":1}"#);
+        spool.push(r#"{"b":2}"#);
+        // Over the size limit
+        spool.push(r#"{"c":3}"#);
+
+        let mut posted = Vec::new();
+        assert!(!spool.replay(|body| {
+            posted.push(body.to_string());
+            posted.len() < 2
+        }));
+
+        assert_eq!(posted, vec![r#"{"a":1}"#, r#"{"b":2}"#]);
+        assert_eq!(spool.read().unwrap(), vec![r#"{"b":2}"#]);
+
+        assert!(spool.replay(|_| true));
+        assert!(!spool.path.exists());
+        assert!(spool.replay(|_| false));
+
+        std::fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+    }
+}